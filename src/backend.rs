@@ -1,582 +1,1330 @@
-//! Types for data storage/retrieval.
-
-pub(crate) mod sqlite;
-
-use crate::protos::Item;
-use core::str::FromStr;
-use std::{fmt::Display, io::{Read, Seek, SeekFrom}, marker::PhantomData};
-use actix_web::{web::Bytes};
-use anyhow::{Error, Context, bail, format_err};
-use bs58;
-use futures::Stream;
-use serde::{Deserialize, de::{self, Visitor}};
-use sizedisplay::SizeDisplay;
-use sodiumoxide::crypto::{hash::sha512, sign};
-
-/// This trait knows how to build a Factory, which in turn can open Backend connections.
-///
-/// It also provides functionality for checking/upgrading the backing database.
-pub trait FactoryBuilder {
-    /// Create a new factory which is capable of opening Backends.
-    /// Must first check that the database exists, and is the correct version.
-    fn factory(&self) -> Result<Box<dyn Factory>, Error>;
-
-    fn db_exists(&self) -> Result<bool, Error>;
-
-    fn db_create(&self) -> Result<(), Error>;
-
-    fn db_needs_upgrade(&self) -> Result<bool, Error>;
-
-    /// Upgrade the database to the currently supported version.
-    fn db_upgrade(&self) -> Result<(), Error>;
-}
-/// Knows how to open Backend "connections".
-pub trait Factory: Send + Sync
-{
-    /// Create a clone of this Factory.
-    /// Like Clone, but can operate on dyn pointers.
-    fn dyn_clone(&self) -> Box<dyn Factory>;
-
-    /// Open a single Backend connection.
-    /// It is recommended that Factory implementions use their own connection pooling.
-    fn open(&self) -> Result<Box<dyn Backend>, Error>;
-}
-
-/// Dumb hack to make dyn Factory impl Cloneable
-/// Clone is required for passing Factory instances to multiple web server threads.
-pub struct FactoryBox {
-    pub factory: Box<dyn Factory>
-}
-
-impl Clone for FactoryBox {
-    fn clone(&self) -> Self {
-        Self {
-            factory: self.factory.dyn_clone()
-        }
-    }
-}
-
-/// Represents a connection to the backend, and logic we want to perform
-/// with it.
-pub trait Backend
-{
-    // TODO: Remove reliance on anyhow::Error. We should define our own error
-    // type here. Should probably impl Error, which requires changes in sqlite.
-    // Maybe Box<dyn Error> is sufficient? https://github.com/dtolnay/anyhow/issues/25
-    
-    /// Find most recent items for users flagged to be displayed on the
-    /// home page, which have timestamps before `before`.
-    /// Items are returned through callback, and will continue to be fetched while callback continues
-    /// to return Ok(true).
-    fn homepage_items<'a>(
-        &self, 
-        time_span: TimeSpan,
-        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
-    ) -> Result<(), Error>;
-
-    /// Find the most recent items for a particular user
-    fn user_items<'a>(
-        &self,
-        user: &UserID,
-        time_span: TimeSpan,
-        callback: RowCallback<'a, ItemRow>,
-    ) -> Result<(), Error>;
-
-    /// Most recent replies to an Item
-    fn reply_items<'a>(
-        &self,
-        user: &UserID,
-        signature: &Signature,
-        before: Timestamp,
-        callback: RowCallback<'a, ItemRow>,
-    ) -> Result<(), Error>;
-
-    /// Find the most recent items from users followed by the given user ID. Includes the users's own items too.
-    fn user_feed_items<'a>(
-        &self,
-        user_id: &UserID,
-        time_span: TimeSpan,
-        callback: RowCallback<'a, ItemDisplayRow>,
-    ) -> Result<(), Error>;
-
-    /// Find one particular UserItem
-    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error>;
-
-    /// Effieicntly check whether a user item exists:
-    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
-
-    /// Save an uploaded item to the data store.
-    fn save_user_item(&mut self, item_row: &ItemRow, item: &Item) -> Result<(), Error>;
-
-    /// Get a "server user" -- a user granted direct access to post to the
-    /// server.
-    fn server_user(&self, user: &UserID) -> Result<Option<ServerUser>, Error>;
-
-    /// List users granted direct access to post to the server.
-    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error>;
-
-    /// Add a new "server user" who is explicitly allowed to post to this server.
-    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error>;
-
-    /// Get the Item(Row) that represents the user's most recently saved profile, if it exists.
-    fn user_profile(&self, user_id: &UserID) -> Result<Option<ItemRow>, Error>;
-
-    /// Is this user ID known to this server?
-    ///
-    /// This is true if any of these are true:
-    /// * The user is a "server user" (given direct permission to post to this server)
-    /// * The user is followed by a "server user". (We want their content so we can create a feed.)
-    fn user_known(&self, user_id: &UserID) -> Result<bool, Error>;
-
-    /// Check whether a user has remaiing quota/permissions to upload a particular item.
-    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error>;
-
-    /// Get a Stream of the bytes of the file attachment.
-    // TODO: Take refs.
-    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str) -> Result<Option<FileStream>, Error>;
-
-    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<FileMeta>, Error>;
-
-    /// Save a file attachment to our content store.
-    /// This assumes you have already validated the content's size and hash match those returned by get_attachment_meta().
-    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error>;
-
-    /// Report on database size usage by user.
-    /// Results sorted by total size desc. 
-    fn usage_by_user(&self, callback: RowCallback<'_, UsageByUserRow>) -> Result<(), Error>;
-
-    /// Remove unused data from the database.
-    fn prune(&self, opts: PruneOpts) -> Result<PruneResult, Error>;
-}
-
-pub struct FileStream {
-    /// file size in bytes
-    pub size: u64,
-
-    /// Stream of Bytes from the file:
-    pub stream: Box<dyn Stream<Item=Result<Bytes, crate::server::SendError>> + Unpin + Send + 'static>,
-}
-
-/// Metadata about a file attachment.
-pub struct FileMeta {
-    /// The hash of the file's contents.
-    pub hash: SHA512,
-    
-    /// Whether the file already exists in our content store.
-    pub exists: bool,
-
-    /// Size of the file in bytes, according to its metadata.
-    pub size: u64,
-
-    /// True iff uploading this attachment would cause the user to exceed their quota.
-    pub quota_exceeded: bool,
-}
-
-/// A callback function used for callback iteration through large database resultsets.
-/// Each row T will be sent to the callback. The callback should return Ok(true) to continue iteration.
-pub type RowCallback<'a, T> = &'a mut dyn FnMut(T) -> Result<bool, Error>; 
-
-/// A UserID is a nacl public key. (32 bytes)
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct UserID {
-    pub_key: sign::PublicKey,
-}
-
-// Expect a 32-byte nacl public key:
-const USER_ID_BYTES: usize = 32;
-
-impl UserID {
-    pub fn to_base58(&self) -> String {
-        bs58::encode(self.bytes()).into_string()
-    }
-
-    pub fn from_base58(value: &str) -> Result<Self, Error> {
-        let bytes = bs58::decode(value).into_vec()?;
-        Self::from_vec(bytes)
-    }
-
-    pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
-        if bytes.len() != USER_ID_BYTES {
-            bail!("Expected {} bytes but found {}", USER_ID_BYTES, bytes.len());
-        }
-
-        let pub_key = sign::PublicKey::from_slice(&bytes).ok_or_else(
-            || format_err!("Error creating nacl::PuublicKey")
-        )?;
-
-        Ok( UserID{ pub_key } )
-    }
-
-    pub fn bytes(&self) -> &[u8] {
-        self.pub_key.as_ref()
-    }
-}
-
-/// Allows easy destructuring from URLs.
-impl FromStr for UserID {
-    type Err = anyhow::Error;
-    fn from_str(value: &str) -> Result<Self, Self::Err> { 
-        UserID::from_base58(value)
-    }
-}
-
-impl Display for UserID {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_base58())
-    }
-}
-
-/// Bytes representing a detached NaCl signature. (64 bytes)
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Signature {
-    signature: sign::Signature,
-}
-
-const SIGNATURE_BYTES: usize = 64;
-
-impl Signature {
-    pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
-        if bytes.len() != SIGNATURE_BYTES {
-            bail!("Signature expected {} bytes but found {}", SIGNATURE_BYTES, bytes.len());
-        }
-
-        let signature = sign::Signature::from_slice(&bytes).ok_or_else(
-            || format_err!("Failure creating nacl::Signature")
-        )?;
-        
-        Ok( Signature{ signature } )
-    }
-
-    pub fn from_base58(value: &str) -> Result<Self, Error> {
-        let bytes = bs58::decode(value).into_vec()?;
-        Self::from_vec(bytes)
-    }
-
-    pub fn to_base58(&self) -> String {
-        bs58::encode(self.bytes()).into_string()
-    }
-
-    pub fn bytes(&self) -> &[u8] {
-        self.signature.as_ref()
-    }
-
-    /// True if this signature is valid for the given user on the given bytes.
-    pub fn is_valid(&self, user: &UserID, bytes: &[u8]) -> bool {
-        let pubkey = sign::PublicKey::from_slice(user.bytes()).expect("pubkey");
-        sign::verify_detached(&self.signature, bytes, &pubkey)
-    }
-
-}
-
-/// Allows easy destructuring from URLs. (in Warp)
-impl FromStr for Signature {
-    type Err = anyhow::Error;
-    fn from_str(value: &str) -> Result<Self, Self::Err> { 
-        Signature::from_base58(value)
-    }
-}
-
-impl <'de> Deserialize<'de> for Signature {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where D: serde::Deserializer<'de> 
-    {
-        deserializer.deserialize_str(FromStrVisitor::<Self>::new())
-    }
-}
-
-impl <'de> Deserialize<'de> for UserID {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where D: serde::Deserializer<'de> 
-    {
-        deserializer.deserialize_str(FromStrVisitor::<Self>::new())
-    }
-}
-
-struct FromStrVisitor<T: FromStr> {
-    _t: PhantomData<T>
-}
-
-impl <T: FromStr> FromStrVisitor<T> {
-    fn new() -> Self {
-        FromStrVisitor { _t: PhantomData }
-    }
-}
-
-impl <'de, T: FromStr<Err=Error>> Visitor<'de> for FromStrVisitor<T> 
-{
-    type Value = T;
-
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            formatter,
-            "a &str that can be converted to a {}",
-            std::any::type_name::<T>()
-        )
-    }
-
-    fn visit_str<E>(self, v: &str)
-    -> Result<Self::Value, E>
-    where E: de::Error
-    {
-        T::from_str(v).map_err(|e| de::Error::custom(format!("{}", e)))
-    }
-}
-
-/// Data that should be stored along with an Item
-/// 
-/// The signature should be validated on the front-end before being
-/// sent to the back-end. (This avoids each back-end having to re-implement
-/// validation logic). Likewise, the front-end may want to validate data returned
-/// by the backend to ensure it hasn't been modified or bit-rot.
-pub struct ItemRow {
-    pub user: UserID,
-    pub signature: Signature,
-
-    // The (signed) timestamp from within item_bytes.
-    pub timestamp: Timestamp,
-    
-    /// The time that this item was received by the server.
-    pub received: Timestamp,
-
-    /// Bytes which can be deserialized into an Item.
-    pub item_bytes: Vec<u8>,
-}
-
-/// An [`ItemRow`] that has extra information (fetched via joins)
-pub struct ItemDisplayRow {
-    pub item: ItemRow,
-
-    // TODO: Make an Arc<String> to avoid heap allocs?
-    // Or just make filling this in optional, since that's only used by the old HTML UI.
-    /// The display name for the author of the item, if available.
-    pub display_name: Option<String>
-}
-
-/// Info about users explicitly allowed on this server.
-/// i.e.: A row in the server_user table.
-#[derive(Debug, Clone)]
-pub struct ServerUser {
-    pub user: UserID,
-    pub notes: String,
-    pub on_homepage: bool,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub struct Timestamp {
-    /// UNIX time, at UTC, in milliseconds:
-    pub unix_utc_ms: i64
-}
-
-impl Timestamp {
-    pub fn now() -> Self {
-        use time::OffsetDateTime;
-        let delta = OffsetDateTime::now_utc() - OffsetDateTime::unix_epoch();
-        Timestamp {
-            unix_utc_ms: delta.whole_milliseconds() as i64,
-        }
-    }
-
-    pub fn format_with_offset(self, minutes: i16) -> String {
-        use time::{Duration, UtcOffset, OffsetDateTime};
-        use std::ops::Add;
-
-        let ms = Duration::milliseconds(self.unix_utc_ms);
-        let datetime = OffsetDateTime::unix_epoch().add(ms);
-        let offset = UtcOffset::minutes(minutes);
-        let datetime = datetime.to_offset(offset);
-
-        datetime.format("%Y-%m-%d %H:%M:%S %z")
-    }
-}
-/// A reason why a user can't post an Item or file attachment.
-pub enum QuotaDenyReason {
-    /// The user already has enough items newer than this one such that posting this one would exceed the quota.
-    /// 
-    // TODO: Use this.
-    #[allow(dead_code)]
-    NewerItemsExceedQuota {
-        /// The maximum bytes of Items this user can store on the server.
-        max_bytes: u64,
-    },
-
-    /// This user is not known to the server, so not allowed to post.
-    UnknownUser,
-
-    /// We already have a profile that proves that this userID has been revoked.
-    ProfileRevoked,
-}
-
-impl std::fmt::Display for QuotaDenyReason {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::NewerItemsExceedQuota { max_bytes } => 
-                write!(f, "Newer items exceed {} byte quota.", max_bytes),
-            Self::UnknownUser => 
-                write!(f, "This user is not known to the server."),
-            Self::ProfileRevoked => 
-                write!(f, "This user ID has been revoked."),
-        }
-    }
-}
-
-/// A 64-byte SHA-512 hash.
-/// Used by nacl internally, but also used by us for hashing file attachments.
-#[derive(PartialEq, Eq)]
-pub struct SHA512 {
-    hash: sodiumoxide::crypto::hash::sha512::Digest,
-}
-
-impl SHA512 {
-    pub fn from_hash_bytes(slice: &[u8]) -> Result<Self,  Error> {
-        use sodiumoxide::crypto::hash::sha512::Digest;
-        let digest = Digest::from_slice(slice);
-        if let Some(digest) = digest {
-            return Ok(Self { hash: digest});
-        }
-
-        bail!("SHA512::from_hash_bytes(): wrong number of bytes: {}", slice.len());
-    }
-
-    pub fn from_digest(digest: sha512::Digest) -> Self { 
-        Self { hash: digest }
-    }
-
-    pub fn bytes(&self) -> &[u8] {
-        return &self.hash.0
-    }
-
-    pub fn from_file<F>(file: &mut F) -> Result<Self, std::io::Error> 
-    where F: Read + Seek
-    {
-        file.seek(SeekFrom::Start(0))?;
-        let mut buf = [0u8; 8 * 1024];
-        let mut hasher = sha512::State::new();
-        let mut bytes = 0;
-        loop {
-            let count = file.read(&mut buf)?;
-            if count == 0 { break; }
-            bytes += count;
-            hasher.update(&buf[..count]);
-        }
-
-        Ok(Self { hash: hasher.finalize() })
-    }
-}
-
-impl Display for SHA512 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "SHA512:")?;
-        for b in self.bytes() {
-            write!(f, "{:02x}", b)?;
-        }
-        Ok(())
-    }
-}
-
-// TODO: Add signature to allow for pagination w/ full ordering:
-// TODO: Allow this to be an actual span so we can specify both ends?
-/// A(n unbounded) range of time we're requesting data for.
-#[derive(Debug)]
-pub enum TimeSpan {
-    /// Requests items before some Timestamp, in reverse chronological order.
-    Before(Timestamp),
-
-    /// Requests items after some timestamp, in (forward) chronological order.
-    After(Timestamp),
-}
-
-impl TimeSpan {
-    pub fn is_before(&self) -> bool {
-        match self {
-            Self::Before(_) => true,
-            _ => false,
-        }
-    }
-}
-
-pub struct UsageByUserOpts {
-
-}
-
-pub struct PruneOpts {
-    /// If set, then we don't actually do the delete and just report on what *would* be deleted.
-    pub dry_run: bool,
-
-    /// Should we delete unreferenced attachments?
-    pub attachments: bool,
-
-    // TODO:
-    // blocked_content
-    // blocked_items,
-
-    /// Delete items from users who are no longer followed?
-    pub items: bool,
-}
-
-
-/// Report how many things would be deleted, and their size.
-pub struct PruneResult {
-    /// Was this a dry run?
-    pub dry_run: bool,
-
-    pub attachments_count: u64,
-    pub attachments_bytes: u64,
-
-    pub items_count: u64,
-    pub items_bytes: u64,
-}
-
-impl Display for PruneResult {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use tablestream::{Stream, col, Column};
-
-        let title = if self.dry_run { "Dry run:" } else { "Pruned:" };
-
-        let mut out = vec![];
-        let mut stream = Stream::new(&mut out, vec![
-            col!(Row: .name).right(),
-            col!(Row: .count).header("Count").right(),
-            col!(Row: .size).header("Size").right(),
-        ]).title(&title).borders(true);
-
-        struct Row {
-            name: &'static str,
-            count: u64,
-            size: SizeDisplay,
-        }
-
-        stream.row(Row{
-            name: "Attachments",
-            count: self.attachments_count,
-            size: SizeDisplay::bytes(self.attachments_bytes),
-        }).map_err(|e| std::fmt::Error)?;
-
-        stream.row(Row{
-            name: "Items",
-            count: self.items_count,
-            size: SizeDisplay::bytes(self.items_count)
-        }).map_err(|e| std::fmt::Error)?;
-
-        let footer = format!("Total size: {}", SizeDisplay::bytes(self.items_bytes + self.attachments_bytes));
-        stream.footer(&footer).map_err(|e| std::fmt::Error)?;
-
-        write!(f, "{}", String::from_utf8_lossy(&out))
-    }
-}
-
-/// Information about a single user's database usage.
-pub struct UsageByUserRow {
-    pub user_id: UserID,
-    pub display_name: Option<String>,
-
-    pub server_user: bool,
-    pub known_user: bool,
-
-    pub attachments_count: u64,
-    pub attachments_bytes: u64,
-
-    pub items_count: u64,
-    pub items_bytes: u64,
-
-    pub total_bytes: u64,
-}
+//! Types for data storage/retrieval.
+
+pub(crate) mod attachments;
+pub(crate) mod memory;
+pub(crate) mod postgres;
+pub(crate) mod sqlite;
+
+#[cfg(test)]
+mod tests;
+
+use crate::protos::{Item, ProtoValid};
+use crate::util::AsHex;
+use core::str::FromStr;
+use std::{cmp::Ordering, collections::{HashMap, VecDeque}, fmt::Display, io::{Read, Seek, SeekFrom}, marker::PhantomData};
+use actix_web::{web::Bytes};
+use anyhow::{Error, Context, bail, format_err};
+use bs58;
+use futures::Stream;
+use protobuf::Message;
+use serde::{Deserialize, de::{self, Visitor}};
+use sizedisplay::SizeDisplay;
+use sodiumoxide::crypto::{box_, hash::sha512, sign};
+
+/// This trait knows how to build a Factory, which in turn can open Backend connections.
+///
+/// It also provides functionality for checking/upgrading the backing database.
+pub trait FactoryBuilder {
+    /// Create a new factory which is capable of opening Backends.
+    /// Must first check that the database exists, and is the correct version.
+    fn factory(&self) -> Result<Box<dyn Factory>, Error>;
+
+    fn db_exists(&self) -> Result<bool, Error>;
+
+    fn db_create(&self) -> Result<(), Error>;
+
+    fn db_needs_upgrade(&self) -> Result<bool, Error>;
+
+    /// Upgrade the database to the currently supported version.
+    fn db_upgrade(&self) -> Result<(), Error>;
+}
+/// Knows how to open Backend "connections".
+pub trait Factory: Send + Sync
+{
+    /// Create a clone of this Factory.
+    /// Like Clone, but can operate on dyn pointers.
+    fn dyn_clone(&self) -> Box<dyn Factory>;
+
+    /// Open a single Backend connection.
+    /// It is recommended that Factory implementions use their own connection pooling.
+    fn open(&self) -> Result<Box<dyn Backend>, Error>;
+
+    /// Like Backend::homepage_items(), but returns rows through an Iterator.
+    /// Each chunk of rows re-runs the query. See: [`RowIter`]
+    fn homepage_items_iter(&self, time_span: TimeSpan) -> RowIter<ItemDisplayRow> {
+        RowIter::new(self.dyn_clone(), time_span, |backend, span, callback| backend.homepage_items(span, callback))
+    }
+
+    /// Like Backend::user_items(), but returns rows through an Iterator.
+    /// Each chunk of rows re-runs the query. See: [`RowIter`]
+    fn user_items_iter(&self, user: &UserID, time_span: TimeSpan) -> RowIter<ItemRow> {
+        let user = user.clone();
+        RowIter::new(self.dyn_clone(), time_span, move |backend, span, callback| backend.user_items(&user, span, callback))
+    }
+
+    /// Like Backend::reply_items(), but returns rows through an Iterator.
+    /// Each chunk of rows re-runs the query. See: [`RowIter`]
+    fn reply_items_iter(&self, user: &UserID, signature: &Signature, time_span: TimeSpan) -> RowIter<ItemRow> {
+        let user = user.clone();
+        let signature = signature.clone();
+        RowIter::new(self.dyn_clone(), time_span, move |backend, span, callback| backend.reply_items(&user, &signature, span, callback))
+    }
+
+    /// Like Backend::user_feed_items(), but returns rows through an Iterator.
+    /// Each chunk of rows re-runs the query. See: [`RowIter`]
+    fn user_feed_items_iter(&self, user_id: &UserID, time_span: TimeSpan) -> RowIter<ItemDisplayRow> {
+        let user_id = user_id.clone();
+        RowIter::new(self.dyn_clone(), time_span, move |backend, span, callback| backend.user_feed_items(&user_id, span, callback))
+    }
+}
+
+/// Dumb hack to make dyn Factory impl Cloneable
+/// Clone is required for passing Factory instances to multiple web server threads.
+pub struct FactoryBox {
+    pub factory: Box<dyn Factory>
+}
+
+impl Clone for FactoryBox {
+    fn clone(&self) -> Self {
+        Self {
+            factory: self.factory.dyn_clone()
+        }
+    }
+}
+
+/// Represents a connection to the backend, and logic we want to perform
+/// with it.
+///
+/// Methods that list items for display (homepage_items(), user_feed_items(), etc.)
+/// leave out blocked items (even before they've been pruned) and quarantined items.
+pub trait Backend
+{
+    // TODO: Remove reliance on anyhow::Error. We should define our own error
+    // type here. Should probably impl Error, which requires changes in sqlite.
+    // Maybe Box<dyn Error> is sufficient? https://github.com/dtolnay/anyhow/issues/25
+    
+    /// Find most recent items for users flagged to be displayed on the
+    /// home page, which have timestamps before `before`.
+    /// Items are returned through callback, and will continue to be fetched while callback continues
+    /// to return Ok(true).
+    /// Excludes DirectMessages.
+    fn homepage_items<'a>(
+        &self, 
+        time_span: TimeSpan,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
+    ) -> Result<(), Error>;
+
+    /// Find the most recent items for a particular user
+    /// Excludes DirectMessages.
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error>;
+
+    /// Most recent replies to an Item
+    fn reply_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error>;
+
+    /// All Comments in the thread beneath an Item, depth-first.
+    ///
+    /// Each Comment is followed by its own replies. Replies to the same Item are
+    /// ordered oldest first. Stops after `limit` Comments.
+    fn thread_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        limit: usize,
+        callback: RowCallback<'a, ThreadRow>,
+    ) -> Result<(), Error>;
+
+    /// Revisions of a Post, most recent first.
+    fn item_revisions<'a>(
+        &self,
+        user: &UserID,
+        original: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error>;
+
+    /// Find DirectMessages sent to `recipient`, newest first.
+    fn inbox_items<'a>(
+        &self,
+        recipient: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error>;
+
+    /// Find the newest Revision of a Post, if it has been revised.
+    fn latest_revision(&self, user: &UserID, original: &Signature) -> Result<Option<ItemRow>, Error>;
+
+    /// If the given item is a Share, get the item that it shares. (If we have it.)
+    fn shared_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemDisplayRow>, Error>;
+
+    /// Get aggregated Reaction counts for an item, sorted by count descending.
+    fn reaction_counts(&self, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error>;
+
+    /// Find the most recent Posts tagged with #`tag`. (Which should be lowercase.)
+    fn tag_items<'a>(
+        &self,
+        tag: &str,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error>;
+
+    /// Find the most recent Posts and Comments that @mention `user`.
+    fn mention_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error>;
+
+    /// Find the most recent Posts and Comments whose text contains all of the
+    /// words in `query`. See: search_terms()
+    fn search_items<'a>(
+        &self,
+        query: &str,
+        scope: SearchScope,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error>;
+
+    /// Get the names to display for users @mentioned by `author`.
+    ///
+    /// Prefers the name `author` gave the user when following them, then the
+    /// user's own Profile display name. Users without either are omitted.
+    fn display_names(&self, author: &UserID, users: &[UserID]) -> Result<HashMap<UserID, String>, Error>;
+
+    /// Find the most recent items from users followed by the given user ID. Includes the users's own items too.
+    /// Excludes DirectMessages.
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error>;
+
+    /// Find one particular UserItem
+    /// Quarantined items aren't returned.
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error>;
+
+    /// Effieicntly check whether a user item exists:
+    /// (Quarantined items don't count, so that a valid copy can be uploaded.)
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Check whether a user item has been deleted by a (signed) Delete item.
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Save an uploaded item to the data store.
+    ///
+    /// If the item is a Delete, the item it references (and its indexes) must be
+    /// removed, but the Delete itself is kept so that it can be synced to other servers.
+    /// Deleting a Post also deletes its Revisions.
+    ///
+    /// Profiles and Deletes can not be deleted. If one arrives after a Delete that
+    /// targets it, that Delete is ignored, and the item is no longer user_item_deleted().
+    ///
+    /// Saving a quarantined item replaces its stored row, and lifts its quarantine.
+    fn save_user_item(&mut self, item_row: &ItemRow, item: &Item) -> Result<(), Error>;
+
+    /// Get a "server user" -- a user granted direct access to post to the
+    /// server.
+    fn server_user(&self, user: &UserID) -> Result<Option<ServerUser>, Error>;
+
+    /// List users granted direct access to post to the server.
+    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error>;
+
+    /// Add a new "server user" who is explicitly allowed to post to this server.
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error>;
+
+    /// Get the Item(Row) that represents the user's most recently saved profile, if it exists.
+    fn user_profile(&self, user_id: &UserID) -> Result<Option<ItemRow>, Error>;
+
+    /// Is this user ID known to this server?
+    ///
+    /// This is true if any of these are true:
+    /// * The user is a "server user" (given direct permission to post to this server)
+    /// * The user is followed by a "server user". (We want their content so we can create a feed.)
+    /// * The user is the successor of a user followed by a "server user".
+    ///   (Only one hop: The successor's own successor must be followed directly.)
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error>;
+
+    /// Has a known user shared this item?
+    ///
+    /// We store and serve shared items even if we don't know their authors.
+    /// But that's limited to the shared item itself. It doesn't make its author known.
+    fn item_shared(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Returns true if we've saved a Profile which revokes this user ID.
+    fn user_revoked(&self, user: &UserID) -> Result<bool, Error>;
+
+    /// Check whether a user has remaiing quota/permissions to upload a particular item.
+    ///
+    /// Profiles and Deletes are never denied for exceeding a quota, so that
+    /// users can always revoke their ID, or free up space.
+    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error>;
+
+    /// Get the quotas for a server user, if they are one.
+    fn server_user_quotas(&self, user: &UserID) -> Result<Option<ServerUserQuotas>, Error>;
+
+    /// Set the quotas for an existing server user.
+    fn set_server_user_quotas(&self, user: &UserID, quotas: &ServerUserQuotas) -> Result<(), Error>;
+
+    /// Get a Stream of the bytes of the file attachment.
+    /// Quarantined contents aren't returned.
+    // TODO: Take refs.
+    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str) -> Result<Option<FileStream>, Error>;
+
+    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<FileMeta>, Error>;
+
+    /// Save a file attachment to our content store.
+    /// This assumes you have already validated the content's size and hash match those returned by get_attachment_meta().
+    /// Saving quarantined contents replaces them, and lifts their quarantine.
+    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error>;
+
+    /// Report on database size usage by user.
+    /// Results sorted by total size desc. 
+    fn usage_by_user(&self, callback: RowCallback<'_, UsageByUserRow>) -> Result<(), Error>;
+
+    /// Remove unused data from the database.
+    fn prune(&self, opts: PruneOpts) -> Result<PruneResult, Error>;
+
+    /// Move attachment contents out of the database, into the configured [`attachments::AttachmentStore`].
+    fn migrate_attachments(&self) -> Result<MigrateResult, Error>;
+
+    /// Every item in the database, sorted by user ID and signature.
+    /// Unlike the other item lists, this includes blocked items and those from unknown users.
+    fn all_items<'a>(&self, callback: RowCallback<'a, ItemRow>) -> Result<(), Error>;
+
+    /// Every item from one user, oldest first.
+    /// Unlike user_items(), this includes DirectMessages, blocked items, and items from unknown users.
+    fn all_user_items<'a>(&self, user: &UserID, callback: RowCallback<'a, ItemRow>) -> Result<(), Error>;
+
+    /// Re-hash the contents of every stored attachment.
+    /// Attachments whose contents are missing, or don't match their hash or size, are sent
+    /// to `callback`. (As are rows with a malformed hash.)
+    /// Returns how many attachments were checked.
+    fn verify_attachments<'a>(&self, callback: RowCallback<'a, BadAttachment>) -> Result<u64, Error>;
+
+    /// Re-check the signature and contents of every stored item.
+    /// Items that fail verification (including rows with a malformed user ID or
+    /// signature) are sent to `callback`.
+    /// Returns how many items were checked.
+    fn verify_items<'a>(&self, callback: RowCallback<'a, BadItem>) -> Result<u64, Error>;
+
+    /// Quarantine corrupt content, so that it's no longer served.
+    /// Quarantining something that's already quarantined updates its problem.
+    ///
+    /// Unlike blocked content, quarantined content isn't removed by `db prune`,
+    /// and uploading a valid copy replaces it (and lifts its quarantine).
+    fn add_quarantine(&self, target: &Quarantine, problem: &str) -> Result<(), Error>;
+
+    /// Block content from being uploaded to, or served from, this server.
+    /// Blocking something that's already blocked updates its notes.
+    ///
+    /// Note: Existing content isn't removed until the next `db prune`.
+    fn add_block(&self, block: &Block, notes: &str) -> Result<(), Error>;
+
+    /// Remove a block. Returns false if it didn't exist.
+    fn remove_block(&self, block: &Block) -> Result<bool, Error>;
+
+    /// List all blocks, oldest first.
+    fn blocks<'a>(&self, callback: RowCallback<'a, BlockRow>) -> Result<(), Error>;
+
+    /// Is this content blocked?
+    /// A [`Block::Item`] is also blocked if its user is.
+    fn is_blocked(&self, block: &Block) -> Result<bool, Error>;
+}
+
+pub struct FileStream {
+    /// file size in bytes
+    pub size: u64,
+
+    /// The mime type given in the file's metadata, if any.
+    pub mime_type: Option<String>,
+
+    /// Stream of Bytes from the file:
+    pub stream: Box<dyn Stream<Item=Result<Bytes, crate::server::SendError>> + Unpin + Send + 'static>,
+}
+
+/// Metadata about a file attachment.
+pub struct FileMeta {
+    /// The hash of the file's contents.
+    pub hash: SHA512,
+    
+    /// Whether the file already exists in our content store.
+    /// (False if its contents are quarantined, so that they can be uploaded again.)
+    pub exists: bool,
+
+    /// Size of the file in bytes, according to its metadata.
+    pub size: u64,
+
+    /// True iff uploading this attachment would cause the user to exceed their quota.
+    pub quota_exceeded: bool,
+}
+
+/// A callback function used for callback iteration through large database resultsets.
+/// Each row T will be sent to the callback. The callback should return Ok(true) to continue iteration.
+pub type RowCallback<'a, T> = &'a mut dyn FnMut(T) -> Result<bool, Error>; 
+
+/// Rows returned by Backend methods that list items.
+pub trait ListRow {
+    fn item_row(&self) -> &ItemRow;
+
+    /// The position of this row in its list.
+    fn cursor(&self) -> Cursor {
+        let row = self.item_row();
+        Cursor::at(row.timestamp, &row.user, &row.signature)
+    }
+}
+
+impl ListRow for ItemRow {
+    fn item_row(&self) -> &ItemRow { self }
+}
+
+impl ListRow for ItemDisplayRow {
+    fn item_row(&self) -> &ItemRow { &self.item }
+}
+
+/// How many rows a RowIter fetches at a time.
+const ROW_ITER_CHUNK_SIZE: usize = 100;
+
+/// Iterates through rows from a Backend list method.
+///
+/// Rows are fetched a chunk at a time, each with its own connection from the
+/// Factory. Each chunk continues from a Cursor at the last row of the previous
+/// one, so we don't hold a query (or a connection) open while the caller
+/// handles each row. The cost is that each chunk re-runs the whole list query,
+/// just starting from the cursor.
+///
+/// Fetching blocks, so iterate from the blocking thread pool, or use
+/// [`RowIter::into_stream()`].
+pub struct RowIter<T> {
+    factory: Box<dyn Factory>,
+
+    fetch: Box<dyn FnMut(&dyn Backend, TimeSpan, RowCallback<'_, T>) -> Result<(), Error> + Send>,
+
+    /// The part of the span we haven't fetched yet. None once we've fetched everything.
+    remaining: Option<TimeSpan>,
+
+    chunk: VecDeque<T>,
+}
+
+/// A Stream of rows from a Backend list method. See: [`RowIter::into_stream()`]
+pub type RowStream<T> = Box<dyn Stream<Item=Result<T, Error>> + Send + Unpin>;
+
+impl<T: ListRow> RowIter<T> {
+    /// `fetch` should send the rows within a TimeSpan to its callback, in order.
+    pub fn new(
+        factory: Box<dyn Factory>,
+        time_span: TimeSpan,
+        fetch: impl FnMut(&dyn Backend, TimeSpan, RowCallback<'_, T>) -> Result<(), Error> + Send + 'static,
+    ) -> Self {
+        Self {
+            factory,
+            fetch: Box::new(fetch),
+            remaining: Some(time_span),
+            chunk: VecDeque::new(),
+        }
+    }
+
+    fn fetch_chunk(&mut self) -> Result<(), Error> {
+        let span = match self.remaining.take() {
+            None => return Ok(()),
+            Some(span) => span,
+        };
+
+        let backend = self.factory.open()?;
+        let mut rows = Vec::with_capacity(ROW_ITER_CHUNK_SIZE);
+        (self.fetch)(backend.as_ref(), span.clone(), &mut |row: T| {
+            // `fetch` may not be able to filter exactly by the span:
+            let item = row.item_row();
+            if !span.contains(item.timestamp, &item.user, &item.signature) {
+                return Ok(true);
+            }
+            rows.push(row);
+            Ok(rows.len() < ROW_ITER_CHUNK_SIZE)
+        })?;
+
+        if rows.len() == ROW_ITER_CHUNK_SIZE {
+            if let Some(last) = rows.last() {
+                self.remaining = Some(span.continued(last.cursor()));
+            }
+        }
+        self.chunk = rows.into();
+
+        Ok(())
+    }
+}
+
+impl<T: ListRow + Send + 'static> RowIter<T> {
+    /// Fetches rows on the blocking thread pool, like [`Backend::get_contents()`] does.
+    pub fn into_stream(self) -> RowStream<T> {
+        Box::new(blocking::Unblock::with_capacity(ROW_ITER_CHUNK_SIZE, self))
+    }
+}
+
+impl<T: ListRow> Iterator for RowIter<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.chunk.is_empty() {
+            if let Err(err) = self.fetch_chunk() {
+                return Some(Err(err));
+            }
+        }
+        self.chunk.pop_front().map(Ok)
+    }
+}
+
+/// A UserID is a nacl public key. (32 bytes)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserID {
+    pub_key: sign::PublicKey,
+}
+
+// Expect a 32-byte nacl public key:
+const USER_ID_BYTES: usize = 32;
+
+/// Prefix for the bytes signed by a Successor. See: [`UserID::successor_bytes`]
+const SUCCESSOR_PREFIX: &[u8] = b"feoblog-successor";
+
+impl UserID {
+    pub fn to_base58(&self) -> String {
+        bs58::encode(self.bytes()).into_string()
+    }
+
+    pub fn from_base58(value: &str) -> Result<Self, Error> {
+        let bytes = bs58::decode(value).into_vec()?;
+        Self::from_vec(bytes)
+    }
+
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
+        if bytes.len() != USER_ID_BYTES {
+            bail!("Expected {} bytes but found {}", USER_ID_BYTES, bytes.len());
+        }
+
+        let pub_key = sign::PublicKey::from_slice(&bytes).ok_or_else(
+            || format_err!("Error creating nacl::PuublicKey")
+        )?;
+
+        Ok( UserID{ pub_key } )
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.pub_key.as_ref()
+    }
+
+    /// Convert this (ed25519 signing) key to the (curve25519) public key used to
+    /// encrypt DirectMessages to this user.
+    pub fn to_box_public_key(&self) -> Result<box_::PublicKey, Error> {
+        sign::ed25519::to_curve25519_pk(&self.pub_key).map_err(
+            |_| format_err!("Error converting UserID to a box_::PublicKey")
+        )
+    }
+
+    /// The bytes that `successor` signs to agree to succeed this user.
+    ///
+    /// They include both IDs (and are prefixed, so they can't be confused with
+    /// any other signed bytes) so that the signature can't be replayed to name
+    /// this successor from some other user's Profile.
+    pub fn successor_bytes(&self, successor: &UserID) -> Vec<u8> {
+        let mut bytes = SUCCESSOR_PREFIX.to_vec();
+        bytes.extend_from_slice(self.bytes());
+        bytes.extend_from_slice(successor.bytes());
+        bytes
+    }
+}
+
+/// Allows easy destructuring from URLs.
+impl FromStr for UserID {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> { 
+        UserID::from_base58(value)
+    }
+}
+
+impl Display for UserID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_base58())
+    }
+}
+
+/// Bytes representing a detached NaCl signature. (64 bytes)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    signature: sign::Signature,
+}
+
+const SIGNATURE_BYTES: usize = 64;
+
+impl Signature {
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
+        if bytes.len() != SIGNATURE_BYTES {
+            bail!("Signature expected {} bytes but found {}", SIGNATURE_BYTES, bytes.len());
+        }
+
+        let signature = sign::Signature::from_slice(&bytes).ok_or_else(
+            || format_err!("Failure creating nacl::Signature")
+        )?;
+        
+        Ok( Signature{ signature } )
+    }
+
+    pub fn from_base58(value: &str) -> Result<Self, Error> {
+        let bytes = bs58::decode(value).into_vec()?;
+        Self::from_vec(bytes)
+    }
+
+    pub fn to_base58(&self) -> String {
+        bs58::encode(self.bytes()).into_string()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.signature.as_ref()
+    }
+
+    /// True if this signature is valid for the given user on the given bytes.
+    pub fn is_valid(&self, user: &UserID, bytes: &[u8]) -> bool {
+        let pubkey = sign::PublicKey::from_slice(user.bytes()).expect("pubkey");
+        sign::verify_detached(&self.signature, bytes, &pubkey)
+    }
+
+}
+
+/// Allows easy destructuring from URLs. (in Warp)
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> { 
+        Signature::from_base58(value)
+    }
+}
+
+impl <'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'de> 
+    {
+        deserializer.deserialize_str(FromStrVisitor::<Self>::new())
+    }
+}
+
+impl <'de> Deserialize<'de> for UserID {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'de> 
+    {
+        deserializer.deserialize_str(FromStrVisitor::<Self>::new())
+    }
+}
+
+struct FromStrVisitor<T: FromStr> {
+    _t: PhantomData<T>
+}
+
+impl <T: FromStr> FromStrVisitor<T> {
+    fn new() -> Self {
+        FromStrVisitor { _t: PhantomData }
+    }
+}
+
+impl <'de, T: FromStr<Err=Error>> Visitor<'de> for FromStrVisitor<T> 
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "a &str that can be converted to a {}",
+            std::any::type_name::<T>()
+        )
+    }
+
+    fn visit_str<E>(self, v: &str)
+    -> Result<Self::Value, E>
+    where E: de::Error
+    {
+        T::from_str(v).map_err(|e| de::Error::custom(format!("{}", e)))
+    }
+}
+
+/// Data that should be stored along with an Item
+/// 
+/// The signature should be validated on the front-end before being
+/// sent to the back-end. (This avoids each back-end having to re-implement
+/// validation logic). Likewise, the front-end may want to validate data returned
+/// by the backend to ensure it hasn't been modified or bit-rot.
+#[derive(Clone)]
+pub struct ItemRow {
+    pub user: UserID,
+    pub signature: Signature,
+
+    // The (signed) timestamp from within item_bytes.
+    pub timestamp: Timestamp,
+    
+    /// The time that this item was received by the server.
+    pub received: Timestamp,
+
+    /// Bytes which can be deserialized into an Item.
+    pub item_bytes: Vec<u8>,
+}
+
+impl ItemRow {
+    /// Check that this row hasn't been modified since its signature was validated.
+    /// Returns the parsed Item.
+    pub fn verify(&self) -> Result<Item, Error> {
+        if !self.signature.is_valid(&self.user, &self.item_bytes) {
+            bail!("Invalid signature");
+        }
+
+        let mut item = Item::new();
+        item.merge_from_bytes(&self.item_bytes)?;
+        item.validate()?;
+
+        if item.timestamp_ms_utc != self.timestamp.unix_utc_ms {
+            bail!("Stored timestamp {} doesn't match the Item's {}", self.timestamp.unix_utc_ms, item.timestamp_ms_utc);
+        }
+
+        Ok(item)
+    }
+}
+
+/// An [`ItemRow`] that has extra information (fetched via joins)
+pub struct ItemDisplayRow {
+    pub item: ItemRow,
+
+    // TODO: Make an Arc<String> to avoid heap allocs?
+    // Or just make filling this in optional, since that's only used by the old HTML UI.
+    /// The display name for the author of the item, if available.
+    pub display_name: Option<String>,
+
+    /// Aggregated Reactions to this item.
+    /// Left empty by the list methods, so that we don't count Reactions for
+    /// rows that get filtered out. See: [`Backend::reaction_counts`]
+    pub reactions: Vec<ReactionCount>,
+
+    /// If this item is a Share, the item it shares. (If we have it.)
+    pub shared: Option<Box<ItemDisplayRow>>,
+}
+
+/// An item in a comment thread. See: [`Backend::thread_items`]
+pub struct ThreadRow {
+    pub item: ItemDisplayRow,
+
+    /// Direct replies have a depth of 1, replies to those 2, etc.
+    pub depth: u32,
+}
+
+/// The number of (distinct) users who reacted to an item with a particular reaction.
+#[derive(Debug, Clone)]
+pub struct ReactionCount {
+    pub reaction: String,
+    pub count: u64,
+}
+
+/// Info about users explicitly allowed on this server.
+/// i.e.: A row in the server_user table.
+#[derive(Debug, Clone)]
+pub struct ServerUser {
+    pub user: UserID,
+    pub notes: String,
+    pub on_homepage: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Timestamp {
+    /// UNIX time, at UTC, in milliseconds:
+    pub unix_utc_ms: i64
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        use time::OffsetDateTime;
+        let delta = OffsetDateTime::now_utc() - OffsetDateTime::unix_epoch();
+        Timestamp {
+            unix_utc_ms: delta.whole_milliseconds() as i64,
+        }
+    }
+
+    pub fn format_with_offset(self, minutes: i16) -> String {
+        use time::{Duration, UtcOffset, OffsetDateTime};
+        use std::ops::Add;
+
+        let ms = Duration::milliseconds(self.unix_utc_ms);
+        let datetime = OffsetDateTime::unix_epoch().add(ms);
+        let offset = UtcOffset::minutes(minutes);
+        let datetime = datetime.to_offset(offset);
+
+        datetime.format("%Y-%m-%d %H:%M:%S %z")
+    }
+}
+/// A reason why a user can't post an Item or file attachment.
+pub enum QuotaDenyReason {
+    /// The user's Items already use enough space that this one would exceed their quota.
+    ItemQuotaExceeded {
+        /// The maximum bytes of Items this user can store on the server.
+        max_bytes: u64,
+    },
+
+    /// This user is not known to the server, so not allowed to post.
+    UnknownUser,
+
+    /// We already have a profile that proves that this userID has been revoked.
+    ProfileRevoked,
+}
+
+impl std::fmt::Display for QuotaDenyReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ItemQuotaExceeded { max_bytes } => 
+                write!(f, "Items would exceed {} quota.", SizeDisplay::bytes(*max_bytes)),
+            Self::UnknownUser => 
+                write!(f, "This user is not known to the server."),
+            Self::ProfileRevoked => 
+                write!(f, "This user ID has been revoked."),
+        }
+    }
+}
+
+/// Limits on how many bytes a user may store on this server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Max total size of the user's Items. None = unlimited.
+    pub item_bytes: Option<u64>,
+
+    /// Max total size of the (distinct) file attachments in the user's Items. None = unlimited.
+    pub attachment_bytes: Option<u64>,
+}
+
+impl Quota {
+    /// Combine the quotas that several server users grant to a user they all follow.
+    /// The most generous one wins.
+    pub fn most_generous(self, other: Quota) -> Quota {
+        fn max(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            Some(std::cmp::max(a?, b?))
+        }
+        Quota {
+            item_bytes: max(self.item_bytes, other.item_bytes),
+            attachment_bytes: max(self.attachment_bytes, other.attachment_bytes),
+        }
+    }
+}
+
+/// Would storing `bytes` more, on top of the `used` bytes, exceed `max_bytes`?
+pub fn quota_exceeded(max_bytes: Option<u64>, used: u64, bytes: u64) -> bool {
+    match max_bytes {
+        None => false,
+        Some(max_bytes) => used + bytes > max_bytes,
+    }
+}
+
+/// The quotas that a server admin has set for a server user.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServerUserQuotas {
+    /// Applies to the server user's own items.
+    pub own: Quota,
+
+    /// Applies to each user followed by the server user.
+    pub follows: Quota,
+}
+
+/// Content that a server admin has blocked. (ex: as required by law.)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    /// All items (and their attachments) from a user.
+    User(UserID),
+
+    /// A single item, and its attachments.
+    Item(UserID, Signature),
+
+    /// Attachment contents, in any item.
+    Attachment(SHA512),
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Block::User(user) => write!(f, "user {}", user),
+            Block::Item(user, signature) => write!(f, "item {}/{}", user, signature.to_base58()),
+            Block::Attachment(hash) => write!(f, "attachment {}", hash),
+        }
+    }
+}
+
+/// Corrupt content found by `db verify`. See: [`Backend::add_quarantine`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Quarantine {
+    Item(UserID, Signature),
+
+    /// Attachment contents, in any item.
+    Attachment(SHA512),
+}
+
+impl Display for Quarantine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quarantine::Item(user, signature) => write!(f, "item {}/{}", user, signature.to_base58()),
+            Quarantine::Attachment(hash) => write!(f, "attachment {}", hash),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockRow {
+    pub block: Block,
+
+    /// Why the server admin blocked this content.
+    pub notes: String,
+
+    /// When the block was added.
+    pub created: Timestamp,
+}
+
+/// A 64-byte SHA-512 hash.
+/// Used by nacl internally, but also used by us for hashing file attachments.
+#[derive(PartialEq, Eq)]
+pub struct SHA512 {
+    hash: sodiumoxide::crypto::hash::sha512::Digest,
+}
+
+impl SHA512 {
+    pub fn from_hash_bytes(slice: &[u8]) -> Result<Self,  Error> {
+        use sodiumoxide::crypto::hash::sha512::Digest;
+        let digest = Digest::from_slice(slice);
+        if let Some(digest) = digest {
+            return Ok(Self { hash: digest});
+        }
+
+        bail!("SHA512::from_hash_bytes(): wrong number of bytes: {}", slice.len());
+    }
+
+    pub fn from_digest(digest: sha512::Digest) -> Self { 
+        Self { hash: digest }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        return &self.hash.0
+    }
+
+    pub fn from_file<F>(file: &mut F) -> Result<Self, std::io::Error> 
+    where F: Read + Seek
+    {
+        Ok(Self::from_file_with_size(file)?.0)
+    }
+
+    /// Like from_file(), but also returns how many bytes were read.
+    pub fn from_file_with_size<F>(file: &mut F) -> Result<(Self, u64), std::io::Error>
+    where F: Read + Seek
+    {
+        file.seek(SeekFrom::Start(0))?;
+        let mut buf = [0u8; 8 * 1024];
+        let mut hasher = sha512::State::new();
+        let mut bytes = 0;
+        loop {
+            let count = file.read(&mut buf)?;
+            if count == 0 { break; }
+            bytes += count;
+            hasher.update(&buf[..count]);
+        }
+
+        Ok((Self { hash: hasher.finalize() }, bytes as u64))
+    }
+}
+
+impl Clone for SHA512 {
+    fn clone(&self) -> Self {
+        Self::from_digest(sha512::Digest(self.hash.0))
+    }
+}
+
+impl Display for SHA512 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SHA512:")?;
+        for b in self.bytes() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SHA512 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Parses hex, as output by Display. (The "SHA512:" prefix is optional.)
+impl FromStr for SHA512 {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.strip_prefix("SHA512:").unwrap_or(value);
+        if !hex.is_ascii() || hex.len() % 2 != 0 {
+            bail!("Invalid SHA512 hex: {}", value);
+        }
+        let bytes = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i+2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .with_context(|| format!("Invalid SHA512 hex: {}", value))?;
+        Self::from_hash_bytes(&bytes)
+    }
+}
+
+/// A position in a list of items.
+///
+/// Items are listed in (timestamp, user ID, signature) order, so that items
+/// which share a timestamp still have a stable order across pages.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub timestamp: Timestamp,
+
+    /// The item at this position. If None, the cursor falls between
+    /// timestamps, and excludes every item at `timestamp`.
+    pub item: Option<(UserID, Signature)>,
+}
+
+impl Cursor {
+    /// A cursor positioned at an item.
+    pub fn at(timestamp: Timestamp, user: &UserID, signature: &Signature) -> Self {
+        Self {
+            timestamp,
+            item: Some((user.clone(), signature.clone())),
+        }
+    }
+
+    /// Where an item falls in relation to this cursor.
+    pub fn cmp_item(&self, timestamp: Timestamp, user: &UserID, signature: &Signature) -> Ordering {
+        let by_time = timestamp.unix_utc_ms.cmp(&self.timestamp.unix_utc_ms);
+        if by_time != Ordering::Equal {
+            return by_time;
+        }
+        match &self.item {
+            None => Ordering::Equal,
+            Some((cursor_user, cursor_signature)) => {
+                (user.bytes(), signature.bytes()).cmp(&(cursor_user.bytes(), cursor_signature.bytes()))
+            }
+        }
+    }
+}
+
+impl From<Timestamp> for Cursor {
+    fn from(timestamp: Timestamp) -> Self {
+        Self { timestamp, item: None }
+    }
+}
+
+/// A range of items we're requesting data for.
+#[derive(Debug, Clone)]
+pub enum TimeSpan {
+    /// Requests items before some Cursor, in reverse chronological order.
+    Before(Cursor),
+
+    /// Requests items after some Cursor, in (forward) chronological order.
+    After(Cursor),
+
+    /// Requests items after the first Cursor and before the second, in
+    /// reverse chronological order.
+    Between(Cursor, Cursor),
+}
+
+impl TimeSpan {
+    /// Are items listed in reverse chronological order?
+    pub fn is_before(&self) -> bool {
+        match self {
+            Self::Before(_) | Self::Between(_, _) => true,
+            Self::After(_) => false,
+        }
+    }
+
+    /// The (exclusive) lower bound of this span, if any.
+    pub fn start(&self) -> Option<&Cursor> {
+        match self {
+            Self::After(start) | Self::Between(start, _) => Some(start),
+            Self::Before(_) => None,
+        }
+    }
+
+    /// The (exclusive) upper bound of this span, if any.
+    pub fn end(&self) -> Option<&Cursor> {
+        match self {
+            Self::Before(end) | Self::Between(_, end) => Some(end),
+            Self::After(_) => None,
+        }
+    }
+
+    /// The rest of this span, after `cursor` in listing order.
+    pub fn continued(&self, cursor: Cursor) -> Self {
+        match self {
+            Self::Before(_) => Self::Before(cursor),
+            Self::After(_) => Self::After(cursor),
+            Self::Between(start, _) => Self::Between(start.clone(), cursor),
+        }
+    }
+
+    /// Does an item fall within this span?
+    pub fn contains(&self, timestamp: Timestamp, user: &UserID, signature: &Signature) -> bool {
+        let after_start = self.start().map_or(true, |start| start.cmp_item(timestamp, user, signature) == Ordering::Greater);
+        let before_end = self.end().map_or(true, |end| end.cmp_item(timestamp, user, signature) == Ordering::Less);
+        after_start && before_end
+    }
+}
+
+/// Which items a search should look through.
+#[derive(Debug, Clone)]
+pub enum SearchScope {
+    /// Everything visible on the homepage, or in users' profiles.
+    All,
+
+    /// Only items posted by this user.
+    User(UserID),
+
+    /// Only items in this user's feed. (Including their own.)
+    Feed(UserID),
+}
+
+/// Split a search query into the (lowercase) words that an item must contain.
+///
+/// Punctuation is ignored, so that users can't inject search syntax
+/// specific to any one backend.
+pub fn search_terms(query: &str) -> Vec<String> {
+    query.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+pub struct UsageByUserOpts {
+
+}
+
+pub struct PruneOpts {
+    /// If set, then we don't actually do the delete and just report on what *would* be deleted.
+    pub dry_run: bool,
+
+    /// Should we delete unreferenced attachments?
+    pub attachments: bool,
+
+    /// Delete blocked users, items and attachments?
+    pub blocked: bool,
+
+    /// Delete items from users who are no longer followed?
+    pub items: bool,
+}
+
+
+/// Report how many things would be deleted, and their size.
+pub struct PruneResult {
+    /// Was this a dry run?
+    pub dry_run: bool,
+
+    pub attachments_count: u64,
+    pub attachments_bytes: u64,
+
+    pub items_count: u64,
+    pub items_bytes: u64,
+
+    pub blocked_items_count: u64,
+    pub blocked_items_bytes: u64,
+
+    pub blocked_attachments_count: u64,
+    pub blocked_attachments_bytes: u64,
+}
+
+impl Display for PruneResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use tablestream::{Stream, col, Column};
+
+        let title = if self.dry_run { "Dry run:" } else { "Pruned:" };
+
+        let mut out = vec![];
+        let mut stream = Stream::new(&mut out, vec![
+            col!(Row: .name).right(),
+            col!(Row: .count).header("Count").right(),
+            col!(Row: .size).header("Size").right(),
+        ]).title(&title).borders(true);
+
+        struct Row {
+            name: &'static str,
+            count: u64,
+            size: SizeDisplay,
+        }
+
+        stream.row(Row{
+            name: "Attachments",
+            count: self.attachments_count,
+            size: SizeDisplay::bytes(self.attachments_bytes),
+        }).map_err(|e| std::fmt::Error)?;
+
+        stream.row(Row{
+            name: "Items",
+            count: self.items_count,
+            size: SizeDisplay::bytes(self.items_count)
+        }).map_err(|e| std::fmt::Error)?;
+
+        stream.row(Row{
+            name: "Blocked Items",
+            count: self.blocked_items_count,
+            size: SizeDisplay::bytes(self.blocked_items_bytes),
+        }).map_err(|e| std::fmt::Error)?;
+
+        stream.row(Row{
+            name: "Blocked Attachments",
+            count: self.blocked_attachments_count,
+            size: SizeDisplay::bytes(self.blocked_attachments_bytes),
+        }).map_err(|e| std::fmt::Error)?;
+
+        let total = self.items_bytes + self.attachments_bytes + self.blocked_items_bytes + self.blocked_attachments_bytes;
+        let footer = format!("Total size: {}", SizeDisplay::bytes(total));
+        stream.footer(&footer).map_err(|e| std::fmt::Error)?;
+
+        write!(f, "{}", String::from_utf8_lossy(&out))
+    }
+}
+
+/// Report how many attachments were moved out of the database.
+pub struct MigrateResult {
+    pub attachments_count: u64,
+    pub attachments_bytes: u64,
+}
+
+impl Display for MigrateResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Moved {} attachments ({}) to the attachment store.",
+            self.attachments_count,
+            SizeDisplay::bytes(self.attachments_bytes),
+        )
+    }
+}
+
+/// An attachment whose stored contents failed verification. See: [`Backend::verify_attachments`]
+pub struct BadAttachment {
+    /// The stored hash, which may itself be malformed.
+    pub hash: Vec<u8>,
+
+    /// What's wrong with the contents.
+    pub problem: String,
+}
+
+impl BadAttachment {
+    /// Compare re-hashed contents (and how many bytes were read) to what we expected.
+    /// `found` is None if the contents are missing. Returns what's wrong, if anything.
+    pub(crate) fn check(hash: &SHA512, size: u64, found: Option<(SHA512, u64)>) -> Option<BadAttachment> {
+        let problem = match found {
+            None => "Contents are missing from the attachment store".to_string(),
+            Some((found, _)) if &found != hash => format!("Contents have hash {}", found),
+            Some((_, found_size)) if found_size != size => format!("Expected {} bytes but found {}", size, found_size),
+            Some(_) => return None,
+        };
+        Some(BadAttachment{ hash: hash.bytes().to_vec(), problem })
+    }
+
+    /// The contents to quarantine. None if the stored hash is malformed,
+    /// since no item can refer to those contents anyway.
+    pub fn quarantine(&self) -> Option<Quarantine> {
+        let hash = SHA512::from_hash_bytes(&self.hash).ok()?;
+        Some(Quarantine::Attachment(hash))
+    }
+}
+
+impl Display for BadAttachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.quarantine() {
+            Some(target) => write!(f, "{}", target),
+            None => write!(f, "attachment with malformed hash {}", self.hash.as_slice().as_hex()),
+        }
+    }
+}
+
+/// An item whose stored row failed verification. See: [`Backend::verify_items`]
+pub struct BadItem {
+    /// The row's user_id and signature columns, which may themselves be malformed.
+    pub user_id: Vec<u8>,
+    pub signature: Vec<u8>,
+
+    /// What's wrong with the row.
+    pub problem: String,
+}
+
+impl BadItem {
+    /// Check an item row's columns, as read from the database.
+    /// Returns None if they're OK.
+    pub(crate) fn check(
+        user_id: Vec<u8>,
+        signature: Vec<u8>,
+        unix_utc_ms: i64,
+        received_utc_ms: i64,
+        item_bytes: Vec<u8>,
+    ) -> Option<BadItem> {
+        let bad = |problem: String| Some(BadItem{
+            user_id: user_id.clone(),
+            signature: signature.clone(),
+            problem,
+        });
+
+        let user = match UserID::from_vec(user_id.clone()) {
+            Ok(user) => user,
+            Err(err) => return bad(format!("Malformed user ID: {}", err)),
+        };
+        let sig = match Signature::from_vec(signature.clone()) {
+            Ok(sig) => sig,
+            Err(err) => return bad(format!("Malformed signature: {}", err)),
+        };
+
+        let row = ItemRow{
+            user,
+            signature: sig,
+            timestamp: Timestamp{ unix_utc_ms },
+            received: Timestamp{ unix_utc_ms: received_utc_ms },
+            item_bytes,
+        };
+        match row.verify() {
+            Ok(_) => None,
+            Err(err) => bad(err.to_string()),
+        }
+    }
+
+    /// The item to quarantine. None if the row's user ID or signature is malformed,
+    /// since such rows can't be requested (or replaced) anyway.
+    pub fn quarantine(&self) -> Option<Quarantine> {
+        let user = UserID::from_vec(self.user_id.clone()).ok()?;
+        let signature = Signature::from_vec(self.signature.clone()).ok()?;
+        Some(Quarantine::Item(user, signature))
+    }
+}
+
+impl Display for BadItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.quarantine() {
+            Some(target) => write!(f, "{}", target),
+            None => write!(
+                f,
+                "item with malformed ID {}/{}",
+                self.user_id.as_slice().as_hex(),
+                self.signature.as_slice().as_hex(),
+            ),
+        }
+    }
+}
+
+/// Information about a single user's database usage.
+pub struct UsageByUserRow {
+    pub user_id: UserID,
+    pub display_name: Option<String>,
+
+    pub server_user: bool,
+    pub known_user: bool,
+
+    pub attachments_count: u64,
+    pub attachments_bytes: u64,
+
+    pub items_count: u64,
+    pub items_bytes: u64,
+
+    pub total_bytes: u64,
+}
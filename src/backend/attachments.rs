@@ -0,0 +1,52 @@
+//! Stores for attachment contents, outside of the database.
+//!
+//! By default, backends store attachment contents in their own database.
+//! When an AttachmentStore is configured, new attachments are saved there
+//! instead, and the database only keeps track of which contents we have.
+
+mod fs;
+mod s3;
+
+use std::io::Read;
+
+use anyhow::{Error, format_err};
+use sodiumoxide::crypto::hash::sha512;
+
+use super::{FileStream, SHA512};
+
+pub(crate) use fs::FsStore;
+pub(crate) use self::s3::S3Store;
+
+/// A content-addressed store for attachment contents, keyed by their SHA-512 hash.
+pub trait AttachmentStore: Send + Sync {
+    /// Get a stream of the stored contents, if we have them.
+    /// The returned FileStream will not have a mime_type set.
+    fn get(&self, hash: &SHA512) -> Result<Option<FileStream>, Error>;
+
+    /// Save contents. Implementations must verify `size` and `hash` before
+    /// making the contents available through get().
+    /// Saving contents that already exist is not an error.
+    fn save(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error>;
+
+    /// Remove contents. Removing contents that don't exist is not an error.
+    fn remove(&self, hash: &SHA512) -> Result<(), Error>;
+
+    /// Re-hash the stored contents, to find bit-rot or other modifications.
+    /// Returns the hash and how many bytes were read, or None if we don't have the contents.
+    fn rehash(&self, hash: &SHA512) -> Result<Option<(SHA512, u64)>, Error> {
+        let file = match self.get(hash)? {
+            None => return Ok(None),
+            Some(file) => file,
+        };
+
+        let mut hasher = sha512::State::new();
+        let mut size: u64 = 0;
+        for chunk in futures::executor::block_on_stream(file.stream) {
+            let chunk = chunk.map_err(|e| format_err!("Error reading {}: {}", hash, e))?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+        }
+
+        Ok(Some((SHA512::from_digest(hasher.finalize()), size)))
+    }
+}
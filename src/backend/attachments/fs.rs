@@ -0,0 +1,152 @@
+//! Stores attachment contents as files in a local directory.
+
+use std::{fs::File, io::{ErrorKind, Read}, path::PathBuf};
+
+use actix_web::web::Bytes;
+use anyhow::{Context, Error, bail};
+use log::debug;
+
+use crate::{backend::{FileStream, SHA512}, util::AsHex};
+
+use super::AttachmentStore;
+
+/// Stores each file at `<root>/<aa>/<bb>/<hash>`, where `aa` and `bb` are the
+/// first two bytes of its (hex) SHA-512 hash. This keeps the number of
+/// files in each directory manageable.
+///
+/// Files are first written into `<root>/tmp/`, and only moved into place
+/// once their hash has been verified.
+pub(crate) struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        if !root.is_dir() {
+            bail!("Attachment directory does not exist: {}", root.display());
+        }
+        Ok(Self{ root })
+    }
+
+    fn path(&self, hash: &SHA512) -> PathBuf {
+        let hex = hash.bytes().as_hex().to_string();
+        self.root.join(&hex[0..2]).join(&hex[2..4]).join(hex)
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.root.join("tmp")
+    }
+}
+
+impl AttachmentStore for FsStore {
+    fn get(&self, hash: &SHA512) -> Result<Option<FileStream>, Error> {
+        let path = self.path(hash);
+        let mut file = match File::open(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            result => result.with_context(|| format!("Error opening {}", path.display()))?,
+        };
+        let size = file.metadata()?.len();
+
+        let mut buf = [0 as u8; 32 * 1024];
+        let iter = std::iter::from_fn(move || -> Option<Result<Bytes, crate::server::SendError>> {
+            let bytes_read = match file.read(&mut buf) {
+                Err(io_err) => return Some(Err(io_err.into())),
+                Ok(x) => x,
+            };
+
+            if bytes_read == 0 {
+                return None;
+            }
+
+            Some(Ok(Bytes::copy_from_slice(&buf[..bytes_read])))
+        });
+
+        let stream = Box::new(blocking::Unblock::with_capacity(2, iter));
+        Ok(Some(FileStream{stream, size, mime_type: None}))
+    }
+
+    fn save(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error> {
+        let path = self.path(hash);
+        if path.exists() {
+            debug!("{} already exists", path.display());
+            return Ok(());
+        }
+
+        let temp_dir = self.temp_dir();
+        std::fs::create_dir_all(&temp_dir)?;
+        let mut temp = tempfile::NamedTempFile::new_in(&temp_dir)
+            .with_context(|| format!("Error creating a file in {}", temp_dir.display()))?;
+
+        let copied = std::io::copy(file, &mut temp)?;
+        if copied != size {
+            bail!("Expected {} bytes but read {}", size, copied);
+        }
+        temp.as_file().sync_all()?;
+
+        let hash_check = SHA512::from_file(temp.as_file_mut())?;
+        if &hash_check != hash {
+            bail!("Expected {} but got {}", hash, hash_check);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        temp.persist(&path)
+            .with_context(|| format!("Error moving attachment to {}", path.display()))?;
+        debug!("Saved {}", path.display());
+
+        Ok(())
+    }
+
+    fn remove(&self, hash: &SHA512) -> Result<(), Error> {
+        let path = self.path(hash);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result.with_context(|| format!("Error removing {}", path.display())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use futures::StreamExt;
+    use sodiumoxide::randombytes::randombytes;
+
+    use super::*;
+
+    #[test]
+    fn save_and_remove() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let store = FsStore::new(dir.path())?;
+
+        let contents = randombytes(100 * 1024);
+        let hash = SHA512::from_file(&mut Cursor::new(&contents))?;
+        assert!(store.get(&hash)?.is_none());
+
+        // Wrong size or hash should never be saved:
+        assert!(store.save(10, &hash, &mut Cursor::new(&contents)).is_err());
+        let other = randombytes(100 * 1024);
+        assert!(store.save(other.len() as u64, &hash, &mut Cursor::new(&other)).is_err());
+        assert!(store.get(&hash)?.is_none());
+
+        store.save(contents.len() as u64, &hash, &mut Cursor::new(&contents))?;
+        let file = store.get(&hash)?.expect("saved file");
+        assert_eq!(file.size, contents.len() as u64);
+
+        let chunks = futures::executor::block_on(file.stream.collect::<Vec<_>>());
+        let mut read = vec![];
+        for chunk in chunks {
+            read.extend_from_slice(&chunk.map_err(|_| anyhow::format_err!("read error"))?);
+        }
+        assert_eq!(read, contents);
+
+        store.remove(&hash)?;
+        assert!(store.get(&hash)?.is_none());
+        store.remove(&hash)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Stores attachment contents as objects in an S3-compatible bucket.
+
+use std::io::{ErrorKind, Seek, SeekFrom};
+
+use actix_web::web::Bytes;
+use anyhow::{Context, Error, bail};
+use log::debug;
+use ::s3::{Bucket, Region, creds::Credentials};
+
+use crate::{backend::{FileStream, SHA512}, util::AsHex};
+
+use super::AttachmentStore;
+
+/// How many bytes of an object to request at a time in get().
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Stores each file as an object named by its (hex) SHA-512 hash.
+pub(crate) struct S3Store {
+    bucket: Bucket,
+}
+
+impl S3Store {
+    /// Credentials are read from the standard AWS environment variables
+    /// (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`) or `~/.aws/credentials`.
+    ///
+    /// If an `endpoint` URL is given, we'll use that S3-compatible service
+    /// (ex: a local MinIO server) instead of AWS.
+    pub fn new(bucket: &str, region: &str, endpoint: Option<&str>) -> Result<Self, Error> {
+        let credentials = Credentials::default().context("Error loading S3 credentials")?;
+
+        let bucket = match endpoint {
+            None => Bucket::new(bucket, region.parse()?, credentials)?,
+            Some(endpoint) => {
+                let region = Region::Custom{
+                    region: region.to_string(),
+                    endpoint: endpoint.to_string(),
+                };
+                // S3-compatible services don't usually support bucket subdomains:
+                Bucket::new(bucket, region, credentials)?.with_path_style()
+            }
+        };
+
+        Ok(Self{ bucket })
+    }
+
+    fn key(hash: &SHA512) -> String {
+        hash.bytes().as_hex().to_string()
+    }
+}
+
+/// Errors that happen while streaming must be std Errors.
+fn stream_error(message: String) -> crate::server::SendError {
+    std::io::Error::new(ErrorKind::Other, message).into()
+}
+
+impl AttachmentStore for S3Store {
+    fn get(&self, hash: &SHA512) -> Result<Option<FileStream>, Error> {
+        let key = Self::key(hash);
+        let (head, status) = self.bucket.head_object(&key)?;
+        match status {
+            200 => {},
+            404 => return Ok(None),
+            _ => bail!("S3 returned status {} for {}", status, key),
+        }
+        let size = match head.content_length {
+            Some(size) => size as u64,
+            None => bail!("S3 did not return a size for {}", key),
+        };
+
+        // Like the postgres backend, fetch the contents a chunk at a time:
+        let bucket = self.bucket.clone();
+        let mut read_pos: u64 = 0;
+
+        let iter = std::iter::from_fn(move || -> Option<Result<Bytes, crate::server::SendError>> {
+            if read_pos >= size {
+                return None;
+            }
+
+            // Note: Ranges are inclusive.
+            let end = std::cmp::min(read_pos + CHUNK_SIZE, size) - 1;
+            let response = match bucket.get_object_range(&key, read_pos, Some(end)) {
+                Ok(response) => response,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            let status = response.status_code();
+            if status != 200 && status != 206 {
+                return Some(Err(stream_error(format!("S3 returned status {} for {}", status, key))));
+            }
+
+            let chunk = response.bytes();
+            if chunk.is_empty() {
+                return None;
+            }
+            read_pos += chunk.len() as u64;
+
+            Some(Ok(Bytes::copy_from_slice(chunk)))
+        });
+
+        let stream = Box::new(blocking::Unblock::with_capacity(2, iter));
+        Ok(Some(FileStream{stream, size, mime_type: None}))
+    }
+
+    fn save(&self, size: u64, hash: &SHA512, file: &mut dyn std::io::Read) -> Result<(), Error> {
+        // Buffer the file locally, so that we can verify it before uploading:
+        let mut temp = tempfile::tempfile().context("Error opening temp file")?;
+        let copied = std::io::copy(file, &mut temp)?;
+        if copied != size {
+            bail!("Expected {} bytes but read {}", size, copied);
+        }
+
+        let hash_check = SHA512::from_file(&mut temp)?;
+        if &hash_check != hash {
+            bail!("Expected {} but got {}", hash, hash_check);
+        }
+
+        let key = Self::key(hash);
+        temp.seek(SeekFrom::Start(0))?;
+        let status = self.bucket.put_object_stream(&mut temp, &key)?;
+        if status != 200 {
+            bail!("S3 returned status {} when uploading {}", status, key);
+        }
+        debug!("Uploaded {} to S3", key);
+
+        Ok(())
+    }
+
+    fn remove(&self, hash: &SHA512) -> Result<(), Error> {
+        let key = Self::key(hash);
+        let response = self.bucket.delete_object(&key)?;
+        match response.status_code() {
+            // S3 returns 204 whether or not the object existed:
+            200 | 204 | 404 => Ok(()),
+            status => bail!("S3 returned status {} when deleting {}", status, key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need an S3 bucket to run against. Set FEOBLOG_TEST_S3_BUCKET
+    //! to enable them. For a local MinIO server, also set FEOBLOG_TEST_S3_ENDPOINT.
+    //! ex: FEOBLOG_TEST_S3_BUCKET=feoblog-test FEOBLOG_TEST_S3_ENDPOINT=http://localhost:9000 cargo test
+
+    use std::io::Cursor;
+
+    use futures::StreamExt;
+    use sodiumoxide::randombytes::randombytes;
+
+    use super::*;
+
+    fn test_store() -> Result<Option<S3Store>, Error> {
+        let bucket = match std::env::var("FEOBLOG_TEST_S3_BUCKET") {
+            Ok(bucket) => bucket,
+            Err(_) => {
+                println!("FEOBLOG_TEST_S3_BUCKET not set. Skipping.");
+                return Ok(None);
+            }
+        };
+        let endpoint = std::env::var("FEOBLOG_TEST_S3_ENDPOINT").ok();
+
+        Ok(Some(S3Store::new(&bucket, "us-east-1", endpoint.as_deref())?))
+    }
+
+    #[test]
+    fn save_and_remove() -> Result<(), Error> {
+        let store = match test_store()? {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        // Larger than CHUNK_SIZE, to test reading multiple chunks:
+        let contents = randombytes(CHUNK_SIZE as usize + 1000);
+        let hash = SHA512::from_file(&mut Cursor::new(&contents))?;
+        assert!(store.get(&hash)?.is_none());
+
+        store.save(contents.len() as u64, &hash, &mut Cursor::new(&contents))?;
+        let file = store.get(&hash)?.expect("saved file");
+        assert_eq!(file.size, contents.len() as u64);
+
+        let chunks = futures::executor::block_on(file.stream.collect::<Vec<_>>());
+        let mut read = vec![];
+        for chunk in chunks {
+            read.extend_from_slice(&chunk.map_err(|_| anyhow::format_err!("read error"))?);
+        }
+        assert_eq!(read, contents);
+
+        store.remove(&hash)?;
+        assert!(store.get(&hash)?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1216 @@
+//! The memory backend keeps all data in memory, and loses it when the server
+//! stops. It's meant for tests, demos, and other throwaway servers.
+//!
+//! Instead of maintaining indexes like the database backends do, it mostly
+//! scans through every item it has. That's slow for large data sets, but
+//! makes it a fairly readable reference for what each Backend method does.
+//! The tests in `backend::tests` hold SQLite to the same behavior.
+
+use std::{collections::{HashMap, HashSet}, io::Read, sync::{Arc, Mutex, MutexGuard}};
+
+use crate::{markdown::{ToHTML, find_tags}, protos::{Item, Post, Profile, ReplyRef}};
+use actix_web::web::Bytes;
+use anyhow::{Error, bail, format_err};
+
+use super::{BadAttachment, BadItem, Block, BlockRow, FileMeta, FileStream, ItemDisplayRow, ItemRow, MigrateResult, PruneOpts, PruneResult, Quarantine, Quota, QuotaDenyReason, ReactionCount, RowCallback, SHA512, SearchScope, ServerUser, ServerUserQuotas, Signature, ThreadRow, TimeSpan, Timestamp, UsageByUserRow, UserID};
+
+/// Don't follow reply chains any deeper than this in thread_items().
+const MAX_THREAD_DEPTH: u32 = 100;
+
+pub(crate) struct FactoryBuilder;
+
+impl FactoryBuilder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl super::FactoryBuilder for FactoryBuilder {
+    /// Each factory gets its own new, empty, database.
+    fn factory(&self) -> Result<Box<dyn super::Factory>, Error> {
+        Ok(Box::new(Factory{
+            db: Arc::new(Mutex::new(Database::default())),
+        }))
+    }
+
+    // There's nothing to create or upgrade:
+    fn db_exists(&self) -> Result<bool, Error> { Ok(true) }
+    fn db_create(&self) -> Result<(), Error> { Ok(()) }
+    fn db_needs_upgrade(&self) -> Result<bool, Error> { Ok(false) }
+    fn db_upgrade(&self) -> Result<(), Error> { Ok(()) }
+}
+
+pub(crate) struct Factory {
+    db: Arc<Mutex<Database>>,
+}
+
+impl super::Factory for Factory {
+    fn open(&self) -> Result<Box<dyn super::Backend>, Error> {
+        Ok(Box::new(Connection{ db: self.db.clone() }))
+    }
+
+    fn dyn_clone(&self) -> Box<dyn super::Factory> {
+        Box::new(Factory{ db: self.db.clone() })
+    }
+}
+
+pub(crate) struct Connection {
+    db: Arc<Mutex<Database>>,
+}
+
+impl Connection {
+    // Note: Never call a RowCallback while holding the lock, since it may use the Backend too.
+    fn lock(&self) -> Result<MutexGuard<'_, Database>, Error> {
+        self.db.lock().map_err(|_| format_err!("In-memory database lock was poisoned"))
+    }
+}
+
+#[derive(Default)]
+struct Database {
+    items: HashMap<(UserID, Signature), StoredItem>,
+
+    /// Items which have been deleted by a Delete item. We won't accept them again.
+    deleted: HashSet<(UserID, Signature)>,
+
+    server_users: HashMap<UserID, ServerUser>,
+
+    /// Quotas for server users. Server users without an entry have no quotas.
+    quotas: HashMap<UserID, ServerUserQuotas>,
+
+    /// The latest Profile for each user.
+    profiles: HashMap<UserID, ProfileRow>,
+
+    /// Keyed by (user_id, signature, file name)
+    attachments: HashMap<(UserID, Signature, String), Attachment>,
+
+    /// Attachment contents, keyed by their SHA-512 hash bytes.
+    store: HashMap<Vec<u8>, Bytes>,
+
+    /// Content blocked by the server admin, oldest first.
+    blocks: Vec<BlockRow>,
+
+    /// Corrupt content found by `db verify`, and what's wrong with it.
+    quarantine: Vec<(Quarantine, String)>,
+}
+
+struct StoredItem {
+    row: ItemRow,
+    item: Item,
+}
+
+struct ProfileRow {
+    signature: Signature,
+    timestamp: Timestamp,
+    profile: Profile,
+}
+
+struct Attachment {
+    size: u64,
+    hash: SHA512,
+    mime_type: Option<String>,
+}
+
+fn item_ref(reply_ref: &ReplyRef) -> Option<(UserID, Signature)> {
+    let user = UserID::from_vec(reply_ref.get_user_id().get_bytes().into()).ok()?;
+    let signature = Signature::from_vec(reply_ref.get_signature().get_bytes().into()).ok()?;
+    Some((user, signature))
+}
+
+/// Sort stored items into the order for `time_span`, skipping those outside of it.
+fn in_time_span<'a>(items: impl Iterator<Item=&'a StoredItem>, time_span: &TimeSpan) -> Vec<&'a StoredItem> {
+    let mut items: Vec<_> = items
+        .filter(|it| time_span.contains(it.row.timestamp, &it.row.user, &it.row.signature))
+        .collect();
+    items.sort_by(|a, b| {
+        let a = (a.row.timestamp.unix_utc_ms, a.row.user.bytes(), a.row.signature.bytes());
+        let b = (b.row.timestamp.unix_utc_ms, b.row.user.bytes(), b.row.signature.bytes());
+        a.cmp(&b)
+    });
+    if time_span.is_before() {
+        items.reverse();
+    }
+    items
+}
+
+/// Pass rows to a callback while it asks for more.
+fn send_rows<T>(rows: Vec<T>, callback: RowCallback<'_, T>) -> Result<(), Error> {
+    for row in rows {
+        if !callback(row)? { break; }
+    }
+    Ok(())
+}
+
+fn post_tags(post: &Post) -> Vec<String> {
+    let mut tags = find_tags(post.get_title());
+    for tag in post.get_body().md_get_tags() {
+        if !tags.contains(&tag) { tags.push(tag); }
+    }
+    tags
+}
+
+impl Database {
+    fn follows(&self, user: &UserID) -> Vec<(UserID, String)> {
+        let profile = match self.profiles.get(user) {
+            None => return vec![],
+            Some(row) => &row.profile,
+        };
+        profile.get_follows().iter().filter_map(|follow| {
+            let followed = UserID::from_vec(follow.get_user().get_bytes().into()).ok()?;
+            Some((followed, follow.get_display_name().to_string()))
+        }).collect()
+    }
+
+    fn successor(&self, user: &UserID) -> Option<UserID> {
+        let profile = &self.profiles.get(user)?.profile;
+        if !profile.has_successor() { return None; }
+        UserID::from_vec(profile.get_successor().get_user_id().get_bytes().into()).ok()
+    }
+
+    fn revoked(&self, user: &UserID) -> bool {
+        self.profiles.get(user).map(|row| row.profile.has_revocation()).unwrap_or(false)
+    }
+
+    fn display_name(&self, user: &UserID) -> Option<String> {
+        self.profiles.get(user).map(|row| row.profile.get_display_name().to_string())
+    }
+
+    /// Server users, and users they follow. (Whether or not the server user has been revoked.)
+    fn vouched_users(&self) -> HashSet<UserID> {
+        let mut users = HashSet::new();
+        for user in self.server_users.keys() {
+            users.insert(user.clone());
+            for (followed, _) in self.follows(user) {
+                users.insert(followed);
+            }
+        }
+        users
+    }
+
+    /// All users whose content this server will store & serve.
+    fn known_users(&self) -> HashSet<UserID> {
+        let mut known = self.vouched_users();
+
+        // Followers of an old ID also follow its successor:
+        for user in self.server_users.keys() {
+            for (followed, _) in self.follows(user) {
+                if let Some(successor) = self.successor(&followed) {
+                    known.insert(successor);
+                }
+            }
+        }
+
+        known
+    }
+
+    /// Items shared by known users, which we store & serve even if we don't otherwise know their authors.
+    fn shared_items(&self) -> HashSet<(UserID, Signature)> {
+        let known = self.known_users();
+        self.items.values()
+            .filter(|it| it.item.has_share() && known.contains(&it.row.user))
+            .filter_map(|it| item_ref(it.item.get_share().get_item()))
+            .collect()
+    }
+
+    /// Whether we'll serve this item. (Assuming it's not blocked.)
+    fn servable(&self, user: &UserID, signature: &Signature) -> bool {
+        self.known_users().contains(user) || self.shared_items().contains(&(user.clone(), signature.clone()))
+    }
+
+    fn is_quarantined(&self, target: &Quarantine) -> bool {
+        self.quarantine.iter().any(|(quarantined, _)| quarantined == target)
+    }
+
+    fn is_blocked(&self, block: &Block) -> bool {
+        self.blocks.iter().any(|row| match (&row.block, block) {
+            (Block::User(blocked), Block::Item(user, _)) => blocked == user,
+            (blocked, block) => blocked == block,
+        })
+    }
+
+    /// The quota for a user, or None if they're not allowed to post here at all.
+    fn user_quota(&self, user_id: &UserID) -> Option<Quota> {
+        if self.server_users.contains_key(user_id) {
+            return Some(self.quotas.get(user_id).copied().unwrap_or_default().own);
+        }
+
+        // Revoked server users can no longer vouch for anyone:
+        let vouching: Vec<&UserID> = self.server_users.keys().filter(|user| !self.revoked(user)).collect();
+
+        // Authors of items shared by known users, so that we can store the shared
+        // items. (put_item() only accepts those items from them.)
+        let sharers: HashSet<UserID> = self.items.values()
+            .filter(|it| it.item.has_share())
+            .filter(|it| item_ref(it.item.get_share().get_item()).map_or(false, |(user, _)| &user == user_id))
+            .map(|it| it.row.user.clone())
+            .collect();
+
+        // Those followed by "server users" (or the successors of those users), and
+        // authors of items that server users (or their follows) have shared.
+        // If several server users vouch for them, the most generous quota wins:
+        let mut follow_quota: Option<Quota> = None;
+        for server_user in &vouching {
+            let follows = |user: &UserID| self.follows(server_user).into_iter()
+                .any(|(followed, _)| &followed == user || self.successor(&followed).as_ref() == Some(user));
+            let vouches = follows(user_id) || sharers.iter().any(|sharer| sharer == *server_user || follows(sharer));
+            if !vouches { continue; }
+
+            let quota = self.quotas.get(*server_user).copied().unwrap_or_default().follows;
+            follow_quota = Some(match follow_quota {
+                None => quota,
+                Some(other) => other.most_generous(quota),
+            });
+        }
+
+        follow_quota
+    }
+
+    /// All users that `user` follows (and themselves), with the display name to use for each.
+    fn follow_names(&self, user: &UserID) -> HashMap<UserID, Option<String>> {
+        fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
+        let mut names = HashMap::new();
+
+        for (followed, follow_name) in self.follows(user) {
+            // Prefer displaying the name that this user has assigned to the follow.
+            let name = Some(follow_name).filter(not_empty)
+                .or_else(|| self.display_name(&followed)).filter(not_empty);
+
+            if let Some(successor) = self.successor(&followed) {
+                let successor_name = name.clone().or_else(|| self.display_name(&successor)).filter(not_empty);
+                names.insert(successor, successor_name);
+            }
+            names.insert(followed, name);
+        }
+
+        if self.profiles.contains_key(user) {
+            names.insert(user.clone(), self.display_name(user).filter(not_empty));
+        }
+
+        names
+    }
+
+    /// Items that may be listed. Blocked items are kept until they're pruned, and
+    /// quarantined items until they're replaced, but neither are listed.
+    fn listed_items(&self) -> impl Iterator<Item=&StoredItem> {
+        self.items.values()
+            .filter(move |it| !self.is_blocked(&Block::Item(it.row.user.clone(), it.row.signature.clone())))
+            .filter(move |it| !self.is_quarantined(&Quarantine::Item(it.row.user.clone(), it.row.signature.clone())))
+    }
+
+    /// Listed items that are not DirectMessages.
+    fn public_items(&self) -> impl Iterator<Item=&StoredItem> {
+        self.listed_items().filter(|it| !it.item.has_direct_message())
+    }
+
+    fn reaction_counts(&self, known: &HashSet<UserID>, user: &UserID, signature: &Signature) -> Vec<ReactionCount> {
+        let mut reactors: HashMap<&str, HashSet<&UserID>> = HashMap::new();
+        for stored in self.listed_items() {
+            if !stored.item.has_reaction() || !known.contains(&stored.row.user) { continue; }
+            let reaction = stored.item.get_reaction();
+            if item_ref(reaction.get_reply_to()).as_ref() != Some(&(user.clone(), signature.clone())) { continue; }
+            reactors.entry(reaction.get_reaction()).or_default().insert(&stored.row.user);
+        }
+
+        let mut counts: Vec<_> = reactors.into_iter().map(|(reaction, users)| ReactionCount{
+            reaction: reaction.to_string(),
+            count: users.len() as u64,
+        }).collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.reaction.cmp(&b.reaction)));
+        counts
+    }
+
+    /// Get the item shared by the given (Share) item, if we have it.
+    fn shared_row(&self, stored: &StoredItem) -> Option<Box<ItemDisplayRow>> {
+        if !stored.item.has_share() { return None; }
+        let (user, signature) = item_ref(stored.item.get_share().get_item())?;
+        if self.is_blocked(&Block::Item(user.clone(), signature.clone())) { return None; }
+        if self.is_quarantined(&Quarantine::Item(user.clone(), signature.clone())) { return None; }
+        let shared = self.items.get(&(user, signature))?;
+        Some(Box::new(ItemDisplayRow{
+            item: shared.row.clone(),
+            display_name: self.display_name(&shared.row.user),
+            reactions: vec![],
+            // We don't display shares of shares:
+            shared: None,
+        }))
+    }
+
+    fn display_row(&self, stored: &StoredItem) -> ItemDisplayRow {
+        ItemDisplayRow{
+            item: stored.row.clone(),
+            display_name: self.display_name(&stored.row.user),
+            reactions: vec![],
+            shared: self.shared_row(stored),
+        }
+    }
+
+    /// The newest Revision of a Post, if it has been revised.
+    fn latest_revision(&self, user: &UserID, original: &Signature) -> Option<&StoredItem> {
+        self.items.values()
+            .filter(|it| &it.row.user == user && it.item.has_revision())
+            .filter(|it| it.item.get_revision().get_original().get_bytes() == original.bytes())
+            .max_by(|a, b| {
+                let a = (a.row.timestamp.unix_utc_ms, a.row.signature.bytes());
+                let b = (b.row.timestamp.unix_utc_ms, b.row.signature.bytes());
+                a.cmp(&b)
+            })
+    }
+
+    /// The current contents of a Post. (i.e.: its latest Revision)
+    fn current_post<'a>(&'a self, stored: &'a StoredItem) -> &'a Post {
+        match self.latest_revision(&stored.row.user, &stored.row.signature) {
+            Some(revision) => revision.item.get_revision().get_post(),
+            None => stored.item.get_post(),
+        }
+    }
+
+    /// Users @mentioned by a Post (in its latest Revision), or a Comment.
+    fn mentions(&self, stored: &StoredItem) -> Vec<UserID> {
+        if stored.item.has_comment() {
+            stored.item.get_comment().get_text().md_get_mentions()
+        } else if stored.item.has_post() {
+            self.current_post(stored).get_body().md_get_mentions()
+        } else {
+            vec![]
+        }
+    }
+
+    /// The searchable text of a Post (in its latest Revision), or a Comment.
+    fn search_text(&self, stored: &StoredItem) -> String {
+        if stored.item.has_comment() {
+            stored.item.get_comment().get_text().to_string()
+        } else if stored.item.has_post() {
+            let post = self.current_post(stored);
+            format!("{}\n{}", post.get_title(), post.get_body())
+        } else {
+            String::new()
+        }
+    }
+
+    /// Comments replying to an item, oldest first.
+    fn replies(&self, known: &HashSet<UserID>, user: &UserID, signature: &Signature) -> Vec<&StoredItem> {
+        let target = Some((user.clone(), signature.clone()));
+        let mut replies: Vec<_> = self.listed_items()
+            .filter(|it| it.item.has_comment() && known.contains(&it.row.user))
+            .filter(|it| item_ref(it.item.get_comment().get_reply_to()) == target)
+            .collect();
+        replies.sort_by(|a, b| {
+            let a = (a.row.timestamp.unix_utc_ms, a.row.signature.bytes());
+            let b = (b.row.timestamp.unix_utc_ms, b.row.signature.bytes());
+            a.cmp(&b)
+        });
+        replies
+    }
+
+    fn add_thread_rows(&self, known: &HashSet<UserID>, user: &UserID, signature: &Signature, depth: u32, limit: usize, rows: &mut Vec<ThreadRow>) {
+        if depth > MAX_THREAD_DEPTH { return; }
+        for reply in self.replies(known, user, signature) {
+            if rows.len() >= limit { return; }
+            rows.push(ThreadRow{
+                item: ItemDisplayRow{
+                    item: reply.row.clone(),
+                    display_name: self.display_name(&reply.row.user),
+                    reactions: vec![],
+                    // Only Comments reply to items:
+                    shared: None,
+                },
+                depth,
+            });
+            self.add_thread_rows(known, &reply.row.user, &reply.row.signature, depth + 1, limit, rows);
+        }
+    }
+
+    /// Save a Profile, if it's newer than the one we have.
+    fn update_profile(&mut self, row: &ItemRow, item: &Item) {
+        let revoking = item.get_profile().has_revocation();
+
+        if let Some(previous) = self.profiles.get(&row.user) {
+            // Revocations are permanent:
+            if previous.profile.has_revocation() {
+                return;
+            }
+
+            // Never replace a newer profile's metadata, unless we're revoking:
+            if previous.timestamp.unix_utc_ms >= row.timestamp.unix_utc_ms && !revoking {
+                return;
+            }
+        }
+
+        // Note: The successor's counter-signature should've been verified before we got here.
+        self.profiles.insert(row.user.clone(), ProfileRow{
+            signature: row.signature.clone(),
+            timestamp: row.timestamp,
+            profile: item.get_profile().clone(),
+        });
+    }
+
+    /// We're saving a Delete. Remove the item it references.
+    fn delete_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error> {
+        let target = Signature::from_vec(item.get_delete().get_signature().get_bytes().into())?;
+
+        // Revisions of a deleted Post are deleted along with it:
+        let mut keys: Vec<(UserID, Signature)> = self.items.values()
+            .filter(|it| it.row.user == row.user && it.item.has_revision())
+            .filter(|it| it.item.get_revision().get_original().get_bytes() == target.bytes())
+            .map(|it| (it.row.user.clone(), it.row.signature.clone()))
+            .collect();
+        keys.push((row.user.clone(), target));
+
+        for key in keys {
+            self.items.remove(&key);
+            self.attachments.retain(|(user, signature, _), _| (user, signature) != (&key.0, &key.1));
+            self.deleted.insert(key);
+        }
+
+        // Note: Attachment contents in `store` may be shared with other items, so
+        // we leave those for `db prune` to clean up.
+        Ok(())
+    }
+
+    fn index_attachments(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error> {
+        // TODO: Eventually support attachments for Profiles (and other types?) too:
+        let post = if item.has_revision() {
+            item.get_revision().get_post()
+        } else {
+            item.get_post()
+        };
+
+        for file in post.get_attachments().get_file() {
+            if file.name.contains("/") || file.name.contains("\\") {
+                bail!("File separators are not allowed in attached file names: {}", file.name);
+            }
+            let key = (row.user.clone(), row.signature.clone(), file.name.clone());
+            self.attachments.insert(key, Attachment{
+                size: file.size,
+                hash: SHA512::from_hash_bytes(file.hash.as_slice())?,
+                mime_type: Some(file.mime_type.clone()).filter(|it| !it.is_empty()),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Attachment hashes used by items, other than those in `removed`.
+    fn used_hashes(&self, removed: &[(UserID, Signature)]) -> HashSet<Vec<u8>> {
+        self.attachments.iter()
+            .filter(|((user, signature, _), _)| {
+                let key = (user.clone(), signature.clone());
+                self.items.contains_key(&key) && !removed.contains(&key)
+            })
+            .map(|(_, attachment)| attachment.hash.bytes().to_vec())
+            .collect()
+    }
+}
+
+impl super::Backend for Connection {
+    fn homepage_items<'a>(
+        &self,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            let homepage = db.public_items().filter(|it| {
+                db.server_users.get(&it.row.user).map(|user| user.on_homepage).unwrap_or(false)
+            });
+            in_time_span(homepage, &time_span).into_iter()
+                .map(|it| db.display_row(it))
+                .collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            if !db.known_users().contains(user) { return Ok(()); }
+            let items = db.public_items().filter(|it| &it.row.user == user);
+            in_time_span(items, &time_span).into_iter().map(|it| it.row.clone()).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn reply_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            let known = db.known_users();
+            let replies = db.replies(&known, user, signature).into_iter();
+            in_time_span(replies, &time_span).into_iter().map(|it| it.row.clone()).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn thread_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        limit: usize,
+        callback: RowCallback<'a, ThreadRow>,
+    ) -> Result<(), Error> {
+        let mut rows = vec![];
+        {
+            let db = self.lock()?;
+            let known = db.known_users();
+            db.add_thread_rows(&known, user, signature, 1, limit, &mut rows);
+        }
+        send_rows(rows, callback)
+    }
+
+    fn item_revisions<'a>(
+        &self,
+        user: &UserID,
+        original: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            if !db.known_users().contains(user) { return Ok(()); }
+            let revisions = db.listed_items()
+                .filter(|it| &it.row.user == user && it.item.has_revision())
+                .filter(|it| it.item.get_revision().get_original().get_bytes() == original.bytes());
+            in_time_span(revisions, &time_span).into_iter().map(|it| it.row.clone()).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn inbox_items<'a>(
+        &self,
+        recipient: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            let known = db.known_users();
+            let messages = db.listed_items()
+                .filter(|it| it.item.has_direct_message() && known.contains(&it.row.user))
+                .filter(|it| it.item.get_direct_message().get_recipient().get_bytes() == recipient.bytes());
+            in_time_span(messages, &time_span).into_iter().map(|it| it.row.clone()).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn latest_revision(&self, user: &UserID, original: &Signature) -> Result<Option<ItemRow>, Error> {
+        let signature = {
+            let db = self.lock()?;
+            db.latest_revision(user, original).map(|it| it.row.signature.clone())
+        };
+        match signature {
+            None => Ok(None),
+            Some(signature) => self.user_item(user, &signature),
+        }
+    }
+
+    fn shared_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemDisplayRow>, Error> {
+        let db = self.lock()?;
+        let share = match db.items.get(&(user.clone(), signature.clone())) {
+            None => return Ok(None),
+            Some(share) => share,
+        };
+        Ok(db.shared_row(share).map(|row| *row))
+    }
+
+    fn reaction_counts(&self, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error> {
+        let db = self.lock()?;
+        let known = db.known_users();
+        Ok(db.reaction_counts(&known, user, signature))
+    }
+
+    fn tag_items<'a>(
+        &self,
+        tag: &str,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let tag = tag.to_lowercase();
+        let rows = {
+            let db = self.lock()?;
+            let known = db.known_users();
+            let tagged = db.listed_items()
+                .filter(|it| it.item.has_post() && known.contains(&it.row.user))
+                .filter(|it| post_tags(db.current_post(it)).contains(&tag));
+            in_time_span(tagged, &time_span).into_iter().map(|it| ItemDisplayRow{
+                // Only Posts are tagged:
+                shared: None,
+                ..db.display_row(it)
+            }).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn mention_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            let known = db.known_users();
+            let mentioning = db.listed_items()
+                .filter(|it| known.contains(&it.row.user))
+                .filter(|it| db.mentions(it).contains(user));
+            in_time_span(mentioning, &time_span).into_iter().map(|it| ItemDisplayRow{
+                // Only Posts and Comments have mentions:
+                shared: None,
+                ..db.display_row(it)
+            }).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn search_items<'a>(
+        &self,
+        query: &str,
+        scope: SearchScope,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let terms = super::search_terms(query);
+        if terms.is_empty() {
+            return Ok(());
+        }
+
+        let rows = {
+            let db = self.lock()?;
+            let known = db.known_users();
+            let in_scope = |user: &UserID| match &scope {
+                SearchScope::All => known.contains(user),
+                SearchScope::User(scope_user) => user == scope_user,
+                SearchScope::Feed(feed_user) => db.follow_names(feed_user).contains_key(user),
+            };
+            let matching = db.listed_items()
+                .filter(|it| it.item.has_post() || it.item.has_comment())
+                .filter(|it| in_scope(&it.row.user))
+                .filter(|it| {
+                    let words: HashSet<String> = super::search_terms(&db.search_text(it)).into_iter().collect();
+                    terms.iter().all(|term| words.contains(term))
+                });
+            in_time_span(matching, &time_span).into_iter().map(|it| ItemDisplayRow{
+                // Only Posts & Comments are searched:
+                shared: None,
+                ..db.display_row(it)
+            }).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn display_names(&self, author: &UserID, users: &[UserID]) -> Result<HashMap<UserID, String>, Error> {
+        let db = self.lock()?;
+        let follows = db.follow_names(author);
+
+        let mut names = HashMap::new();
+        for user in users {
+            let name = follows.get(user).cloned().flatten()
+                .or_else(|| db.display_name(user).filter(|it| !it.trim().is_empty()));
+            if let Some(name) = name {
+                names.insert(user.clone(), name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let rows = {
+            let db = self.lock()?;
+            let follows = db.follow_names(user_id);
+            let items = db.public_items().filter(|it| follows.contains_key(&it.row.user));
+            in_time_span(items, &time_span).into_iter().map(|it| ItemDisplayRow{
+                display_name: follows.get(&it.row.user).cloned().flatten(),
+                ..db.display_row(it)
+            }).collect()
+        };
+        send_rows(rows, callback)
+    }
+
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> {
+        let db = self.lock()?;
+        if !db.servable(user, signature) { return Ok(None); }
+        if db.is_quarantined(&Quarantine::Item(user.clone(), signature.clone())) { return Ok(None); }
+        Ok(db.items.get(&(user.clone(), signature.clone())).map(|it| it.row.clone()))
+    }
+
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let db = self.lock()?;
+        let quarantined = db.is_quarantined(&Quarantine::Item(user.clone(), signature.clone()));
+        Ok(!quarantined && db.items.contains_key(&(user.clone(), signature.clone())))
+    }
+
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let db = self.lock()?;
+        Ok(db.deleted.contains(&(user.clone(), signature.clone())))
+    }
+
+    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error> {
+        let mut db = self.lock()?;
+
+        let key = (row.user.clone(), row.signature.clone());
+
+        // A quarantined item was indexed when it was first saved, from the same
+        // signed Item, so only its row needs replacing:
+        let target = Quarantine::Item(row.user.clone(), row.signature.clone());
+        if db.is_quarantined(&target) {
+            db.quarantine.retain(|(quarantined, _)| quarantined != &target);
+            if let Some(stored) = db.items.get_mut(&key) {
+                stored.row = row.clone();
+                return Ok(());
+            }
+        }
+
+        if db.items.contains_key(&key) {
+            bail!("Item already exists: {}/{}", row.user, row.signature.to_base58());
+        }
+
+        // Check for errors before we change anything:
+        let mut attachments = Database::default();
+        attachments.index_attachments(row, item)?;
+
+        // Profiles and Deletes can not be deleted. If a Delete for this item
+        // arrived before it did, ignore that Delete:
+        if item.has_profile() || item.has_delete() {
+            db.deleted.remove(&key);
+        }
+
+        if item.has_profile() {
+            db.update_profile(row, item);
+        }
+
+        if item.has_delete() {
+            db.delete_item(row, item)?;
+        }
+
+        db.attachments.extend(attachments.attachments);
+        db.items.insert(key, StoredItem{
+            row: row.clone(),
+            item: item.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn server_user(&self, user: &UserID) -> Result<Option<ServerUser>, Error> {
+        let db = self.lock()?;
+        Ok(db.server_users.get(user).cloned())
+    }
+
+    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error> {
+        let mut users: Vec<ServerUser> = {
+            let db = self.lock()?;
+            db.server_users.values().cloned().collect()
+        };
+        users.sort_by(|a, b| (a.on_homepage, a.user.bytes()).cmp(&(b.on_homepage, b.user.bytes())));
+        send_rows(users, cb)
+    }
+
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
+        let mut db = self.lock()?;
+        if db.server_users.contains_key(&server_user.user) {
+            bail!("User {} is already a server user", server_user.user);
+        }
+        db.server_users.insert(server_user.user.clone(), server_user.clone());
+        Ok(())
+    }
+
+    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
+        let signature = {
+            let db = self.lock()?;
+            db.profiles.get(user).map(|row| row.signature.clone())
+        };
+        match signature {
+            None => Ok(None),
+            Some(signature) => self.user_item(user, &signature),
+        }
+    }
+
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
+        let db = self.lock()?;
+        Ok(db.known_users().contains(user_id))
+    }
+
+    fn item_shared(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let db = self.lock()?;
+        Ok(db.shared_items().contains(&(user.clone(), signature.clone())))
+    }
+
+    fn user_revoked(&self, user: &UserID) -> Result<bool, Error> {
+        let db = self.lock()?;
+        Ok(db.revoked(user))
+    }
+
+    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
+        let db = self.lock()?;
+
+        if db.revoked(user_id) {
+            return Ok(Some(QuotaDenyReason::ProfileRevoked));
+        }
+
+        let quota = match db.user_quota(user_id) {
+            None => return Ok(Some(QuotaDenyReason::UnknownUser)),
+            Some(quota) => quota,
+        };
+
+        if item.has_profile() || item.has_delete() {
+            return Ok(None);
+        }
+
+        if let Some(max_bytes) = quota.item_bytes {
+            let used: u64 = db.items.values()
+                .filter(|it| &it.row.user == user_id)
+                .map(|it| it.row.item_bytes.len() as u64)
+                .sum();
+            if super::quota_exceeded(Some(max_bytes), used, bytes.len() as u64) {
+                return Ok(Some(QuotaDenyReason::ItemQuotaExceeded{ max_bytes }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn server_user_quotas(&self, user: &UserID) -> Result<Option<ServerUserQuotas>, Error> {
+        let db = self.lock()?;
+        if !db.server_users.contains_key(user) {
+            return Ok(None);
+        }
+        Ok(Some(db.quotas.get(user).copied().unwrap_or_default()))
+    }
+
+    fn set_server_user_quotas(&self, user: &UserID, quotas: &ServerUserQuotas) -> Result<(), Error> {
+        let mut db = self.lock()?;
+        if !db.server_users.contains_key(user) {
+            bail!("User {} is not a server user", user);
+        }
+        db.quotas.insert(user.clone(), *quotas);
+        Ok(())
+    }
+
+    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str) -> Result<Option<FileStream>, Error> {
+        let db = self.lock()?;
+        if !db.servable(&user_id, &signature) { return Ok(None); }
+
+        let attachment = match db.attachments.get(&(user_id, signature, file_name.to_string())) {
+            None => return Ok(None),
+            Some(attachment) => attachment,
+        };
+        if db.is_quarantined(&Quarantine::Attachment(attachment.hash.clone())) { return Ok(None); }
+        let contents = match db.store.get(attachment.hash.bytes()) {
+            None => return Ok(None),
+            Some(contents) => contents.clone(),
+        };
+
+        let size = contents.len() as u64;
+        if size != attachment.size {
+            bail!("Item expected {} bytes but found {}", attachment.size, size);
+        }
+
+        let chunks: Vec<Result<Bytes, crate::server::SendError>> = vec![Ok(contents)];
+        Ok(Some(FileStream{
+            stream: Box::new(futures::stream::iter(chunks)),
+            size,
+            mime_type: attachment.mime_type.clone(),
+        }))
+    }
+
+    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<FileMeta>, Error> {
+        let db = self.lock()?;
+        if !db.servable(user_id, signature) { return Ok(None); }
+
+        let attachment = match db.attachments.get(&(user_id.clone(), signature.clone(), file_name.to_string())) {
+            None => return Ok(None),
+            Some(attachment) => attachment,
+        };
+
+        let stored = db.store.contains_key(attachment.hash.bytes());
+        let quarantined = db.is_quarantined(&Quarantine::Attachment(attachment.hash.clone()));
+
+        // Files we already have don't take up any more space:
+        let quota_exceeded = !stored && match db.user_quota(user_id) {
+            None => true,
+            Some(quota) => {
+                let hashes: HashSet<&[u8]> = db.attachments.iter()
+                    .filter(|((user, _, _), _)| user == user_id)
+                    .map(|(_, it)| it.hash.bytes())
+                    .collect();
+                let used: u64 = hashes.into_iter()
+                    .filter_map(|hash| db.store.get(hash))
+                    .map(|contents| contents.len() as u64)
+                    .sum();
+                super::quota_exceeded(quota.attachment_bytes, used, attachment.size)
+            },
+        };
+
+        Ok(Some(FileMeta{
+            exists: stored && !quarantined,
+            hash: SHA512::from_hash_bytes(attachment.hash.bytes())?,
+            size: attachment.size,
+            quota_exceeded,
+        }))
+    }
+
+    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error> {
+        let mut contents = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut contents)?;
+
+        if contents.len() as u64 != size {
+            bail!("Expected {} bytes but read {}", size, contents.len());
+        }
+
+        let hash_check = SHA512::from_file(&mut std::io::Cursor::new(&contents))?;
+        if &hash_check != hash {
+            bail!("Expected {} but got {}", hash, hash_check);
+        }
+
+        let mut db = self.lock()?;
+        db.store.insert(hash.bytes().to_vec(), Bytes::from(contents));
+        let target = Quarantine::Attachment(hash.clone());
+        db.quarantine.retain(|(quarantined, _)| quarantined != &target);
+        Ok(())
+    }
+
+    fn usage_by_user(&self, callback: RowCallback<'_, UsageByUserRow>) -> Result<(), Error> {
+        let mut rows: Vec<UsageByUserRow> = {
+            let db = self.lock()?;
+            let known = db.known_users();
+
+            let mut usage: HashMap<&UserID, (u64, u64, HashSet<&[u8]>)> = HashMap::new();
+            for stored in db.items.values() {
+                let (count, bytes, _) = usage.entry(&stored.row.user).or_default();
+                *count += 1;
+                *bytes += stored.row.item_bytes.len() as u64;
+            }
+            for ((user, signature, _), attachment) in &db.attachments {
+                if !db.items.contains_key(&(user.clone(), signature.clone())) { continue; }
+                if !db.store.contains_key(attachment.hash.bytes()) { continue; }
+                if let Some((_, _, hashes)) = usage.get_mut(user) {
+                    hashes.insert(attachment.hash.bytes());
+                }
+            }
+
+            usage.into_iter().map(|(user, (items_count, items_bytes, hashes))| {
+                let attachments_bytes: u64 = hashes.iter().map(|hash| db.store[*hash].len() as u64).sum();
+                UsageByUserRow{
+                    user_id: user.clone(),
+                    display_name: db.display_name(user),
+                    server_user: db.server_users.contains_key(user),
+                    known_user: known.contains(user),
+                    attachments_count: hashes.len() as u64,
+                    attachments_bytes,
+                    items_count,
+                    items_bytes,
+                    total_bytes: items_bytes + attachments_bytes,
+                }
+            }).collect()
+        };
+
+        rows.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
+        send_rows(rows, callback)
+    }
+
+    fn prune(&self, opts: PruneOpts) -> Result<PruneResult, Error> {
+        let mut db = self.lock()?;
+        let known = db.known_users();
+        let shared = db.shared_items();
+
+        let unknown_items: Vec<(UserID, Signature)> = if opts.items {
+            db.items.keys().filter(|key| !known.contains(&key.0) && !shared.contains(*key)).cloned().collect()
+        } else {
+            vec![]
+        };
+
+        // If we're also deleting items from unknown users, their attachments become unused too:
+        let used = db.used_hashes(&unknown_items);
+        let unused_hashes: Vec<Vec<u8>> = if opts.attachments {
+            db.store.keys().filter(|hash| !used.contains(*hash)).cloned().collect()
+        } else {
+            vec![]
+        };
+
+        let blocked_items: Vec<(UserID, Signature)> = if opts.blocked {
+            db.items.keys()
+                .filter(|(user, signature)| db.is_blocked(&Block::Item(user.clone(), signature.clone())))
+                .cloned()
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let mut blocked_hashes: Vec<Vec<u8>> = vec![];
+        if opts.blocked {
+            for hash in db.store.keys() {
+                if db.is_blocked(&Block::Attachment(SHA512::from_hash_bytes(hash)?)) {
+                    blocked_hashes.push(hash.clone());
+                }
+            }
+        }
+
+        let result = PruneResult{
+            dry_run: opts.dry_run,
+            items_count: unknown_items.len() as u64,
+            items_bytes: unknown_items.iter().map(|key| {
+                let row = &db.items[key].row;
+                (row.item_bytes.len() + row.user.bytes().len() + row.signature.bytes().len()) as u64
+            }).sum(),
+            attachments_count: unused_hashes.len() as u64,
+            attachments_bytes: unused_hashes.iter().map(|hash| db.store[hash].len() as u64).sum(),
+            blocked_items_count: blocked_items.len() as u64,
+            blocked_items_bytes: blocked_items.iter().map(|key| {
+                let row = &db.items[key].row;
+                (row.item_bytes.len() + row.user.bytes().len() + row.signature.bytes().len()) as u64
+            }).sum(),
+            blocked_attachments_count: blocked_hashes.len() as u64,
+            blocked_attachments_bytes: blocked_hashes.iter().map(|hash| db.store[hash].len() as u64).sum(),
+        };
+
+        if opts.dry_run {
+            return Ok(result);
+        }
+
+        for key in &blocked_items {
+            db.items.remove(key);
+        }
+        // Profiles (and so, their follows) that were blocked, or were from blocked users:
+        let blocked_profiles: Vec<UserID> = db.profiles.iter()
+            .filter(|(user, row)| db.is_blocked(&Block::Item((*user).clone(), row.signature.clone())))
+            .map(|(user, _)| user.clone())
+            .collect();
+        for user in &blocked_profiles {
+            db.profiles.remove(user);
+        }
+        for hash in &blocked_hashes {
+            db.store.remove(hash);
+        }
+
+        for key in &unknown_items {
+            db.items.remove(key);
+        }
+        let items = std::mem::take(&mut db.items);
+        db.attachments.retain(|(user, signature, _), _| items.contains_key(&(user.clone(), signature.clone())));
+        db.items = items;
+
+        for hash in &unused_hashes {
+            db.store.remove(hash);
+        }
+
+        Ok(result)
+    }
+
+    fn migrate_attachments(&self) -> Result<MigrateResult, Error> {
+        bail!("The in-memory backend keeps attachments in memory, and can't use an attachment store.")
+    }
+
+    fn all_items<'a>(&self, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        let mut rows: Vec<ItemRow> = self.lock()?.items.values().map(|it| it.row.clone()).collect();
+        rows.sort_by(|a, b| (a.user.bytes(), a.signature.bytes()).cmp(&(b.user.bytes(), b.signature.bytes())));
+        send_rows(rows, callback)
+    }
+
+    fn all_user_items<'a>(&self, user: &UserID, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        let mut rows: Vec<ItemRow> = self.lock()?.items.values()
+            .filter(|it| &it.row.user == user)
+            .map(|it| it.row.clone())
+            .collect();
+        rows.sort_by(|a, b| (a.timestamp.unix_utc_ms, a.signature.bytes()).cmp(&(b.timestamp.unix_utc_ms, b.signature.bytes())));
+        send_rows(rows, callback)
+    }
+
+    fn verify_attachments<'a>(&self, callback: RowCallback<'a, BadAttachment>) -> Result<u64, Error> {
+        let (checked, bad) = {
+            let db = self.lock()?;
+            let mut bad = vec![];
+            for (hash_bytes, contents) in &db.store {
+                let hash = match SHA512::from_hash_bytes(hash_bytes) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        bad.push(BadAttachment{hash: hash_bytes.clone(), problem: format!("Malformed hash: {}", err)});
+                        continue;
+                    },
+                };
+                // The store keeps no separate size, so only the hash can be wrong:
+                let found = SHA512::from_file_with_size(&mut std::io::Cursor::new(contents))?;
+                if let Some(it) = BadAttachment::check(&hash, contents.len() as u64, Some(found)) {
+                    bad.push(it);
+                }
+            }
+            (db.store.len() as u64, bad)
+        };
+        send_rows(bad, callback)?;
+        Ok(checked)
+    }
+
+    fn verify_items<'a>(&self, callback: RowCallback<'a, BadItem>) -> Result<u64, Error> {
+        let (checked, mut bad) = {
+            let db = self.lock()?;
+            let bad: Vec<BadItem> = db.items.values()
+                .filter_map(|it| {
+                    let row = &it.row;
+                    BadItem::check(
+                        row.user.bytes().to_vec(),
+                        row.signature.bytes().to_vec(),
+                        row.timestamp.unix_utc_ms,
+                        row.received.unix_utc_ms,
+                        row.item_bytes.clone(),
+                    )
+                })
+                .collect();
+            (db.items.len() as u64, bad)
+        };
+        bad.sort_by(|a, b| (&a.user_id, &a.signature).cmp(&(&b.user_id, &b.signature)));
+        send_rows(bad, callback)?;
+        Ok(checked)
+    }
+
+    fn add_quarantine(&self, target: &Quarantine, problem: &str) -> Result<(), Error> {
+        let mut db = self.lock()?;
+        match db.quarantine.iter_mut().find(|(quarantined, _)| quarantined == target) {
+            Some((_, old)) => *old = problem.to_string(),
+            None => db.quarantine.push((target.clone(), problem.to_string())),
+        }
+        Ok(())
+    }
+
+    fn add_block(&self, block: &Block, notes: &str) -> Result<(), Error> {
+        let mut db = self.lock()?;
+        match db.blocks.iter_mut().find(|row| &row.block == block) {
+            Some(row) => row.notes = notes.to_string(),
+            None => db.blocks.push(BlockRow{
+                block: block.clone(),
+                notes: notes.to_string(),
+                created: Timestamp::now(),
+            }),
+        }
+        Ok(())
+    }
+
+    fn remove_block(&self, block: &Block) -> Result<bool, Error> {
+        let mut db = self.lock()?;
+        let count = db.blocks.len();
+        db.blocks.retain(|row| &row.block != block);
+        Ok(db.blocks.len() < count)
+    }
+
+    fn blocks<'a>(&self, callback: RowCallback<'a, BlockRow>) -> Result<(), Error> {
+        let blocks = self.lock()?.blocks.clone();
+        send_rows(blocks, callback)
+    }
+
+    fn is_blocked(&self, block: &Block) -> Result<bool, Error> {
+        Ok(self.lock()?.is_blocked(block))
+    }
+}
+
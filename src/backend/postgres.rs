@@ -0,0 +1,3170 @@
+//! The postgres backend stores all data (including BLOBs) in a PostgreSQL
+//! database. It mirrors the sqlite backend's schema and queries, but allows
+//! multiple servers/processes to share the same database, and scales past
+//! what a single SQLite file is comfortable with.
+//!
+//! Like the sqlite backend, attachment contents may instead be kept in an
+//! [`AttachmentStore`], leaving NULL `contents` in the `store` table.
+//!
+//! The database itself must already exist. `feoblog db init --postgres <url>`
+//! will create the tables inside of it.
+
+mod upgraders;
+
+use std::{cell::{Cell, RefCell, RefMut}, collections::HashMap, io::Read, sync::Arc};
+
+use crate::{backend::UsageByUserRow, markdown::{ToHTML, find_tags}, protos::{Item, Post}, util::AsHex};
+use actix_web::web::Bytes;
+use backend::{BadAttachment, BadItem, FileMeta, MigrateResult, RowCallback, SHA512, attachments::AttachmentStore};
+use log::debug;
+use protobuf::Message;
+use postgres::{GenericClient, NoTls, Row, Transaction, types::ToSql};
+use r2d2_postgres::PostgresConnectionManager;
+use crate::backend::{self, Block, BlockRow, UserID, Signature, ItemRow, ItemDisplayRow, Quarantine, Quota, ReactionCount, SearchScope, ServerUserQuotas, ThreadRow, Timestamp, ServerUser, QuotaDenyReason};
+
+use anyhow::{Error, bail, Context};
+
+use super::{FileStream, PruneResult, TimeSpan};
+
+const CURRENT_VERSION: u32 = 7;
+
+/// Don't follow reply chains any deeper than this in thread_items().
+const MAX_THREAD_DEPTH: i32 = 100;
+
+/// Leaves blocked and quarantined items out of listings. See: sqlite::NOT_HIDDEN
+const NOT_HIDDEN: &str = "
+    NOT EXISTS(SELECT 1 FROM blocked_user AS bu WHERE bu.user_id = i.user_id)
+    AND NOT EXISTS(
+        SELECT 1 FROM blocked_item AS bi
+        WHERE bi.user_id = i.user_id AND bi.signature = i.signature
+    )
+    AND NOT EXISTS(
+        SELECT 1 FROM quarantined_item AS qi
+        WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+    )
+";
+
+/// How many rows to fetch at a time when iterating through large result sets.
+const FETCH_SIZE: i32 = 100;
+
+/// How many bytes of an attachment to read at a time in get_contents().
+const CHUNK_SIZE: i32 = 256 * 1024;
+
+type Pool = r2d2::Pool<PostgresConnectionManager<NoTls>>;
+type PConn = r2d2::PooledConnection<PostgresConnectionManager<NoTls>>;
+
+pub(crate) struct FactoryBuilder {
+    /// A connection string/URL. ex: postgresql://feoblog@localhost/feoblog
+    url: String,
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+impl FactoryBuilder {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            attachment_store: None,
+        }
+    }
+
+    /// Save new attachment contents in `store` instead of in the database.
+    pub fn with_attachment_store(mut self, store: Arc<dyn AttachmentStore>) -> Self {
+        self.attachment_store = Some(store);
+        self
+    }
+}
+
+impl backend::FactoryBuilder for FactoryBuilder {
+    fn factory(&self) -> Result<Box<dyn backend::Factory>, Error> {
+        if !self.db_exists()? {
+            bail!("\
+                    Error: Database tables not found.\n\
+                    You may need to run `feoblog db init` to create them.\
+            ");
+        }
+
+        if self.db_needs_upgrade()? {
+            bail!("\
+                Error: Database needs an upgrade.\n\
+                Run `feoblog db upgrade` to upgrade it.
+            ");
+        }
+
+        Ok(Box::new(Factory{
+            pool: self.pool()?,
+            attachment_store: self.attachment_store.clone(),
+        }))
+    }
+
+    fn db_exists(&self) -> Result<bool, Error> {
+        // Note: We don't create the database itself, just the tables in it.
+        let conn = self.connection()?;
+        conn.has_version_table()
+    }
+
+    fn db_needs_upgrade(&self) -> Result<bool, Error> {
+        let conn = self.connection()?;
+        let db_version = conn.get_version()?;
+        Ok(db_version < CURRENT_VERSION)
+    }
+
+    fn db_upgrade(&self) -> Result<(), Error> {
+        if !self.db_exists()? {
+            bail!("No feoblog tables found in the database.")
+        }
+
+        let upgraders = upgraders::Upgraders::new();
+        let conn = self.connection()?;
+        upgraders.upgrade(&conn)?;
+
+        Ok(())
+    }
+
+    fn db_create(&self) -> Result<(), Error> {
+        if self.db_exists()? {
+            bail!("Database already exists")
+        }
+
+        println!("Creating database tables.");
+        let conn = self.connection()?;
+        conn.initialize()?;
+        println!("Database created.");
+
+        drop(conn);
+        if self.db_needs_upgrade()? {
+            self.db_upgrade()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FactoryBuilder {
+    // Open a connection to a DB that may be in a bad state. See: sqlite::FactoryBuilder::connection().
+    fn connection(&self) -> Result<Connection, Error> {
+        let pool = self.pool()?;
+        Ok(
+            Connection {
+                conn: RefCell::new(pool.get()?),
+                pool,
+                cursors: Cell::new(0),
+                attachment_store: self.attachment_store.clone(),
+            }
+        )
+    }
+
+    fn pool(&self) -> Result<Pool, Error> {
+        let config: postgres::Config = self.url.parse().context("Parsing the PostgreSQL connection URL")?;
+        let manager = PostgresConnectionManager::new(config, NoTls);
+        // Each open Connection holds one connection from the pool. Attachment downloads
+        // borrow one more until they finish. Everything else reuses the Connection's own.
+        let pool = r2d2::Pool::builder()
+            .min_idle(Some(0)) // defaults to max_size. (Which defaults to 10.)
+            .build(manager)?;
+        Ok(pool)
+    }
+}
+
+pub(crate) struct Factory
+{
+    pool: Pool,
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+impl backend::Factory for Factory
+{
+    fn open(&self) -> Result<Box<dyn backend::Backend>, Error>
+    {
+        let conn = Connection{
+            conn: RefCell::new(self.pool.get()?),
+            pool: self.pool.clone(),
+            cursors: Cell::new(0),
+            attachment_store: self.attachment_store.clone(),
+        };
+        Ok(Box::new(conn))
+    }
+
+    fn dyn_clone(&self) -> Box<dyn backend::Factory> {
+        let new_factory = Factory {
+            pool: self.pool.clone(),
+            attachment_store: self.attachment_store.clone(),
+        };
+        Box::new(new_factory)
+    }
+}
+
+
+pub(crate) struct Connection
+{
+    // postgres::Client requires &mut for all queries, but Backend mostly takes &self:
+    conn: RefCell<PConn>,
+
+    // Attachment contents are streamed on their own connection from the pool, since the
+    // stream outlives this Connection.
+    pool: Pool,
+
+    // How many each_row() cursors are open on `conn`.
+    cursors: Cell<u32>,
+
+    /// Where to save attachment contents, if not in the database.
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+/// private methods for Connection
+impl Connection
+{
+    fn client(&self) -> RefMut<'_, postgres::Client> {
+        RefMut::map(self.conn.borrow_mut(), |conn| &mut **conn)
+    }
+
+    /// Iterate through a (possibly large) result set, FETCH_SIZE rows at a time,
+    /// while `callback` returns Ok(true).
+    ///
+    /// Rows are read from a cursor on our own connection, which is only borrowed while fetching.
+    /// This leaves it free for per-row lookups, and for callbacks which make their own queries.
+    /// (Even nested calls to each_row().) But since the cursors live in a read-only transaction,
+    /// callbacks can't write. Collect changes and make them afterward, as `db verify` does.
+    fn each_row(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        callback: &mut dyn FnMut(&Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let depth = self.cursors.get();
+        if depth == 0 {
+            self.client().batch_execute("BEGIN READ ONLY")?;
+        }
+
+        let cursor = format!("rows_{}", depth);
+        self.cursors.set(depth + 1);
+        let result = self.fetch_rows(&cursor, query, params, callback);
+        self.cursors.set(depth);
+
+        // There's nothing to commit. Ending the transaction also closes its cursors.
+        let closed = if depth == 0 {
+            self.client().batch_execute("ROLLBACK")
+        } else {
+            self.client().batch_execute(&format!("CLOSE {}", cursor))
+        };
+
+        result?;
+        closed?;
+        Ok(())
+    }
+
+    fn fetch_rows(
+        &self,
+        cursor: &str,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+        callback: &mut dyn FnMut(&Row) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let declare = format!("DECLARE {} NO SCROLL CURSOR FOR {}", cursor, query);
+        self.client().execute(declare.as_str(), params)?;
+
+        let fetch = format!("FETCH {} FROM {}", FETCH_SIZE, cursor);
+        loop {
+            let rows = self.client().query(fetch.as_str(), &[])?;
+            for row in &rows {
+                if !callback(row)? {
+                    return Ok(());
+                }
+            }
+            if rows.len() < FETCH_SIZE as usize {
+                return Ok(());
+            }
+        }
+    }
+
+    fn initialize(&self) -> Result<(), Error>
+    {
+        // Unlike SQLite, we can just create the current schema in one go.
+        // Schema changes after version 1 belong in upgraders.rs.
+        let mut client = self.client();
+        let mut tx = client.transaction()?;
+
+        tx.batch_execute("
+            CREATE TABLE version (
+                -- The current version of the database schema.
+                version INTEGER NOT NULL
+            );
+            INSERT INTO version VALUES(1);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE item(
+                -- An Item is the core data structure of FeoBlog.
+                -- It is a BLOB of protobuf v3 bytes defining an item in a
+                -- user's collection of items
+                bytes BYTEA NOT NULL
+
+                -- An item must be accompanied by a nacl public key (user_id)
+                -- and (detached) signature so that its authenticity can be
+                -- verified.
+                , user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+
+                -- A copy of the signed timestamp from within `bytes`
+                -- this allows for sorting queries by timestamp.
+                , unix_utc_ms BIGINT NOT NULL
+
+                -- The date this item was received by this server. May differ
+                -- from above.
+                , received_utc_ms BIGINT NOT NULL
+
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE INDEX item_user_chrono_idx ON item(user_id, unix_utc_ms);
+            CREATE INDEX item_user_chrono_received_idx ON item(user_id, received_utc_ms);
+            CREATE INDEX item_unix_utc_idx ON item(unix_utc_ms);
+            CREATE INDEX item_received_utc_idx ON item(received_utc_ms);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE server_user(
+                -- These users have been granted direct access to the server.
+                user_id BYTEA PRIMARY KEY
+
+                -- Information about this user.
+                -- Not displayed on the web UI, just here to let the server
+                -- admin leave a human-readable note about who this user is.
+                , notes TEXT NOT NULL DEFAULT ''
+
+                -- should this user's posts appear on the home page of this server?
+                , on_homepage BOOLEAN NOT NULL DEFAULT FALSE
+
+                -- How many bytes will the server cache for this user?
+                -- 0 = unlimited.
+                , max_bytes BIGINT
+
+                -- A copy of profile.revoked, so that we can efficiently
+                -- exclude revoked server users (& their follows) from queries.
+                , revoked BOOLEAN NOT NULL DEFAULT FALSE
+            );
+            CREATE INDEX server_user_homepage_idx ON server_user(on_homepage, user_id);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE follow(
+                -- Lists which users follow which other users.
+                -- Always represents the latest Profile saved by a user.
+                source_user_id BYTEA NOT NULL
+                , followed_user_id BYTEA NOT NULL
+                , display_name TEXT
+                , PRIMARY KEY (source_user_id, followed_user_id)
+            );
+            CREATE INDEX follow_followed_idx ON follow(followed_user_id);
+
+            CREATE TABLE profile(
+                -- Always contains a reference to the latest profile uploaded by a user
+                user_id BYTEA PRIMARY KEY
+                , signature BYTEA NOT NULL
+                , display_name TEXT
+
+                -- Has this Profile revoked this user ID?
+                , revoked BOOLEAN NOT NULL DEFAULT FALSE
+
+                -- The userID that this user has moved to, if any.
+                -- The counter-signature is verified before the Profile is saved.
+                , successor_user_id BYTEA
+            );
+            CREATE INDEX profile_successor_idx ON profile(successor_user_id);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE reply (
+                -- Tracks Items that 'reply to' other items.
+                from_user_id BYTEA NOT NULL
+                , from_signature BYTEA NOT NULL
+
+                , to_user_id BYTEA NOT NULL
+                , to_signature BYTEA NOT NULL
+            );
+            CREATE INDEX reply_to_idx ON reply(to_user_id, to_signature);
+            CREATE INDEX reply_from_idx ON reply(from_user_id, from_signature);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE item_attachment(
+                -- maps items to their attached files.
+                user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+
+                -- The name of the file
+                , name TEXT NOT NULL
+
+                -- The size of the attachment (in bytes)
+                -- (allows us to calculate quotas even if files haven't been attached yet)
+                , size BIGINT NOT NULL
+
+                -- the 64-byte sha-512 hash of the file (as bytes).
+                -- used to look up the file contents in the 'store' table.
+                , hash BYTEA NOT NULL
+
+                -- Optional metadata from the File:
+                , mime_type TEXT
+                , width INTEGER
+                , height INTEGER
+                , alt_text TEXT
+
+                , PRIMARY KEY (user_id, signature, name)
+            );
+            CREATE INDEX item_attachment_hash_idx ON item_attachment(hash);
+
+            CREATE TABLE store(
+                -- a content-addressable blob store.
+
+                -- The 64-byte sha-512 hash of the blob's contents.
+                hash BYTEA PRIMARY KEY
+
+                , contents BYTEA NOT NULL
+            );
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE deleted_item(
+                -- Items which have been deleted by a (signed) Delete item.
+                -- We keep these so that we don't accept the deleted item again
+                -- (ex: when syncing from another server.)
+                user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+
+                -- The signature of the Delete item that deleted this item.
+                , deleted_by BYTEA NOT NULL
+
+                , PRIMARY KEY (user_id, signature)
+            );
+
+            CREATE TABLE item_revision(
+                -- Maps Revision items to the original Post that they revise.
+                user_id BYTEA NOT NULL
+                -- The signature of the Revision item:
+                , signature BYTEA NOT NULL
+                -- The signature of the original Post:
+                , original_signature BYTEA NOT NULL
+
+                -- A copy of the Revision's timestamp, so that we can find the latest one.
+                , unix_utc_ms BIGINT NOT NULL
+
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE INDEX item_revision_original_idx ON item_revision(user_id, original_signature, unix_utc_ms);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE reaction(
+                -- Tracks Reaction items, and the items they react to.
+                from_user_id BYTEA NOT NULL
+                , from_signature BYTEA NOT NULL
+
+                , to_user_id BYTEA NOT NULL
+                , to_signature BYTEA NOT NULL
+
+                -- The (short) reaction string. ex: an emoji.
+                , reaction TEXT NOT NULL
+
+                , PRIMARY KEY (from_user_id, from_signature)
+            );
+            CREATE INDEX reaction_to_idx ON reaction(to_user_id, to_signature, reaction);
+
+            CREATE TABLE share(
+                -- Tracks Share items, and the items they share.
+                from_user_id BYTEA NOT NULL
+                , from_signature BYTEA NOT NULL
+
+                , to_user_id BYTEA NOT NULL
+                , to_signature BYTEA NOT NULL
+
+                , PRIMARY KEY (from_user_id, from_signature)
+            );
+            CREATE INDEX share_to_idx ON share(to_user_id, to_signature);
+
+            CREATE TABLE direct_message(
+                -- Tracks DirectMessage items, so that we can list them in the
+                -- recipient's inbox, and exclude them from other item lists.
+                user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+
+                , recipient_user_id BYTEA NOT NULL
+
+                -- A copy of the item's timestamp, for ordering the inbox.
+                , unix_utc_ms BIGINT NOT NULL
+
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE INDEX direct_message_recipient_idx ON direct_message(recipient_user_id, unix_utc_ms);
+        ")?;
+
+        tx.batch_execute("
+            CREATE TABLE item_tag(
+                -- Maps #tags to the Posts that contain them.
+                -- If a Post has been revised, contains tags for the latest Revision.
+                user_id BYTEA NOT NULL
+                -- The signature of the (original) Post:
+                , signature BYTEA NOT NULL
+
+                -- lowercase, without the leading '#'.
+                , tag TEXT NOT NULL
+
+                , PRIMARY KEY (user_id, signature, tag)
+            );
+            CREATE INDEX item_tag_tag_idx ON item_tag(tag);
+
+            CREATE TABLE mention(
+                -- Maps @mentioned users to the Posts & Comments that mention them.
+                -- If a Post has been revised, contains mentions for the latest Revision.
+                user_id BYTEA NOT NULL
+                -- The signature of the Comment, or (original) Post:
+                , signature BYTEA NOT NULL
+
+                , mentioned_user_id BYTEA NOT NULL
+
+                , PRIMARY KEY (user_id, signature, mentioned_user_id)
+            );
+            CREATE INDEX mention_mentioned_user_idx ON mention(mentioned_user_id);
+        ")?;
+
+        // See the sqlite upgraders for notes on this view.
+        tx.batch_execute("
+            CREATE VIEW known_users (user_id) AS
+            -- For internal use only. All 'known users' of the server.
+                SELECT user_id
+                FROM server_user
+            UNION ALL
+                SELECT followed_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            UNION ALL
+                -- Followers of an old ID also follow its successor:
+                SELECT p.successor_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+                INNER JOIN profile AS p
+                    ON (f.followed_user_id=p.user_id)
+                WHERE p.successor_user_id IS NOT NULL
+            UNION ALL
+                -- Authors of items shared by server users:
+                SELECT sh.to_user_id
+                FROM share AS sh
+                INNER JOIN server_user AS s
+                    ON (sh.from_user_id=s.user_id)
+            UNION ALL
+                -- ... and by users they follow:
+                SELECT sh.to_user_id
+                FROM share AS sh
+                INNER JOIN follow AS f
+                    ON (sh.from_user_id=f.followed_user_id)
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            ;
+        ")?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn run(&self, sql: &str) -> Result<(), Error>
+    {
+        self.client().batch_execute(sql)?;
+        Ok(())
+    }
+
+    /// The store for attachment contents that aren't kept in the database.
+    fn attachment_store(&self) -> Result<&Arc<dyn AttachmentStore>, Error> {
+        match &self.attachment_store {
+            Some(store) => Ok(store),
+            None => bail!("Some attachments are stored outside of the database. Use --attachments-dir or --attachments-s3-bucket to find them."),
+        }
+    }
+
+    fn has_version_table(&self) -> Result<bool, Error>
+    {
+        let row = self.client().query_one("
+            SELECT EXISTS(
+                SELECT 1
+                FROM information_schema.tables
+                WHERE table_schema = current_schema()
+                AND table_name = 'version'
+            )
+        ", &[])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    fn get_version(&self) -> Result<u32, Error>
+    {
+        if !self.has_version_table()? {
+            bail!("No version table found. This may not be a valid feoblog database.")
+        }
+
+        let rows = self.client().query("SELECT version FROM version LIMIT 2", &[])?;
+
+        if rows.len() == 0 {
+            bail!("Found no version in the database. This may not be a valid feoblog database.");
+        }
+        if rows.len() > 1 {
+            bail!("Found more than one version in the database. This database may have been corrupted.");
+        }
+
+        let version: i32 = rows[0].try_get(0)?;
+        Ok(version as u32)
+    }
+
+    fn set_version(&self, version: u32) -> Result<(), Error> {
+        self.client().execute("UPDATE version SET version = $1", &[&(version as i32)])?;
+
+        Ok(())
+    }
+}
+
+
+/// Read the common (user_id, signature, unix_utc_ms, received_utc_ms, bytes) columns into an ItemRow.
+fn to_item_row(row: &Row) -> Result<ItemRow, Error> {
+    Ok(ItemRow{
+        user: UserID::from_vec(row.try_get(0)?)?,
+        signature: Signature::from_vec(row.try_get(1)?)?,
+        timestamp: Timestamp{ unix_utc_ms: row.try_get(2)? },
+        received: Timestamp{ unix_utc_ms: row.try_get(3)? },
+        item_bytes: row.try_get(4)?,
+    })
+}
+
+/// The SQL to filter & order items by a TimeSpan. See: sqlite::TimeSpanSql
+struct TimeSpanSql {
+    order: &'static str,
+    start: Option<CursorSql>,
+    end: Option<CursorSql>,
+}
+
+/// Params for one end of a TimeSpan.
+struct CursorSql {
+    timestamp: i64,
+    user_id: Option<Vec<u8>>,
+    signature: Option<Vec<u8>>,
+}
+
+impl CursorSql {
+    fn new(cursor: &backend::Cursor) -> Self {
+        let (user, signature) = match &cursor.item {
+            None => (None, None),
+            Some((user, signature)) => (Some(user.bytes().to_vec()), Some(signature.bytes().to_vec())),
+        };
+        Self {
+            timestamp: cursor.timestamp.unix_utc_ms,
+            user_id: user,
+            signature,
+        }
+    }
+}
+
+impl TimeSpanSql {
+    fn new(time_span: &TimeSpan) -> Self {
+        Self {
+            order: if time_span.is_before() { "DESC" } else { "ASC" },
+            start: time_span.start().map(CursorSql::new),
+            end: time_span.end().map(CursorSql::new),
+        }
+    }
+
+    /// Filter the item table (with alias `table`, ex: "i.") by the TimeSpan.
+    /// Uses 3 params per bound, starting at `$first_param`. (see: params())
+    fn filter(&self, table: &str, first_param: usize) -> String {
+        let mut bounds = vec![];
+        let mut param = first_param;
+        if self.start.is_some() {
+            bounds.push(Self::bound(table, ">", param));
+            param += 3;
+        }
+        if self.end.is_some() {
+            bounds.push(Self::bound(table, "<", param));
+        }
+        bounds.join(" AND ")
+    }
+
+    fn bound(table: &str, op: &str, first_param: usize) -> String {
+        // If the cursor has no item, the NULL comparison excludes all items at its timestamp.
+        format!(
+            "(
+                {t}unix_utc_ms {op} ${ts}
+                OR ({t}unix_utc_ms = ${ts} AND ({t}user_id, {t}signature) {op} (${user}, ${sig}))
+            )",
+            t=table,
+            op=op,
+            ts=first_param,
+            user=first_param + 1,
+            sig=first_param + 2,
+        )
+    }
+
+    fn order(&self, table: &str) -> String {
+        format!("{t}unix_utc_ms {o}, {t}user_id {o}, {t}signature {o}", t=table, o=self.order)
+    }
+
+    /// The query's other params, followed by those used by filter().
+    fn params<'a>(&'a self, others: &[&'a (dyn ToSql + Sync)]) -> Vec<&'a (dyn ToSql + Sync)> {
+        let mut params = others.to_vec();
+        for bound in self.start.iter().chain(self.end.iter()) {
+            params.push(&bound.timestamp);
+            params.push(&bound.user_id);
+            params.push(&bound.signature);
+        }
+        params
+    }
+}
+
+/// We're saving a profile. If it's new, update the profile and follow tables.
+fn update_profile(conn: &mut Transaction<'_>, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
+
+    let previous = conn.query_opt("
+        SELECT i.unix_utc_ms, p.revoked
+        FROM profile AS p
+        INNER JOIN item AS i USING (user_id, signature)
+        WHERE user_id = $1
+    ", &[&item_row.user.bytes()])?;
+
+    let revoking = item.get_profile().has_revocation();
+
+    if let Some(previous) = previous {
+        let prev_timestamp: i64 = previous.try_get(0)?;
+        let prev_revoked: bool = previous.try_get(1)?;
+
+        // Revocations are permanent:
+        if prev_revoked {
+            return Ok(())
+        }
+
+        // Never replace a newer profile's metadata, unless we're revoking:
+        if prev_timestamp >= item.timestamp_ms_utc && !revoking {
+            return Ok(())
+        }
+    }
+
+    // Replace all follows with new ones listed in the profile:
+    conn.execute("DELETE FROM follow WHERE source_user_id = $1", &[&item_row.user.bytes()])?;
+
+    // Behavior is undefined if duplicate follows exist in a Profile. So we just replace:
+    let add_follow = conn.prepare("
+        INSERT INTO follow (source_user_id, followed_user_id, display_name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (source_user_id, followed_user_id)
+        DO UPDATE SET display_name = EXCLUDED.display_name
+    ")?;
+
+    for follow in item.get_profile().get_follows() {
+        conn.execute(&add_follow, &[
+            &item_row.user.bytes(),
+            &follow.get_user().get_bytes(),
+            &follow.get_display_name(),
+        ])?;
+    }
+
+    // Note: The successor's counter-signature should've been verified before we got here.
+    let profile = item.get_profile();
+    let successor: Option<&[u8]> = if profile.has_successor() {
+        Some(profile.get_successor().get_user_id().get_bytes())
+    } else {
+        None
+    };
+
+    conn.execute("
+        INSERT INTO profile(user_id, signature, display_name, revoked, successor_user_id)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id) DO UPDATE SET
+            signature = EXCLUDED.signature
+            , display_name = EXCLUDED.display_name
+            , revoked = EXCLUDED.revoked
+            , successor_user_id = EXCLUDED.successor_user_id
+    ", &[
+        &item_row.user.bytes(),
+        &item_row.signature.bytes(),
+        &profile.get_display_name(),
+        &revoking,
+        &successor,
+    ])?;
+
+    if revoking {
+        conn.execute(
+            "UPDATE server_user SET revoked = TRUE WHERE user_id = $1",
+            &[&item_row.user.bytes()]
+        )?;
+    }
+
+    Ok(())
+}
+
+fn save_comment_reply(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let reply_to = item.get_comment().get_reply_to();
+    let to_user_id = UserID::from_vec(reply_to.get_user_id().get_bytes().into())?;
+    let to_signature = Signature::from_vec(reply_to.get_signature().get_bytes().into())?;
+
+    conn.execute("
+        INSERT INTO reply (from_user_id, from_signature, to_user_id, to_signature)
+        VALUES ($1, $2, $3, $4)
+    ", &[
+        &row.user.bytes(),
+        &row.signature.bytes(),
+        &to_user_id.bytes(),
+        &to_signature.bytes(),
+    ])?;
+
+    Ok(())
+}
+
+/// We're saving a Delete. Remove the item it references, and anything we've indexed for it.
+fn delete_item(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let target = Signature::from_vec(item.get_delete().get_signature().get_bytes().into())?;
+
+    // Revisions of a deleted Post are deleted along with it:
+    let mut targets = revision_signatures(conn, &row.user, &target)?;
+    targets.push(target);
+
+    for target in &targets {
+        conn.execute("
+            INSERT INTO deleted_item(user_id, signature, deleted_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, signature) DO UPDATE SET deleted_by = EXCLUDED.deleted_by
+        ", &[
+            &row.user.bytes(),
+            &target.bytes(),
+            &row.signature.bytes(),
+        ])?;
+
+        remove_item(conn, &row.user, target)?;
+    }
+
+    Ok(())
+}
+
+/// Signatures of all Revisions of a Post.
+fn revision_signatures(conn: &mut impl GenericClient, user: &UserID, original: &Signature) -> Result<Vec<Signature>, Error> {
+    let rows = conn.query("
+        SELECT signature
+        FROM item_revision
+        WHERE user_id = $1
+        AND original_signature = $2
+    ", &[&user.bytes(), &original.bytes()])?;
+
+    rows.iter().map(|row| Signature::from_vec(row.try_get(0)?)).collect()
+}
+
+/// Profiles and Deletes can not be deleted. See: sqlite::ignore_early_delete()
+fn ignore_early_delete(conn: &mut impl GenericClient, row: &ItemRow) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM deleted_item WHERE user_id = $1 AND signature = $2",
+        &[&row.user.bytes(), &row.signature.bytes()],
+    )?;
+    Ok(())
+}
+
+/// Remove an item, and everything we've indexed for it.
+fn remove_item(conn: &mut impl GenericClient, user: &UserID, signature: &Signature) -> Result<(), Error> {
+    // If this is a Revision, its Post was indexed with its contents:
+    let original = conn.query_opt(
+        "SELECT original_signature FROM item_revision WHERE user_id = $1 AND signature = $2",
+        &[&user.bytes(), &signature.bytes()],
+    )?;
+
+    let queries = [
+        "DELETE FROM item WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM item_attachment WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM reply WHERE from_user_id = $1 AND from_signature = $2",
+        "DELETE FROM item_revision WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM reaction WHERE from_user_id = $1 AND from_signature = $2",
+        "DELETE FROM share WHERE from_user_id = $1 AND from_signature = $2",
+        "DELETE FROM direct_message WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM item_tag WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM mention WHERE user_id = $1 AND signature = $2",
+        "DELETE FROM search_item WHERE user_id = $1 AND signature = $2",
+    ];
+    for query in &queries {
+        conn.execute(*query, &[&user.bytes(), &signature.bytes()])?;
+    }
+
+    if let Some(row) = original {
+        reindex_post(conn, user, &Signature::from_vec(row.try_get(0)?)?)?;
+    }
+
+    // Note: Attachment contents in `store` may be shared with other items, so
+    // we leave those for `db prune` to clean up.
+
+    Ok(())
+}
+
+/// Re-index a Post from its latest remaining Revision. See: sqlite::reindex_post()
+fn reindex_post(conn: &mut impl GenericClient, user: &UserID, original: &Signature) -> Result<(), Error> {
+    let latest = latest_revision_signature(conn, user, original)?;
+    let signature = latest.as_ref().unwrap_or(original);
+    let row = conn.query_opt(
+        "SELECT bytes FROM item WHERE user_id = $1 AND signature = $2",
+        &[&user.bytes(), &signature.bytes()],
+    )?;
+
+    let row = match row {
+        Some(row) => row,
+        // The Post hasn't arrived yet. It'll be indexed when it does.
+        None => return Ok(()),
+    };
+    let bytes: Vec<u8> = row.try_get(0)?;
+    let mut item = Item::new();
+    item.merge_from_bytes(&bytes)?;
+    let post = if item.has_revision() { item.get_revision().get_post() } else { item.get_post() };
+
+    save_item_tags(conn, user, original, &post_tags(post))?;
+    save_item_mentions(conn, user, original, &post.get_body().md_get_mentions())?;
+    save_item_text(conn, user, original, post.get_title(), post.get_body())?;
+
+    Ok(())
+}
+
+fn save_revision(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let original = Signature::from_vec(item.get_revision().get_original().get_bytes().into())?;
+
+    conn.execute("
+        INSERT INTO item_revision(user_id, signature, original_signature, unix_utc_ms)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, signature) DO UPDATE SET
+            original_signature = EXCLUDED.original_signature
+            , unix_utc_ms = EXCLUDED.unix_utc_ms
+    ", &[
+        &row.user.bytes(),
+        &row.signature.bytes(),
+        &original.bytes(),
+        &row.timestamp.unix_utc_ms,
+    ])?;
+
+    Ok(())
+}
+
+/// Find the signature of the newest Revision of a Post, if it has been revised.
+fn latest_revision_signature(conn: &mut impl GenericClient, user: &UserID, original: &Signature) -> Result<Option<Signature>, Error> {
+    let row = conn.query_opt("
+        SELECT signature
+        FROM item_revision
+        WHERE user_id = $1
+        AND original_signature = $2
+        ORDER BY unix_utc_ms DESC, signature DESC
+        LIMIT 1
+    ", &[&user.bytes(), &original.bytes()])?;
+
+    match row {
+        None => Ok(None),
+        Some(row) => Ok(Some(Signature::from_vec(row.try_get(0)?)?)),
+    }
+}
+
+/// Find the Post contents to index for a Post, or a Revision of a Post.
+/// See: sqlite::get_indexed_post()
+fn get_indexed_post<'i>(conn: &mut impl GenericClient, row: &ItemRow, item: &'i Item) -> Result<Option<(Signature, &'i Post)>, Error> {
+    if item.has_post() {
+        if latest_revision_signature(conn, &row.user, &row.signature)?.is_some() {
+            // The latest Revision's contents win.
+            return Ok(None);
+        }
+        return Ok(Some((row.signature.clone(), item.get_post())));
+    }
+
+    if item.has_revision() {
+        let original = Signature::from_vec(item.get_revision().get_original().get_bytes().into())?;
+        if latest_revision_signature(conn, &row.user, &original)?.as_ref() != Some(&row.signature) {
+            // We've already indexed a newer Revision.
+            return Ok(None);
+        }
+        return Ok(Some((original, item.get_revision().get_post())));
+    }
+
+    Ok(None)
+}
+
+/// Find the #tags to index for a Post, or a Revision of a Post.
+fn get_item_tags(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<Option<(Signature, Vec<String>)>, Error> {
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post_tags(post))))
+}
+
+fn post_tags(post: &Post) -> Vec<String> {
+    let mut tags = find_tags(post.get_title());
+    for tag in post.get_body().md_get_tags() {
+        if !tags.contains(&tag) { tags.push(tag); }
+    }
+    tags
+}
+
+/// Replace the tags indexed for an item.
+fn save_item_tags(conn: &mut impl GenericClient, user: &UserID, signature: &Signature, tags: &[String]) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM item_tag WHERE user_id = $1 AND signature = $2",
+        &[&user.bytes(), &signature.bytes()],
+    )?;
+
+    let stmt = conn.prepare("
+        INSERT INTO item_tag(user_id, signature, tag)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+    ")?;
+    for tag in tags {
+        conn.execute(&stmt, &[&user.bytes(), &signature.bytes(), tag])?;
+    }
+
+    Ok(())
+}
+
+/// Find the @mentions to index for a Post, Revision, or Comment.
+fn get_item_mentions(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<Option<(Signature, Vec<UserID>)>, Error> {
+    if item.has_comment() {
+        return Ok(Some((row.signature.clone(), item.get_comment().get_text().md_get_mentions())));
+    }
+
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post.get_body().md_get_mentions())))
+}
+
+/// Replace the mentions indexed for an item.
+fn save_item_mentions(conn: &mut impl GenericClient, user: &UserID, signature: &Signature, mentions: &[UserID]) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM mention WHERE user_id = $1 AND signature = $2",
+        &[&user.bytes(), &signature.bytes()],
+    )?;
+
+    let stmt = conn.prepare("
+        INSERT INTO mention(user_id, signature, mentioned_user_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+    ")?;
+    for mentioned in mentions {
+        conn.execute(&stmt, &[&user.bytes(), &signature.bytes(), &mentioned.bytes()])?;
+    }
+
+    Ok(())
+}
+
+/// Find the (title, body) text to index for searching a Post, Revision, or Comment.
+fn get_item_text(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<Option<(Signature, String, String)>, Error> {
+    if item.has_comment() {
+        return Ok(Some((row.signature.clone(), String::new(), item.get_comment().get_text().to_string())));
+    }
+
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post.get_title().to_string(), post.get_body().to_string())))
+}
+
+/// Replace the text indexed for an item.
+fn save_item_text(conn: &mut impl GenericClient, user: &UserID, signature: &Signature, title: &str, body: &str) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO search_item(user_id, signature, search_text)
+        VALUES ($1, $2, to_tsvector('simple', $3::TEXT || ' ' || $4::TEXT))
+        ON CONFLICT (user_id, signature) DO UPDATE SET search_text = EXCLUDED.search_text
+    ", &[&user.bytes(), &signature.bytes(), &title, &body])?;
+
+    Ok(())
+}
+
+fn save_reaction(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let reaction = item.get_reaction();
+    let reply_to = reaction.get_reply_to();
+    let to_user_id = UserID::from_vec(reply_to.get_user_id().get_bytes().into())?;
+    let to_signature = Signature::from_vec(reply_to.get_signature().get_bytes().into())?;
+
+    conn.execute("
+        INSERT INTO reaction(from_user_id, from_signature, to_user_id, to_signature, reaction)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (from_user_id, from_signature) DO UPDATE SET
+            to_user_id = EXCLUDED.to_user_id
+            , to_signature = EXCLUDED.to_signature
+            , reaction = EXCLUDED.reaction
+    ", &[
+        &row.user.bytes(),
+        &row.signature.bytes(),
+        &to_user_id.bytes(),
+        &to_signature.bytes(),
+        &reaction.get_reaction(),
+    ])?;
+
+    Ok(())
+}
+
+fn save_share(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let shared = item.get_share().get_item();
+    let to_user_id = UserID::from_vec(shared.get_user_id().get_bytes().into())?;
+    let to_signature = Signature::from_vec(shared.get_signature().get_bytes().into())?;
+
+    conn.execute("
+        INSERT INTO share(from_user_id, from_signature, to_user_id, to_signature)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (from_user_id, from_signature) DO UPDATE SET
+            to_user_id = EXCLUDED.to_user_id
+            , to_signature = EXCLUDED.to_signature
+    ", &[
+        &row.user.bytes(),
+        &row.signature.bytes(),
+        &to_user_id.bytes(),
+        &to_signature.bytes(),
+    ])?;
+
+    Ok(())
+}
+
+fn save_direct_message(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let recipient = UserID::from_vec(item.get_direct_message().get_recipient().get_bytes().into())?;
+
+    conn.execute("
+        INSERT INTO direct_message(user_id, signature, recipient_user_id, unix_utc_ms)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, signature) DO UPDATE SET
+            recipient_user_id = EXCLUDED.recipient_user_id
+            , unix_utc_ms = EXCLUDED.unix_utc_ms
+    ", &[
+        &row.user.bytes(),
+        &row.signature.bytes(),
+        &recipient.bytes(),
+        &row.timestamp.unix_utc_ms,
+    ])?;
+
+    Ok(())
+}
+
+/// Get the item shared by the given (Share) item, if we have it.
+fn get_shared_row(conn: &mut impl GenericClient, user: &UserID, signature: &Signature) -> Result<Option<Box<ItemDisplayRow>>, Error> {
+    let query = format!("
+        SELECT
+            i.user_id
+            , i.signature
+            , i.unix_utc_ms
+            , i.received_utc_ms
+            , i.bytes
+            , p.display_name
+        FROM share AS s
+        INNER JOIN item AS i ON (
+            i.user_id = s.to_user_id
+            AND i.signature = s.to_signature
+        )
+        LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+        WHERE
+            s.from_user_id = $1
+            AND s.from_signature = $2
+            AND {not_hidden}
+        ",
+        not_hidden=NOT_HIDDEN,
+    );
+    let row = conn.query_opt(query.as_str(), &[&user.bytes(), &signature.bytes()])?;
+
+    let row = match row {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    let item = to_item_row(&row)?;
+
+    Ok(Some(Box::new(ItemDisplayRow{
+        reactions: vec![],
+        display_name: row.try_get(5)?,
+        item,
+        // We don't display shares of shares:
+        shared: None,
+    })))
+}
+
+fn get_reaction_counts(conn: &mut impl GenericClient, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error> {
+    let query = format!("
+        SELECT r.reaction, COUNT(DISTINCT r.from_user_id) AS reaction_count
+        FROM reaction AS r
+        INNER JOIN item AS i ON (
+            i.user_id = r.from_user_id
+            AND i.signature = r.from_signature
+        )
+        WHERE
+            r.to_user_id = $1
+            AND r.to_signature = $2
+            AND EXISTS(SELECT user_id FROM known_users WHERE user_id = r.from_user_id)
+            AND {not_hidden}
+        GROUP BY r.reaction
+        ORDER BY reaction_count DESC, r.reaction ASC
+        ",
+        not_hidden=NOT_HIDDEN,
+    );
+    let rows = conn.query(query.as_str(), &[&user.bytes(), &signature.bytes()])?;
+
+    let mut counts = vec![];
+    for row in rows {
+        counts.push(ReactionCount {
+            reaction: row.try_get(0)?,
+            count: row.try_get::<_, i64>(1)? as u64,
+        });
+    }
+
+    Ok(counts)
+}
+
+
+impl backend::Backend for Connection
+{
+    fn homepage_items<'a>(
+        &self,
+        time_span: TimeSpan,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+                , p.display_name
+            FROM item AS i
+            LEFT OUTER JOIN profile AS p USING (user_id)
+            WHERE {filter_ts}
+            AND i.user_id IN (
+                SELECT user_id
+                FROM server_user
+                WHERE on_homepage
+            )
+            AND NOT EXISTS(
+                SELECT 1 FROM direct_message AS dm
+                WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+            )
+            AND {not_hidden}
+            ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 1),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        self.each_row(&query, &span.params(&[]), &mut |row| {
+            let item = to_item_row(row)?;
+            let mut client = self.client();
+            let display_row = ItemDisplayRow{
+                reactions: vec![],
+                shared: get_shared_row(&mut *client, &item.user, &item.signature)?,
+                item,
+                display_name: row.try_get(5)?,
+            };
+            drop(client);
+            callback(display_row)
+        })
+    }
+
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item AS i
+            WHERE
+                {filter_ts}
+                AND user_id = $1
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND NOT EXISTS(
+                    SELECT 1 FROM direct_message AS dm
+                    WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+                )
+                AND {not_hidden}
+            ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 2),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let user_id = user.bytes();
+        self.each_row(&query, &span.params(&[&user_id]), &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn reply_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let query = format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item AS i
+            INNER JOIN reply AS r ON (
+                r.from_user_id = i.user_id
+                AND r.from_signature = i.signature
+            )
+            WHERE
+                {filter_ts}
+                AND r.to_user_id = $1
+                AND r.to_signature = $2
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("i.", 3),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let user_id = user.bytes();
+        let signature = signature.bytes();
+        self.each_row(&query, &span.params(&[&user_id, &signature]), &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn thread_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        limit: usize,
+        callback: RowCallback<'a, ThreadRow>,
+    ) -> Result<(), Error> {
+        // `path` concatenates the (fixed-width) timestamp & signature of each
+        // ancestor, so that ordering by it gives us a depth-first traversal.
+        // Timestamps are written in (two's complement) hex, prefixed so that
+        // negative ones sort first.
+        // Unlike SQLite, PostgreSQL can't order a recursive query, so it has to
+        // find the whole (depth-limited) thread before applying `limit`.
+        // Blocked Comments are left out, along with their replies.
+        let query = format!("
+            WITH RECURSIVE thread(user_id, signature, depth, path) AS (
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , 1
+                    , (CASE WHEN i.unix_utc_ms < 0 THEN '0' ELSE '1' END) || lpad(to_hex(i.unix_utc_ms), 16, '0') || encode(i.signature, 'hex')
+                FROM reply AS r
+                INNER JOIN item AS i ON (
+                    i.user_id = r.from_user_id
+                    AND i.signature = r.from_signature
+                )
+                WHERE
+                    r.to_user_id = $1
+                    AND r.to_signature = $2
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+
+                UNION ALL
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , t.depth + 1
+                    , t.path || '/' || (CASE WHEN i.unix_utc_ms < 0 THEN '0' ELSE '1' END) || lpad(to_hex(i.unix_utc_ms), 16, '0') || encode(i.signature, 'hex')
+                FROM thread AS t
+                INNER JOIN reply AS r ON (
+                    r.to_user_id = t.user_id
+                    AND r.to_signature = t.signature
+                )
+                INNER JOIN item AS i ON (
+                    i.user_id = r.from_user_id
+                    AND i.signature = r.from_signature
+                )
+                WHERE
+                    t.depth < $3
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+            )
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+                , p.display_name
+                , t.depth
+            FROM thread AS t
+            INNER JOIN item AS i USING (user_id, signature)
+            LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+            ORDER BY t.path
+            LIMIT $4
+        ",
+            not_hidden=NOT_HIDDEN,
+        );
+
+        let limit = limit as i64;
+        self.each_row(&query, &[&user.bytes(), &signature.bytes(), &MAX_THREAD_DEPTH, &limit], &mut |row| {
+            let item = to_item_row(row)?;
+            let depth: i32 = row.try_get(6)?;
+            let thread_row = ThreadRow{
+                item: ItemDisplayRow{
+                    reactions: vec![],
+                    // Only Comments reply to items:
+                    shared: None,
+                    item,
+                    display_name: row.try_get(5)?,
+                },
+                depth: depth as u32,
+            };
+            callback(thread_row)
+        })
+    }
+
+    fn item_revisions<'a>(
+        &self,
+        user: &UserID,
+        original: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let query = format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+            FROM item AS i
+            INNER JOIN item_revision AS r USING (user_id, signature)
+            WHERE
+                {filter_ts}
+                AND r.user_id = $1
+                AND r.original_signature = $2
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("i.", 3),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let user_id = user.bytes();
+        let original = original.bytes();
+        self.each_row(&query, &span.params(&[&user_id, &original]), &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn inbox_items<'a>(
+        &self,
+        recipient: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let query = format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+            FROM direct_message AS dm
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE
+                dm.recipient_user_id = $1
+                AND {filter_ts}
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("dm.", 2),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("dm."),
+        );
+
+        let recipient = recipient.bytes();
+        self.each_row(&query, &span.params(&[&recipient]), &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn latest_revision(&self, user: &UserID, original: &Signature) -> Result<Option<ItemRow>, Error> {
+        let signature = latest_revision_signature(&mut *self.client(), user, original)?;
+        match signature {
+            None => Ok(None),
+            Some(signature) => self.user_item(user, &signature),
+        }
+    }
+
+    fn shared_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemDisplayRow>, Error> {
+        Ok(get_shared_row(&mut *self.client(), user, signature)?.map(|row| *row))
+    }
+
+    fn reaction_counts(&self, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error> {
+        get_reaction_counts(&mut *self.client(), user, signature)
+    }
+
+    fn tag_items<'a>(
+        &self,
+        tag: &str,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM item_tag AS t
+                INNER JOIN item AS i ON (
+                    i.user_id = t.user_id
+                    AND i.signature = t.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    t.tag = $1
+                    AND {filter_ts}
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 2),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let tag = tag.to_lowercase();
+        self.each_row(&query, &span.params(&[&tag]), &mut |row| {
+            let item = to_item_row(row)?;
+            let display_row = ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts are tagged:
+                shared: None,
+                item,
+                display_name: row.try_get(5)?,
+            };
+            callback(display_row)
+        })
+    }
+
+    fn mention_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM mention AS m
+                INNER JOIN item AS i ON (
+                    i.user_id = m.user_id
+                    AND i.signature = m.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    m.mentioned_user_id = $1
+                    AND {filter_ts}
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 2),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let user_id = user.bytes();
+        self.each_row(&query, &span.params(&[&user_id]), &mut |row| {
+            let item = to_item_row(row)?;
+            let display_row = ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts and Comments have mentions:
+                shared: None,
+                item,
+                display_name: row.try_get(5)?,
+            };
+            callback(display_row)
+        })
+    }
+
+    fn search_items<'a>(
+        &self,
+        query: &str,
+        scope: SearchScope,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let terms = backend::search_terms(query);
+        if terms.is_empty() {
+            return Ok(());
+        }
+        // search_terms() only returns words, so this is safe to pass as a tsquery:
+        let ts_query = terms.join(" & ");
+
+        let span = TimeSpanSql::new(&time_span);
+
+        let filter_users = match scope {
+            SearchScope::All => {
+                "EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)".to_string()
+            },
+            SearchScope::User(user) => {
+                format!("i.user_id = decode('{}', 'hex')", user.bytes().as_hex())
+            },
+            SearchScope::Feed(user) => {
+                // See: user_feed_items()
+                let follows = get_follows(&mut *self.client(), &user)?;
+                if follows.is_empty() {
+                    return Ok(());
+                }
+                let user_ids: Vec<String> = follows.keys().map(|uid| {
+                    format!("decode('{}', 'hex')", uid.bytes().as_hex())
+                }).collect();
+                format!("i.user_id IN ({})", user_ids.join(", "))
+            },
+        };
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM search_item AS s
+                INNER JOIN item AS i ON (
+                    i.user_id = s.user_id
+                    AND i.signature = s.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    s.search_text @@ to_tsquery('simple', $1)
+                    AND {filter_ts}
+                    AND {filter_users}
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 2),
+            filter_users=filter_users,
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        self.each_row(&query, &span.params(&[&ts_query]), &mut |row| {
+            let item = to_item_row(row)?;
+            let display_row = ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts & Comments are indexed:
+                shared: None,
+                item,
+                display_name: row.try_get(5)?,
+            };
+            callback(display_row)
+        })
+    }
+
+    fn display_names(&self, author: &UserID, users: &[UserID]) -> Result<HashMap<UserID, String>, Error> {
+        let mut names = HashMap::new();
+        if users.is_empty() { return Ok(names); }
+
+        let mut client = self.client();
+        let follows = get_follows(&mut *client, author)?;
+
+        let profile_name = client.prepare("
+            SELECT display_name
+            FROM profile
+            WHERE user_id = $1
+        ")?;
+
+        for user in users {
+            let follow_name = follows.get(user).and_then(|info| info.display_name.clone());
+            let name = match follow_name {
+                Some(name) => Some(name),
+                None => {
+                    let row = client.query_opt(&profile_name, &[&user.bytes()])?;
+                    let name: Option<String> = match row {
+                        None => None,
+                        Some(row) => row.try_get(0)?,
+                    };
+                    name.filter(|it| !it.trim().is_empty())
+                }
+            };
+
+            if let Some(name) = name {
+                names.insert(user.clone(), name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        // Like the sqlite backend, make N separate queries against the (user_id, timestamp) index and merge them.
+        let follows = get_follows(&mut *self.client(), user_id)?;
+        let subselects: Vec<String> = follows.keys().map(|uid| {
+            format!(
+                "
+                    SELECT * from iv
+                    WHERE user_id = decode('{uid}', 'hex')
+                ",
+                uid=uid.bytes().as_hex(),
+            )
+        }).collect();
+
+        if subselects.is_empty() {
+            return Ok(());
+        }
+
+        let query = format!(
+            "
+                WITH iv AS NOT MATERIALIZED (
+                    SELECT
+                        user_id
+                        , signature
+                        , unix_utc_ms
+                        , received_utc_ms
+                        , bytes
+                    FROM item AS i
+                    WHERE {filter_ts}
+                    AND NOT EXISTS(
+                        SELECT 1 FROM direct_message AS dm
+                        WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+                    )
+                    AND {not_hidden}
+                )
+                {subselects}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i.", 1),
+            not_hidden=NOT_HIDDEN,
+            order=span.order(""),
+            subselects=subselects.join("\n\nUNION ALL\n")
+        );
+
+        self.each_row(&query, &span.params(&[]), &mut |row| {
+            let item = to_item_row(row)?;
+            let mut client = self.client();
+            let display_row = ItemDisplayRow{
+                display_name: follows.get(&item.user).map(|info| info.display_name.clone()).flatten(),
+                reactions: vec![],
+                shared: get_shared_row(&mut *client, &item.user, &item.signature)?,
+                item,
+            };
+            drop(client);
+            callback(display_row)
+        })
+    }
+
+    fn server_user(&self, user: &UserID)
+    -> Result<Option<backend::ServerUser>, Error>
+    {
+        let row = self.client().query_opt("
+            SELECT notes, on_homepage
+            FROM server_user
+            WHERE user_id = $1
+        ", &[&user.bytes()])?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        Ok(Some(ServerUser {
+            user: user.clone(),
+            notes: row.try_get(0)?,
+            on_homepage: row.try_get(1)?,
+        }))
+    }
+
+    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error> {
+        let rows = self.client().query("
+            SELECT
+                user_id
+                , notes
+                , on_homepage
+            FROM server_user
+            ORDER BY on_homepage, user_id
+        ", &[])?;
+
+        for row in rows {
+            let user = ServerUser {
+                user: UserID::from_vec(row.try_get(0)?)?,
+                notes: row.try_get(1)?,
+                on_homepage: row.try_get(2)?,
+            };
+            let more = cb(user)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let row = self.client().query_one("
+            SELECT COUNT(*)
+            FROM item AS i
+            WHERE user_id = $1
+            AND signature = $2
+            AND NOT EXISTS(
+                SELECT 1 FROM quarantined_item AS qi
+                WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+            )
+        ", &[&user.bytes(), &signature.bytes()])?;
+
+        let count: i64 = row.try_get(0)?;
+        if count > 1 {
+            bail!("Found {} matches!? (user_id,signature) should be unique!", count);
+        }
+
+        Ok(count > 0)
+    }
+
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let row = self.client().query_one("
+            SELECT EXISTS(
+                SELECT 1
+                FROM deleted_item
+                WHERE user_id = $1
+                AND signature = $2
+            )
+        ", &[&user.bytes(), &signature.bytes()])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    fn user_revoked(&self, user: &UserID) -> Result<bool, Error> {
+        let row = self.client().query_one("
+            SELECT EXISTS(
+                SELECT 1
+                FROM profile
+                WHERE user_id = $1
+                AND revoked
+            )
+        ", &[&user.bytes()])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> {
+        // Note: query_opt() errors if (user_id, signature) weren't unique.
+        let row = self.client().query_opt("
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item AS i
+            WHERE user_id = $1
+            AND signature = $2
+            AND (
+                EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = i.user_id AND signature = i.signature)
+            )
+            AND NOT EXISTS(
+                SELECT 1 FROM quarantined_item AS qi
+                WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+            )
+        ", &[&user.bytes(), &signature.bytes()])?;
+
+        match row {
+            None => Ok(None),
+            Some(row) => Ok(Some(to_item_row(&row)?)),
+        }
+    }
+
+    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
+    {
+        let conn = self.conn.get_mut();
+        let mut tx = conn.transaction().context("getting a transaction")?;
+
+        // A quarantined item's other tables were filled in when it was first
+        // saved, from the same signed Item, so only its row needs replacing:
+        let quarantined = tx.execute(
+            "DELETE FROM quarantined_item WHERE user_id = $1 AND signature = $2",
+            &[&row.user.bytes(), &row.signature.bytes()],
+        )? > 0;
+        if quarantined {
+            let replaced = tx.execute("
+                UPDATE item
+                SET unix_utc_ms = $3, received_utc_ms = $4, bytes = $5
+                WHERE user_id = $1 AND signature = $2
+            ", &[
+                &row.user.bytes(),
+                &row.signature.bytes(),
+                &row.timestamp.unix_utc_ms,
+                &row.received.unix_utc_ms,
+                &row.item_bytes,
+            ])?;
+            if replaced > 0 {
+                tx.commit().context("committing")?;
+                return Ok(());
+            }
+        }
+
+        tx.execute("
+            INSERT INTO item (
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            ) VALUES ($1, $2, $3, $4, $5)
+        ", &[
+            &row.user.bytes(),
+            &row.signature.bytes(),
+            &row.timestamp.unix_utc_ms,
+            &row.received.unix_utc_ms,
+            &row.item_bytes,
+        ])?;
+
+        if item.has_profile() || item.has_delete() {
+            ignore_early_delete(&mut tx, row)?;
+        }
+
+        if item.has_profile() {
+            update_profile(&mut tx, row, item)?;
+        }
+
+        if item.has_comment() {
+            save_comment_reply(&mut tx, row, item)?;
+        }
+
+        if item.has_delete() {
+            delete_item(&mut tx, row, item)?;
+        }
+
+        if item.has_revision() {
+            save_revision(&mut tx, row, item)?;
+        }
+
+        if let Some((signature, tags)) = get_item_tags(&mut tx, row, item)? {
+            save_item_tags(&mut tx, &row.user, &signature, &tags)?;
+        }
+
+        if let Some((signature, mentions)) = get_item_mentions(&mut tx, row, item)? {
+            save_item_mentions(&mut tx, &row.user, &signature, &mentions)?;
+        }
+
+        if let Some((signature, title, body)) = get_item_text(&mut tx, row, item)? {
+            save_item_text(&mut tx, &row.user, &signature, &title, &body)?;
+        }
+
+        if item.has_reaction() {
+            save_reaction(&mut tx, row, item)?;
+        }
+
+        if item.has_share() {
+            save_share(&mut tx, row, item)?;
+        }
+
+        if item.has_direct_message() {
+            save_direct_message(&mut tx, row, item)?;
+        }
+
+        index_attachments(&mut tx, row, item)?;
+
+        tx.commit().context("committing")?;
+        Ok(())
+    }
+
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
+
+        // Note: We may have already seen this user revoke their ID:
+        self.client().execute("
+            INSERT INTO server_user(user_id, notes, on_homepage, revoked)
+            VALUES ($1, $2, $3, EXISTS(SELECT 1 FROM profile WHERE user_id = $1 AND revoked))
+        ", &[
+            &server_user.user.bytes(),
+            &server_user.notes,
+            &server_user.on_homepage,
+        ])?;
+
+        Ok(())
+    }
+
+    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
+        let row = self.client().query_opt("
+            SELECT user_id, signature
+            FROM profile
+            WHERE user_id = $1
+        ", &[&user.bytes()])?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let user_id = UserID::from_vec(row.try_get(0)?)?;
+        let signature = Signature::from_vec(row.try_get(1)?)?;
+
+        self.user_item(&user_id, &signature)
+    }
+
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
+        let row = self.client().query_one("
+            SELECT
+                EXISTS(SELECT user_id FROM server_user WHERE user_id = $1)
+                OR EXISTS(
+                    SELECT followed_user_id
+                    FROM follow AS f
+                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
+                    WHERE followed_user_id = $1
+                )
+                -- Followers of an old ID also follow its successor:
+                OR EXISTS(
+                    SELECT p.successor_user_id
+                    FROM profile AS p
+                    INNER JOIN follow AS f ON (f.followed_user_id = p.user_id)
+                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
+                    WHERE p.successor_user_id = $1
+                )
+        ", &[&user_id.bytes()])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    fn item_shared(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let row = self.client().query_one(
+            "SELECT EXISTS(SELECT 1 FROM shared_items WHERE user_id = $1 AND signature = $2)",
+            &[&user.bytes(), &signature.bytes()],
+        )?;
+        Ok(row.try_get(0)?)
+    }
+
+    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
+
+        if self.user_revoked(user_id)? {
+            return Ok(Some(QuotaDenyReason::ProfileRevoked));
+        }
+
+        let mut client = self.client();
+        let quota = match get_user_quota(&mut *client, user_id)? {
+            None => return Ok(Some(QuotaDenyReason::UnknownUser)),
+            Some(quota) => quota,
+        };
+
+        if item.has_profile() || item.has_delete() {
+            return Ok(None);
+        }
+
+        if let Some(max_bytes) = quota.item_bytes {
+            let used: i64 = client.query_one(
+                "SELECT COALESCE(SUM(LENGTH(bytes)), 0)::BIGINT FROM item WHERE user_id = $1",
+                &[&user_id.bytes()],
+            )?.try_get(0)?;
+            if backend::quota_exceeded(Some(max_bytes), used as u64, bytes.len() as u64) {
+                return Ok(Some(QuotaDenyReason::ItemQuotaExceeded{ max_bytes }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn server_user_quotas(&self, user: &UserID) -> Result<Option<ServerUserQuotas>, Error> {
+        get_server_user_quotas(&mut *self.client(), user)
+    }
+
+    fn set_server_user_quotas(&self, user: &UserID, quotas: &ServerUserQuotas) -> Result<(), Error> {
+        let to_sql = |bytes: Option<u64>| bytes.map(|b| b as i64);
+        let updated = self.client().execute("
+            UPDATE server_user
+            SET
+                max_item_bytes = $1
+                , max_attachment_bytes = $2
+                , follow_max_item_bytes = $3
+                , follow_max_attachment_bytes = $4
+            WHERE user_id = $5
+        ", &[
+            &to_sql(quotas.own.item_bytes),
+            &to_sql(quotas.own.attachment_bytes),
+            &to_sql(quotas.follows.item_bytes),
+            &to_sql(quotas.follows.attachment_bytes),
+            &user.bytes(),
+        ])?;
+
+        if updated == 0 {
+            bail!("User {} is not a server user", user);
+        }
+        Ok(())
+    }
+
+    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str)
+    -> Result< Option<FileStream> , Error>
+    {
+        // Note: query_opt() errors if (user_id, signature, name) weren't unique.
+        let row = self.client().query_opt("
+            SELECT s.hash, s.size, a.size, a.mime_type, s.contents IS NULL AS external
+            FROM store AS s
+            INNER JOIN item_attachment AS a USING(hash)
+            WHERE
+                a.user_id = $1
+                AND a.signature = $2
+                AND a.name = $3
+                AND (
+                    EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
+                    OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = a.user_id AND signature = a.signature)
+                )
+                AND NOT EXISTS(SELECT 1 FROM quarantined_attachment AS q WHERE q.hash = a.hash)
+        ", &[&user_id.bytes(), &signature.bytes(), &file_name])?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let hash: Vec<u8> = row.try_get(0)?;
+        let size = row.try_get::<_, i64>(1)? as u64;
+        let expected_size = row.try_get::<_, i64>(2)? as u64;
+        let mime_type: Option<String> = row.try_get(3)?;
+        let external: bool = row.try_get(4)?;
+
+        if size != expected_size {
+            bail!("Item expected {} bytes but found {}", expected_size, size);
+        }
+
+        if external {
+            let hash = SHA512::from_hash_bytes(&hash)?;
+            let mut file = match self.attachment_store()?.get(&hash)? {
+                Some(file) => file,
+                None => bail!("{} is missing from the attachment store", hash),
+            };
+            if file.size != expected_size {
+                bail!("Item expected {} bytes but found {}", expected_size, file.size);
+            }
+            file.mime_type = mime_type;
+            return Ok(Some(file));
+        }
+
+        // Open a new pooled connection that will be owned just by our Iterator/Stream,
+        // and read the contents a chunk at a time:
+        let mut conn = self.pool.get()?;
+        let mut read_pos: u64 = 0;
+
+        let iter = std::iter::from_fn(move || -> Option<Result<Bytes,crate::server::SendError>> {
+            if read_pos >= size {
+                return None;
+            }
+
+            // Note: substring() positions are 1-based. BYTEA values are limited to 1GiB, so i32 is fine.
+            let result = conn.query_one(
+                "SELECT substring(contents FROM $1 FOR $2) FROM store WHERE hash = $3",
+                &[&(read_pos as i32 + 1), &CHUNK_SIZE, &hash],
+            ).and_then(|row| row.try_get::<_, Vec<u8>>(0));
+
+            let chunk = match result {
+                Ok(chunk) => chunk,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            if chunk.is_empty() {
+                return None;
+            }
+            read_pos += chunk.len() as u64;
+
+            Some(Ok(Bytes::from(chunk)))
+        });
+
+        let stream = blocking::Unblock::with_capacity(2, iter);
+        let stream = Box::new(stream);
+        Ok(Some(FileStream{stream, size, mime_type}))
+    }
+
+    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<backend::FileMeta>, Error> {
+        let row = self.client().query_opt("
+            SELECT
+                a.size,
+                a.hash,
+                s.hash IS NOT NULL AS contents_exist,
+                EXISTS(SELECT 1 FROM quarantined_attachment AS q WHERE q.hash = a.hash) AS quarantined
+            FROM item_attachment AS a
+            LEFT OUTER JOIN store AS s USING (hash)
+            WHERE
+                a.user_id = $1
+                AND a.signature = $2
+                AND a.name = $3
+                AND (
+                    EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
+                    OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = a.user_id AND signature = a.signature)
+                )
+        ", &[&user_id.bytes(), &signature.bytes(), &file_name])?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let size = row.try_get::<_, i64>(0)? as u64;
+        let hash_bytes: Vec<u8> = row.try_get(1)?;
+        let hash = SHA512::from_hash_bytes(&hash_bytes)?;
+        let stored: bool = row.try_get(2)?;
+        let quarantined: bool = row.try_get(3)?;
+
+        // Files we already have don't take up any more space:
+        let mut client = self.client();
+        let quota_exceeded = !stored && match get_user_quota(&mut *client, user_id)? {
+            None => true,
+            Some(Quota{ attachment_bytes: None, .. }) => false,
+            Some(quota) => {
+                // See: sqlite::Connection::user_attachment_bytes()
+                let used: i64 = client.query_one("
+                    SELECT COALESCE(SUM(size), 0)::BIGINT
+                    FROM (
+                        SELECT DISTINCT hash
+                        FROM item
+                        INNER JOIN item_attachment USING (user_id, signature)
+                        WHERE user_id = $1
+                    ) AS user_hashes
+                    INNER JOIN store USING (hash)
+                ", &[&user_id.bytes()])?.try_get(0)?;
+                backend::quota_exceeded(quota.attachment_bytes, used as u64, size)
+            },
+        };
+
+        let meta = FileMeta{
+            exists: stored && !quarantined,
+            hash,
+            size,
+            quota_exceeded,
+        };
+
+        Ok(Some(meta))
+    }
+
+    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error> {
+        let quarantined: bool = self.client().query_one(
+            "SELECT EXISTS(SELECT 1 FROM quarantined_attachment WHERE hash = $1)",
+            &[&hash.bytes()],
+        )?.try_get(0)?;
+        if quarantined {
+            // Remove the corrupt contents, wherever they're stored:
+            let row = self.client().query_opt(
+                "SELECT contents IS NULL AS external FROM store WHERE hash = $1",
+                &[&hash.bytes()],
+            )?;
+            let external = match row {
+                Some(row) => row.try_get(0)?,
+                None => false,
+            };
+            if external {
+                self.attachment_store()?.remove(hash)?;
+            }
+            self.client().execute("DELETE FROM store WHERE hash = $1", &[&hash.bytes()])?;
+        }
+
+        if let Some(store) = &self.attachment_store {
+            store.save(size, hash, file)?;
+            self.client().execute("
+                INSERT INTO store (hash, size, contents) VALUES ($1, $2, NULL)
+                ON CONFLICT (hash) DO NOTHING
+            ", &[&hash.bytes(), &(size as i64)])?;
+            self.client().execute("DELETE FROM quarantined_attachment WHERE hash = $1", &[&hash.bytes()])?;
+            return Ok(());
+        }
+
+        // BYTEA values are limited to 1GiB, and must be sent in one piece, so
+        // we read the whole file into memory.
+        if size > (1 << 30) {
+            bail!("PostgreSQL can not store attachments larger than 1GiB");
+        }
+
+        let mut contents = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut contents)?;
+
+        if contents.len() as u64 != size {
+            bail!("Expected {} bytes but read {}", size, contents.len());
+        }
+
+        // See: sqlite::Connection::save_attachment()
+        let hash_check = SHA512::from_file(&mut std::io::Cursor::new(&contents))?;
+        if &hash_check != hash {
+            bail!("Expected {} but got {}", hash, hash_check);
+        }
+        debug!("Verified attachment hash: {}", hash);
+
+        // Inserts are atomic, so no need for the temporary hash used by the sqlite backend.
+        self.client().execute("
+            INSERT INTO store (hash, size, contents) VALUES ($1, $2, $3)
+            ON CONFLICT (hash) DO NOTHING
+        ", &[&hash.bytes(), &(size as i64), &contents])?;
+        self.client().execute("DELETE FROM quarantined_attachment WHERE hash = $1", &[&hash.bytes()])?;
+
+        debug!("save_attachment() done.");
+        Ok(())
+    }
+
+    fn prune(&self, opts: backend::PruneOpts) -> Result<backend::PruneResult, Error> {
+
+        let mut result = PruneResult{
+            dry_run: opts.dry_run,
+            attachments_bytes: 0,
+            attachments_count: 0,
+            items_bytes: 0,
+            items_count: 0,
+            blocked_items_bytes: 0,
+            blocked_items_count: 0,
+            blocked_attachments_bytes: 0,
+            blocked_attachments_count: 0,
+        };
+
+        let mut client = self.client();
+
+        if opts.items {
+            let row = client.query_one("
+                SELECT
+                    COUNT(*) AS item_count
+                    , COALESCE(SUM(LENGTH(bytes) + LENGTH(user_id) + LENGTH(signature)), 0) AS size
+                FROM item AS i
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM known_users
+                    WHERE user_id = i.user_id
+                )
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM shared_items
+                    WHERE user_id = i.user_id
+                    AND signature = i.signature
+                )
+            ", &[])?;
+            result.items_count = row.try_get::<_, i64>(0)? as u64;
+            result.items_bytes = row.try_get::<_, i64>(1)? as u64;
+        }
+
+        if opts.attachments {
+            let query = if opts.items {
+                "
+                SELECT COUNT(*) AS attachment_count, COALESCE(SUM(size), 0)::BIGINT AS size
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                )
+                "
+            } else {
+                // We'll delete more if we're also deleting items:
+                "
+                SELECT COUNT(*) AS attachment_count, COALESCE(SUM(size), 0)::BIGINT AS size
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment AS ia
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                    AND (
+                        EXISTS(SELECT 1 FROM known_users WHERE user_id = ia.user_id)
+                        OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = ia.user_id AND signature = ia.signature)
+                    )
+                )
+                "
+            };
+
+            // Find attachments that are no longer referenced.
+            // NOTE: Can't just do a simple LEFT OUTER JOIN and check for NULL.
+            // That could lead to false positives when one ref is dangling but another exists.
+            let row = client.query_one(query, &[])?;
+            result.attachments_count = row.try_get::<_, i64>(0)? as u64;
+            result.attachments_bytes = row.try_get::<_, i64>(1)? as u64;
+        }
+
+        // Blocked content gets deleted even if it's still referenced:
+        let blocked_items = "
+            FROM item AS i
+            WHERE EXISTS (
+                SELECT 1
+                FROM blocked_user
+                WHERE user_id = i.user_id
+            )
+            OR EXISTS (
+                SELECT 1
+                FROM blocked_item
+                WHERE user_id = i.user_id
+                AND signature = i.signature
+            )
+        ";
+        let blocked_attachments = "
+            FROM store AS s
+            WHERE EXISTS (
+                SELECT 1
+                FROM blocked_attachment
+                WHERE hash = s.hash
+            )
+        ";
+
+        if opts.blocked {
+            let query = format!(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(bytes) + LENGTH(user_id) + LENGTH(signature)), 0)::BIGINT {}",
+                blocked_items
+            );
+            let row = client.query_one(query.as_str(), &[])?;
+            result.blocked_items_count = row.try_get::<_, i64>(0)? as u64;
+            result.blocked_items_bytes = row.try_get::<_, i64>(1)? as u64;
+
+            let query = format!("SELECT COUNT(*), COALESCE(SUM(size), 0)::BIGINT {}", blocked_attachments);
+            let row = client.query_one(query.as_str(), &[])?;
+            result.blocked_attachments_count = row.try_get::<_, i64>(0)? as u64;
+            result.blocked_attachments_bytes = row.try_get::<_, i64>(1)? as u64;
+        }
+
+        if opts.dry_run {
+            return Ok(result)
+        }
+
+        if opts.blocked {
+            let rows = client.query(format!("SELECT user_id, signature {}", blocked_items).as_str(), &[])?;
+            for row in rows {
+                let user = UserID::from_vec(row.try_get(0)?)?;
+                let signature = Signature::from_vec(row.try_get(1)?)?;
+                remove_item(&mut *client, &user, &signature)?;
+            }
+
+            // Profiles (and their follows) that were blocked, or were from blocked users:
+            client.execute("
+                DELETE FROM profile AS p
+                WHERE EXISTS (
+                    SELECT 1
+                    FROM blocked_user
+                    WHERE user_id = p.user_id
+                )
+                OR EXISTS (
+                    SELECT 1
+                    FROM blocked_item
+                    WHERE user_id = p.user_id
+                    AND signature = p.signature
+                )
+            ", &[])?;
+            client.execute("
+                DELETE FROM follow AS f
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM profile
+                    WHERE user_id = f.source_user_id
+                )
+            ", &[])?;
+
+            let rows = client.query(format!("SELECT hash {} AND contents IS NULL", blocked_attachments).as_str(), &[])?;
+            for row in rows {
+                let hash = SHA512::from_hash_bytes(&row.try_get::<_, Vec<u8>>(0)?)?;
+                self.attachment_store()?.remove(&hash)?;
+            }
+
+            client.execute(format!("DELETE {}", blocked_attachments).as_str(), &[])?;
+        }
+
+        // Note: Delete items first, which makes more things available to delete from store if we do that:
+        if opts.items {
+            client.execute("
+                DELETE FROM item AS i
+                WHERE NOT EXISTS(
+                    SELECT 1
+                    FROM known_users
+                    WHERE user_id = i.user_id
+                )
+                AND NOT EXISTS(
+                    SELECT 1
+                    FROM shared_items
+                    WHERE user_id = i.user_id
+                    AND signature = i.signature
+                )
+            ", &[])?;
+
+            // Delete attachments now abandoned:
+            client.execute("
+                DELETE FROM item_attachment AS ia
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item
+                    WHERE user_id = ia.user_id
+                    AND signature = ia.signature
+                )
+            ", &[])?;
+        }
+
+        if opts.attachments {
+            // Note: We can get by w/ one query here because we already deleted
+            // items from unknown users if that was desired.
+            let unused = "
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                )
+            ";
+
+            // Remove files from the attachment store before we forget about them:
+            let rows = client.query(format!("SELECT hash {} AND contents IS NULL", unused).as_str(), &[])?;
+            for row in rows {
+                let hash = SHA512::from_hash_bytes(&row.try_get::<_, Vec<u8>>(0)?)?;
+                self.attachment_store()?.remove(&hash)?;
+            }
+
+            client.execute(format!("DELETE {}", unused).as_str(), &[])?;
+        }
+
+        // Note: Must not be run within a transaction.
+        client.batch_execute("VACUUM")?;
+
+        Ok(result)
+    }
+
+    fn usage_by_user(&self, callback: RowCallback<'_, backend::UsageByUserRow>) -> Result<(), Error> {
+        let query = "
+            SELECT
+                s1.user_id,
+                s1.display_name,
+                EXISTS(SELECT 1 FROM known_users WHERE user_id=s1.user_id) AS known_user,
+                EXISTS(SELECT 1 FROM server_user WHERE user_id=s1.user_id) AS server_user,
+                item_count,
+                item_size,
+                COALESCE(attachment_size, 0) AS attachment_size,
+                COALESCE(attachment_count, 0) AS attachment_count,
+                item_size + COALESCE(attachment_size, 0) as total_size
+            FROM (
+                SELECT
+                    user_id,
+                    p.display_name,
+                    COUNT(*) as item_count,
+                    SUM(length(bytes)) as item_size
+                FROM item AS i
+                LEFT OUTER JOIN profile AS p USING (user_id)
+                GROUP BY user_id, p.display_name
+            ) AS s1
+            LEFT OUTER JOIN (
+                SELECT
+                    user_id,
+                    SUM(size)::BIGINT as attachment_size,
+                    COUNT(*) AS attachment_count
+                FROM (
+                    SELECT DISTINCT user_id, hash
+                    FROM item
+                    INNER JOIN item_attachment USING (user_id, signature)
+                ) AS user_hashes
+                INNER JOIN store USING (hash)
+                GROUP BY user_id
+            ) AS s2 USING (user_id)
+            ORDER BY total_size DESC
+        ";
+
+        self.each_row(query, &[], &mut |row| {
+            let usage = UsageByUserRow {
+                user_id: UserID::from_vec(row.try_get(0)?)?,
+                display_name: row.try_get("display_name")?,
+                items_count: row.try_get::<_, i64>("item_count")? as u64,
+                items_bytes: row.try_get::<_, i64>("item_size")? as u64,
+                attachments_count: row.try_get::<_, i64>("attachment_count")? as u64,
+                attachments_bytes: row.try_get::<_, i64>("attachment_size")? as u64,
+                known_user: row.try_get("known_user")?,
+                server_user: row.try_get("server_user")?,
+                total_bytes: row.try_get::<_, i64>("total_size")? as u64,
+            };
+
+            callback(usage)
+        })
+    }
+
+    fn migrate_attachments(&self) -> Result<MigrateResult, Error> {
+        let store = self.attachment_store()?;
+        let mut result = MigrateResult{
+            attachments_count: 0,
+            attachments_bytes: 0,
+        };
+
+        let rows = self.client().query("SELECT hash FROM store WHERE contents IS NOT NULL", &[])?;
+        for row in rows {
+            let hash_bytes: Vec<u8> = row.try_get(0)?;
+            let hash = SHA512::from_hash_bytes(&hash_bytes)?;
+
+            // BYTEA values are limited to 1GiB, so we can read each one into memory:
+            let contents: Vec<u8> = self.client().query_one(
+                "SELECT contents FROM store WHERE hash = $1",
+                &[&hash_bytes],
+            )?.try_get(0)?;
+            let size = contents.len() as u64;
+
+            // Note: The store verifies the hash, so we'll also catch any bit-rot here.
+            store.save(size, &hash, &mut std::io::Cursor::new(contents))?;
+
+            self.client().execute("UPDATE store SET contents = NULL WHERE hash = $1", &[&hash_bytes])?;
+            result.attachments_count += 1;
+            result.attachments_bytes += size;
+        }
+
+        if result.attachments_count > 0 {
+            // Note: Must not be run within a transaction.
+            self.client().batch_execute("VACUUM")?;
+        }
+
+        Ok(result)
+    }
+
+    fn all_items<'a>(&self, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        let query = "
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            ORDER BY user_id, signature
+        ";
+
+        self.each_row(query, &[], &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn all_user_items<'a>(&self, user: &UserID, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        let query = "
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            WHERE user_id = $1
+            ORDER BY unix_utc_ms, signature
+        ";
+
+        let user_id = user.bytes();
+        self.each_row(query, &[&user_id], &mut |row| {
+            callback(to_item_row(row)?)
+        })
+    }
+
+    fn verify_attachments<'a>(&self, callback: RowCallback<'a, BadAttachment>) -> Result<u64, Error> {
+        let rows = self.client().query("SELECT hash, size, contents IS NULL AS external FROM store", &[])?;
+
+        let mut checked = 0;
+        for row in rows {
+            checked += 1;
+            let hash_bytes: Vec<u8> = row.try_get(0)?;
+            let size: i64 = row.try_get(1)?;
+            let external: bool = row.try_get(2)?;
+            let hash = match SHA512::from_hash_bytes(&hash_bytes) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    // The hash is the store's primary key, so it identifies the row:
+                    let problem = format!("Malformed hash: {}", err);
+                    if !callback(BadAttachment{hash: hash_bytes, problem})? { break; }
+                    continue;
+                },
+            };
+
+            let found = if external {
+                self.attachment_store()?.rehash(&hash)?
+            } else {
+                // BYTEA values are limited to 1GiB, so we can read each one into memory:
+                let contents: Vec<u8> = self.client().query_one(
+                    "SELECT contents FROM store WHERE hash = $1",
+                    &[&hash_bytes],
+                )?.try_get(0)?;
+                Some(SHA512::from_file_with_size(&mut std::io::Cursor::new(contents))?)
+            };
+
+            if let Some(bad) = BadAttachment::check(&hash, size as u64, found) {
+                if !callback(bad)? { break; }
+            }
+        }
+
+        Ok(checked)
+    }
+
+    fn verify_items<'a>(&self, callback: RowCallback<'a, BadItem>) -> Result<u64, Error> {
+        let query = "
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            ORDER BY user_id, signature
+        ";
+
+        let mut checked = 0;
+        self.each_row(query, &[], &mut |row| {
+            checked += 1;
+            let bad = BadItem::check(
+                row.try_get(0)?,
+                row.try_get(1)?,
+                row.try_get(2)?,
+                row.try_get(3)?,
+                row.try_get(4)?,
+            );
+            match bad {
+                Some(bad) => callback(bad),
+                None => Ok(true),
+            }
+        })?;
+
+        Ok(checked)
+    }
+
+    fn add_quarantine(&self, target: &Quarantine, problem: &str) -> Result<(), Error> {
+        let now = Timestamp::now().unix_utc_ms;
+        let mut client = self.client();
+        match target {
+            Quarantine::Item(user, signature) => client.execute("
+                INSERT INTO quarantined_item(user_id, signature, problem, created_utc_ms)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, signature) DO UPDATE SET problem = EXCLUDED.problem
+            ", &[&user.bytes(), &signature.bytes(), &problem, &now])?,
+            Quarantine::Attachment(hash) => client.execute("
+                INSERT INTO quarantined_attachment(hash, problem, created_utc_ms)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (hash) DO UPDATE SET problem = EXCLUDED.problem
+            ", &[&hash.bytes(), &problem, &now])?,
+        };
+        Ok(())
+    }
+
+    fn add_block(&self, block: &Block, notes: &str) -> Result<(), Error> {
+        let now = Timestamp::now().unix_utc_ms;
+        let mut client = self.client();
+        match block {
+            Block::User(user) => client.execute("
+                INSERT INTO blocked_user(user_id, notes, created_utc_ms)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE SET notes = EXCLUDED.notes
+            ", &[&user.bytes(), &notes, &now])?,
+            Block::Item(user, signature) => client.execute("
+                INSERT INTO blocked_item(user_id, signature, notes, created_utc_ms)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, signature) DO UPDATE SET notes = EXCLUDED.notes
+            ", &[&user.bytes(), &signature.bytes(), &notes, &now])?,
+            Block::Attachment(hash) => client.execute("
+                INSERT INTO blocked_attachment(hash, notes, created_utc_ms)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (hash) DO UPDATE SET notes = EXCLUDED.notes
+            ", &[&hash.bytes(), &notes, &now])?,
+        };
+        Ok(())
+    }
+
+    fn remove_block(&self, block: &Block) -> Result<bool, Error> {
+        let mut client = self.client();
+        let deleted = match block {
+            Block::User(user) => client.execute(
+                "DELETE FROM blocked_user WHERE user_id = $1",
+                &[&user.bytes()],
+            )?,
+            Block::Item(user, signature) => client.execute(
+                "DELETE FROM blocked_item WHERE user_id = $1 AND signature = $2",
+                &[&user.bytes(), &signature.bytes()],
+            )?,
+            Block::Attachment(hash) => client.execute(
+                "DELETE FROM blocked_attachment WHERE hash = $1",
+                &[&hash.bytes()],
+            )?,
+        };
+        Ok(deleted > 0)
+    }
+
+    fn blocks<'a>(&self, callback: RowCallback<'a, BlockRow>) -> Result<(), Error> {
+        let rows = self.client().query("
+            SELECT user_id, NULL::BYTEA AS signature, NULL::BYTEA AS hash, notes, created_utc_ms
+            FROM blocked_user
+            UNION ALL
+            SELECT user_id, signature, NULL, notes, created_utc_ms
+            FROM blocked_item
+            UNION ALL
+            SELECT NULL, NULL, hash, notes, created_utc_ms
+            FROM blocked_attachment
+            ORDER BY created_utc_ms
+        ", &[])?;
+
+        for row in rows {
+            let user: Option<Vec<u8>> = row.try_get(0)?;
+            let signature: Option<Vec<u8>> = row.try_get(1)?;
+            let hash: Option<Vec<u8>> = row.try_get(2)?;
+
+            let block = match (user, signature, hash) {
+                (Some(user), None, None) => Block::User(UserID::from_vec(user)?),
+                (Some(user), Some(signature), None) => Block::Item(UserID::from_vec(user)?, Signature::from_vec(signature)?),
+                (None, None, Some(hash)) => Block::Attachment(SHA512::from_hash_bytes(&hash)?),
+                _ => bail!("Invalid block row"),
+            };
+
+            let more = callback(BlockRow{
+                block,
+                notes: row.try_get(3)?,
+                created: Timestamp{ unix_utc_ms: row.try_get(4)? },
+            })?;
+            if !more { break; }
+        }
+
+        Ok(())
+    }
+
+    fn is_blocked(&self, block: &Block) -> Result<bool, Error> {
+        let mut client = self.client();
+        let row = match block {
+            Block::User(user) => client.query_one(
+                "SELECT EXISTS(SELECT 1 FROM blocked_user WHERE user_id = $1)",
+                &[&user.bytes()],
+            )?,
+            Block::Item(user, signature) => client.query_one("
+                SELECT
+                    EXISTS(SELECT 1 FROM blocked_user WHERE user_id = $1)
+                    OR EXISTS(SELECT 1 FROM blocked_item WHERE user_id = $1 AND signature = $2)
+            ", &[&user.bytes(), &signature.bytes()])?,
+            Block::Attachment(hash) => client.query_one(
+                "SELECT EXISTS(SELECT 1 FROM blocked_attachment WHERE hash = $1)",
+                &[&hash.bytes()],
+            )?,
+        };
+        Ok(row.try_get(0)?)
+    }
+}
+
+/// A row from the item_attachment table.
+struct AttachmentRow {
+    user_id: UserID,
+    signature: Signature,
+    name: String,
+
+    // The size of the attachment (in bytes)
+    size: i64,
+
+    hash: SHA512,
+
+    // Optional metadata, from the File proto:
+    mime_type: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    alt_text: Option<String>,
+}
+
+fn index_attachments(conn: &mut impl GenericClient, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    save_attachment_rows(conn, get_attachment_rows(row, item)?)
+}
+
+fn get_attachment_rows(row: &ItemRow, item: &Item) -> Result<Vec<AttachmentRow>, Error> {
+    let mut rows = vec![];
+
+    // TODO: Eventually support attachments for Profiles (and other types?) too:
+    let post = if item.has_revision() {
+        item.get_revision().get_post()
+    } else {
+        item.get_post()
+    };
+
+    let attachments = post.get_attachments().get_file();
+    for attachment in attachments {
+        let row = AttachmentRow {
+            name: attachment.name.clone(),
+            hash: SHA512::from_hash_bytes(attachment.hash.as_slice())?,
+            user_id: row.user.clone(),
+            signature: row.signature.clone(),
+            size: attachment.size as i64,
+            mime_type: Some(attachment.mime_type.clone()).filter(|it| !it.is_empty()),
+            width: Some(attachment.width as i32).filter(|it| *it > 0),
+            height: Some(attachment.height as i32).filter(|it| *it > 0),
+            alt_text: Some(attachment.alt_text.clone()).filter(|it| !it.trim().is_empty()),
+        };
+        if row.name.contains("/") || row.name.contains("\\") {
+            bail!("File separators are not allowed in attached file names: {}", row.name);
+        }
+        if row.size < 0 {
+            bail!("File sizes greater than {} bytes are unsupported", i64::MAX);
+        }
+
+        rows.push(row);
+    }
+    return Ok(rows);
+}
+
+fn save_attachment_rows(conn: &mut impl GenericClient, rows: Vec<AttachmentRow>) -> Result<(), Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let stmt = conn.prepare("
+        INSERT INTO item_attachment(user_id, signature, name, hash, size, mime_type, width, height, alt_text)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (user_id, signature, name) DO UPDATE SET
+            hash = EXCLUDED.hash
+            , size = EXCLUDED.size
+            , mime_type = EXCLUDED.mime_type
+            , width = EXCLUDED.width
+            , height = EXCLUDED.height
+            , alt_text = EXCLUDED.alt_text
+    ")?;
+
+    for row in rows {
+        conn.execute(&stmt, &[
+            &row.user_id.bytes(),
+            &row.signature.bytes(),
+            &row.name,
+            &row.hash.bytes(),
+            &row.size,
+            &row.mime_type,
+            &row.width,
+            &row.height,
+            &row.alt_text,
+        ])?;
+    }
+
+    Ok(())
+}
+
+fn get_server_user_quotas(conn: &mut impl GenericClient, user: &UserID) -> Result<Option<ServerUserQuotas>, Error> {
+    let row = conn.query_opt("
+        SELECT
+            max_item_bytes
+            , max_attachment_bytes
+            , follow_max_item_bytes
+            , follow_max_attachment_bytes
+        FROM server_user
+        WHERE user_id = $1
+    ", &[&user.bytes()])?;
+
+    let row = match row {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    Ok(Some(ServerUserQuotas{
+        own: to_quota(&row, 0)?,
+        follows: to_quota(&row, 2)?,
+    }))
+}
+
+/// Read a Quota from the (item_bytes, attachment_bytes) columns starting at `index`.
+fn to_quota(row: &Row, index: usize) -> Result<Quota, Error> {
+    let item_bytes: Option<i64> = row.try_get(index)?;
+    let attachment_bytes: Option<i64> = row.try_get(index + 1)?;
+    Ok(Quota{
+        item_bytes: item_bytes.map(|b| b as u64),
+        attachment_bytes: attachment_bytes.map(|b| b as u64),
+    })
+}
+
+/// Find the quota for a user, or None if they're not allowed to post here at all.
+/// See: sqlite::Connection::user_quota()
+fn get_user_quota(conn: &mut impl GenericClient, user_id: &UserID) -> Result<Option<Quota>, Error> {
+    if let Some(quotas) = get_server_user_quotas(conn, user_id)? {
+        return Ok(Some(quotas.own));
+    }
+
+    // Check those followed by "server users" (or the successors of those users),
+    // and authors of items that they (or their follows) have shared:
+    let rows = conn.query("
+        SELECT
+            su.follow_max_item_bytes
+            , su.follow_max_attachment_bytes
+        FROM
+            follow AS f
+            INNER JOIN server_user AS su ON su.user_id = f.source_user_id
+            LEFT OUTER JOIN profile AS p ON p.user_id = f.followed_user_id
+        WHERE
+            (f.followed_user_id = $1 OR p.successor_user_id = $1)
+            -- Revoked server users can no longer vouch for anyone:
+            AND NOT su.revoked
+        UNION ALL
+        -- Authors of items shared by known users, so that we can store the shared items.
+        -- (put_item() only accepts those items from them.) A share vouches for them like a follow:
+        SELECT
+            su.follow_max_item_bytes
+            , su.follow_max_attachment_bytes
+        FROM
+            share AS sh
+            INNER JOIN server_user AS su ON (
+                su.user_id = sh.from_user_id
+                OR EXISTS(
+                    SELECT 1
+                    FROM follow AS f
+                    LEFT OUTER JOIN profile AS p ON p.user_id = f.followed_user_id
+                    WHERE
+                        f.source_user_id = su.user_id
+                        AND (f.followed_user_id = sh.from_user_id OR p.successor_user_id = sh.from_user_id)
+                )
+            )
+        WHERE
+            sh.to_user_id = $1
+            AND NOT su.revoked
+    ", &[&user_id.bytes()])?;
+    let mut follow_quota: Option<Quota> = None;
+    for row in &rows {
+        let quota = to_quota(row, 0)?;
+        follow_quota = Some(match follow_quota {
+            None => quota,
+            Some(other) => other.most_generous(quota),
+        });
+    }
+
+    Ok(follow_quota)
+}
+
+/// Get all users that `user_id` follows (and themselves).
+/// See: sqlite::get_follows()
+fn get_follows(conn: &mut impl GenericClient, user_id: &UserID) -> Result<HashMap<UserID, FollowInfo>, Error> {
+    let mut map = HashMap::new();
+
+    let rows = conn.query("
+        SELECT
+            f.followed_user_id AS user_id
+            , f.display_name AS follow_display_name
+            , p.display_name
+        FROM follow AS f
+        LEFT OUTER JOIN profile AS p ON (f.followed_user_id = p.user_id)
+        WHERE f.source_user_id = $1
+
+        UNION ALL
+        SELECT
+            p.user_id
+            , p.display_name AS follow_display_name
+            , p.display_name
+        FROM profile AS p
+        WHERE p.user_id = $1
+
+        -- Followers of an old ID also follow its successor:
+        UNION ALL
+        SELECT
+            p.successor_user_id AS user_id
+            , f.display_name AS follow_display_name
+            , sp.display_name
+        FROM follow AS f
+        INNER JOIN profile AS p ON (f.followed_user_id = p.user_id)
+        LEFT OUTER JOIN profile AS sp ON (p.successor_user_id = sp.user_id)
+        WHERE
+            f.source_user_id = $1
+            AND p.successor_user_id IS NOT NULL
+    ", &[&user_id.bytes()])?;
+
+    fn to_info(row: &Row) -> Result<FollowInfo, Error> {
+        let display_name: Option<String> = row.try_get("display_name")?;
+        let follow_display_name: Option<String> = row.try_get("follow_display_name")?;
+        fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
+
+        Ok(FollowInfo {
+            user_id: UserID::from_vec(row.try_get("user_id")?)?,
+
+            // Prefer displaying the name that this user has assigned to the follow.
+            display_name: follow_display_name.filter(not_empty).or(display_name).filter(not_empty),
+        })
+    }
+
+    for row in &rows {
+        let info = to_info(row)?;
+        map.insert(info.user_id.clone(), info);
+    }
+
+    Ok(map)
+}
+
+struct FollowInfo {
+    user_id: UserID,
+    // The display name specified by this user, or (fallback) the user they followed.
+    display_name: Option<String>
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests need a PostgreSQL database to run against. Set the
+    //! FEOBLOG_TEST_POSTGRES environment variable to its URL to enable them.
+    //! ex: FEOBLOG_TEST_POSTGRES=postgresql://feoblog@localhost/feoblog_test cargo test
+
+    use protobuf::Message;
+    use sodiumoxide::randombytes::randombytes;
+
+    use crate::backend::FactoryBuilder as _;
+    use super::*;
+
+    fn test_factory() -> Result<Option<Box<dyn backend::Factory>>, Error> {
+        let url = match std::env::var("FEOBLOG_TEST_POSTGRES") {
+            Ok(url) => url,
+            Err(_) => {
+                println!("FEOBLOG_TEST_POSTGRES not set. Skipping.");
+                return Ok(None);
+            }
+        };
+
+        let builder = FactoryBuilder::new(url);
+        if !builder.db_exists()? {
+            builder.db_create()?;
+        }
+
+        Ok(Some(builder.factory()?))
+    }
+
+    #[test]
+    fn save_and_load_items() -> Result<(), Error> {
+        let factory = match test_factory()? {
+            Some(factory) => factory,
+            None => return Ok(()),
+        };
+        let mut conn = factory.open()?;
+
+        let user = UserID::from_vec(randombytes(32))?;
+        conn.add_server_user(&ServerUser{
+            user: user.clone(),
+            notes: "postgres test user".into(),
+            on_homepage: false,
+        })?;
+        assert!(conn.user_known(&user)?);
+
+        let now = Timestamp::now();
+        let mut post = Post::new();
+        post.set_title("Hello, #Postgres".into());
+        post.set_body("Testing, testing.".into());
+        let mut item = Item::new();
+        item.set_timestamp_ms_utc(now.unix_utc_ms);
+        item.set_post(post);
+
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(randombytes(64))?,
+            timestamp: now,
+            received: now,
+            item_bytes: item.write_to_bytes()?,
+        };
+        conn.save_user_item(&row, &item)?;
+
+        assert!(conn.user_item_exists(&row.user, &row.signature)?);
+        let loaded = conn.user_item(&row.user, &row.signature)?.expect("saved item");
+        assert_eq!(loaded.item_bytes, row.item_bytes);
+
+        let mut tagged = vec![];
+        let before = Timestamp{ unix_utc_ms: now.unix_utc_ms + 1 };
+        conn.tag_items("postgres", TimeSpan::Before(before.into()), &mut |display_row| {
+            tagged.push(display_row.item.signature);
+            Ok(true)
+        })?;
+        assert!(tagged.contains(&row.signature));
+
+        let mut user_items = vec![];
+        conn.user_items(&user, TimeSpan::Before(before.into()), &mut |item_row| {
+            user_items.push(item_row.signature);
+            Ok(true)
+        })?;
+        assert_eq!(user_items, vec![row.signature.clone()]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Types that know how to upgrade the PostgreSQL database.
+//!
+//! Connection::initialize() creates version 1 of the schema. Later schema
+//! changes are applied here, the same way as in `sqlite::upgraders`.
+
+use anyhow::{Error, bail};
+use protobuf::Message;
+
+use crate::protos::Item;
+
+use super::{CURRENT_VERSION, Connection, each_row, get_item_text, save_item_text, to_item_row};
+
+pub(crate) struct Upgraders {
+    upgraders: Vec<Box<dyn Upgrader>>
+}
+
+impl Upgraders {
+    pub fn new() -> Self {
+        Self { upgraders: vec![
+            Box::new(From1To2),
+            Box::new(From2To3),
+            Box::new(From3To4),
+            Box::new(From4To5),
+            Box::new(From5To6),
+            Box::new(From6To7),
+        ]}
+    }
+
+    pub fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        let mut current_version = conn.get_version()?;
+        while current_version < CURRENT_VERSION {
+            let upgrader = self.upgrader_from(current_version)?;
+            println!("Upgrading from db version {} to {} ...", current_version, upgrader.to_version());
+            upgrader.upgrade(conn)?;
+            current_version = conn.get_version()?;
+            if current_version != upgrader.to_version() {
+                bail!("Upgrader failed to upgrade to advertised version: {}, still {}", upgrader.to_version(), current_version);
+            }
+        }
+
+        println!("Upgrade complete. Current database version: {}", current_version);
+
+        Ok(())
+    }
+
+    fn upgrader_from(&self, version: u32) -> Result<&dyn Upgrader, Error> {
+        for upgrader in &self.upgraders {
+            if upgrader.from_version() == version {
+                return Ok(upgrader.as_ref())
+            }
+        }
+        bail!("Couldn't find a way to upgrade from version {}", version);
+    }
+}
+
+
+trait Upgrader {
+    fn from_version(&self) -> u32;
+    fn to_version(&self) -> u32;
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error>;
+}
+
+// Track the size of stored attachments, so that their contents can be kept
+// outside of the database. (In an AttachmentStore)
+struct From1To2;
+impl Upgrader for From1To2 {
+    fn from_version(&self) -> u32 { 1 }
+    fn to_version(&self) -> u32 { 2 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            ALTER TABLE store ADD COLUMN size BIGINT;
+            UPDATE store SET size = LENGTH(contents);
+            ALTER TABLE store ALTER COLUMN size SET NOT NULL;
+            ALTER TABLE store ALTER COLUMN contents DROP NOT NULL;
+        ")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a full-text search index for Posts & Comments.
+struct From2To3;
+impl Upgrader for From2To3 {
+    fn from_version(&self) -> u32 { 2 }
+    fn to_version(&self) -> u32 { 3 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE search_item(
+                -- Full-text search index for Posts & Comments.
+                -- If a Post has been revised, contains the latest Revision's text.
+                user_id BYTEA NOT NULL
+                -- The signature of the Comment, or (original) Post:
+                , signature BYTEA NOT NULL
+
+                , search_text TSVECTOR NOT NULL
+
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE INDEX search_item_text_idx ON search_item USING GIN (search_text);
+        ")?;
+
+        // Index text in existing items:
+        let mut stream = conn.pool.get()?;
+        each_row(&mut stream, "
+            SELECT user_id, signature, unix_utc_ms, received_utc_ms, bytes
+            FROM item
+        ", &[], &mut |row| {
+            let row = to_item_row(row)?;
+            let mut item = Item::new();
+            item.merge_from_bytes(row.item_bytes.as_slice())?;
+
+            let mut client = conn.client();
+            if let Some((signature, title, body)) = get_item_text(&mut *client, &row, &item)? {
+                save_item_text(&mut *client, &row.user, &signature, &title, &body)?;
+            }
+            Ok(true)
+        })?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds storage quotas to server users. See: sqlite::upgraders::From19To20
+struct From3To4;
+impl Upgrader for From3To4 {
+    fn from_version(&self) -> u32 { 3 }
+    fn to_version(&self) -> u32 { 4 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            ALTER TABLE server_user ADD COLUMN max_item_bytes BIGINT;
+            ALTER TABLE server_user ADD COLUMN max_attachment_bytes BIGINT;
+            ALTER TABLE server_user ADD COLUMN follow_max_item_bytes BIGINT;
+            ALTER TABLE server_user ADD COLUMN follow_max_attachment_bytes BIGINT;
+        ")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Lets server admins block users, items and attachment contents. See: sqlite::upgraders::From20To21
+struct From4To5;
+impl Upgrader for From4To5 {
+    fn from_version(&self) -> u32 { 4 }
+    fn to_version(&self) -> u32 { 5 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE blocked_user(
+                user_id BYTEA PRIMARY KEY
+                -- Why the user was blocked:
+                , notes TEXT NOT NULL
+                , created_utc_ms BIGINT NOT NULL
+            );
+            CREATE TABLE blocked_item(
+                user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+                , notes TEXT NOT NULL
+                , created_utc_ms BIGINT NOT NULL
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE TABLE blocked_attachment(
+                -- The SHA-512 hash of the blocked contents:
+                hash BYTEA PRIMARY KEY
+                , notes TEXT NOT NULL
+                , created_utc_ms BIGINT NOT NULL
+            );
+        ")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Sharing an item no longer makes its author a "known user". Instead, we
+// store & serve only the shared item itself. See: sqlite::upgraders::From21To22
+struct From5To6;
+impl Upgrader for From5To6 {
+    fn from_version(&self) -> u32 { 5 }
+    fn to_version(&self) -> u32 { 6 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            DROP VIEW known_users;
+            CREATE VIEW known_users (user_id) AS
+            -- For internal use only. All 'known users' of the server.
+                SELECT user_id
+                FROM server_user
+            UNION ALL
+                SELECT followed_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            UNION ALL
+                -- Followers of an old ID also follow its successor:
+                SELECT p.successor_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+                INNER JOIN profile AS p
+                    ON (f.followed_user_id=p.user_id)
+                WHERE p.successor_user_id IS NOT NULL
+            ;
+
+            CREATE VIEW shared_items (user_id, signature) AS
+            -- For internal use only. Items shared by known users, which we
+            -- store & serve even if we don't otherwise know their authors.
+                SELECT sh.to_user_id, sh.to_signature
+                FROM share AS sh
+                WHERE EXISTS(SELECT user_id FROM known_users WHERE user_id = sh.from_user_id)
+            ;
+        ")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Lets `db verify --quarantine` set aside corrupt items and attachment contents.
+// See: sqlite::upgraders::From22To23
+struct From6To7;
+impl Upgrader for From6To7 {
+    fn from_version(&self) -> u32 { 6 }
+    fn to_version(&self) -> u32 { 7 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE quarantined_item(
+                user_id BYTEA NOT NULL
+                , signature BYTEA NOT NULL
+                -- What db verify found wrong with the item:
+                , problem TEXT NOT NULL
+                , created_utc_ms BIGINT NOT NULL
+                , PRIMARY KEY (user_id, signature)
+            );
+            CREATE TABLE quarantined_attachment(
+                -- The SHA-512 hash that the contents should have:
+                hash BYTEA PRIMARY KEY
+                , problem TEXT NOT NULL
+                , created_utc_ms BIGINT NOT NULL
+            );
+        ")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
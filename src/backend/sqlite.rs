@@ -1,1592 +1,3397 @@
-//! The sqlite backend just stores all data (including BLOBs) in a single
-//! sqlite3 file. SQLite is great at storing lots of small blobs this way,
-//! but may perform poorly for lots of large files.
-//! 
-//! Mostly, this makes data management trivial since it's all in one file.
-//! But if performance is an issue we can implement a different backend.
-
-// TODO: Consider running ANALYZE: https://www.sqlite.org/lang_analyze.html -- results in better default query plans.
-
-mod upgraders;
-
-use std::{io::{Read, Write}, ops::DerefMut, path::Path, collections::HashMap};
-
-use crate::{backend::UsageByUserRow, protos::Item, util::AsHex};
-use actix_web::web::Bytes;
-use backend::{FileMeta, RowCallback, SHA512};
-use futures::Stream;
-use log::{debug, warn};
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{DatabaseName, NO_PARAMS, OpenFlags, named_params};
-use sodiumoxide::randombytes::randombytes;
-use crate::backend::{self, UserID, Signature, ItemRow, ItemDisplayRow, Timestamp, ServerUser, QuotaDenyReason};
-
-use anyhow::{Error, bail, Context};
-use rusqlite::{params, OptionalExtension, Row};
-
-use super::{FileStream, PruneResult, TimeSpan};
-
-const CURRENT_VERSION: u32 = 7;
-
-type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
-type PConn = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
-
-pub(crate) struct FactoryBuilder {
-    sqlite_file: String
-}
-
-impl FactoryBuilder {
-    pub fn new(sqlite_file: String) -> Self {
-        Self {
-            sqlite_file
-        }
-    }
-}
-
-impl backend::FactoryBuilder for FactoryBuilder {
-    fn factory(&self) -> Result<Box<dyn backend::Factory>, Error> {
-        if !self.db_exists()? {
-            bail!("\
-                    Error: Database file not found.\n\
-                    You may need to run `feoblog db init` to create the a database.\
-            ");
-        }
-
-        if self.db_needs_upgrade()? {
-            bail!("\
-                Error: Database needs an upgrade.\n\
-                Run `feoblog db upgrade` to upgrade it.
-            ");
-        }
-
-        self.set_wal()?;
-
-        Ok(Box::new(self.build_factory()?))
-    }
-
-    fn db_exists(&self) -> Result<bool, Error> {
-        let path = Path::new(self.sqlite_file.as_str());
-        Ok(path.exists())
-    }
-
-    fn db_needs_upgrade(&self) -> Result<bool, Error> {
-        let conn = self.connection()?;
-        let db_version = conn.get_version()?;
-        Ok(db_version < CURRENT_VERSION)
-    }
-
-    fn db_upgrade(&self) -> Result<(), Error> {
-        if !self.db_exists()? {
-            bail!("No such database file: {}", self.sqlite_file)
-        }
-
-        let upgraders = upgraders::Upgraders::new();
-        let conn = self.connection()?;
-        upgraders.upgrade(&conn)?;
-
-        Ok(())
-    }
-
-    fn db_create(&self) -> Result<(), Error> {
-        if self.db_exists()? {
-            bail!("Database already exists")
-        }
-
-        println!("Creating database: {}", self.sqlite_file);
-        let pool = self.pool_builder().build(
-            self.connection_manager()
-            // Let sqlite create the DB file since that is explicitly our intention here:
-            .with_flags(OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE)
-        )?;
-
-        let conn = Connection{ 
-            conn: pool.get()?,
-            pool: pool.clone(),
-        };
-        conn.initialize()?;
-        println!("Database created.");
-
-
-        // This allows me to be lazy, I can specify new DB additions as version upgrades and not have to keep updating the
-        // main initialize() code. BUT, I probably should if the upgrade path gets too long.
-        drop(conn);
-        drop(pool);
-        if self.db_needs_upgrade()? {
-            self.db_upgrade()?;
-        }
-
-        Ok(())
-    }
-}
-
-impl FactoryBuilder {
-    // Shortcut the FactoryBuilder::new() checks and open a connection to a DB that may be in a bad state.
-    // e.g.:
-    // * needs tables created
-    // * needs to be upgraded.
-    fn connection(&self) -> Result<Connection, Error> {
-        let pool = self.pool()?;
-        Ok(
-            Connection { 
-                conn: pool.get()?,
-                pool,
-            }
-        )
-    }
-
-    fn pool(&self) -> Result<r2d2::Pool<SqliteConnectionManager>, r2d2::Error> {
-        self.pool_builder().build(self.connection_manager())
-    }
-
-    fn pool_builder(&self) -> r2d2::Builder<SqliteConnectionManager> {
-        r2d2::Pool::builder()
-        .min_idle(Some(0)) // defaults to max_size. (Which defaults to 10.)
-    }
-
-    fn build_factory(&self) -> Result<Factory, Error> {
-        Ok(Factory{ pool: self.pool()? })
-    }
-
-    fn connection_manager(&self) -> r2d2_sqlite::SqliteConnectionManager {
-        r2d2_sqlite::SqliteConnectionManager
-            ::file(self.sqlite_file.as_str())
-            // Note: explicitly NOT SQLITE_OPEN_CREATE
-            .with_flags(OpenFlags::SQLITE_OPEN_READ_WRITE)
-    }
-
-    /// Enable write-ahead-logging mode for SQLite.
-    /// This greatly improves write performance, which helps in general, but in particular
-    /// when syncing your feed.
-    /// See: https://sqlite.org/wal.html
-    fn set_wal(&self) -> Result<(), Error> {
-        let conn = self.connection()?;
-        let wal_mode = "wal";
-        let new_mode: String = conn.conn.pragma_update_and_check(
-            None,
-            "journal_mode",
-            &wal_mode,
-            |row| { row.get(0) },
-        )?;
-        if wal_mode != &new_mode {
-            warn!("Could not set journal_mode to WAL mode. Using {}", new_mode);
-        } else {
-            debug!("WAL mode set.");
-        }
-
-        Ok(())
-    }
-}
-
-pub(crate) struct Factory
-{
-    pool: Pool,
-}
-
-impl backend::Factory for Factory
-{
-    fn open(&self) -> Result<Box<dyn backend::Backend>, Error>
-    {
-        let conn = Connection{
-            conn: self.pool.get()?,
-            pool: self.pool.clone(),
-        };
-        Ok(Box::new(conn))
-    }
-
-    fn dyn_clone(&self) -> Box<dyn backend::Factory> {
-        let new_factory = Factory {
-            pool: self.pool.clone()
-        };
-        Box::new(new_factory)
-    }
-}
-
-
-pub(crate) struct Connection
-{
-    // Mostly, we'll use an open connection:
-    conn: PConn,
-
-    // But also let's get an Arc copy of the pool in case we need to open more connections.
-    pool: Pool,
-}
-
-trait SqliteConn: DerefMut<Target=rusqlite::Connection> {}
-impl <T: DerefMut<Target=rusqlite::Connection>> SqliteConn for T {}
-
-
-/// private methods for Conneciton
-impl Connection
-{
-    fn initialize(&self) -> Result<(), Error>
-    {
-        self.run("
-            CREATE TABLE version (
-                -- The current version of the database schema.
-                version INTEGER
-            )
-        ")?;
-        self.run("INSERT INTO version VALUES(3)")?;
-
-        self.run("
-            CREATE TABLE item(
-                -- An Item is the core data structure of FeoBlog.
-                -- It is a BLOB of protobuf v3 bytes defining an item in a
-                -- user's collection of items
-                bytes BLOB
-
-                -- An item must be accompanied by a nacl public key (user_id)
-                -- and (detached) signature so that its authenticity can be
-                -- verified.
-                , user_id BLOB
-                , signature BLOB
-
-                -- A copy of the signed timestamp from within `bytes`
-                -- this allows for sorting queries by timestamp.
-                , unix_utc_ms INTEGER
-
-                -- The date this item was received by this server. May differ
-                -- from above.
-                , received_utc_ms INTEGER
-            )
-        ")?;
-        self.run("
-            CREATE UNIQUE INDEX item_primary_idx
-            ON item(user_id, signature)
-        ")?;
-        self.run("
-            CREATE INDEX item_user_chrono_idx
-            ON item(user_id, unix_utc_ms)
-        ")?;
-        self.run("
-            CREATE INDEX item_user_chrono_received_idx
-            ON item(user_id, received_utc_ms)
-        ")?;
-        self.run("
-            CREATE INDEX item_unix_utc_idx
-            ON item(unix_utc_ms)
-        ")?;
-        self.run("
-            CREATE INDEX item_received_utc_idx
-            ON item(received_utc_ms)
-        ")?;
-
-        self.run("
-            CREATE TABLE server_user(
-                -- These users have been granted direct access to the server.
-                
-                user_id BLOB
-
-                -- Information about this user.
-                -- Not displayed on the web UI, just here to let the server
-                -- admin leave a human-readable note about who this user is.
-                , notes TEXT
-
-                -- bool 0/1 -- should this user's posts appear on the home page
-                -- of this server?
-                , on_homepage INTEGER
-
-                -- How many bytes will the server cache for this user?
-                -- 0 = unlimited.
-                , max_bytes INTEGER 
-            )
-        ")?;
-
-        self.run("
-            CREATE UNIQUE INDEX server_user_primary_idx
-            ON server_user(user_id)
-        ")?;
-
-        self.run("
-            CREATE INDEX server_user_homepage_idx
-            ON server_user(on_homepage, user_id)
-        ")?;
-
-
-        self.run("
-            CREATE TABLE follow(
-                -- Lists which users follow which other users.
-                -- Always represents the latest Profile saved by a user.
-                source_user_id BLOB,
-                followed_user_id BLOB,
-                display_name TEXT
-            )
-        ")?;
-
-        self.run("
-            CREATE UNIQUE INDEX follow_primary_idx
-            ON follow(source_user_id, followed_user_id)
-        ")?;
-
-        self.run("
-            CREATE TABLE profile(
-                -- Always contains a reference to the latest profile uploaded by a user
-                user_id BLOB,
-                signature BLOB,
-                display_name TEXT
-            )
-        ")?;
-
-        self.run("
-            CREATE UNIQUE INDEX profile_primary_idx
-            ON profile(user_id)
-        ")?;
-
-        // See upgraders.rs for newer DB additions.
-
-        Ok(())
-    }
-
-    fn run(&self, sql: &str) -> Result<(), Error>
-    {
-        self.conn.execute(sql, params![])?;
-        Ok(())
-    }
-
-    fn get_version(&self) -> Result<u32, Error>
-    {
-        let table_count: u32  = self.conn.prepare(
-            "SELECT count()
-            FROM sqlite_master
-            WHERE type = 'table'
-            AND name = 'version'
-            "
-        )?.query_row(
-            params![],
-            |row|  Ok(row.get(0)?)
-        )?;
-
-        if table_count == 0 {
-            bail!("No version table found. This may not be a valid feoblog database.")
-        }
-
-        let mut stmt = self.conn.prepare(
-            "SELECT version from version"
-        )?; 
-        let versions = stmt.query_map(
-            params![],
-            |row| -> rusqlite::Result<u32> { Ok(row.get(0)?) }
-        )?;
-
-        let versions: Vec<u32> = versions.take(2).collect::<rusqlite::Result<Vec<u32>>>()?;
-
-        if versions.len() == 0 {
-            bail!("Found no version in the database. This may not be a valid feoblog database.");
-        }
-        if versions.len() > 1 {
-            bail!("Found more than one version in the database. This database may have been corrupted.");
-        }
-
-        Ok(versions[0])
-    }
-
-    fn set_version(&self, version: u32) -> Result<(), Error> {
-        self.conn.execute("UPDATE version SET version = ?", params![version])?;
-
-        Ok(())
-    }
-
-    fn all_items<'a>(&self, after_uid: &Option<UserID>, after_sig: &Option<Signature>, callback: RowCallback<'a, ItemRow>) -> Result<(), Error>{
-        let mut stmt;
-        let mut rows;
-        if let (Some(uid), Some(sig)) = (after_uid, after_sig) {
-            stmt = self.conn.prepare("
-                SELECT
-                    user_id,
-                    signature,
-                    unix_utc_ms,
-                    received_utc_ms,
-                    bytes
-                FROM item
-                WHERE (user_id > :uid)
-                OR (user_id = :uid AND signature > :sig)
-                ORDER BY user_id, signature
-            ")?;
-            rows = stmt.query_named(named_params! {
-                "uid": uid.bytes(),
-                "sig": sig.bytes(),
-            })?;
-        } else {
-            // Start from the beginning:
-            stmt = self.conn.prepare("
-                SELECT
-                    user_id,
-                    signature,
-                    unix_utc_ms,
-                    received_utc_ms,
-                    bytes
-                FROM item
-                ORDER BY user_id, signature
-            ")?;
-            rows = stmt.query(params![])?;
-        }
-
-        let mut fetch_more = true;
-        while fetch_more {
-            let row = match rows.next()? {
-                None => return Ok(()), // No more results.
-                Some(row) => row,
-            };
-
-            let ir = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp {  unix_utc_ms: row.get(2)? },
-                received: Timestamp {  unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
-            fetch_more = callback(ir)?;
-        }
-
-        Ok(())
-    }
-
-}
-
-/// We're saving a profile. If it's new, update the profile and follow tables.
-fn update_profile(conn: &rusqlite::Savepoint, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
-
-    let prev_timestamp: Option<i64> =  
-        conn.prepare("
-            SELECT i.unix_utc_ms
-            FROM profile AS p
-            INNER JOIN item AS i USING (user_id, signature)
-            WHERE user_id = ?
-        ")?
-        .query(params![ item_row.user.bytes() ])?
-        .next()?
-        .map(|row| row.get(0))
-        .transpose()?
-    ;
-
-    // Never replace a newer profile's metadata:
-    if let Some(previous) = prev_timestamp {
-        if previous >= item.timestamp_ms_utc {
-            return Ok(())
-        }
-    }
-
-    // Replace all follows with new ones listed in the profile:
-    conn.execute("DELETE FROM follow WHERE source_user_id = ?", params![item_row.user.bytes()])?;
-
-    // Behavior is undefined if duplicate follows exist in a Profile. So we just replace:
-    let mut add_follow = conn.prepare("
-        INSERT OR REPLACE INTO follow (source_user_id, followed_user_id, display_name)
-        VALUES (?, ?, ?)
-    ")?;
-
-    for follow in item.get_profile().get_follows() {
-        add_follow.execute(params![
-            item_row.user.bytes(),
-            follow.get_user().get_bytes(),
-            follow.get_display_name(),
-        ])?;
-    }
-
-    let mut add_profile = conn.prepare("
-        INSERT OR REPLACE INTO profile(user_id, signature, display_name)
-        VALUES (?,?,?)
-    ")?;
-    add_profile.execute(params![
-        item_row.user.bytes(),
-        item_row.signature.bytes(),
-        item.get_profile().get_display_name()
-    ])?;
-
-    Ok(())
-}
-
-fn save_comment_reply(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
-    if !item.has_comment() {
-        return Ok(())
-    }
-
-    let comment = item.get_comment();
-    let reply = ReplyRow {
-        from_user_id: row.user.clone(),
-        from_signature: row.signature.clone(),
-        to_user_id: UserID::from_vec(comment.get_reply_to().get_user_id().get_bytes().into())?,
-        to_signature: Signature::from_vec(comment.get_reply_to().get_signature().get_bytes().into())?,
-    };
-
-    save_reply_rows(conn, &[reply])
-}
-
-fn save_reply_rows(conn: &rusqlite::Connection, replies: &[ReplyRow]) -> Result<(), Error> {
-    let mut stmt = conn.prepare("
-        INSERT INTO reply (from_user_id, from_signature, to_user_id, to_signature)
-        VALUES (?,?,?,?)
-    ")?;
-    for reply in replies {
-        stmt.execute(params![
-            reply.from_user_id.bytes(),
-            reply.from_signature.bytes(),
-            reply.to_user_id.bytes(),
-            reply.to_signature.bytes(),
-        ])?;
-    }
-    
-    Ok(())
-}
-
-
-impl backend::Backend for Connection
-{
-    fn homepage_items<'a>(
-        &self,
-        time_span: TimeSpan,
-        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
-    ) -> Result<(), Error> {
-
-        let mut params = vec![];
-        let query = match time_span {
-            TimeSpan::Before(before) => {
-                params.push(before.unix_utc_ms);
-                "
-                    SELECT
-                        user_id
-                        , i.signature
-                        , unix_utc_ms
-                        , received_utc_ms
-                        , bytes
-                        , p.display_name
-                    FROM item AS i
-                    LEFT OUTER JOIN profile AS p USING (user_id)
-                    WHERE unix_utc_ms < ?
-                    AND user_id IN (
-                        SELECT user_id
-                        FROM server_user
-                        WHERE on_homepage = 1
-                    )
-                    ORDER BY unix_utc_ms DESC, i.signature DESC
-                "
-            },
-            TimeSpan::After(after) => {
-                params.push(after.unix_utc_ms);
-                "
-                    SELECT
-                        user_id
-                        , i.signature
-                        , unix_utc_ms
-                        , received_utc_ms
-                        , bytes
-                        , p.display_name
-                    FROM item AS i
-                    LEFT OUTER JOIN profile AS p USING (user_id)
-                    WHERE unix_utc_ms > ?
-                    AND user_id IN (
-                        SELECT user_id
-                        FROM server_user
-                        WHERE on_homepage = 1
-                    )
-                    ORDER BY unix_utc_ms ASC, i.signature DESC
-                "
-            },
-        };
-
-        let mut stmt = self.conn.prepare(query)?;
-        let mut rows = stmt.query(params)?;
-
-
-        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
-
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
-
-            Ok(ItemDisplayRow{
-                item,
-                display_name: row.get(5)?
-            })
-        };
-
-        while let Some(row) = rows.next()? {
-            let item = to_item_profile_row(row)?;
-            let result = callback(item)?;
-            if !result { break; }
-        }
-
-        Ok( () )
-    }
-
-    fn user_items<'a>(
-        &self,
-        user: &UserID,
-        time_span: TimeSpan,
-        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool,Error>
-    ) -> Result<(), Error> {
-
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![];
-        let query = match time_span {
-            TimeSpan::Before(before) => {
-                params.push(Box::new(before.unix_utc_ms));
-                params.push(Box::new(user.bytes().to_vec()));
-                "
-                    SELECT
-                        i.user_id
-                        , i.signature
-                        , unix_utc_ms
-                        , received_utc_ms
-                        , bytes
-                    FROM item AS i
-                    WHERE
-                        unix_utc_ms < ?
-                        AND user_id = ?
-                        AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
-                    ORDER BY unix_utc_ms DESC, i.signature DESC
-                "
-            },
-            TimeSpan::After(after) => {
-                params.push(Box::new(after.unix_utc_ms));
-                params.push(Box::new(user.bytes().to_vec()));
-                "
-                    SELECT
-                        i.user_id
-                        , i.signature
-                        , unix_utc_ms
-                        , received_utc_ms
-                        , bytes
-                    FROM item AS i
-                    WHERE
-                        unix_utc_ms > ?
-                        AND user_id = ?
-                        AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
-                    ORDER BY unix_utc_ms ASC, i.signature ASC
-                "          
-            }
-        };
-        
-        let mut stmt = self.conn.prepare(query)?;
-        let mut rows = stmt.query(params)?;
-
-        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
-
-            Ok(item)
-        };
-
-        while let Some(row) = rows.next()? {
-            let item = convert(row)?;
-            let result = callback(item)?;
-            if !result { break; }
-        }
-
-        Ok( () )
-    }
-
-    fn reply_items<'a>(
-        &self,
-        user: &UserID,
-        signature: &Signature,
-        before: Timestamp,
-        callback: RowCallback<'a, ItemRow>,
-    ) -> Result<(), Error> {
-        let mut stmt = self.conn.prepare("
-            SELECT
-                i.user_id
-                , i.signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            FROM item AS i
-            INNER JOIN reply AS r ON (
-                r.from_user_id = i.user_id
-                AND r.from_signature = i.signature
-            )
-            WHERE
-                unix_utc_ms < ?
-                AND r.to_user_id = ?
-                AND r.to_signature = ?
-                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
-            ORDER BY unix_utc_ms DESC, i.signature DESC
-        ")?;
-
-        let mut rows = stmt.query(params![
-            before.unix_utc_ms,
-            user.bytes(),
-            signature.bytes(),
-        ])?;
-
-        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
-
-            Ok(item)
-        };
-
-        while let Some(row) = rows.next()? {
-            let item = convert(row)?;
-            let result = callback(item)?;
-            if !result { break; }
-        }
-
-        Ok( () )
-    }
-
-    fn user_feed_items<'a>(
-        &self,
-        user_id: &UserID,
-        time_span: TimeSpan,
-        callback: RowCallback<'a, ItemDisplayRow>,
-    ) -> Result<(), Error> {
-
-  
-
-
-        let timestamp;
-        let ts_order;
-        let filter_ts;
-        match time_span {
-            TimeSpan::Before(ts) => {
-                timestamp = ts;
-                filter_ts = "unix_utc_ms < :timestamp";
-                ts_order = "DESC";
-            },
-            TimeSpan::After(ts) => {
-                timestamp = ts;
-                filter_ts = "unix_utc_ms > :timestamp";
-                ts_order = "ASC";
-            }
-        };
-
-        // Because we follow N users, and the indexes for (user_id, timestamp) are fast, make N separate queries
-        // against those indexes and merge them with a UNION ALL. This forces SQLite to walk & merge them like should
-        // scale well, vs... whatever it was trying to do.
-
-        let follows = get_follows(&self, user_id)?;
-        let subselects: Vec<String> = follows.keys().map(|uid| {
-            format!(
-                "
-                    SELECT * from iv 
-                    WHERE user_id = x'{uid}'
-                ",
-                uid=uid.bytes().as_hex(),
-            )
-        }).collect();
-
-        if subselects.is_empty() {
-            return Ok(());
-        }
-
-        let query = format!(
-            "
-                WITH iv AS (
-                    SELECT 
-                        user_id
-                        , signature
-                        , unix_utc_ms
-                        , received_utc_ms
-                        , bytes
-                    FROM item
-                    WHERE {filter_ts}
-                )
-                {subselects}
-                ORDER BY unix_utc_ms {ts_order}, signature {ts_order}
-            ", 
-            filter_ts=filter_ts,
-            ts_order=ts_order,
-            subselects=subselects.join("\n\nUNION ALL\n")
-        );
-
-        let mut stmt = self.conn.prepare(&query)?;
-
-        let mut rows = stmt.query_named(&[
-            (":timestamp", &timestamp.unix_utc_ms),
-        ])?;
-
-        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
-
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
-
-            Ok(ItemDisplayRow{
-                display_name: follows.get(&item.user).map(|info| info.display_name.clone()).flatten(),
-                item,
-            })
-        };
-
-        while let Some(row) = rows.next()? {
-            let item = to_item_profile_row(row)?;
-            let result = callback(item)?;
-            if !result { break; }
-        }
-
-        Ok( () )
-    }
-
-    fn server_user(&self, user: &UserID)
-    -> Result<Option<backend::ServerUser>, Error> 
-    { 
-        let mut stmt = self.conn.prepare("
-            SELECT notes, on_homepage
-            FROM server_user
-            WHERE user_id = ?
-        ")?;
-
-        let to_server_user = |row: &Row<'_>| {
-            let on_homepage: isize = row.get(1)?;
-             Ok(
-                 ServerUser {
-                    user: user.clone(),
-                    notes: row.get(0)?,
-                    on_homepage: on_homepage != 0,
-                }
-            )
-        };
-
-        let item = stmt.query_row(
-            params![user.bytes()],
-            to_server_user,
-        ).optional()?;
-
-        Ok(item)
-
-    }
-
-    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error> {
-        let mut stmt = self.conn.prepare("
-            SELECT 
-                user_id
-                , notes
-                , on_homepage
-            FROM server_user
-            ORDER BY on_homepage, user_id
-        ")?;
-
-        let mut rows = stmt.query(NO_PARAMS)?;
-
-        while let Some(row) = rows.next()? {
-            let on_homepage: isize = row.get(2)?;
-            let on_homepage = on_homepage != 0;
-
-            let user = ServerUser {
-                user: UserID::from_vec(row.get(0)?)?,
-                notes: row.get(1)?,
-                on_homepage,
-            };
-            let more = cb(user)?;
-            if !more {break;}
-        }
-
-        Ok(())
-    }
-    
-    
-    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> { 
-        let mut stmt = self.conn.prepare("
-            SELECT COUNT(*)
-            FROM item
-            WHERE user_id = ?
-            AND signature = ?
-        ")?;
-
-        let count: u32 = stmt.query_row(
-            params![
-                user.bytes(),
-                signature.bytes(),
-            ],
-            |row| { Ok(row.get(0)?) }
-        )?;
-
-        if count > 1 {
-            bail!("Found {} matches!? (user_id,signature) should be unique!", count);
-        }
-
-        Ok(count > 0)
-    }
-
-    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> { 
-        let mut stmt = self.conn.prepare("
-            SELECT
-                user_id
-                , signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            FROM item AS i
-            WHERE user_id = ?
-            AND signature = ?
-            AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
-        ")?;
-
-        let mut rows = stmt.query(params![
-            user.bytes(),
-            signature.bytes(),
-        ])?;
-
-        let row = match rows.next()? {
-            None => return Ok(None),
-            Some(row) => row,
-        };
-
-        let item = ItemRow{
-            user: UserID::from_vec(row.get(0)?)?,
-            signature: Signature::from_vec(row.get(1)?)?,
-            timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-            received: Timestamp{ unix_utc_ms: row.get(3)? },
-            item_bytes: row.get(4)?,
-        };
-
-        if rows.next()?.is_some() {
-            bail!("Found multiple matching rows!? (user_id,signature) should be unique!");
-        }
-
-        Ok(Some(item))
-    }
-
-    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
-    {
-        let tx = self.conn.savepoint().context("getting a transaction")?;
-
-        let stmt = "
-            INSERT INTO item (
-                user_id
-                , signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            ) VALUES (?, ?, ?, ?, ?);
-       ";
-
-        tx.execute(stmt, params![
-            row.user.bytes(),
-            row.signature.bytes(),
-            row.timestamp.unix_utc_ms,
-            row.received.unix_utc_ms,
-            row.item_bytes.as_slice(),
-        ])?;
-
-        if item.has_profile() {
-            update_profile(&tx, row, item)?;
-        }
-
-        if item.has_comment() {
-            save_comment_reply(&tx, row, item)?;
-        }
-
-        index_attachments(&tx, row, item)?;
-
-        tx.commit().context("committing")?;
-        Ok(())
-    }
-
-    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
-
-        let stmt = "
-            INSERT INTO server_user(user_id, notes, on_homepage)
-            VALUES (?,?,?)
-        ";
-
-        let on_homepage = if server_user.on_homepage { 1 } else { 0 };
-
-        self.conn.execute(stmt, params![
-            server_user.user.bytes(),
-            server_user.notes.as_str(),
-            on_homepage
-        ])?;
-
-        Ok(())
-    }
-
-    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
-
-        // TODO: I'm not crazy about making 2 queries here instead of a join, but it lets me
-        // re-use the user_item() loading logic.
-        let mut find_profile = self.conn.prepare("
-            SELECT user_id, signature
-            FROM profile
-            WHERE user_id = ?
-        ")?;
-
-        let mut rows = find_profile.query(params![user.bytes()])?;
-        let row = match rows.next()? {
-            None => return Ok(None),
-            Some(row) => row,
-        };
-
-        let user_id: Vec<u8> = row.get(0)?;
-        let signature: Vec<u8> = row.get(1)?;
-
-        let user_id = UserID::from_vec(user_id)?;
-        let signature = Signature::from_vec(signature)?;
-
-        self.user_item(&user_id, &signature)
-    }
-
-    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
-        let mut query = self.conn.prepare("
-            SELECT
-                EXISTS(SELECT user_id FROM server_user WHERE user_id = :user_id)
-                OR EXISTS(
-                    SELECT followed_user_id
-                    FROM follow AS f
-                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
-                    WHERE followed_user_id = :user_id
-                )
-        ")?;
-
-        let mut result = query.query_named(&[
-            (":user_id", &user_id.bytes())
-        ])?;
-
-        let row = match result.next()? {
-            Some(row) => row,
-            None => bail!("Expected at least 1 row from SQLite."),
-        };
-
-        Ok(row.get(0)?)
-    }
-
-    fn quota_check_item(&self, user_id: &UserID, _bytes: &[u8], _item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
-        
-        if self.server_user(user_id)?.is_some() {
-            // TODO: Implement optional quotas for "server users".
-            // For now, there is no quota for them:
-            return Ok(None);
-        };
-
-        // Check those followed by "server users":
-        let mut statement = self.conn.prepare("
-            SELECT
-                f.followed_user_id
-            FROM
-                follow AS f
-                INNER JOIN server_user AS su ON su.user_id = f.source_user_id
-            WHERE
-                f.followed_user_id = ?
-        ")?;
-        let mut rows = statement.query(params![user_id.bytes()])?;
-        if rows.next()?.is_some() {
-            // TODO Implement quotas in follows. For now, presence of a follow gives unlimited quota.
-            // TODO: Exclude server users whose profiles/IDs have been revoked.
-            return Ok(None);
-        }
-
-        // TODO: When "pinning" is implemented, allow posting items which are pinned by server users and their follows.
-        // TODO: I've since decided that "pinning" might be prone to abuse. I should write up my thoughts there.
-
-        Ok(Some(QuotaDenyReason::UnknownUser))
-    }
-   
-    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str) 
-    -> Result< Option<FileStream> , Error> 
-    {
-        let mut stmt = self.conn.prepare("
-            SELECT store.rowid, length(store.contents), a.size
-            FROM store 
-            INNER JOIN item_attachment AS a USING(hash)
-            WHERE 
-                a.user_id = ?
-                AND a.signature = ?
-                AND a.name = ?
-                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
-        ")?;
-
-        let mut rows = stmt.query(params![
-            user_id.bytes(),
-            signature.bytes(),
-            file_name,
-        ])?;
-
-        let row = match rows.next()? {
-            None => return Ok(None),
-            Some(row) => row,
-        };
-
-        let rowid: i64 = row.get(0)?;
-        let size = row.get::<_, i64>(1)? as u64;
-        let expected_size = row.get::<_, i64>(2)? as u64;
-
-        if size != expected_size {
-            bail!("Item expected {} bytes but found {}", expected_size, size);
-        }
-
-        if rows.next()?.is_some() {
-            bail!("UNIQUE constraint failure, found 2 results for file");
-        }
-
-        drop(rows);
-        drop(stmt);
-
-
-        // Open a new pooled connection that will be owned just by our Iterator/Stream:
-        // TODO: Maybe we should just re-open the connection every time if we have to for the BLOB too?
-        let conn = self.pool.get()?;
-        let mut buf = [0 as u8; 32 * 1024];
-        let mut read_pos = 0;
-
-        let iter = std::iter::from_fn(move || -> Option<Result<Bytes,crate::server::SendError>> {
-            // Have to re-open the BLOB every time because it's not Send (due to its lifetime on &Connection?).
-            let blob = conn.blob_open(
-                DatabaseName::Main, 
-                "store",
-                "contents",
-                rowid,
-                true // read-only
-            );
-
-            let blob = match blob {
-                Ok(b) => b,
-                Err(err) => return Some(Err(err.into())),
-            };
-    
-            let bytes_read = match blob.read_at(&mut buf, read_pos) {
-                Err(io_err) => return Some(Err(io_err.into())),
-                Ok(x) => x,
-            };
-            read_pos += bytes_read;
-
-            if bytes_read == 0 {
-                return None;
-            }
-
-            let bytes = Bytes::copy_from_slice(&buf[..bytes_read]);
-            return Some(Ok(bytes));
-        });
-
-        let stream = blocking::Unblock::with_capacity(2, iter);
-        let stream = Box::new(stream);
-        Ok(Some(FileStream{stream, size}))
-    }
-
-    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<backend::FileMeta>, Error> {
-        
-        let mut stmt = self.conn.prepare("
-            SELECT 
-                a.size,
-                a.hash,
-                s.hash IS NOT NULL AS contents_exist
-            FROM item_attachment AS a
-            LEFT OUTER JOIN store AS s USING (hash)
-            WHERE 
-                a.user_id = ?
-                AND a.signature = ?
-                AND a.name = ?
-                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
-        ")?;
-
-        let mut rows = stmt.query(params![
-            user_id.bytes(),
-            signature.bytes(),
-            file_name
-        ])?;
-
-        let row = match rows.next()? {
-            Some(row) => row,
-            None => return Ok(None),
-        };
-
-        let size = row.get::<_, i64>(0)? as u64;
-        let hash_bytes: Vec<u8> = row.get(1)?;
-        let hash = SHA512::from_hash_bytes(&hash_bytes)?;
-        let exists = row.get(2)?;
-
-        let meta = FileMeta{
-            exists,
-            hash,
-            size,
-            quota_exceeded: false, // TODO
-        };
-
-        Ok(Some(meta))
-    }
-
-    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error> {
-        // Save to a temporary hash while we stream the data into the database.
-        // Note, this is 31 bytes, which is easily distinguishable from SHA-512's 64-bytes:
-        let temp_hash = randombytes(31);
-
-        // In practice, SQLite's max BLOB size defaults to <1GiB. 
-        // See: https://sqlite.org/limits.html
-        // We'll just rely on this insert failing to tell us what it is:
-        debug!("Inserting zeroblob into 'store'");
-        self.conn.execute(
-            "INSERT INTO store (hash, contents) VALUES(?, zeroblob(?))",
-            params![
-                &temp_hash,
-                size as i64
-            ],
-        )?;
-
-        let row_id: i64 = self.conn.query_row(
-            "SELECT rowid FROM store WHERE hash = ?",
-            params![ &temp_hash ], 
-            |row| row.get(0)
-        )?;
-
-        let mut blob = self.conn.blob_open(
-            DatabaseName::Main,
-            "store",
-            "contents",
-            row_id,
-            false // read_only=false
-        )?; 
-
-        debug!("Copying temp file into sqlite");
-        std::io::copy(file, &mut blob)?;
-        blob.flush()?;
-        debug!("Finished copy.");
-
-        // Check blob hash:
-        // I know the docs say we expect the caller to have performed the hash, but 
-        // getting the wrong content here is annoying so I'm going to do it again anyway:
-        let hash_check = SHA512::from_file(&mut blob)?;
-        debug!("Verified BLOB hash: {}", hash);
-        
-        if &hash_check != hash {
-            bail!("SQLite expected {} but got {}", hash, hash_check);
-        }
-
-        drop(blob);
-
-        // Now that the copy has finished, move the blob into its final location atomically:
-        let updated = self.conn.execute(
-            "UPDATE store SET hash = ? WHERE hash = ?",
-            params![hash.bytes(), &temp_hash],
-        )?;
-
-        if updated != 1 {
-            bail!("Error updating content hash from {:?} to {}", temp_hash, hash);
-        }
-        debug!("save_attachment() done.");
-
-        Ok(())
-    }
-
-    fn prune(&self, opts: backend::PruneOpts) -> Result<backend::PruneResult, Error> {
-        
-        let mut result = PruneResult{
-            dry_run: opts.dry_run,
-            attachments_bytes: 0,
-            attachments_count: 0,
-            items_bytes: 0,
-            items_count: 0,
-        };
-
-        if opts.items {
-            let query = "
-                SELECT 
-                    COUNT(*) AS `count`
-                    , COALESCE(SUM(LENGTH(bytes) + LENGTH(user_id) + LENGTH(signature)), 0) AS size
-                FROM item AS i
-                WHERE NOT EXISTS (
-                    SELECT 1
-                    FROM known_users
-                    WHERE user_id = i.user_id
-                )
-            ";
-
-            let (count, bytes) = self.conn.query_row(
-                query,
-                params![],
-                |row| Ok((row.get::<usize,i64>(0)? as u64, row.get::<usize,i64>(1)? as u64))
-            )?;
-            result.items_count = count;
-            result.items_bytes = bytes;
-        }
-
-        if opts.attachments {
-            let query = if opts.items {
-                "
-                SELECT COUNT(*) AS `count`, COALESCE(SUM(LENGTH(contents)), 0) AS size
-                FROM store AS s
-                WHERE NOT EXISTS (
-                    SELECT 1
-                    FROM item_attachment
-                    INNER JOIN item USING (user_id, signature)
-                    WHERE hash = s.hash
-                )
-                "
-            } else {
-                // We'll delete more if we're also deleting items:
-                "
-                SELECT COUNT(*) AS `count`, COALESCE(SUM(LENGTH(contents)), 0) AS size
-                FROM store AS s
-                WHERE NOT EXISTS (
-                    SELECT 1
-                    FROM item_attachment
-                    INNER JOIN item USING (user_id, signature)
-                    INNER JOIN known_users USING (user_id)
-                    WHERE hash = s.hash
-                )
-                "
-            };
-
-
-            // Find attachments that are no longer referenced.
-            // NOTE: Can't just do a simple LEFT OUTER JOIN and check for NULL.
-            // That could lead to false positives when one ref is dangling but another exists.
-            let (count, bytes) = self.conn.query_row(
-                query,
-                params![],
-                |row| Ok((row.get::<usize, i64>(0)? as u64, row.get::<usize,i64>(1)? as u64)),
-            )?;
-            result.attachments_count = count;
-            result.attachments_bytes = bytes;
-        }
-
-        if opts.dry_run {
-            return Ok(result)
-        }
-
-        // Note: Delete items first, which makes more things available to delete from store if we do that:
-        if opts.items {
-            let query = "
-                DELETE FROM ITEM AS i
-                WHERE NOT EXISTS(
-                    SELECT 1
-                    FROM known_users
-                    WHERE user_id = i.user_id
-                )
-            ";
-            self.conn.execute(query, params![])?;
-
-            // Delete attachments now abandoned:
-            let query = "
-                DELETE FROM item_attachment AS ia
-                WHERE NOT EXISTS (
-                    SELECT 1
-                    FROM item
-                    WHERE user_id = ia.user_id
-                    AND signature = ia.signature
-                )
-            ";
-            self.conn.execute(query, params![])?;
-        }
-
-        if opts.attachments {
-            // Note: We can get by w/ one query here because we already deleted
-            // items from unknown users if that was desired.
-            let query = "
-            DELETE FROM store AS s
-            WHERE NOT EXISTS (
-                SELECT 1
-                FROM item_attachment
-                INNER JOIN item USING (user_id, signature)
-                WHERE hash = s.hash
-            )
-            ";
-            self.conn.execute(query, params![])?;
-        }
-
-        self.conn.execute("VACUUM", params![])?;
-
-        Ok(result)
-    }
-
-    fn usage_by_user(&self, callback: RowCallback<'_, backend::UsageByUserRow>) -> Result<(), Error> {
-        let query = "
-            SELECT
-                s1.user_id,
-                s1.display_name,
-                EXISTS(SELECT 1 FROM known_users WHERE user_id=s1.user_id) AS known_user,
-                EXISTS(SELECT 1 FROM server_user WHERE user_id=s1.user_id) AS server_user,
-                item_count,
-                item_size,
-                IFNULL(attachment_size,0) AS attachment_size,
-                IFNULL(attachment_count,0) AS attachment_count,
-                item_size + COALESCE(attachment_size, 0) as total_size
-            FROM (
-                SELECT
-                    user_id,
-                    p.display_name,
-                    COUNT(*) as item_count,
-                    SUM(length(bytes)) as item_size
-                FROM item AS i
-                LEFT OUTER JOIN profile AS p USING (user_id)
-                GROUP BY user_id
-            ) AS s1
-            LEFT OUTER JOIN (
-                SELECT 
-                    user_id,
-                    SUM(IFNULL(LENGTH(contents),0)) as attachment_size,
-                    COUNT(*) AS attachment_count
-                FROM (
-                    SELECT DISTINCT user_id, hash
-                    FROM item
-                    INNER JOIN item_attachment USING (user_id, signature)
-                ) AS user_hashes
-                INNER JOIN store USING (hash)
-                GROUP BY user_id
-            ) AS s2 USING (user_id)
-            ORDER BY total_size DESC
-        ";
-
-        let mut stmt = self.conn.prepare(&query)?;
-        let mut rows = stmt.query(params![])?;
-
-        let mut fetch_more = true;
-        while fetch_more {
-            let row = match rows.next()? {
-                None => return Ok(()), // No more results.
-                Some(row) => row,
-            };
-            
-            let usage = UsageByUserRow {
-                user_id: UserID::from_vec(row.get(0)?)?,
-                display_name: row.get("display_name")?,
-                items_count: row.get::<&str, i64>("item_count")? as u64,
-                items_bytes: row.get::<&str, i64>("item_size")? as u64,
-                attachments_count: row.get::<&str, i64>("attachment_count")? as u64,
-                attachments_bytes: row.get::<&str, i64>("attachment_size")? as u64,
-                known_user: row.get("known_user")?,
-                server_user: row.get("server_user")?,
-                total_bytes: row.get::<&str, i64>("total_size")? as u64,
-            };
-
-            fetch_more = callback(usage)?;
-        }
-
-        Ok(())
-    }
-}
-
-struct ReplyRow {
-    from_user_id: UserID,
-    from_signature: Signature,
-    to_user_id: UserID,
-    to_signature: Signature,
-}
-
-/// A row from the item_attachment table.
-struct AttachmentRow {
-    user_id: UserID,
-    signature: Signature,
-    name: String,
-
-    // The size of the attachment (in bytes)
-    // Unfortunately must be i64 because SQLite doesn't support u64.
-    size: i64,
-
-    hash: SHA512,
-}
-
-fn index_attachments(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
-    save_attachment_rows(conn, get_attachment_rows(row, item)?)
-}
-
-fn get_attachment_rows(row: &ItemRow, item: &Item) -> Result<Vec<AttachmentRow>, Error> {
-    let mut rows = vec![];
-
-    // TODO: Eventually support attachments for Profiles (and other types?) too:
-    let post = item.get_post();
-
-    let attachments = post.get_attachments().get_file();
-    for attachment in attachments {
-        let row = AttachmentRow {
-            name: attachment.name.clone(),
-            hash: SHA512::from_hash_bytes(attachment.hash.as_slice())?,
-            user_id: row.user.clone(),
-            signature: row.signature.clone(),
-            size: attachment.size as i64,
-        };
-        if row.name.contains("/") || row.name.contains("\\") {
-            bail!("File separators are not allowed in attached file names: {}", row.name);
-        }
-        if row.size < 0 {
-            bail!("File sizes greater than {} bytes are unsupported", i64::MAX);
-        }
-
-        rows.push(row);
-    }
-    return Ok(rows);
-}
-
-fn save_attachment_rows(conn: &rusqlite::Connection, rows: Vec<AttachmentRow>) -> Result<(), Error> {
-    if rows.is_empty() {
-        return Ok(());
-    }
-
-    let mut stmt = conn.prepare("
-        INSERT OR REPLACE INTO item_attachment(user_id, signature, name, hash, size)
-        VALUES (?,?,?,?,?)
-    ")?;
-
-    for row in rows {
-        stmt.execute(params![
-            row.user_id.bytes(),
-            row.signature.bytes(),
-            row.name,
-            row.hash.bytes(),
-            row.size as i64,
-        ])?;
-    }
-
-    Ok(())
-}
-
-/// Get all users that `user_id` follows (and themselves).
-//
-// note: gets ALL follows, could be abused/DoS. (TODO: Protect against unreasonable amount of follows?)
-fn get_follows(conn: &Connection, user_id: &UserID) -> Result<HashMap<UserID, FollowInfo>, Error> {
-    let mut map = HashMap::new();
-
-    let mut stmt = conn.conn.prepare("
-        SELECT
-            f.followed_user_id AS user_id
-            , f.display_name AS follow_display_name
-            , p.display_name
-        FROM follow AS f
-        LEFT OUTER JOIN profile AS p ON (f.followed_user_id = p.user_id)
-        WHERE f.source_user_id = :user_id
-
-        UNION ALL
-        SELECT 
-            p.user_id
-            , p.display_name AS follow_display_name
-            , p.display_name 
-        FROM profile AS p
-        WHERE p.user_id = :user_id
-    ")?;
-
-    let mut rows = stmt.query_named(&[
-        (":user_id", &user_id.bytes()),
-    ])?;
-
-    fn to_info(row: &Row<'_>) -> Result<FollowInfo, Error> {
-        let display_name: Option<String> = row.get("display_name")?;
-        let follow_display_name: Option<String> = row.get("follow_display_name")?;
-        fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
-
-        Ok(FollowInfo {
-            user_id: UserID::from_vec(row.get("user_id")?)?,
-
-            // Prefer displaying the name that this user has assigned to the follow.
-            // TODO: This seems maybe business-logic-y? Should we move it out of Backend?
-            display_name: follow_display_name.filter(not_empty).or(display_name).filter(not_empty),
-        })
-    }
-
-    while let Some(row) = rows.next()? {
-        let info = to_info(row)?;
-        map.insert(info.user_id.clone(), info);
-    }
-
-    Ok(map)
-}
-
-struct FollowInfo {
-    user_id: UserID,
-    // The display name specified by this user, or (fallback) the user they followed.
-    display_name: Option<String>
+//! The sqlite backend just stores all data (including BLOBs) in a single
+//! sqlite3 file. SQLite is great at storing lots of small blobs this way,
+//! but may perform poorly for lots of large files.
+//! 
+//! Mostly, this makes data management trivial since it's all in one file.
+//! But if performance is an issue, attachment contents can instead be kept in
+//! an [`AttachmentStore`]. In that case, the `store` table keeps only the
+//! hash and size of each file, with NULL `contents`.
+
+// TODO: Consider running ANALYZE: https://www.sqlite.org/lang_analyze.html -- results in better default query plans.
+
+mod upgraders;
+
+use std::{io::{Read, Write}, ops::DerefMut, path::Path, collections::HashMap, sync::Arc};
+
+use crate::{backend::UsageByUserRow, markdown::{ToHTML, find_tags}, protos::{Item, Post}, util::AsHex};
+use actix_web::web::Bytes;
+use backend::{BadAttachment, BadItem, FileMeta, MigrateResult, RowCallback, SHA512, attachments::AttachmentStore};
+use futures::Stream;
+use log::{debug, warn};
+use protobuf::Message;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{DatabaseName, NO_PARAMS, OpenFlags, named_params};
+use sodiumoxide::randombytes::randombytes;
+use crate::backend::{self, Block, BlockRow, UserID, Signature, ItemRow, ItemDisplayRow, Quarantine, Quota, ReactionCount, SearchScope, ServerUserQuotas, ThreadRow, Timestamp, ServerUser, QuotaDenyReason};
+
+use anyhow::{Error, bail, Context};
+use rusqlite::{params, OptionalExtension, Row};
+
+use super::{FileStream, PruneResult, TimeSpan};
+
+const CURRENT_VERSION: u32 = 23;
+
+/// Don't follow reply chains any deeper than this in thread_items().
+const MAX_THREAD_DEPTH: u32 = 100;
+
+/// Leaves blocked and quarantined items out of listings.
+/// (Blocked items stay in the `item` table until they're pruned. Quarantined ones stay until they're replaced.)
+/// Expects the item table to be aliased as `i`.
+const NOT_HIDDEN: &str = "
+    NOT EXISTS(SELECT 1 FROM blocked_user AS bu WHERE bu.user_id = i.user_id)
+    AND NOT EXISTS(
+        SELECT 1 FROM blocked_item AS bi
+        WHERE bi.user_id = i.user_id AND bi.signature = i.signature
+    )
+    AND NOT EXISTS(
+        SELECT 1 FROM quarantined_item AS qi
+        WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+    )
+";
+
+type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
+type PConn = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
+
+pub(crate) struct FactoryBuilder {
+    sqlite_file: String,
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+impl FactoryBuilder {
+    pub fn new(sqlite_file: String) -> Self {
+        Self {
+            sqlite_file,
+            attachment_store: None,
+        }
+    }
+
+    /// Save new attachment contents in `store` instead of in the database.
+    pub fn with_attachment_store(mut self, store: Arc<dyn AttachmentStore>) -> Self {
+        self.attachment_store = Some(store);
+        self
+    }
+}
+
+impl backend::FactoryBuilder for FactoryBuilder {
+    fn factory(&self) -> Result<Box<dyn backend::Factory>, Error> {
+        if !self.db_exists()? {
+            bail!("\
+                    Error: Database file not found.\n\
+                    You may need to run `feoblog db init` to create the a database.\
+            ");
+        }
+
+        if self.db_needs_upgrade()? {
+            bail!("\
+                Error: Database needs an upgrade.\n\
+                Run `feoblog db upgrade` to upgrade it.
+            ");
+        }
+
+        self.set_wal()?;
+
+        Ok(Box::new(self.build_factory()?))
+    }
+
+    fn db_exists(&self) -> Result<bool, Error> {
+        let path = Path::new(self.sqlite_file.as_str());
+        Ok(path.exists())
+    }
+
+    fn db_needs_upgrade(&self) -> Result<bool, Error> {
+        let conn = self.connection()?;
+        let db_version = conn.get_version()?;
+        Ok(db_version < CURRENT_VERSION)
+    }
+
+    fn db_upgrade(&self) -> Result<(), Error> {
+        if !self.db_exists()? {
+            bail!("No such database file: {}", self.sqlite_file)
+        }
+
+        let upgraders = upgraders::Upgraders::new();
+        let conn = self.connection()?;
+        upgraders.upgrade(&conn)?;
+
+        Ok(())
+    }
+
+    fn db_create(&self) -> Result<(), Error> {
+        if self.db_exists()? {
+            bail!("Database already exists")
+        }
+
+        println!("Creating database: {}", self.sqlite_file);
+        let pool = self.pool_builder().build(
+            self.connection_manager()
+            // Let sqlite create the DB file since that is explicitly our intention here:
+            .with_flags(OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE)
+        )?;
+
+        let conn = Connection{ 
+            conn: pool.get()?,
+            pool: pool.clone(),
+            attachment_store: None,
+        };
+        conn.initialize()?;
+        println!("Database created.");
+
+
+        // This allows me to be lazy, I can specify new DB additions as version upgrades and not have to keep updating the
+        // main initialize() code. BUT, I probably should if the upgrade path gets too long.
+        drop(conn);
+        drop(pool);
+        if self.db_needs_upgrade()? {
+            self.db_upgrade()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FactoryBuilder {
+    // Shortcut the FactoryBuilder::new() checks and open a connection to a DB that may be in a bad state.
+    // e.g.:
+    // * needs tables created
+    // * needs to be upgraded.
+    fn connection(&self) -> Result<Connection, Error> {
+        let pool = self.pool()?;
+        Ok(
+            Connection { 
+                conn: pool.get()?,
+                pool,
+                attachment_store: self.attachment_store.clone(),
+            }
+        )
+    }
+
+    fn pool(&self) -> Result<r2d2::Pool<SqliteConnectionManager>, r2d2::Error> {
+        self.pool_builder().build(self.connection_manager())
+    }
+
+    fn pool_builder(&self) -> r2d2::Builder<SqliteConnectionManager> {
+        r2d2::Pool::builder()
+        .min_idle(Some(0)) // defaults to max_size. (Which defaults to 10.)
+    }
+
+    fn build_factory(&self) -> Result<Factory, Error> {
+        Ok(Factory{
+            pool: self.pool()?,
+            attachment_store: self.attachment_store.clone(),
+        })
+    }
+
+    fn connection_manager(&self) -> r2d2_sqlite::SqliteConnectionManager {
+        r2d2_sqlite::SqliteConnectionManager
+            ::file(self.sqlite_file.as_str())
+            // Note: explicitly NOT SQLITE_OPEN_CREATE
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_WRITE)
+    }
+
+    /// Enable write-ahead-logging mode for SQLite.
+    /// This greatly improves write performance, which helps in general, but in particular
+    /// when syncing your feed.
+    /// See: https://sqlite.org/wal.html
+    fn set_wal(&self) -> Result<(), Error> {
+        let conn = self.connection()?;
+        let wal_mode = "wal";
+        let new_mode: String = conn.conn.pragma_update_and_check(
+            None,
+            "journal_mode",
+            &wal_mode,
+            |row| { row.get(0) },
+        )?;
+        if wal_mode != &new_mode {
+            warn!("Could not set journal_mode to WAL mode. Using {}", new_mode);
+        } else {
+            debug!("WAL mode set.");
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) struct Factory
+{
+    pool: Pool,
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+impl backend::Factory for Factory
+{
+    fn open(&self) -> Result<Box<dyn backend::Backend>, Error>
+    {
+        let conn = Connection{
+            conn: self.pool.get()?,
+            pool: self.pool.clone(),
+            attachment_store: self.attachment_store.clone(),
+        };
+        Ok(Box::new(conn))
+    }
+
+    fn dyn_clone(&self) -> Box<dyn backend::Factory> {
+        let new_factory = Factory {
+            pool: self.pool.clone(),
+            attachment_store: self.attachment_store.clone(),
+        };
+        Box::new(new_factory)
+    }
+}
+
+
+pub(crate) struct Connection
+{
+    // Mostly, we'll use an open connection:
+    conn: PConn,
+
+    // But also let's get an Arc copy of the pool in case we need to open more connections.
+    pool: Pool,
+
+    /// Where to save attachment contents, if not in the database.
+    attachment_store: Option<Arc<dyn AttachmentStore>>,
+}
+
+trait SqliteConn: DerefMut<Target=rusqlite::Connection> {}
+impl <T: DerefMut<Target=rusqlite::Connection>> SqliteConn for T {}
+
+
+/// private methods for Conneciton
+impl Connection
+{
+    fn initialize(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE version (
+                -- The current version of the database schema.
+                version INTEGER
+            )
+        ")?;
+        self.run("INSERT INTO version VALUES(3)")?;
+
+        self.run("
+            CREATE TABLE item(
+                -- An Item is the core data structure of FeoBlog.
+                -- It is a BLOB of protobuf v3 bytes defining an item in a
+                -- user's collection of items
+                bytes BLOB
+
+                -- An item must be accompanied by a nacl public key (user_id)
+                -- and (detached) signature so that its authenticity can be
+                -- verified.
+                , user_id BLOB
+                , signature BLOB
+
+                -- A copy of the signed timestamp from within `bytes`
+                -- this allows for sorting queries by timestamp.
+                , unix_utc_ms INTEGER
+
+                -- The date this item was received by this server. May differ
+                -- from above.
+                , received_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX item_primary_idx
+            ON item(user_id, signature)
+        ")?;
+        self.run("
+            CREATE INDEX item_user_chrono_idx
+            ON item(user_id, unix_utc_ms)
+        ")?;
+        self.run("
+            CREATE INDEX item_user_chrono_received_idx
+            ON item(user_id, received_utc_ms)
+        ")?;
+        self.run("
+            CREATE INDEX item_unix_utc_idx
+            ON item(unix_utc_ms)
+        ")?;
+        self.run("
+            CREATE INDEX item_received_utc_idx
+            ON item(received_utc_ms)
+        ")?;
+
+        self.run("
+            CREATE TABLE server_user(
+                -- These users have been granted direct access to the server.
+                
+                user_id BLOB
+
+                -- Information about this user.
+                -- Not displayed on the web UI, just here to let the server
+                -- admin leave a human-readable note about who this user is.
+                , notes TEXT
+
+                -- bool 0/1 -- should this user's posts appear on the home page
+                -- of this server?
+                , on_homepage INTEGER
+
+                -- How many bytes will the server cache for this user?
+                -- 0 = unlimited.
+                , max_bytes INTEGER 
+            )
+        ")?;
+
+        self.run("
+            CREATE UNIQUE INDEX server_user_primary_idx
+            ON server_user(user_id)
+        ")?;
+
+        self.run("
+            CREATE INDEX server_user_homepage_idx
+            ON server_user(on_homepage, user_id)
+        ")?;
+
+
+        self.run("
+            CREATE TABLE follow(
+                -- Lists which users follow which other users.
+                -- Always represents the latest Profile saved by a user.
+                source_user_id BLOB,
+                followed_user_id BLOB,
+                display_name TEXT
+            )
+        ")?;
+
+        self.run("
+            CREATE UNIQUE INDEX follow_primary_idx
+            ON follow(source_user_id, followed_user_id)
+        ")?;
+
+        self.run("
+            CREATE TABLE profile(
+                -- Always contains a reference to the latest profile uploaded by a user
+                user_id BLOB,
+                signature BLOB,
+                display_name TEXT
+            )
+        ")?;
+
+        self.run("
+            CREATE UNIQUE INDEX profile_primary_idx
+            ON profile(user_id)
+        ")?;
+
+        // See upgraders.rs for newer DB additions.
+
+        Ok(())
+    }
+
+    fn run(&self, sql: &str) -> Result<(), Error>
+    {
+        self.conn.execute(sql, params![])?;
+        Ok(())
+    }
+
+    /// The store for attachment contents that aren't kept in the database.
+    fn attachment_store(&self) -> Result<&Arc<dyn AttachmentStore>, Error> {
+        match &self.attachment_store {
+            Some(store) => Ok(store),
+            None => bail!("Some attachments are stored outside of the database. Use --attachments-dir or --attachments-s3-bucket to find them."),
+        }
+    }
+
+    fn get_version(&self) -> Result<u32, Error>
+    {
+        let table_count: u32  = self.conn.prepare(
+            "SELECT count()
+            FROM sqlite_master
+            WHERE type = 'table'
+            AND name = 'version'
+            "
+        )?.query_row(
+            params![],
+            |row|  Ok(row.get(0)?)
+        )?;
+
+        if table_count == 0 {
+            bail!("No version table found. This may not be a valid feoblog database.")
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT version from version"
+        )?; 
+        let versions = stmt.query_map(
+            params![],
+            |row| -> rusqlite::Result<u32> { Ok(row.get(0)?) }
+        )?;
+
+        let versions: Vec<u32> = versions.take(2).collect::<rusqlite::Result<Vec<u32>>>()?;
+
+        if versions.len() == 0 {
+            bail!("Found no version in the database. This may not be a valid feoblog database.");
+        }
+        if versions.len() > 1 {
+            bail!("Found more than one version in the database. This database may have been corrupted.");
+        }
+
+        Ok(versions[0])
+    }
+
+    fn set_version(&self, version: u32) -> Result<(), Error> {
+        self.conn.execute("UPDATE version SET version = ?", params![version])?;
+
+        Ok(())
+    }
+
+    fn all_items<'a>(&self, after_uid: &Option<UserID>, after_sig: &Option<Signature>, callback: RowCallback<'a, ItemRow>) -> Result<(), Error>{
+        let mut stmt;
+        let mut rows;
+        if let (Some(uid), Some(sig)) = (after_uid, after_sig) {
+            stmt = self.conn.prepare("
+                SELECT
+                    user_id,
+                    signature,
+                    unix_utc_ms,
+                    received_utc_ms,
+                    bytes
+                FROM item
+                WHERE (user_id > :uid)
+                OR (user_id = :uid AND signature > :sig)
+                ORDER BY user_id, signature
+            ")?;
+            rows = stmt.query_named(named_params! {
+                "uid": uid.bytes(),
+                "sig": sig.bytes(),
+            })?;
+        } else {
+            // Start from the beginning:
+            stmt = self.conn.prepare("
+                SELECT
+                    user_id,
+                    signature,
+                    unix_utc_ms,
+                    received_utc_ms,
+                    bytes
+                FROM item
+                ORDER BY user_id, signature
+            ")?;
+            rows = stmt.query(params![])?;
+        }
+
+        let mut fetch_more = true;
+        while fetch_more {
+            let row = match rows.next()? {
+                None => return Ok(()), // No more results.
+                Some(row) => row,
+            };
+
+            let ir = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp {  unix_utc_ms: row.get(2)? },
+                received: Timestamp {  unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            fetch_more = callback(ir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the quota for a user, or None if they're not allowed to post here at all.
+    fn user_quota(&self, user_id: &UserID) -> Result<Option<Quota>, Error> {
+        if let Some(quotas) = get_server_user_quotas(&self.conn, user_id)? {
+            return Ok(Some(quotas.own));
+        };
+
+        // Check those followed by "server users" (or the successors of those users),
+        // and authors of items that they (or their follows) have shared:
+        let mut statement = self.conn.prepare("
+            SELECT
+                su.follow_max_item_bytes
+                , su.follow_max_attachment_bytes
+            FROM
+                follow AS f
+                INNER JOIN server_user AS su ON su.user_id = f.source_user_id
+                LEFT OUTER JOIN profile AS p ON p.user_id = f.followed_user_id
+            WHERE
+                (f.followed_user_id = :user_id OR p.successor_user_id = :user_id)
+                -- Revoked server users can no longer vouch for anyone:
+                AND su.revoked = 0
+            UNION ALL
+            -- Authors of items shared by known users, so that we can store the shared items.
+            -- (put_item() only accepts those items from them.) A share vouches for them like a follow:
+            SELECT
+                su.follow_max_item_bytes
+                , su.follow_max_attachment_bytes
+            FROM
+                share AS sh
+                INNER JOIN server_user AS su ON (
+                    su.user_id = sh.from_user_id
+                    OR EXISTS(
+                        SELECT 1
+                        FROM follow AS f
+                        LEFT OUTER JOIN profile AS p ON p.user_id = f.followed_user_id
+                        WHERE
+                            f.source_user_id = su.user_id
+                            AND (f.followed_user_id = sh.from_user_id OR p.successor_user_id = sh.from_user_id)
+                    )
+                )
+            WHERE
+                sh.to_user_id = :user_id
+                AND su.revoked = 0
+        ")?;
+        let mut rows = statement.query_named(&[(":user_id", &user_id.bytes())])?;
+        let mut follow_quota: Option<Quota> = None;
+        while let Some(row) = rows.next()? {
+            let quota = to_quota(row, 0)?;
+            follow_quota = Some(match follow_quota {
+                None => quota,
+                Some(other) => other.most_generous(quota),
+            });
+        }
+        if follow_quota.is_some() {
+            return Ok(follow_quota);
+        }
+
+        // TODO: When "pinning" is implemented, allow posting items which are pinned by server users and their follows.
+        // TODO: I've since decided that "pinning" might be prone to abuse. I should write up my thoughts there.
+
+        Ok(None)
+    }
+
+    /// Total bytes of the Items we store for a user.
+    fn user_item_bytes(&self, user_id: &UserID) -> Result<u64, Error> {
+        let bytes: i64 = self.conn.query_row(
+            "SELECT IFNULL(SUM(LENGTH(bytes)), 0) FROM item WHERE user_id = ?",
+            params![user_id.bytes()],
+            |row| row.get(0),
+        )?;
+        Ok(bytes as u64)
+    }
+
+    /// Total bytes of the (distinct) attachments we store for a user.
+    /// Like usage_by_user(), attachments shared by multiple users count against each of them.
+    fn user_attachment_bytes(&self, user_id: &UserID) -> Result<u64, Error> {
+        let bytes: i64 = self.conn.query_row("
+            SELECT IFNULL(SUM(size), 0)
+            FROM (
+                SELECT DISTINCT hash
+                FROM item
+                INNER JOIN item_attachment USING (user_id, signature)
+                WHERE user_id = ?
+            ) AS user_hashes
+            INNER JOIN store USING (hash)
+        ", params![user_id.bytes()], |row| row.get(0))?;
+        Ok(bytes as u64)
+    }
+}
+
+fn get_server_user_quotas(conn: &rusqlite::Connection, user: &UserID) -> Result<Option<ServerUserQuotas>, Error> {
+    let mut stmt = conn.prepare("
+        SELECT
+            max_item_bytes
+            , max_attachment_bytes
+            , follow_max_item_bytes
+            , follow_max_attachment_bytes
+        FROM server_user
+        WHERE user_id = ?
+    ")?;
+    let mut rows = stmt.query(params![user.bytes()])?;
+    let row = match rows.next()? {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    Ok(Some(ServerUserQuotas{
+        own: to_quota(row, 0)?,
+        follows: to_quota(row, 2)?,
+    }))
+}
+
+/// Read a Quota from the (item_bytes, attachment_bytes) columns starting at `index`.
+fn to_quota(row: &Row<'_>, index: usize) -> Result<Quota, Error> {
+    let item_bytes: Option<i64> = row.get(index)?;
+    let attachment_bytes: Option<i64> = row.get(index + 1)?;
+    Ok(Quota{
+        item_bytes: item_bytes.map(|b| b as u64),
+        attachment_bytes: attachment_bytes.map(|b| b as u64),
+    })
+}
+
+/// We're saving a profile. If it's new, update the profile and follow tables.
+fn update_profile(conn: &rusqlite::Savepoint, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
+
+    let previous: Option<(i64, bool)> =  
+        conn.prepare("
+            SELECT i.unix_utc_ms, p.revoked
+            FROM profile AS p
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE user_id = ?
+        ")?
+        .query(params![ item_row.user.bytes() ])?
+        .next()?
+        .map(|row| -> rusqlite::Result<_> { Ok((row.get(0)?, row.get(1)?)) })
+        .transpose()?
+    ;
+
+    let revoking = item.get_profile().has_revocation();
+
+    if let Some((prev_timestamp, prev_revoked)) = previous {
+        // Revocations are permanent:
+        if prev_revoked {
+            return Ok(())
+        }
+
+        // Never replace a newer profile's metadata, unless we're revoking:
+        if prev_timestamp >= item.timestamp_ms_utc && !revoking {
+            return Ok(())
+        }
+    }
+
+    // Replace all follows with new ones listed in the profile:
+    conn.execute("DELETE FROM follow WHERE source_user_id = ?", params![item_row.user.bytes()])?;
+
+    // Behavior is undefined if duplicate follows exist in a Profile. So we just replace:
+    let mut add_follow = conn.prepare("
+        INSERT OR REPLACE INTO follow (source_user_id, followed_user_id, display_name)
+        VALUES (?, ?, ?)
+    ")?;
+
+    for follow in item.get_profile().get_follows() {
+        add_follow.execute(params![
+            item_row.user.bytes(),
+            follow.get_user().get_bytes(),
+            follow.get_display_name(),
+        ])?;
+    }
+
+    // Note: The successor's counter-signature should've been verified before we got here.
+    let profile = item.get_profile();
+    let successor: Option<&[u8]> = if profile.has_successor() {
+        Some(profile.get_successor().get_user_id().get_bytes())
+    } else {
+        None
+    };
+
+    let mut add_profile = conn.prepare("
+        INSERT OR REPLACE INTO profile(user_id, signature, display_name, revoked, successor_user_id)
+        VALUES (?,?,?,?,?)
+    ")?;
+    add_profile.execute(params![
+        item_row.user.bytes(),
+        item_row.signature.bytes(),
+        profile.get_display_name(),
+        revoking,
+        successor,
+    ])?;
+
+    if revoking {
+        conn.execute(
+            "UPDATE server_user SET revoked = 1 WHERE user_id = ?",
+            params![item_row.user.bytes()]
+        )?;
+    }
+
+    Ok(())
+}
+
+fn save_comment_reply(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    if !item.has_comment() {
+        return Ok(())
+    }
+
+    let comment = item.get_comment();
+    let reply = ReplyRow {
+        from_user_id: row.user.clone(),
+        from_signature: row.signature.clone(),
+        to_user_id: UserID::from_vec(comment.get_reply_to().get_user_id().get_bytes().into())?,
+        to_signature: Signature::from_vec(comment.get_reply_to().get_signature().get_bytes().into())?,
+    };
+
+    save_reply_rows(conn, &[reply])
+}
+
+/// We're saving a Delete. Remove the item it references, and anything we've indexed for it.
+fn delete_item(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let target = Signature::from_vec(item.get_delete().get_signature().get_bytes().into())?;
+
+    // Revisions of a deleted Post are deleted along with it:
+    let mut targets = revision_signatures(conn, &row.user, &target)?;
+    targets.push(target);
+
+    for target in &targets {
+        conn.execute("
+            INSERT OR REPLACE INTO deleted_item(user_id, signature, deleted_by)
+            VALUES (?, ?, ?)
+        ", params![
+            row.user.bytes(),
+            target.bytes(),
+            row.signature.bytes(),
+        ])?;
+
+        remove_item(conn, &row.user, target)?;
+    }
+
+    Ok(())
+}
+
+/// Signatures of all Revisions of a Post.
+fn revision_signatures(conn: &rusqlite::Connection, user: &UserID, original: &Signature) -> Result<Vec<Signature>, Error> {
+    let mut stmt = conn.prepare("
+        SELECT signature
+        FROM item_revision
+        WHERE user_id = ?
+        AND original_signature = ?
+    ")?;
+    let rows = stmt.query_map(params![user.bytes(), original.bytes()], |row| row.get::<_, Vec<u8>>(0))?;
+
+    let mut signatures = vec![];
+    for signature in rows {
+        signatures.push(Signature::from_vec(signature?)?);
+    }
+    Ok(signatures)
+}
+
+/// Profiles and Deletes can not be deleted. But a Delete may arrive before the
+/// item it targets (ex: when syncing out of order), so we only find out once
+/// that item arrives. Forget that the item was "deleted".
+fn ignore_early_delete(conn: &rusqlite::Connection, row: &ItemRow) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM deleted_item WHERE user_id = ? AND signature = ?",
+        params![row.user.bytes(), row.signature.bytes()],
+    )?;
+    Ok(())
+}
+
+/// Remove an item, and everything we've indexed for it.
+fn remove_item(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<(), Error> {
+    // If this is a Revision, its Post was indexed with its contents:
+    let original: Option<Vec<u8>> = conn.query_row(
+        "SELECT original_signature FROM item_revision WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+        |row| row.get(0),
+    ).optional()?;
+
+    conn.execute(
+        "DELETE FROM item WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM item_attachment WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM reply WHERE from_user_id = ? AND from_signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM item_revision WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM reaction WHERE from_user_id = ? AND from_signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM share WHERE from_user_id = ? AND from_signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM direct_message WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM item_tag WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "DELETE FROM mention WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    delete_item_text(conn, user, signature)?;
+
+    if let Some(original) = original {
+        reindex_post(conn, user, &Signature::from_vec(original)?)?;
+    }
+
+    // Note: Attachment contents in `store` may be shared with other items, so
+    // we leave those for `db prune` to clean up.
+
+    Ok(())
+}
+
+/// Re-index a Post's tags, mentions & text from its latest remaining Revision
+/// (or the Post itself), after one of its Revisions has been removed.
+fn reindex_post(conn: &rusqlite::Connection, user: &UserID, original: &Signature) -> Result<(), Error> {
+    let latest = latest_revision_signature(conn, user, original)?;
+    let signature = latest.as_ref().unwrap_or(original);
+    let bytes: Option<Vec<u8>> = conn.query_row(
+        "SELECT bytes FROM item WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+        |row| row.get(0),
+    ).optional()?;
+
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        // The Post hasn't arrived yet. It'll be indexed when it does.
+        None => return Ok(()),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&bytes)?;
+    let post = if item.has_revision() { item.get_revision().get_post() } else { item.get_post() };
+
+    save_item_tags(conn, user, original, &post_tags(post))?;
+    save_item_mentions(conn, user, original, &post.get_body().md_get_mentions())?;
+    save_item_text(conn, user, original, post.get_title(), post.get_body())?;
+
+    Ok(())
+}
+
+fn save_revision(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let original = Signature::from_vec(item.get_revision().get_original().get_bytes().into())?;
+
+    conn.execute("
+        INSERT OR REPLACE INTO item_revision(user_id, signature, original_signature, unix_utc_ms)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        row.user.bytes(),
+        row.signature.bytes(),
+        original.bytes(),
+        row.timestamp.unix_utc_ms,
+    ])?;
+
+    Ok(())
+}
+
+/// Find the signature of the newest Revision of a Post, if it has been revised.
+fn latest_revision_signature(conn: &rusqlite::Connection, user: &UserID, original: &Signature) -> Result<Option<Signature>, Error> {
+    let signature: Option<Vec<u8>> = conn.query_row(
+        "
+            SELECT signature
+            FROM item_revision
+            WHERE user_id = ?
+            AND original_signature = ?
+            ORDER BY unix_utc_ms DESC, signature DESC
+            LIMIT 1
+        ",
+        params![user.bytes(), original.bytes()],
+        |row| row.get(0)
+    ).optional()?;
+
+    signature.map(Signature::from_vec).transpose()
+}
+
+/// Find the Post contents to index for a Post, or a Revision of a Post.
+///
+/// Posts are always indexed under the original Post's signature, using the
+/// contents of its latest Revision. Returns that signature & the contents, or None
+/// if there's nothing to (re)index for this item.
+fn get_indexed_post<'i>(conn: &rusqlite::Connection, row: &ItemRow, item: &'i Item) -> Result<Option<(Signature, &'i Post)>, Error> {
+    if item.has_post() {
+        if latest_revision_signature(conn, &row.user, &row.signature)?.is_some() {
+            // The latest Revision's contents win.
+            return Ok(None);
+        }
+        return Ok(Some((row.signature.clone(), item.get_post())));
+    }
+
+    if item.has_revision() {
+        let original = Signature::from_vec(item.get_revision().get_original().get_bytes().into())?;
+        if latest_revision_signature(conn, &row.user, &original)?.as_ref() != Some(&row.signature) {
+            // We've already indexed a newer Revision.
+            return Ok(None);
+        }
+        return Ok(Some((original, item.get_revision().get_post())));
+    }
+
+    Ok(None)
+}
+
+/// Find the #tags to index for a Post, or a Revision of a Post.
+fn get_item_tags(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<Option<(Signature, Vec<String>)>, Error> {
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post_tags(post))))
+}
+
+fn post_tags(post: &Post) -> Vec<String> {
+    let mut tags = find_tags(post.get_title());
+    for tag in post.get_body().md_get_tags() {
+        if !tags.contains(&tag) { tags.push(tag); }
+    }
+    tags
+}
+
+/// Replace the tags indexed for an item.
+fn save_item_tags(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, tags: &[String]) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM item_tag WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+
+    let mut stmt = conn.prepare("
+        INSERT OR IGNORE INTO item_tag(user_id, signature, tag)
+        VALUES (?, ?, ?)
+    ")?;
+    for tag in tags {
+        stmt.execute(params![user.bytes(), signature.bytes(), tag])?;
+    }
+
+    Ok(())
+}
+
+/// Find the @mentions to index for a Post, Revision, or Comment.
+///
+/// Like tags, mentions in a Post are indexed under the original Post's signature.
+fn get_item_mentions(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<Option<(Signature, Vec<UserID>)>, Error> {
+    if item.has_comment() {
+        return Ok(Some((row.signature.clone(), item.get_comment().get_text().md_get_mentions())));
+    }
+
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post.get_body().md_get_mentions())))
+}
+
+/// Replace the mentions indexed for an item.
+fn save_item_mentions(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, mentions: &[UserID]) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM mention WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+
+    let mut stmt = conn.prepare("
+        INSERT OR IGNORE INTO mention(user_id, signature, mentioned_user_id)
+        VALUES (?, ?, ?)
+    ")?;
+    for mentioned in mentions {
+        stmt.execute(params![user.bytes(), signature.bytes(), mentioned.bytes()])?;
+    }
+
+    Ok(())
+}
+
+/// Find the (title, body) text to index for searching a Post, Revision, or Comment.
+///
+/// Like tags, the text of a Post is indexed under the original Post's signature.
+fn get_item_text(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<Option<(Signature, String, String)>, Error> {
+    if item.has_comment() {
+        return Ok(Some((row.signature.clone(), String::new(), item.get_comment().get_text().to_string())));
+    }
+
+    let indexed = get_indexed_post(conn, row, item)?;
+    Ok(indexed.map(|(signature, post)| (signature, post.get_title().to_string(), post.get_body().to_string())))
+}
+
+/// Replace the text indexed for an item.
+fn save_item_text(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, title: &str, body: &str) -> Result<(), Error> {
+    delete_item_text(conn, user, signature)?;
+
+    conn.execute(
+        "INSERT INTO search_item(user_id, signature) VALUES (?, ?)",
+        params![user.bytes(), signature.bytes()],
+    )?;
+    conn.execute(
+        "INSERT INTO search_text(rowid, title, body) VALUES (?, ?, ?)",
+        params![conn.last_insert_rowid(), title, body],
+    )?;
+
+    Ok(())
+}
+
+fn delete_item_text(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<(), Error> {
+    conn.execute("
+        DELETE FROM search_text WHERE rowid IN (
+            SELECT id FROM search_item WHERE user_id = ? AND signature = ?
+        )
+    ", params![user.bytes(), signature.bytes()])?;
+    conn.execute(
+        "DELETE FROM search_item WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+    )?;
+
+    Ok(())
+}
+
+fn save_reaction(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let reaction = item.get_reaction();
+    let reply_to = reaction.get_reply_to();
+    let to_user_id = UserID::from_vec(reply_to.get_user_id().get_bytes().into())?;
+    let to_signature = Signature::from_vec(reply_to.get_signature().get_bytes().into())?;
+
+    conn.execute("
+        INSERT OR REPLACE INTO reaction(from_user_id, from_signature, to_user_id, to_signature, reaction)
+        VALUES (?, ?, ?, ?, ?)
+    ", params![
+        row.user.bytes(),
+        row.signature.bytes(),
+        to_user_id.bytes(),
+        to_signature.bytes(),
+        reaction.get_reaction(),
+    ])?;
+
+    Ok(())
+}
+
+fn save_share(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let shared = item.get_share().get_item();
+    let to_user_id = UserID::from_vec(shared.get_user_id().get_bytes().into())?;
+    let to_signature = Signature::from_vec(shared.get_signature().get_bytes().into())?;
+
+    conn.execute("
+        INSERT OR REPLACE INTO share(from_user_id, from_signature, to_user_id, to_signature)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        row.user.bytes(),
+        row.signature.bytes(),
+        to_user_id.bytes(),
+        to_signature.bytes(),
+    ])?;
+
+    Ok(())
+}
+
+fn save_direct_message(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let recipient = UserID::from_vec(item.get_direct_message().get_recipient().get_bytes().into())?;
+
+    conn.execute("
+        INSERT OR REPLACE INTO direct_message(user_id, signature, recipient_user_id, unix_utc_ms)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        row.user.bytes(),
+        row.signature.bytes(),
+        recipient.bytes(),
+        row.timestamp.unix_utc_ms,
+    ])?;
+
+    Ok(())
+}
+
+/// Get the item shared by the given (Share) item, if we have it.
+fn get_shared_row(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<Option<Box<ItemDisplayRow>>, Error> {
+    let mut stmt = conn.prepare(&format!("
+        SELECT
+            i.user_id
+            , i.signature
+            , i.unix_utc_ms
+            , i.received_utc_ms
+            , i.bytes
+            , p.display_name
+        FROM share AS s
+        INNER JOIN item AS i ON (
+            i.user_id = s.to_user_id
+            AND i.signature = s.to_signature
+        )
+        LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+        WHERE
+            s.from_user_id = ?
+            AND s.from_signature = ?
+            AND {not_hidden}
+        ",
+        not_hidden=NOT_HIDDEN,
+    ))?;
+
+    let mut rows = stmt.query(params![user.bytes(), signature.bytes()])?;
+    let row = match rows.next()? {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    let item = ItemRow{
+        user: UserID::from_vec(row.get(0)?)?,
+        signature: Signature::from_vec(row.get(1)?)?,
+        timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+        received: Timestamp{ unix_utc_ms: row.get(3)? },
+        item_bytes: row.get(4)?,
+    };
+
+    Ok(Some(Box::new(ItemDisplayRow{
+        reactions: vec![],
+        display_name: row.get(5)?,
+        item,
+        // We don't display shares of shares:
+        shared: None,
+    })))
+}
+
+fn get_reaction_counts(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error> {
+    let mut stmt = conn.prepare(&format!("
+        SELECT r.reaction, COUNT(DISTINCT r.from_user_id) AS reaction_count
+        FROM reaction AS r
+        INNER JOIN item AS i ON (
+            i.user_id = r.from_user_id
+            AND i.signature = r.from_signature
+        )
+        WHERE
+            r.to_user_id = ?
+            AND r.to_signature = ?
+            AND EXISTS(SELECT user_id FROM known_users WHERE user_id = r.from_user_id)
+            AND {not_hidden}
+        GROUP BY r.reaction
+        ORDER BY reaction_count DESC, r.reaction ASC
+        ",
+        not_hidden=NOT_HIDDEN,
+    ))?;
+
+    let mut rows = stmt.query(params![user.bytes(), signature.bytes()])?;
+    let mut counts = vec![];
+    while let Some(row) = rows.next()? {
+        counts.push(ReactionCount {
+            reaction: row.get(0)?,
+            count: row.get::<_, i64>(1)? as u64,
+        });
+    }
+
+    Ok(counts)
+}
+
+fn save_reply_rows(conn: &rusqlite::Connection, replies: &[ReplyRow]) -> Result<(), Error> {
+    let mut stmt = conn.prepare("
+        INSERT INTO reply (from_user_id, from_signature, to_user_id, to_signature)
+        VALUES (?,?,?,?)
+    ")?;
+    for reply in replies {
+        stmt.execute(params![
+            reply.from_user_id.bytes(),
+            reply.from_signature.bytes(),
+            reply.to_user_id.bytes(),
+            reply.to_signature.bytes(),
+        ])?;
+    }
+    
+    Ok(())
+}
+
+/// The SQL to filter & order items by a TimeSpan.
+struct TimeSpanSql {
+    order: &'static str,
+    start: Option<CursorSql>,
+    end: Option<CursorSql>,
+}
+
+/// Params for one end of a TimeSpan.
+struct CursorSql {
+    timestamp: i64,
+    user_id: Option<Vec<u8>>,
+    signature: Option<Vec<u8>>,
+}
+
+impl CursorSql {
+    fn new(cursor: &backend::Cursor) -> Self {
+        let (user, signature) = match &cursor.item {
+            None => (None, None),
+            Some((user, signature)) => (Some(user.bytes().to_vec()), Some(signature.bytes().to_vec())),
+        };
+        Self {
+            timestamp: cursor.timestamp.unix_utc_ms,
+            user_id: user,
+            signature,
+        }
+    }
+}
+
+impl TimeSpanSql {
+    fn new(time_span: &TimeSpan) -> Self {
+        Self {
+            order: if time_span.is_before() { "DESC" } else { "ASC" },
+            start: time_span.start().map(CursorSql::new),
+            end: time_span.end().map(CursorSql::new),
+        }
+    }
+
+    /// Filter the item table (with alias `table`, ex: "i.") by the TimeSpan.
+    /// Uses the named params from params().
+    fn filter(&self, table: &str) -> String {
+        let mut bounds = vec![];
+        if self.start.is_some() {
+            bounds.push(Self::bound(table, ">", "start"));
+        }
+        if self.end.is_some() {
+            bounds.push(Self::bound(table, "<", "end"));
+        }
+        bounds.join(" AND ")
+    }
+
+    fn bound(table: &str, op: &str, name: &str) -> String {
+        // If the cursor has no item, the NULL comparison excludes all items at its timestamp.
+        format!(
+            "(
+                {t}unix_utc_ms {op} :{n}_timestamp
+                OR ({t}unix_utc_ms = :{n}_timestamp AND ({t}user_id, {t}signature) {op} (:{n}_user_id, :{n}_signature))
+            )",
+            t=table,
+            op=op,
+            n=name,
+        )
+    }
+
+    fn order(&self, table: &str) -> String {
+        format!("{t}unix_utc_ms {o}, {t}user_id {o}, {t}signature {o}", t=table, o=self.order)
+    }
+
+    fn params(&self) -> Vec<(&'static str, &dyn rusqlite::ToSql)> {
+        let mut params: Vec<(&'static str, &dyn rusqlite::ToSql)> = vec![];
+        if let Some(start) = &self.start {
+            params.push((":start_timestamp", &start.timestamp));
+            params.push((":start_user_id", &start.user_id));
+            params.push((":start_signature", &start.signature));
+        }
+        if let Some(end) = &self.end {
+            params.push((":end_timestamp", &end.timestamp));
+            params.push((":end_user_id", &end.user_id));
+            params.push((":end_signature", &end.signature));
+        }
+        params
+    }
+}
+
+impl backend::Backend for Connection
+{
+    fn homepage_items<'a>(
+        &self,
+        time_span: TimeSpan,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+
+        let span = TimeSpanSql::new(&time_span);
+        let query = format!(
+            "
+                SELECT
+                    user_id
+                    , i.signature
+                    , unix_utc_ms
+                    , received_utc_ms
+                    , bytes
+                    , p.display_name
+                FROM item AS i
+                LEFT OUTER JOIN profile AS p USING (user_id)
+                WHERE {filter_ts}
+                AND user_id IN (
+                    SELECT user_id
+                    FROM server_user
+                    WHERE on_homepage = 1
+                )
+                AND NOT EXISTS(
+                    SELECT 1 FROM direct_message AS dm
+                    WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+                )
+                AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query_named(&span.params())?;
+
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                reactions: vec![],
+                shared: get_shared_row(&self.conn, &item.user, &item.signature)?,
+                item,
+                display_name: row.get(5)?,
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+
+        let span = TimeSpanSql::new(&time_span);
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , unix_utc_ms
+                    , received_utc_ms
+                    , bytes
+                FROM item AS i
+                WHERE
+                    {filter_ts}
+                    AND user_id = :user_id
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND NOT EXISTS(
+                        SELECT 1 FROM direct_message AS dm
+                        WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+                    )
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let user_id = user.bytes();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":user_id", &user_id)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(item)
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = convert(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn reply_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let mut stmt = self.conn.prepare(&format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item AS i
+            INNER JOIN reply AS r ON (
+                r.from_user_id = i.user_id
+                AND r.from_signature = i.signature
+            )
+            WHERE
+                {filter_ts}
+                AND r.to_user_id = :user_id
+                AND r.to_signature = :signature
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        ))?;
+
+        let user_id = user.bytes();
+        let signature = signature.bytes();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":user_id", &user_id), (":signature", &signature)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(item)
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = convert(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn thread_items<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        limit: usize,
+        callback: RowCallback<'a, ThreadRow>,
+    ) -> Result<(), Error> {
+        // `path` concatenates the (fixed-width) timestamp & signature of each
+        // ancestor, so that ordering by it gives us a depth-first traversal.
+        // Timestamps are written in (two's complement) hex, prefixed so that
+        // negative ones sort first.
+        // Ordering the recursive query by `path` also makes SQLite walk the
+        // thread depth-first, so it can stop once it has found `limit` Comments.
+        // Blocked Comments are left out, along with their replies.
+        let mut stmt = self.conn.prepare(&format!("
+            WITH RECURSIVE thread(user_id, signature, depth, path) AS (
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , 1
+                    , (CASE WHEN i.unix_utc_ms < 0 THEN '0' ELSE '1' END) || printf('%016X', i.unix_utc_ms) || hex(i.signature)
+                FROM reply AS r
+                INNER JOIN item AS i ON (
+                    i.user_id = r.from_user_id
+                    AND i.signature = r.from_signature
+                )
+                WHERE
+                    r.to_user_id = :user_id
+                    AND r.to_signature = :signature
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+
+                UNION ALL
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , t.depth + 1
+                    , t.path || '/' || (CASE WHEN i.unix_utc_ms < 0 THEN '0' ELSE '1' END) || printf('%016X', i.unix_utc_ms) || hex(i.signature)
+                FROM thread AS t
+                INNER JOIN reply AS r ON (
+                    r.to_user_id = t.user_id
+                    AND r.to_signature = t.signature
+                )
+                INNER JOIN item AS i ON (
+                    i.user_id = r.from_user_id
+                    AND i.signature = r.from_signature
+                )
+                WHERE
+                    t.depth < :max_depth
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+
+                ORDER BY 4
+                LIMIT :limit
+            )
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+                , p.display_name
+                , t.depth
+            FROM thread AS t
+            INNER JOIN item AS i USING (user_id, signature)
+            LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+            ORDER BY t.path
+        ",
+            not_hidden=NOT_HIDDEN,
+        ))?;
+
+        let mut rows = stmt.query_named(&[
+            (":user_id", &user.bytes()),
+            (":signature", &signature.bytes()),
+            (":max_depth", &MAX_THREAD_DEPTH),
+            (":limit", &(limit as i64)),
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            let thread_row = ThreadRow{
+                item: ItemDisplayRow{
+                    reactions: vec![],
+                    // Only Comments reply to items:
+                    shared: None,
+                    item,
+                    display_name: row.get(5)?,
+                },
+                depth: row.get(6)?,
+            };
+            let result = callback(thread_row)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn item_revisions<'a>(
+        &self,
+        user: &UserID,
+        original: &Signature,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let mut stmt = self.conn.prepare(&format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+            FROM item AS i
+            INNER JOIN item_revision AS r USING (user_id, signature)
+            WHERE
+                {filter_ts}
+                AND r.user_id = :user_id
+                AND r.original_signature = :original
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        ))?;
+
+        let user_id = user.bytes();
+        let original = original.bytes();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":user_id", &user_id), (":original", &original)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        while let Some(row) = rows.next()? {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn inbox_items<'a>(
+        &self,
+        recipient: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+        let mut stmt = self.conn.prepare(&format!("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+            FROM direct_message AS dm
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE
+                dm.recipient_user_id = :recipient
+                AND {filter_ts}
+                AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                AND {not_hidden}
+            ORDER BY {order}
+        ",
+            filter_ts=span.filter("dm."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("dm."),
+        ))?;
+
+        let recipient = recipient.bytes();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":recipient", &recipient)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(item)
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = convert(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn latest_revision(&self, user: &UserID, original: &Signature) -> Result<Option<ItemRow>, Error> {
+        match latest_revision_signature(&self.conn, user, original)? {
+            None => Ok(None),
+            Some(signature) => self.user_item(user, &signature),
+        }
+    }
+
+    fn shared_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemDisplayRow>, Error> {
+        Ok(get_shared_row(&self.conn, user, signature)?.map(|row| *row))
+    }
+
+    fn reaction_counts(&self, user: &UserID, signature: &Signature) -> Result<Vec<ReactionCount>, Error> {
+        get_reaction_counts(&self.conn, user, signature)
+    }
+
+    fn tag_items<'a>(
+        &self,
+        tag: &str,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM item_tag AS t
+                INNER JOIN item AS i ON (
+                    i.user_id = t.user_id
+                    AND i.signature = t.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    t.tag = :tag
+                    AND {filter_ts}
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let tag = tag.to_lowercase();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":tag", &tag)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts are tagged:
+                shared: None,
+                item,
+                display_name: row.get(5)?,
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn mention_items<'a>(
+        &self,
+        user: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let span = TimeSpanSql::new(&time_span);
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM mention AS m
+                INNER JOIN item AS i ON (
+                    i.user_id = m.user_id
+                    AND i.signature = m.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    m.mentioned_user_id = :user_id
+                    AND {filter_ts}
+                    AND EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let user_id = user.bytes();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":user_id", &user_id)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts and Comments have mentions:
+                shared: None,
+                item,
+                display_name: row.get(5)?,
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn search_items<'a>(
+        &self,
+        query: &str,
+        scope: SearchScope,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let terms = backend::search_terms(query);
+        if terms.is_empty() {
+            return Ok(());
+        }
+        // Quote each term so that FTS5 treats it as a word, not as query syntax.
+        // Separate terms are implicitly ANDed together.
+        let fts_query = terms.iter()
+            .map(|term| format!("\"{}\"", term))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let span = TimeSpanSql::new(&time_span);
+
+        let filter_users = match scope {
+            SearchScope::All => {
+                "EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)".to_string()
+            },
+            SearchScope::User(user) => {
+                format!("i.user_id = x'{}'", user.bytes().as_hex())
+            },
+            SearchScope::Feed(user) => {
+                // See: user_feed_items()
+                let follows = get_follows(&self, &user)?;
+                if follows.is_empty() {
+                    return Ok(());
+                }
+                let user_ids: Vec<String> = follows.keys().map(|uid| {
+                    format!("x'{}'", uid.bytes().as_hex())
+                }).collect();
+                format!("i.user_id IN ({})", user_ids.join(", "))
+            },
+        };
+
+        let query = format!(
+            "
+                SELECT
+                    i.user_id
+                    , i.signature
+                    , i.unix_utc_ms
+                    , i.received_utc_ms
+                    , i.bytes
+                    , p.display_name
+                FROM search_text
+                INNER JOIN search_item AS s ON (s.id = search_text.rowid)
+                INNER JOIN item AS i ON (
+                    i.user_id = s.user_id
+                    AND i.signature = s.signature
+                )
+                LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+                WHERE
+                    search_text MATCH :query
+                    AND {filter_ts}
+                    AND {filter_users}
+                    AND {not_hidden}
+                ORDER BY {order}
+            ",
+            filter_ts=span.filter("i."),
+            filter_users=filter_users,
+            not_hidden=NOT_HIDDEN,
+            order=span.order("i."),
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":query", &fts_query)];
+        params.extend_from_slice(&span.params());
+        let mut rows = stmt.query_named(&params)?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                reactions: vec![],
+                // Only Posts & Comments are indexed:
+                shared: None,
+                item,
+                display_name: row.get(5)?,
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn display_names(&self, author: &UserID, users: &[UserID]) -> Result<HashMap<UserID, String>, Error> {
+        let mut names = HashMap::new();
+        if users.is_empty() { return Ok(names); }
+
+        let follows = get_follows(self, author)?;
+
+        let mut profile_name = self.conn.prepare("
+            SELECT display_name
+            FROM profile
+            WHERE user_id = ?
+        ")?;
+
+        for user in users {
+            let follow_name = follows.get(user).and_then(|info| info.display_name.clone());
+            let name = match follow_name {
+                Some(name) => Some(name),
+                None => {
+                    let name: Option<Option<String>> = profile_name.query_row(
+                        params![user.bytes()],
+                        |row| row.get(0),
+                    ).optional()?;
+                    name.flatten().filter(|it| !it.trim().is_empty())
+                }
+            };
+
+            if let Some(name) = name {
+                names.insert(user.clone(), name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        time_span: TimeSpan,
+        callback: RowCallback<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+
+  
+
+
+        let span = TimeSpanSql::new(&time_span);
+
+        // Because we follow N users, and the indexes for (user_id, timestamp) are fast, make N separate queries
+        // against those indexes and merge them with a UNION ALL. This forces SQLite to walk & merge them like should
+        // scale well, vs... whatever it was trying to do.
+
+        let follows = get_follows(&self, user_id)?;
+        let subselects: Vec<String> = follows.keys().map(|uid| {
+            format!(
+                "
+                    SELECT * from iv 
+                    WHERE user_id = x'{uid}'
+                ",
+                uid=uid.bytes().as_hex(),
+            )
+        }).collect();
+
+        if subselects.is_empty() {
+            return Ok(());
+        }
+
+        let query = format!(
+            "
+                WITH iv AS (
+                    SELECT 
+                        user_id
+                        , signature
+                        , unix_utc_ms
+                        , received_utc_ms
+                        , bytes
+                    FROM item AS i
+                    WHERE {filter_ts}
+                    AND NOT EXISTS(
+                        SELECT 1 FROM direct_message AS dm
+                        WHERE dm.user_id = i.user_id AND dm.signature = i.signature
+                    )
+                    AND {not_hidden}
+                )
+                {subselects}
+                ORDER BY {order}
+            ", 
+            filter_ts=span.filter("i."),
+            not_hidden=NOT_HIDDEN,
+            order=span.order(""),
+            subselects=subselects.join("\n\nUNION ALL\n")
+        );
+
+        let mut stmt = self.conn.prepare(&query)?;
+
+        let mut rows = stmt.query_named(&span.params())?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                display_name: follows.get(&item.user).map(|info| info.display_name.clone()).flatten(),
+                reactions: vec![],
+                shared: get_shared_row(&self.conn, &item.user, &item.signature)?,
+                item,
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn server_user(&self, user: &UserID)
+    -> Result<Option<backend::ServerUser>, Error> 
+    { 
+        let mut stmt = self.conn.prepare("
+            SELECT notes, on_homepage
+            FROM server_user
+            WHERE user_id = ?
+        ")?;
+
+        let to_server_user = |row: &Row<'_>| {
+            let on_homepage: isize = row.get(1)?;
+             Ok(
+                 ServerUser {
+                    user: user.clone(),
+                    notes: row.get(0)?,
+                    on_homepage: on_homepage != 0,
+                }
+            )
+        };
+
+        let item = stmt.query_row(
+            params![user.bytes()],
+            to_server_user,
+        ).optional()?;
+
+        Ok(item)
+
+    }
+
+    fn server_users<'a>(&self, cb: RowCallback<'a, ServerUser>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT 
+                user_id
+                , notes
+                , on_homepage
+            FROM server_user
+            ORDER BY on_homepage, user_id
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let on_homepage: isize = row.get(2)?;
+            let on_homepage = on_homepage != 0;
+
+            let user = ServerUser {
+                user: UserID::from_vec(row.get(0)?)?,
+                notes: row.get(1)?,
+                on_homepage,
+            };
+            let more = cb(user)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+    
+    
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> { 
+        let mut stmt = self.conn.prepare("
+            SELECT COUNT(*)
+            FROM item AS i
+            WHERE user_id = ?
+            AND signature = ?
+            AND NOT EXISTS(
+                SELECT 1 FROM quarantined_item AS qi
+                WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+            )
+        ")?;
+
+        let count: u32 = stmt.query_row(
+            params![
+                user.bytes(),
+                signature.bytes(),
+            ],
+            |row| { Ok(row.get(0)?) }
+        )?;
+
+        if count > 1 {
+            bail!("Found {} matches!? (user_id,signature) should be unique!", count);
+        }
+
+        Ok(count > 0)
+    }
+
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let deleted: bool = self.conn.query_row(
+            "
+                SELECT EXISTS(
+                    SELECT 1
+                    FROM deleted_item
+                    WHERE user_id = ?
+                    AND signature = ?
+                )
+            ",
+            params![
+                user.bytes(),
+                signature.bytes(),
+            ],
+            |row| row.get(0)
+        )?;
+
+        Ok(deleted)
+    }
+
+    fn user_revoked(&self, user: &UserID) -> Result<bool, Error> {
+        let revoked: bool = self.conn.query_row(
+            "
+                SELECT EXISTS(
+                    SELECT 1
+                    FROM profile
+                    WHERE user_id = ?
+                    AND revoked = 1
+                )
+            ",
+            params![user.bytes()],
+            |row| row.get(0)
+        )?;
+
+        Ok(revoked)
+    }
+
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> { 
+        let mut stmt = self.conn.prepare("
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item AS i
+            WHERE user_id = ?
+            AND signature = ?
+            AND (
+                EXISTS(SELECT user_id FROM known_users WHERE user_id = i.user_id)
+                OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = i.user_id AND signature = i.signature)
+            )
+            AND NOT EXISTS(
+                SELECT 1 FROM quarantined_item AS qi
+                WHERE qi.user_id = i.user_id AND qi.signature = i.signature
+            )
+        ")?;
+
+        let mut rows = stmt.query(params![
+            user.bytes(),
+            signature.bytes(),
+        ])?;
+
+        let row = match rows.next()? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let item = ItemRow{
+            user: UserID::from_vec(row.get(0)?)?,
+            signature: Signature::from_vec(row.get(1)?)?,
+            timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+            received: Timestamp{ unix_utc_ms: row.get(3)? },
+            item_bytes: row.get(4)?,
+        };
+
+        if rows.next()?.is_some() {
+            bail!("Found multiple matching rows!? (user_id,signature) should be unique!");
+        }
+
+        Ok(Some(item))
+    }
+
+    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
+    {
+        let tx = self.conn.savepoint().context("getting a transaction")?;
+
+        // A quarantined item's other tables were filled in when it was first
+        // saved, from the same signed Item, so only its row needs replacing:
+        let quarantined = tx.execute(
+            "DELETE FROM quarantined_item WHERE user_id = ? AND signature = ?",
+            params![row.user.bytes(), row.signature.bytes()],
+        )? > 0;
+        if quarantined {
+            let replaced = tx.execute("
+                UPDATE item
+                SET unix_utc_ms = ?, received_utc_ms = ?, bytes = ?
+                WHERE user_id = ? AND signature = ?
+            ", params![
+                row.timestamp.unix_utc_ms,
+                row.received.unix_utc_ms,
+                row.item_bytes.as_slice(),
+                row.user.bytes(),
+                row.signature.bytes(),
+            ])?;
+            if replaced > 0 {
+                tx.commit().context("committing")?;
+                return Ok(());
+            }
+        }
+
+        let stmt = "
+            INSERT INTO item (
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            ) VALUES (?, ?, ?, ?, ?);
+       ";
+
+        tx.execute(stmt, params![
+            row.user.bytes(),
+            row.signature.bytes(),
+            row.timestamp.unix_utc_ms,
+            row.received.unix_utc_ms,
+            row.item_bytes.as_slice(),
+        ])?;
+
+        if item.has_profile() || item.has_delete() {
+            ignore_early_delete(&tx, row)?;
+        }
+
+        if item.has_profile() {
+            update_profile(&tx, row, item)?;
+        }
+
+        if item.has_comment() {
+            save_comment_reply(&tx, row, item)?;
+        }
+
+        if item.has_delete() {
+            delete_item(&tx, row, item)?;
+        }
+
+        if item.has_revision() {
+            save_revision(&tx, row, item)?;
+        }
+
+        if let Some((signature, tags)) = get_item_tags(&tx, row, item)? {
+            save_item_tags(&tx, &row.user, &signature, &tags)?;
+        }
+
+        if let Some((signature, mentions)) = get_item_mentions(&tx, row, item)? {
+            save_item_mentions(&tx, &row.user, &signature, &mentions)?;
+        }
+
+        if let Some((signature, title, body)) = get_item_text(&tx, row, item)? {
+            save_item_text(&tx, &row.user, &signature, &title, &body)?;
+        }
+
+        if item.has_reaction() {
+            save_reaction(&tx, row, item)?;
+        }
+
+        if item.has_share() {
+            save_share(&tx, row, item)?;
+        }
+
+        if item.has_direct_message() {
+            save_direct_message(&tx, row, item)?;
+        }
+
+        index_attachments(&tx, row, item)?;
+
+        tx.commit().context("committing")?;
+        Ok(())
+    }
+
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
+
+        // Note: We may have already seen this user revoke their ID:
+        let stmt = "
+            INSERT INTO server_user(user_id, notes, on_homepage, revoked)
+            VALUES (?,?,?, EXISTS(SELECT 1 FROM profile WHERE user_id = ? AND revoked = 1))
+        ";
+
+        let on_homepage = if server_user.on_homepage { 1 } else { 0 };
+
+        self.conn.execute(stmt, params![
+            server_user.user.bytes(),
+            server_user.notes.as_str(),
+            on_homepage,
+            server_user.user.bytes(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
+
+        // TODO: I'm not crazy about making 2 queries here instead of a join, but it lets me
+        // re-use the user_item() loading logic.
+        let mut find_profile = self.conn.prepare("
+            SELECT user_id, signature
+            FROM profile
+            WHERE user_id = ?
+        ")?;
+
+        let mut rows = find_profile.query(params![user.bytes()])?;
+        let row = match rows.next()? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let user_id: Vec<u8> = row.get(0)?;
+        let signature: Vec<u8> = row.get(1)?;
+
+        let user_id = UserID::from_vec(user_id)?;
+        let signature = Signature::from_vec(signature)?;
+
+        self.user_item(&user_id, &signature)
+    }
+
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
+        let mut query = self.conn.prepare("
+            SELECT
+                EXISTS(SELECT user_id FROM server_user WHERE user_id = :user_id)
+                OR EXISTS(
+                    SELECT followed_user_id
+                    FROM follow AS f
+                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
+                    WHERE followed_user_id = :user_id
+                )
+                -- Followers of an old ID also follow its successor:
+                OR EXISTS(
+                    SELECT p.successor_user_id
+                    FROM profile AS p
+                    INNER JOIN follow AS f ON (f.followed_user_id = p.user_id)
+                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
+                    WHERE p.successor_user_id = :user_id
+                )
+        ")?;
+
+        let mut result = query.query_named(&[
+            (":user_id", &user_id.bytes())
+        ])?;
+
+        let row = match result.next()? {
+            Some(row) => row,
+            None => bail!("Expected at least 1 row from SQLite."),
+        };
+
+        Ok(row.get(0)?)
+    }
+
+    fn item_shared(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let shared = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM shared_items WHERE user_id = ? AND signature = ?)",
+            params![user.bytes(), signature.bytes()],
+            |row| row.get(0),
+        )?;
+        Ok(shared)
+    }
+
+    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
+
+        if self.user_revoked(user_id)? {
+            return Ok(Some(QuotaDenyReason::ProfileRevoked));
+        }
+
+        let quota = match self.user_quota(user_id)? {
+            None => return Ok(Some(QuotaDenyReason::UnknownUser)),
+            Some(quota) => quota,
+        };
+
+        if item.has_profile() || item.has_delete() {
+            return Ok(None);
+        }
+
+        if let Some(max_bytes) = quota.item_bytes {
+            let used = self.user_item_bytes(user_id)?;
+            if backend::quota_exceeded(Some(max_bytes), used, bytes.len() as u64) {
+                return Ok(Some(QuotaDenyReason::ItemQuotaExceeded{ max_bytes }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn server_user_quotas(&self, user: &UserID) -> Result<Option<ServerUserQuotas>, Error> {
+        get_server_user_quotas(&self.conn, user)
+    }
+
+    fn set_server_user_quotas(&self, user: &UserID, quotas: &ServerUserQuotas) -> Result<(), Error> {
+        let to_sql = |bytes: Option<u64>| bytes.map(|b| b as i64);
+        let updated = self.conn.execute("
+            UPDATE server_user
+            SET
+                max_item_bytes = ?
+                , max_attachment_bytes = ?
+                , follow_max_item_bytes = ?
+                , follow_max_attachment_bytes = ?
+            WHERE user_id = ?
+        ", params![
+            to_sql(quotas.own.item_bytes),
+            to_sql(quotas.own.attachment_bytes),
+            to_sql(quotas.follows.item_bytes),
+            to_sql(quotas.follows.attachment_bytes),
+            user.bytes(),
+        ])?;
+
+        if updated == 0 {
+            bail!("User {} is not a server user", user);
+        }
+        Ok(())
+    }
+   
+    fn get_contents(&self, user_id: UserID, signature: Signature, file_name: &str) 
+    -> Result< Option<FileStream> , Error> 
+    {
+        let mut stmt = self.conn.prepare("
+            SELECT store.rowid, store.size, a.size, a.mime_type, store.hash, store.contents IS NULL AS external
+            FROM store 
+            INNER JOIN item_attachment AS a USING(hash)
+            WHERE 
+                a.user_id = ?
+                AND a.signature = ?
+                AND a.name = ?
+                AND (
+                    EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
+                    OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = a.user_id AND signature = a.signature)
+                )
+                AND NOT EXISTS(SELECT 1 FROM quarantined_attachment AS q WHERE q.hash = a.hash)
+        ")?;
+
+        let mut rows = stmt.query(params![
+            user_id.bytes(),
+            signature.bytes(),
+            file_name,
+        ])?;
+
+        let row = match rows.next()? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let rowid: i64 = row.get(0)?;
+        let size = row.get::<_, i64>(1)? as u64;
+        let expected_size = row.get::<_, i64>(2)? as u64;
+        let mime_type: Option<String> = row.get(3)?;
+        let hash = SHA512::from_hash_bytes(&row.get::<_, Vec<u8>>(4)?)?;
+        let external: bool = row.get(5)?;
+
+        if size != expected_size {
+            bail!("Item expected {} bytes but found {}", expected_size, size);
+        }
+
+        if rows.next()?.is_some() {
+            bail!("UNIQUE constraint failure, found 2 results for file");
+        }
+
+        drop(rows);
+        drop(stmt);
+
+        if external {
+            let mut file = match self.attachment_store()?.get(&hash)? {
+                Some(file) => file,
+                None => bail!("{} is missing from the attachment store", hash),
+            };
+            if file.size != expected_size {
+                bail!("Item expected {} bytes but found {}", expected_size, file.size);
+            }
+            file.mime_type = mime_type;
+            return Ok(Some(file));
+        }
+
+
+        // Open a new pooled connection that will be owned just by our Iterator/Stream:
+        // TODO: Maybe we should just re-open the connection every time if we have to for the BLOB too?
+        let conn = self.pool.get()?;
+        let mut buf = [0 as u8; 32 * 1024];
+        let mut read_pos = 0;
+
+        let iter = std::iter::from_fn(move || -> Option<Result<Bytes,crate::server::SendError>> {
+            // Have to re-open the BLOB every time because it's not Send (due to its lifetime on &Connection?).
+            let blob = conn.blob_open(
+                DatabaseName::Main, 
+                "store",
+                "contents",
+                rowid,
+                true // read-only
+            );
+
+            let blob = match blob {
+                Ok(b) => b,
+                Err(err) => return Some(Err(err.into())),
+            };
+    
+            let bytes_read = match blob.read_at(&mut buf, read_pos) {
+                Err(io_err) => return Some(Err(io_err.into())),
+                Ok(x) => x,
+            };
+            read_pos += bytes_read;
+
+            if bytes_read == 0 {
+                return None;
+            }
+
+            let bytes = Bytes::copy_from_slice(&buf[..bytes_read]);
+            return Some(Ok(bytes));
+        });
+
+        let stream = blocking::Unblock::with_capacity(2, iter);
+        let stream = Box::new(stream);
+        Ok(Some(FileStream{stream, size, mime_type}))
+    }
+
+    fn get_attachment_meta(&self, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<Option<backend::FileMeta>, Error> {
+        
+        let mut stmt = self.conn.prepare("
+            SELECT 
+                a.size,
+                a.hash,
+                s.hash IS NOT NULL AS contents_exist,
+                EXISTS(SELECT 1 FROM quarantined_attachment AS q WHERE q.hash = a.hash) AS quarantined
+            FROM item_attachment AS a
+            LEFT OUTER JOIN store AS s USING (hash)
+            WHERE 
+                a.user_id = ?
+                AND a.signature = ?
+                AND a.name = ?
+                AND (
+                    EXISTS(SELECT user_id FROM known_users WHERE user_id = a.user_id)
+                    OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = a.user_id AND signature = a.signature)
+                )
+        ")?;
+
+        let mut rows = stmt.query(params![
+            user_id.bytes(),
+            signature.bytes(),
+            file_name
+        ])?;
+
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let size = row.get::<_, i64>(0)? as u64;
+        let hash_bytes: Vec<u8> = row.get(1)?;
+        let hash = SHA512::from_hash_bytes(&hash_bytes)?;
+        let stored: bool = row.get(2)?;
+        let quarantined: bool = row.get(3)?;
+
+        // Files we already have don't take up any more space:
+        let quota_exceeded = !stored && match self.user_quota(user_id)? {
+            None => true,
+            Some(Quota{ attachment_bytes: None, .. }) => false,
+            Some(quota) => {
+                let used = self.user_attachment_bytes(user_id)?;
+                backend::quota_exceeded(quota.attachment_bytes, used, size)
+            },
+        };
+
+        let meta = FileMeta{
+            exists: stored && !quarantined,
+            hash,
+            size,
+            quota_exceeded,
+        };
+
+        Ok(Some(meta))
+    }
+
+    fn save_attachment(&self, size: u64, hash: &SHA512, file: &mut dyn Read) -> Result<(), Error> {
+        let quarantined: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM quarantined_attachment WHERE hash = ?)",
+            params![hash.bytes()],
+            |row| row.get(0),
+        )?;
+        if quarantined {
+            // Remove the corrupt contents, wherever they're stored:
+            let external: Option<bool> = self.conn.query_row(
+                "SELECT contents IS NULL FROM store WHERE hash = ?",
+                params![hash.bytes()],
+                |row| row.get(0),
+            ).optional()?;
+            if external == Some(true) {
+                self.attachment_store()?.remove(hash)?;
+            }
+            self.conn.execute("DELETE FROM store WHERE hash = ?", params![hash.bytes()])?;
+        }
+
+        if let Some(store) = &self.attachment_store {
+            store.save(size, hash, file)?;
+            self.conn.execute(
+                "INSERT OR IGNORE INTO store (hash, size, contents) VALUES (?, ?, NULL)",
+                params![hash.bytes(), size as i64],
+            )?;
+            self.conn.execute("DELETE FROM quarantined_attachment WHERE hash = ?", params![hash.bytes()])?;
+            return Ok(());
+        }
+
+        // Save to a temporary hash while we stream the data into the database.
+        // Note, this is 31 bytes, which is easily distinguishable from SHA-512's 64-bytes:
+        let temp_hash = randombytes(31);
+
+        // In practice, SQLite's max BLOB size defaults to <1GiB. 
+        // See: https://sqlite.org/limits.html
+        // We'll just rely on this insert failing to tell us what it is:
+        debug!("Inserting zeroblob into 'store'");
+        self.conn.execute(
+            "INSERT INTO store (hash, size, contents) VALUES(?, ?, zeroblob(?))",
+            params![
+                &temp_hash,
+                size as i64,
+                size as i64
+            ],
+        )?;
+
+        let row_id: i64 = self.conn.query_row(
+            "SELECT rowid FROM store WHERE hash = ?",
+            params![ &temp_hash ], 
+            |row| row.get(0)
+        )?;
+
+        let mut blob = self.conn.blob_open(
+            DatabaseName::Main,
+            "store",
+            "contents",
+            row_id,
+            false // read_only=false
+        )?; 
+
+        debug!("Copying temp file into sqlite");
+        std::io::copy(file, &mut blob)?;
+        blob.flush()?;
+        debug!("Finished copy.");
+
+        // Check blob hash:
+        // I know the docs say we expect the caller to have performed the hash, but 
+        // getting the wrong content here is annoying so I'm going to do it again anyway:
+        let hash_check = SHA512::from_file(&mut blob)?;
+        debug!("Verified BLOB hash: {}", hash);
+        
+        if &hash_check != hash {
+            bail!("SQLite expected {} but got {}", hash, hash_check);
+        }
+
+        drop(blob);
+
+        // Now that the copy has finished, move the blob into its final location atomically:
+        let updated = self.conn.execute(
+            "UPDATE store SET hash = ? WHERE hash = ?",
+            params![hash.bytes(), &temp_hash],
+        )?;
+
+        if updated != 1 {
+            bail!("Error updating content hash from {:?} to {}", temp_hash, hash);
+        }
+        self.conn.execute("DELETE FROM quarantined_attachment WHERE hash = ?", params![hash.bytes()])?;
+        debug!("save_attachment() done.");
+
+        Ok(())
+    }
+
+    fn prune(&self, opts: backend::PruneOpts) -> Result<backend::PruneResult, Error> {
+        
+        let mut result = PruneResult{
+            dry_run: opts.dry_run,
+            attachments_bytes: 0,
+            attachments_count: 0,
+            items_bytes: 0,
+            items_count: 0,
+            blocked_items_bytes: 0,
+            blocked_items_count: 0,
+            blocked_attachments_bytes: 0,
+            blocked_attachments_count: 0,
+        };
+
+        if opts.items {
+            let query = "
+                SELECT 
+                    COUNT(*) AS `count`
+                    , COALESCE(SUM(LENGTH(bytes) + LENGTH(user_id) + LENGTH(signature)), 0) AS size
+                FROM item AS i
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM known_users
+                    WHERE user_id = i.user_id
+                )
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM shared_items
+                    WHERE user_id = i.user_id
+                    AND signature = i.signature
+                )
+            ";
+
+            let (count, bytes) = self.conn.query_row(
+                query,
+                params![],
+                |row| Ok((row.get::<usize,i64>(0)? as u64, row.get::<usize,i64>(1)? as u64))
+            )?;
+            result.items_count = count;
+            result.items_bytes = bytes;
+        }
+
+        if opts.attachments {
+            let query = if opts.items {
+                "
+                SELECT COUNT(*) AS `count`, COALESCE(SUM(size), 0) AS size
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                )
+                "
+            } else {
+                // We'll delete more if we're also deleting items:
+                "
+                SELECT COUNT(*) AS `count`, COALESCE(SUM(size), 0) AS size
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment AS ia
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                    AND (
+                        EXISTS(SELECT 1 FROM known_users WHERE user_id = ia.user_id)
+                        OR EXISTS(SELECT 1 FROM shared_items WHERE user_id = ia.user_id AND signature = ia.signature)
+                    )
+                )
+                "
+            };
+
+
+            // Find attachments that are no longer referenced.
+            // NOTE: Can't just do a simple LEFT OUTER JOIN and check for NULL.
+            // That could lead to false positives when one ref is dangling but another exists.
+            let (count, bytes) = self.conn.query_row(
+                query,
+                params![],
+                |row| Ok((row.get::<usize, i64>(0)? as u64, row.get::<usize,i64>(1)? as u64)),
+            )?;
+            result.attachments_count = count;
+            result.attachments_bytes = bytes;
+        }
+
+        // Blocked content gets deleted even if it's still referenced:
+        let blocked_items = "
+            FROM item AS i
+            WHERE EXISTS (
+                SELECT 1
+                FROM blocked_user
+                WHERE user_id = i.user_id
+            )
+            OR EXISTS (
+                SELECT 1
+                FROM blocked_item
+                WHERE user_id = i.user_id
+                AND signature = i.signature
+            )
+        ";
+        let blocked_attachments = "
+            FROM store AS s
+            WHERE EXISTS (
+                SELECT 1
+                FROM blocked_attachment
+                WHERE hash = s.hash
+            )
+        ";
+
+        if opts.blocked {
+            let query = format!(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(bytes) + LENGTH(user_id) + LENGTH(signature)), 0) {}",
+                blocked_items
+            );
+            let (count, bytes) = self.conn.query_row(
+                &query,
+                params![],
+                |row| Ok((row.get::<usize, i64>(0)? as u64, row.get::<usize,i64>(1)? as u64)),
+            )?;
+            result.blocked_items_count = count;
+            result.blocked_items_bytes = bytes;
+
+            let query = format!("SELECT COUNT(*), COALESCE(SUM(size), 0) {}", blocked_attachments);
+            let (count, bytes) = self.conn.query_row(
+                &query,
+                params![],
+                |row| Ok((row.get::<usize, i64>(0)? as u64, row.get::<usize,i64>(1)? as u64)),
+            )?;
+            result.blocked_attachments_count = count;
+            result.blocked_attachments_bytes = bytes;
+        }
+
+        if opts.dry_run {
+            return Ok(result)
+        }
+
+        if opts.blocked {
+            let mut items = vec![];
+            let mut stmt = self.conn.prepare(&format!("SELECT user_id, signature {}", blocked_items))?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                items.push((
+                    UserID::from_vec(row.get(0)?)?,
+                    Signature::from_vec(row.get(1)?)?,
+                ));
+            }
+            drop(rows);
+            drop(stmt);
+
+            for (user, signature) in items {
+                remove_item(&self.conn, &user, &signature)?;
+            }
+
+            // Profiles (and their follows) that were blocked, or were from blocked users:
+            self.conn.execute("
+                DELETE FROM profile AS p
+                WHERE EXISTS (
+                    SELECT 1
+                    FROM blocked_user
+                    WHERE user_id = p.user_id
+                )
+                OR EXISTS (
+                    SELECT 1
+                    FROM blocked_item
+                    WHERE user_id = p.user_id
+                    AND signature = p.signature
+                )
+            ", params![])?;
+            self.conn.execute("
+                DELETE FROM follow AS f
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM profile
+                    WHERE user_id = f.source_user_id
+                )
+            ", params![])?;
+
+            let mut stmt = self.conn.prepare(&format!("SELECT hash {} AND contents IS NULL", blocked_attachments))?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let hash = SHA512::from_hash_bytes(&row.get::<_, Vec<u8>>(0)?)?;
+                self.attachment_store()?.remove(&hash)?;
+            }
+            drop(rows);
+            drop(stmt);
+
+            self.conn.execute(&format!("DELETE {}", blocked_attachments), params![])?;
+        }
+
+        // Note: Delete items first, which makes more things available to delete from store if we do that:
+        if opts.items {
+            let query = "
+                DELETE FROM ITEM AS i
+                WHERE NOT EXISTS(
+                    SELECT 1
+                    FROM known_users
+                    WHERE user_id = i.user_id
+                )
+                AND NOT EXISTS(
+                    SELECT 1
+                    FROM shared_items
+                    WHERE user_id = i.user_id
+                    AND signature = i.signature
+                )
+            ";
+            self.conn.execute(query, params![])?;
+
+            // Delete attachments now abandoned:
+            let query = "
+                DELETE FROM item_attachment AS ia
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item
+                    WHERE user_id = ia.user_id
+                    AND signature = ia.signature
+                )
+            ";
+            self.conn.execute(query, params![])?;
+        }
+
+        if opts.attachments {
+            // Note: We can get by w/ one query here because we already deleted
+            // items from unknown users if that was desired.
+            let unused = "
+                FROM store AS s
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM item_attachment
+                    INNER JOIN item USING (user_id, signature)
+                    WHERE hash = s.hash
+                )
+            ";
+
+            // Remove files from the attachment store before we forget about them:
+            let mut stmt = self.conn.prepare(&format!("SELECT hash {} AND contents IS NULL", unused))?;
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let hash = SHA512::from_hash_bytes(&row.get::<_, Vec<u8>>(0)?)?;
+                self.attachment_store()?.remove(&hash)?;
+            }
+            drop(rows);
+            drop(stmt);
+
+            self.conn.execute(&format!("DELETE {}", unused), params![])?;
+        }
+
+        self.conn.execute("VACUUM", params![])?;
+
+        Ok(result)
+    }
+
+    fn usage_by_user(&self, callback: RowCallback<'_, backend::UsageByUserRow>) -> Result<(), Error> {
+        let query = "
+            SELECT
+                s1.user_id,
+                s1.display_name,
+                EXISTS(SELECT 1 FROM known_users WHERE user_id=s1.user_id) AS known_user,
+                EXISTS(SELECT 1 FROM server_user WHERE user_id=s1.user_id) AS server_user,
+                item_count,
+                item_size,
+                IFNULL(attachment_size,0) AS attachment_size,
+                IFNULL(attachment_count,0) AS attachment_count,
+                item_size + COALESCE(attachment_size, 0) as total_size
+            FROM (
+                SELECT
+                    user_id,
+                    p.display_name,
+                    COUNT(*) as item_count,
+                    SUM(length(bytes)) as item_size
+                FROM item AS i
+                LEFT OUTER JOIN profile AS p USING (user_id)
+                GROUP BY user_id
+            ) AS s1
+            LEFT OUTER JOIN (
+                SELECT 
+                    user_id,
+                    SUM(size) as attachment_size,
+                    COUNT(*) AS attachment_count
+                FROM (
+                    SELECT DISTINCT user_id, hash
+                    FROM item
+                    INNER JOIN item_attachment USING (user_id, signature)
+                ) AS user_hashes
+                INNER JOIN store USING (hash)
+                GROUP BY user_id
+            ) AS s2 USING (user_id)
+            ORDER BY total_size DESC
+        ";
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query(params![])?;
+
+        let mut fetch_more = true;
+        while fetch_more {
+            let row = match rows.next()? {
+                None => return Ok(()), // No more results.
+                Some(row) => row,
+            };
+            
+            let usage = UsageByUserRow {
+                user_id: UserID::from_vec(row.get(0)?)?,
+                display_name: row.get("display_name")?,
+                items_count: row.get::<&str, i64>("item_count")? as u64,
+                items_bytes: row.get::<&str, i64>("item_size")? as u64,
+                attachments_count: row.get::<&str, i64>("attachment_count")? as u64,
+                attachments_bytes: row.get::<&str, i64>("attachment_size")? as u64,
+                known_user: row.get("known_user")?,
+                server_user: row.get("server_user")?,
+                total_bytes: row.get::<&str, i64>("total_size")? as u64,
+            };
+
+            fetch_more = callback(usage)?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate_attachments(&self) -> Result<MigrateResult, Error> {
+        let store = self.attachment_store()?;
+        let mut result = MigrateResult{
+            attachments_count: 0,
+            attachments_bytes: 0,
+        };
+
+        let to_move: Vec<(i64, Vec<u8>, i64)> = {
+            let mut stmt = self.conn.prepare("SELECT rowid, hash, size FROM store WHERE contents IS NOT NULL")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        for (row_id, hash, size) in to_move {
+            let hash = SHA512::from_hash_bytes(&hash)?;
+            let mut blob = self.conn.blob_open(
+                DatabaseName::Main,
+                "store",
+                "contents",
+                row_id,
+                true // read-only
+            )?;
+
+            // Note: The store verifies the hash, so we'll also catch any bit-rot here.
+            store.save(size as u64, &hash, &mut blob)?;
+            drop(blob);
+
+            self.conn.execute("UPDATE store SET contents = NULL WHERE rowid = ?", params![row_id])?;
+            result.attachments_count += 1;
+            result.attachments_bytes += size as u64;
+        }
+
+        if result.attachments_count > 0 {
+            // Actually give the space back:
+            self.conn.execute("VACUUM", params![])?;
+        }
+
+        Ok(result)
+    }
+
+    fn all_items<'a>(&self, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        // Note: Calls the inherent all_items(), which upgraders also use to resume from a given item.
+        self.all_items(&None, &None, callback)
+    }
+
+    fn all_user_items<'a>(&self, user: &UserID, callback: RowCallback<'a, ItemRow>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            WHERE user_id = ?
+            ORDER BY unix_utc_ms, signature
+        ")?;
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            if !callback(item)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn verify_attachments<'a>(&self, callback: RowCallback<'a, BadAttachment>) -> Result<u64, Error> {
+        let stored: Vec<(i64, Option<Vec<u8>>, i64, bool)> = {
+            let mut stmt = self.conn.prepare("SELECT rowid, hash, size, contents IS NULL AS external FROM store")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        let mut checked = 0;
+        for (row_id, hash_bytes, size, external) in stored {
+            checked += 1;
+            let hash_bytes = hash_bytes.unwrap_or_default();
+            let hash = match SHA512::from_hash_bytes(&hash_bytes) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    let problem = format!("Malformed hash in store row {}: {}", row_id, err);
+                    if !callback(BadAttachment{hash: hash_bytes, problem})? { break; }
+                    continue;
+                },
+            };
+
+            let found = if external {
+                self.attachment_store()?.rehash(&hash)?
+            } else {
+                let mut blob = self.conn.blob_open(
+                    DatabaseName::Main,
+                    "store",
+                    "contents",
+                    row_id,
+                    true // read-only
+                )?;
+                Some(SHA512::from_file_with_size(&mut blob)?)
+            };
+
+            if let Some(bad) = BadAttachment::check(&hash, size as u64, found) {
+                if !callback(bad)? { break; }
+            }
+        }
+
+        Ok(checked)
+    }
+
+    fn verify_items<'a>(&self, callback: RowCallback<'a, BadItem>) -> Result<u64, Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            ORDER BY user_id, signature
+        ")?;
+        let mut rows = stmt.query(params![])?;
+
+        let mut checked = 0;
+        while let Some(row) = rows.next()? {
+            checked += 1;
+            let bad = BadItem::check(
+                row.get::<_, Option<Vec<u8>>>(0)?.unwrap_or_default(),
+                row.get::<_, Option<Vec<u8>>>(1)?.unwrap_or_default(),
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            );
+            if let Some(bad) = bad {
+                if !callback(bad)? { break; }
+            }
+        }
+
+        Ok(checked)
+    }
+
+    fn add_quarantine(&self, target: &Quarantine, problem: &str) -> Result<(), Error> {
+        let now = Timestamp::now().unix_utc_ms;
+        match target {
+            Quarantine::Item(user, signature) => self.conn.execute("
+                INSERT OR REPLACE INTO quarantined_item(user_id, signature, problem, created_utc_ms)
+                VALUES (?, ?, ?, ?)
+            ", params![user.bytes(), signature.bytes(), problem, now])?,
+            Quarantine::Attachment(hash) => self.conn.execute("
+                INSERT OR REPLACE INTO quarantined_attachment(hash, problem, created_utc_ms)
+                VALUES (?, ?, ?)
+            ", params![hash.bytes(), problem, now])?,
+        };
+        Ok(())
+    }
+
+    fn add_block(&self, block: &Block, notes: &str) -> Result<(), Error> {
+        let now = Timestamp::now().unix_utc_ms;
+        match block {
+            Block::User(user) => self.conn.execute("
+                INSERT OR REPLACE INTO blocked_user(user_id, notes, created_utc_ms)
+                VALUES (?, ?, ?)
+            ", params![user.bytes(), notes, now])?,
+            Block::Item(user, signature) => self.conn.execute("
+                INSERT OR REPLACE INTO blocked_item(user_id, signature, notes, created_utc_ms)
+                VALUES (?, ?, ?, ?)
+            ", params![user.bytes(), signature.bytes(), notes, now])?,
+            Block::Attachment(hash) => self.conn.execute("
+                INSERT OR REPLACE INTO blocked_attachment(hash, notes, created_utc_ms)
+                VALUES (?, ?, ?)
+            ", params![hash.bytes(), notes, now])?,
+        };
+        Ok(())
+    }
+
+    fn remove_block(&self, block: &Block) -> Result<bool, Error> {
+        let deleted = match block {
+            Block::User(user) => self.conn.execute(
+                "DELETE FROM blocked_user WHERE user_id = ?",
+                params![user.bytes()],
+            )?,
+            Block::Item(user, signature) => self.conn.execute(
+                "DELETE FROM blocked_item WHERE user_id = ? AND signature = ?",
+                params![user.bytes(), signature.bytes()],
+            )?,
+            Block::Attachment(hash) => self.conn.execute(
+                "DELETE FROM blocked_attachment WHERE hash = ?",
+                params![hash.bytes()],
+            )?,
+        };
+        Ok(deleted > 0)
+    }
+
+    fn blocks<'a>(&self, callback: RowCallback<'a, BlockRow>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, NULL AS signature, NULL AS hash, notes, created_utc_ms
+            FROM blocked_user
+            UNION ALL
+            SELECT user_id, signature, NULL, notes, created_utc_ms
+            FROM blocked_item
+            UNION ALL
+            SELECT NULL, NULL, hash, notes, created_utc_ms
+            FROM blocked_attachment
+            ORDER BY created_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let user: Option<Vec<u8>> = row.get(0)?;
+            let signature: Option<Vec<u8>> = row.get(1)?;
+            let hash: Option<Vec<u8>> = row.get(2)?;
+
+            let block = match (user, signature, hash) {
+                (Some(user), None, None) => Block::User(UserID::from_vec(user)?),
+                (Some(user), Some(signature), None) => Block::Item(UserID::from_vec(user)?, Signature::from_vec(signature)?),
+                (None, None, Some(hash)) => Block::Attachment(SHA512::from_hash_bytes(&hash)?),
+                _ => bail!("Invalid block row"),
+            };
+
+            let more = callback(BlockRow{
+                block,
+                notes: row.get(3)?,
+                created: Timestamp{ unix_utc_ms: row.get(4)? },
+            })?;
+            if !more { break; }
+        }
+
+        Ok(())
+    }
+
+    fn is_blocked(&self, block: &Block) -> Result<bool, Error> {
+        let user_blocked = |user: &UserID| -> Result<bool, Error> {
+            let blocked: bool = self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM blocked_user WHERE user_id = ?)",
+                params![user.bytes()],
+                |row| row.get(0),
+            )?;
+            Ok(blocked)
+        };
+
+        let blocked = match block {
+            Block::User(user) => user_blocked(user)?,
+            Block::Item(user, signature) => user_blocked(user)? || self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM blocked_item WHERE user_id = ? AND signature = ?)",
+                params![user.bytes(), signature.bytes()],
+                |row| row.get(0),
+            )?,
+            Block::Attachment(hash) => self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM blocked_attachment WHERE hash = ?)",
+                params![hash.bytes()],
+                |row| row.get(0),
+            )?,
+        };
+        Ok(blocked)
+    }
+}
+
+struct ReplyRow {
+    from_user_id: UserID,
+    from_signature: Signature,
+    to_user_id: UserID,
+    to_signature: Signature,
+}
+
+/// A row from the item_attachment table.
+struct AttachmentRow {
+    user_id: UserID,
+    signature: Signature,
+    name: String,
+
+    // The size of the attachment (in bytes)
+    // Unfortunately must be i64 because SQLite doesn't support u64.
+    size: i64,
+
+    hash: SHA512,
+
+    // Optional metadata, from the File proto:
+    mime_type: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    alt_text: Option<String>,
+}
+
+fn index_attachments(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    save_attachment_rows(conn, get_attachment_rows(row, item)?)
+}
+
+fn get_attachment_rows(row: &ItemRow, item: &Item) -> Result<Vec<AttachmentRow>, Error> {
+    let mut rows = vec![];
+
+    // TODO: Eventually support attachments for Profiles (and other types?) too:
+    let post = if item.has_revision() {
+        item.get_revision().get_post()
+    } else {
+        item.get_post()
+    };
+
+    let attachments = post.get_attachments().get_file();
+    for attachment in attachments {
+        let row = AttachmentRow {
+            name: attachment.name.clone(),
+            hash: SHA512::from_hash_bytes(attachment.hash.as_slice())?,
+            user_id: row.user.clone(),
+            signature: row.signature.clone(),
+            size: attachment.size as i64,
+            mime_type: Some(attachment.mime_type.clone()).filter(|it| !it.is_empty()),
+            width: Some(attachment.width).filter(|it| *it > 0),
+            height: Some(attachment.height).filter(|it| *it > 0),
+            alt_text: Some(attachment.alt_text.clone()).filter(|it| !it.trim().is_empty()),
+        };
+        if row.name.contains("/") || row.name.contains("\\") {
+            bail!("File separators are not allowed in attached file names: {}", row.name);
+        }
+        if row.size < 0 {
+            bail!("File sizes greater than {} bytes are unsupported", i64::MAX);
+        }
+
+        rows.push(row);
+    }
+    return Ok(rows);
+}
+
+fn save_attachment_rows(conn: &rusqlite::Connection, rows: Vec<AttachmentRow>) -> Result<(), Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut stmt = conn.prepare("
+        INSERT OR REPLACE INTO item_attachment(user_id, signature, name, hash, size, mime_type, width, height, alt_text)
+        VALUES (?,?,?,?,?,?,?,?,?)
+    ")?;
+
+    for row in rows {
+        stmt.execute(params![
+            row.user_id.bytes(),
+            row.signature.bytes(),
+            row.name,
+            row.hash.bytes(),
+            row.size as i64,
+            row.mime_type,
+            row.width,
+            row.height,
+            row.alt_text,
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Get all users that `user_id` follows (and themselves).
+//
+// note: gets ALL follows, could be abused/DoS. (TODO: Protect against unreasonable amount of follows?)
+fn get_follows(conn: &Connection, user_id: &UserID) -> Result<HashMap<UserID, FollowInfo>, Error> {
+    let mut map = HashMap::new();
+
+    let mut stmt = conn.conn.prepare("
+        SELECT
+            f.followed_user_id AS user_id
+            , f.display_name AS follow_display_name
+            , p.display_name
+        FROM follow AS f
+        LEFT OUTER JOIN profile AS p ON (f.followed_user_id = p.user_id)
+        WHERE f.source_user_id = :user_id
+
+        UNION ALL
+        SELECT 
+            p.user_id
+            , p.display_name AS follow_display_name
+            , p.display_name 
+        FROM profile AS p
+        WHERE p.user_id = :user_id
+
+        -- Followers of an old ID also follow its successor:
+        UNION ALL
+        SELECT
+            p.successor_user_id AS user_id
+            , f.display_name AS follow_display_name
+            , sp.display_name
+        FROM follow AS f
+        INNER JOIN profile AS p ON (f.followed_user_id = p.user_id)
+        LEFT OUTER JOIN profile AS sp ON (p.successor_user_id = sp.user_id)
+        WHERE
+            f.source_user_id = :user_id
+            AND p.successor_user_id IS NOT NULL
+    ")?;
+
+    let mut rows = stmt.query_named(&[
+        (":user_id", &user_id.bytes()),
+    ])?;
+
+    fn to_info(row: &Row<'_>) -> Result<FollowInfo, Error> {
+        let display_name: Option<String> = row.get("display_name")?;
+        let follow_display_name: Option<String> = row.get("follow_display_name")?;
+        fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
+
+        Ok(FollowInfo {
+            user_id: UserID::from_vec(row.get("user_id")?)?,
+
+            // Prefer displaying the name that this user has assigned to the follow.
+            // TODO: This seems maybe business-logic-y? Should we move it out of Backend?
+            display_name: follow_display_name.filter(not_empty).or(display_name).filter(not_empty),
+        })
+    }
+
+    while let Some(row) = rows.next()? {
+        let info = to_info(row)?;
+        map.insert(info.user_id.clone(), info);
+    }
+
+    Ok(map)
+}
+
+struct FollowInfo {
+    user_id: UserID,
+    // The display name specified by this user, or (fallback) the user they followed.
+    display_name: Option<String>
 }
\ No newline at end of file
@@ -6,7 +6,7 @@ use rusqlite::params;
 
 use crate::{backend::{ItemRow, RowCallback, Signature, UserID}, protos::Item};
 
-use super::{AttachmentRow, CURRENT_VERSION, Connection, ReplyRow, get_attachment_rows, save_attachment_rows, save_reply_rows};
+use super::{AttachmentRow, CURRENT_VERSION, Connection, ReplyRow, get_attachment_rows, get_item_mentions, get_item_tags, get_item_text, save_attachment_rows, save_item_mentions, save_item_tags, save_item_text, save_reply_rows};
 
 pub(crate) struct Upgraders {
     upgraders: Vec<Box<dyn Upgrader>>
@@ -19,6 +19,22 @@ impl Upgraders {
             Box::new(From4To5),
             Box::new(From5To6),
             Box::new(From6To7),
+            Box::new(From7To8),
+            Box::new(From8To9),
+            Box::new(From9To10),
+            Box::new(From10To11),
+            Box::new(From11To12),
+            Box::new(From12To13),
+            Box::new(From13To14),
+            Box::new(From14To15),
+            Box::new(From15To16),
+            Box::new(From16To17),
+            Box::new(From17To18),
+            Box::new(From18To19),
+            Box::new(From19To20),
+            Box::new(From20To21),
+            Box::new(From21To22),
+            Box::new(From22To23),
         ]}
     }
 
@@ -313,7 +329,20 @@ impl Upgrader for From5To6 {
             })?;
 
             // Write cached reply_tos to the database:
-            save_attachment_rows(&conn.conn, rows_to_insert)?;
+            // (Only the columns that exist at this version. Later ones are filled in by From16To17.)
+            let mut stmt = conn.conn.prepare("
+                INSERT OR REPLACE INTO item_attachment(user_id, signature, name, hash, size)
+                VALUES (?,?,?,?,?)
+            ")?;
+            for row in rows_to_insert {
+                stmt.execute(params![
+                    row.user_id.bytes(),
+                    row.signature.bytes(),
+                    row.name,
+                    row.hash.bytes(),
+                    row.size,
+                ])?;
+            }
             rows_to_insert = vec![];
                 
         }
@@ -344,4 +373,719 @@ impl Upgrader for From6To7 {
         conn.set_version(self.to_version())?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+// Adds a table to track items that have been deleted by a Delete item.
+struct From7To8;
+impl Upgrader for From7To8 {
+    fn from_version(&self) -> u32 { 7 }
+    fn to_version(&self) -> u32 { 8 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE deleted_item(
+                -- Items which have been deleted by a (signed) Delete item.
+                -- We keep these so that we don't accept the deleted item again
+                -- (ex: when syncing from another server.)
+
+                user_id BLOB,
+                signature BLOB,
+
+                -- The signature of the Delete item that deleted this item.
+                deleted_by BLOB
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX deleted_item_primary_idx
+            ON deleted_item(user_id, signature)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to track Revisions of Posts.
+struct From8To9;
+impl Upgrader for From8To9 {
+    fn from_version(&self) -> u32 { 8 }
+    fn to_version(&self) -> u32 { 9 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE item_revision(
+                -- Maps Revision items to the original Post that they revise.
+
+                user_id BLOB,
+                -- The signature of the Revision item:
+                signature BLOB,
+                -- The signature of the original Post:
+                original_signature BLOB,
+
+                -- A copy of the Revision's timestamp, so that we can find the latest one.
+                unix_utc_ms INTEGER
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX item_revision_primary_idx
+            ON item_revision(user_id, signature)
+        ")?;
+
+        conn.run("
+            CREATE INDEX item_revision_original_idx
+            ON item_revision(user_id, original_signature, unix_utc_ms)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds columns to track user IDs that have been revoked by their Profile.
+struct From9To10;
+impl Upgrader for From9To10 {
+    fn from_version(&self) -> u32 { 9 }
+    fn to_version(&self) -> u32 { 10 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        // bool 0/1 -- has the profile.signature Profile revoked this user ID?
+        conn.run("ALTER TABLE profile ADD COLUMN revoked INTEGER NOT NULL DEFAULT 0")?;
+
+        // bool 0/1 -- a copy of profile.revoked, so that we can efficiently
+        // exclude revoked server users (& their follows) from queries.
+        conn.run("ALTER TABLE server_user ADD COLUMN revoked INTEGER NOT NULL DEFAULT 0")?;
+
+        // Profiles saved before this upgrade couldn't have contained a revocation,
+        // (clients wouldn't know about the field) so there's nothing to backfill.
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Tracks users' successor IDs, and treats successors of followed users as "known users".
+struct From10To11;
+impl Upgrader for From10To11 {
+    fn from_version(&self) -> u32 { 10 }
+    fn to_version(&self) -> u32 { 11 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        // The userID that this user has moved to, if any. (NULL if none.)
+        // The counter-signature is verified before the Profile is saved.
+        conn.run("ALTER TABLE profile ADD COLUMN successor_user_id BLOB")?;
+
+        conn.run("
+            CREATE INDEX profile_successor_idx
+            ON profile(successor_user_id)
+        ")?;
+
+        // See From4To5 for notes on this view.
+        conn.run("DROP VIEW known_users")?;
+        conn.run("
+            CREATE VIEW known_users (user_id) AS
+            -- For internal use only. All 'known users' of the server.
+                SELECT user_id
+                FROM server_user
+            UNION ALL
+                SELECT followed_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            UNION ALL
+                -- Followers of an old ID also follow its successor:
+                SELECT p.successor_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+                INNER JOIN profile AS p
+                    ON (f.followed_user_id=p.user_id)
+                WHERE p.successor_user_id IS NOT NULL
+            ;
+        ")?;
+
+        // Profiles saved before this upgrade couldn't have contained a successor.
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to index Reactions by the item they react to.
+struct From11To12;
+impl Upgrader for From11To12 {
+    fn from_version(&self) -> u32 { 11 }
+    fn to_version(&self) -> u32 { 12 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE reaction(
+                -- Tracks Reaction items, and the items they react to.
+
+                from_user_id BLOB,
+                from_signature BLOB,
+
+                to_user_id BLOB,
+                to_signature BLOB,
+
+                -- The (short) reaction string. ex: an emoji.
+                reaction TEXT
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX reaction_primary_idx
+            ON reaction(from_user_id, from_signature)
+        ")?;
+
+        conn.run("
+            CREATE INDEX reaction_to_idx
+            ON reaction(to_user_id, to_signature, reaction)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to index Shares, and makes the authors of shared items "known users".
+struct From12To13;
+impl Upgrader for From12To13 {
+    fn from_version(&self) -> u32 { 12 }
+    fn to_version(&self) -> u32 { 13 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE share(
+                -- Tracks Share items, and the items they share.
+
+                from_user_id BLOB,
+                from_signature BLOB,
+
+                to_user_id BLOB,
+                to_signature BLOB
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX share_primary_idx
+            ON share(from_user_id, from_signature)
+        ")?;
+
+        conn.run("
+            CREATE INDEX share_to_idx
+            ON share(to_user_id, to_signature)
+        ")?;
+
+        // See From4To5 for notes on this view.
+        conn.run("DROP VIEW known_users")?;
+        conn.run("
+            CREATE VIEW known_users (user_id) AS
+            -- For internal use only. All 'known users' of the server.
+                SELECT user_id
+                FROM server_user
+            UNION ALL
+                SELECT followed_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            UNION ALL
+                -- Followers of an old ID also follow its successor:
+                SELECT p.successor_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+                INNER JOIN profile AS p
+                    ON (f.followed_user_id=p.user_id)
+                WHERE p.successor_user_id IS NOT NULL
+            UNION ALL
+                -- Authors of items shared by server users:
+                SELECT sh.to_user_id
+                FROM share AS sh
+                INNER JOIN server_user AS s
+                    ON (sh.from_user_id=s.user_id)
+            UNION ALL
+                -- ... and by users they follow:
+                SELECT sh.to_user_id
+                FROM share AS sh
+                INNER JOIN follow AS f
+                    ON (sh.from_user_id=f.followed_user_id)
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            ;
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to index DirectMessages by their recipient.
+struct From13To14;
+impl Upgrader for From13To14 {
+    fn from_version(&self) -> u32 { 13 }
+    fn to_version(&self) -> u32 { 14 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE direct_message(
+                -- Tracks DirectMessage items, so that we can list them in the
+                -- recipient's inbox, and exclude them from other item lists.
+
+                user_id BLOB,
+                signature BLOB,
+
+                recipient_user_id BLOB,
+
+                -- A copy of the item's timestamp, for ordering the inbox.
+                unix_utc_ms INTEGER
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX direct_message_primary_idx
+            ON direct_message(user_id, signature)
+        ")?;
+
+        conn.run("
+            CREATE INDEX direct_message_recipient_idx
+            ON direct_message(recipient_user_id, unix_utc_ms)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to index #tags in Posts.
+struct From14To15;
+impl Upgrader for From14To15 {
+    fn from_version(&self) -> u32 { 14 }
+    fn to_version(&self) -> u32 { 15 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE item_tag(
+                -- Maps #tags to the Posts that contain them.
+                -- If a Post has been revised, contains tags for the latest Revision.
+
+                user_id BLOB,
+                -- The signature of the (original) Post:
+                signature BLOB,
+
+                -- lowercase, without the leading '#'.
+                tag TEXT
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX item_tag_primary_idx
+            ON item_tag(user_id, signature, tag)
+        ")?;
+
+        conn.run("
+            CREATE INDEX item_tag_tag_idx
+            ON item_tag(tag)
+        ")?;
+
+        // Index tags for existing items:
+        // TODO: Newer rusqlite supports u64 & usize:
+        let item_count: u32 = conn.conn.query_row(
+            "SELECT COUNT(*) FROM item",
+            params![],
+            |row| Ok(row.get(0)?)
+        )?;
+
+        if item_count > 1000 {
+            println!("Scanning {} items for #tags. This may take a some time.", item_count);
+        }
+
+        let mut pager = ItemPager::new();
+
+        // If SQLite hasn't enabled WAL (not the default, & not supported
+        // everywhere) then we can't read & write from the DB at the same time.
+        // Batch up rows to write here, and periodically write them:
+        let mut tag_rows = Vec::<(UserID, Signature, Vec<String>)>::new();
+        let max_rows = 1000;
+
+        while !pager.done {
+            pager.iterate(conn, &mut |row| {
+                let mut item = Item::new();
+                item.merge_from_bytes(row.item_bytes.as_slice())?;
+
+                if let Some((signature, tags)) = get_item_tags(&conn.conn, &row, &item)? {
+                    if !tags.is_empty() {
+                        tag_rows.push((row.user.clone(), signature, tags));
+                    }
+                }
+
+                Ok(tag_rows.len() < max_rows)
+            })?;
+
+            for (user_id, signature, tags) in tag_rows.drain(..) {
+                save_item_tags(&conn.conn, &user_id, &signature, &tags)?;
+            }
+        }
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a table to index @mentions of users in Posts & Comments.
+struct From15To16;
+impl Upgrader for From15To16 {
+    fn from_version(&self) -> u32 { 15 }
+    fn to_version(&self) -> u32 { 16 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE mention(
+                -- Maps @mentioned users to the Posts & Comments that mention them.
+                -- If a Post has been revised, contains mentions for the latest Revision.
+
+                user_id BLOB,
+                -- The signature of the Comment, or (original) Post:
+                signature BLOB,
+
+                mentioned_user_id BLOB
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX mention_primary_idx
+            ON mention(user_id, signature, mentioned_user_id)
+        ")?;
+
+        conn.run("
+            CREATE INDEX mention_mentioned_user_idx
+            ON mention(mentioned_user_id)
+        ")?;
+
+        // Index mentions in existing items:
+        let item_count: u32 = conn.conn.query_row(
+            "SELECT COUNT(*) FROM item",
+            params![],
+            |row| Ok(row.get(0)?)
+        )?;
+
+        if item_count > 1000 {
+            println!("Scanning {} items for @mentions. This may take a some time.", item_count);
+        }
+
+        let mut pager = ItemPager::new();
+
+        // See: From14To15
+        let mut mention_rows = Vec::<(UserID, Signature, Vec<UserID>)>::new();
+        let max_rows = 1000;
+
+        while !pager.done {
+            pager.iterate(conn, &mut |row| {
+                let mut item = Item::new();
+                item.merge_from_bytes(row.item_bytes.as_slice())?;
+
+                if let Some((signature, mentions)) = get_item_mentions(&conn.conn, &row, &item)? {
+                    if !mentions.is_empty() {
+                        mention_rows.push((row.user.clone(), signature, mentions));
+                    }
+                }
+
+                Ok(mention_rows.len() < max_rows)
+            })?;
+
+            for (user_id, signature, mentions) in mention_rows.drain(..) {
+                save_item_mentions(&conn.conn, &user_id, &signature, &mentions)?;
+            }
+        }
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds optional metadata to file attachments.
+struct From16To17;
+impl Upgrader for From16To17 {
+    fn from_version(&self) -> u32 { 16 }
+    fn to_version(&self) -> u32 { 17 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        // All nullable. If NULL, we fall back to guessing from the file name, etc.
+        conn.run("ALTER TABLE item_attachment ADD COLUMN mime_type TEXT")?;
+        conn.run("ALTER TABLE item_attachment ADD COLUMN width INTEGER")?;
+        conn.run("ALTER TABLE item_attachment ADD COLUMN height INTEGER")?;
+        conn.run("ALTER TABLE item_attachment ADD COLUMN alt_text TEXT")?;
+
+        // Re-index attachments, since their Items may already contain metadata
+        // we didn't know how to read:
+        let item_count: u32 = conn.conn.query_row(
+            "SELECT COUNT(*) FROM item",
+            params![],
+            |row| Ok(row.get(0)?)
+        )?;
+
+        if item_count > 1000 {
+            println!("Scanning {} items for file attachments. This may take a some time.", item_count);
+        }
+
+        let mut pager = ItemPager::new();
+
+        // See: From14To15
+        let mut rows_to_insert = Vec::<AttachmentRow>::new();
+        let max_rows = 1000;
+
+        while !pager.done {
+            pager.iterate(conn, &mut |row| {
+                let mut item = Item::new();
+                item.merge_from_bytes(row.item_bytes.as_slice())?;
+
+                match get_attachment_rows(&row, &item) {
+                    Err(err) => {
+                        println!(
+                            "Not indexing file attachments for /u/{}/i/{} due to error: {}",
+                            row.user.to_base58(),
+                            row.signature.to_base58(),
+                            err
+                        );
+                    },
+                    Ok(rows) => rows_to_insert.extend(rows),
+                };
+
+                Ok(rows_to_insert.len() < max_rows)
+            })?;
+
+            save_attachment_rows(&conn.conn, rows_to_insert)?;
+            rows_to_insert = vec![];
+        }
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Track the size of stored attachments, so that their contents can be kept
+// outside of the database. (In an AttachmentStore)
+struct From17To18;
+impl Upgrader for From17To18 {
+    fn from_version(&self) -> u32 { 17 }
+    fn to_version(&self) -> u32 { 18 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("ALTER TABLE store ADD COLUMN size INTEGER")?;
+        conn.run("UPDATE store SET size = LENGTH(contents)")?;
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds a full-text search index for Posts & Comments.
+struct From18To19;
+impl Upgrader for From18To19 {
+    fn from_version(&self) -> u32 { 18 }
+    fn to_version(&self) -> u32 { 19 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE search_item(
+                -- Maps rows in search_text to the Posts & Comments they index.
+                -- If a Post has been revised, search_text has the latest Revision's text.
+
+                -- Explicitly an INTEGER PRIMARY KEY, so that it is stable across VACUUMs:
+                id INTEGER PRIMARY KEY,
+                user_id BLOB NOT NULL,
+                -- The signature of the Comment, or (original) Post:
+                signature BLOB NOT NULL
+            )
+        ")?;
+
+        conn.run("
+            CREATE UNIQUE INDEX search_item_primary_idx
+            ON search_item(user_id, signature)
+        ")?;
+
+        // rowid = search_item.id
+        conn.run("CREATE VIRTUAL TABLE search_text USING fts5(title, body)")?;
+
+        // Index text in existing items:
+        let item_count: u32 = conn.conn.query_row(
+            "SELECT COUNT(*) FROM item",
+            params![],
+            |row| Ok(row.get(0)?)
+        )?;
+
+        if item_count > 1000 {
+            println!("Indexing {} items for search. This may take a some time.", item_count);
+        }
+
+        let mut pager = ItemPager::new();
+
+        // See: From14To15
+        let mut text_rows = Vec::<(UserID, Signature, String, String)>::new();
+        let max_rows = 1000;
+
+        while !pager.done {
+            pager.iterate(conn, &mut |row| {
+                let mut item = Item::new();
+                item.merge_from_bytes(row.item_bytes.as_slice())?;
+
+                if let Some((signature, title, body)) = get_item_text(&conn.conn, &row, &item)? {
+                    text_rows.push((row.user.clone(), signature, title, body));
+                }
+
+                Ok(text_rows.len() < max_rows)
+            })?;
+
+            for (user_id, signature, title, body) in text_rows.drain(..) {
+                save_item_text(&conn.conn, &user_id, &signature, &title, &body)?;
+            }
+        }
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Adds storage quotas to server users.
+struct From19To20;
+impl Upgrader for From19To20 {
+    fn from_version(&self) -> u32 { 19 }
+    fn to_version(&self) -> u32 { 20 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        // All nullable. NULL = unlimited.
+        // These replace server_user.max_bytes, which was never used.
+        conn.run("ALTER TABLE server_user ADD COLUMN max_item_bytes INTEGER")?;
+        conn.run("ALTER TABLE server_user ADD COLUMN max_attachment_bytes INTEGER")?;
+
+        // The quota for each user followed by this server user:
+        conn.run("ALTER TABLE server_user ADD COLUMN follow_max_item_bytes INTEGER")?;
+        conn.run("ALTER TABLE server_user ADD COLUMN follow_max_attachment_bytes INTEGER")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Lets server admins block users, items and attachment contents.
+struct From20To21;
+impl Upgrader for From20To21 {
+    fn from_version(&self) -> u32 { 20 }
+    fn to_version(&self) -> u32 { 21 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE blocked_user(
+                user_id BLOB,
+                -- Why the user was blocked:
+                notes TEXT NOT NULL,
+                created_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        conn.run("
+            CREATE UNIQUE INDEX blocked_user_primary_idx
+            ON blocked_user(user_id)
+        ")?;
+
+        conn.run("
+            CREATE TABLE blocked_item(
+                user_id BLOB,
+                signature BLOB,
+                notes TEXT NOT NULL,
+                created_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        conn.run("
+            CREATE UNIQUE INDEX blocked_item_primary_idx
+            ON blocked_item(user_id, signature)
+        ")?;
+
+        conn.run("
+            CREATE TABLE blocked_attachment(
+                -- The SHA-512 hash of the blocked contents:
+                hash BLOB,
+                notes TEXT NOT NULL,
+                created_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        conn.run("
+            CREATE UNIQUE INDEX blocked_attachment_primary_idx
+            ON blocked_attachment(hash)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Sharing an item no longer makes its author a "known user". Instead, we
+// store & serve only the shared item itself. (See: the shared_items view.)
+struct From21To22;
+impl Upgrader for From21To22 {
+    fn from_version(&self) -> u32 { 21 }
+    fn to_version(&self) -> u32 { 22 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        // See From4To5 for notes on this view.
+        conn.run("DROP VIEW known_users")?;
+        conn.run("
+            CREATE VIEW known_users (user_id) AS
+            -- For internal use only. All 'known users' of the server.
+                SELECT user_id
+                FROM server_user
+            UNION ALL
+                SELECT followed_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+            UNION ALL
+                -- Followers of an old ID also follow its successor:
+                SELECT p.successor_user_id
+                FROM follow AS f
+                INNER JOIN server_user AS s
+                    ON (f.source_user_id=s.user_id)
+                INNER JOIN profile AS p
+                    ON (f.followed_user_id=p.user_id)
+                WHERE p.successor_user_id IS NOT NULL
+            ;
+        ")?;
+
+        conn.run("
+            CREATE VIEW shared_items (user_id, signature) AS
+            -- For internal use only. Items shared by known users, which we
+            -- store & serve even if we don't otherwise know their authors.
+                SELECT sh.to_user_id, sh.to_signature
+                FROM share AS sh
+                WHERE EXISTS(SELECT user_id FROM known_users WHERE user_id = sh.from_user_id)
+            ;
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
+
+// Lets `db verify --quarantine` set aside corrupt items and attachment contents.
+// Unlike blocks, quarantined content is kept by `db prune`, and can be replaced
+// by uploading a valid copy.
+struct From22To23;
+impl Upgrader for From22To23 {
+    fn from_version(&self) -> u32 { 22 }
+    fn to_version(&self) -> u32 { 23 }
+    fn upgrade(&self, conn: &Connection) -> Result<(), Error> {
+        conn.run("
+            CREATE TABLE quarantined_item(
+                user_id BLOB,
+                signature BLOB,
+                -- What db verify found wrong with the item:
+                problem TEXT NOT NULL,
+                created_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        conn.run("
+            CREATE UNIQUE INDEX quarantined_item_primary_idx
+            ON quarantined_item(user_id, signature)
+        ")?;
+
+        conn.run("
+            CREATE TABLE quarantined_attachment(
+                -- The SHA-512 hash that the contents should have:
+                hash BLOB,
+                problem TEXT NOT NULL,
+                created_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        conn.run("
+            CREATE UNIQUE INDEX quarantined_attachment_primary_idx
+            ON quarantined_attachment(hash)
+        ")?;
+
+        conn.set_version(self.to_version())?;
+        Ok(())
+    }
+}
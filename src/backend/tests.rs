@@ -0,0 +1,741 @@
+//! Tests for the behavior every [`Backend`] must share.
+//!
+//! Each test runs against the memory backend, which is the reference for what
+//! each method does, and against SQLite in a temporary file. (The PostgreSQL
+//! backend needs a server to run against. See: `postgres::tests`.)
+
+use std::collections::HashSet;
+
+use protobuf::Message;
+use sodiumoxide::randombytes::randombytes;
+
+use crate::protos::{Item, Post};
+use super::*;
+
+/// Runs `test` once for each backend, with a new, empty database each time.
+fn each_factory(test: impl Fn(&dyn Factory)) {
+    println!("Testing the memory backend");
+    let factory = memory::FactoryBuilder::new().factory().unwrap();
+    test(factory.as_ref());
+
+    println!("Testing the sqlite backend");
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("feoblog.sqlite3");
+    let builder = sqlite::FactoryBuilder::new(file.to_string_lossy().into_owned());
+    builder.db_create().unwrap();
+    let factory = builder.factory().unwrap();
+    test(factory.as_ref());
+}
+
+/// Like each_factory(), but with a Backend connection.
+fn each_backend(test: impl Fn(&mut dyn Backend)) {
+    each_factory(|factory| test(factory.open().unwrap().as_mut()));
+}
+
+fn server_user(conn: &dyn Backend) -> UserID {
+    let user = UserID::from_vec(randombytes(32)).unwrap();
+    conn.add_server_user(&ServerUser{
+        user: user.clone(),
+        notes: String::new(),
+        on_homepage: true,
+    }).unwrap();
+    user
+}
+
+fn save(conn: &mut dyn Backend, user: &UserID, unix_utc_ms: i64, item: &mut Item) -> Signature {
+    item.set_timestamp_ms_utc(unix_utc_ms);
+    let row = ItemRow{
+        user: user.clone(),
+        signature: Signature::from_vec(randombytes(64)).unwrap(),
+        timestamp: Timestamp{ unix_utc_ms },
+        received: Timestamp{ unix_utc_ms },
+        item_bytes: item.write_to_bytes().unwrap(),
+    };
+    conn.save_user_item(&row, item).unwrap();
+    row.signature
+}
+
+fn post(title: &str) -> Item {
+    let mut post = Post::new();
+    post.set_title(title.into());
+    let mut item = Item::new();
+    item.set_post(post);
+    item
+}
+
+#[test]
+fn items_in_order() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let first = save(conn, &user, 1000, &mut post("first"));
+        let second = save(conn, &user, 2000, &mut post("second"));
+
+        let mut newest_first = vec![];
+        conn.homepage_items(TimeSpan::Before(Timestamp{ unix_utc_ms: 3000 }.into()), &mut |row| {
+            newest_first.push(row.item.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(newest_first, vec![second.clone(), first.clone()]);
+
+        let mut oldest_first = vec![];
+        conn.user_items(&user, TimeSpan::After(Timestamp{ unix_utc_ms: 1000 }.into()), &mut |row| {
+            oldest_first.push(row.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(oldest_first, vec![second]);
+    });
+}
+
+#[test]
+fn items_with_same_timestamp() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        for title in &["a", "b", "c"] {
+            save(conn, &user, 1000, &mut post(title));
+        }
+
+        // Page through them one item at a time:
+        let mut seen = HashSet::new();
+        let mut span = TimeSpan::Before(Timestamp{ unix_utc_ms: 2000 }.into());
+        loop {
+            let mut page = None;
+            conn.user_items(&user, span.clone(), &mut |row| {
+                page = Some(row);
+                Ok(false)
+            }).unwrap();
+
+            let row = match page {
+                None => break,
+                Some(row) => row,
+            };
+            span = TimeSpan::Before(Cursor::at(row.timestamp, &row.user, &row.signature));
+            assert!(seen.insert(row.signature));
+        }
+        assert_eq!(seen.len(), 3);
+    });
+}
+
+#[test]
+fn items_between() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        save(conn, &user, 1000, &mut post("too old"));
+        let second = save(conn, &user, 2000, &mut post("second"));
+        let third = save(conn, &user, 3000, &mut post("third"));
+        save(conn, &user, 4000, &mut post("too new"));
+
+        let span = TimeSpan::Between(
+            Timestamp{ unix_utc_ms: 1000 }.into(),
+            Timestamp{ unix_utc_ms: 4000 }.into(),
+        );
+        let mut newest_first = vec![];
+        conn.user_items(&user, span, &mut |row| {
+            newest_first.push(row.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(newest_first, vec![third, second]);
+    });
+}
+
+#[test]
+fn revisions_between() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let original = save(conn, &user, 1, &mut post("original"));
+
+        let mut revisions = vec![];
+        for unix_utc_ms in &[1000, 2000, 3000, 4000] {
+            let mut revision = Item::new();
+            revision.mut_revision().mut_original().set_bytes(original.bytes().to_vec());
+            revision.mut_revision().mut_post().set_title("revised".into());
+            revisions.push(save(conn, &user, *unix_utc_ms, &mut revision));
+        }
+
+        let span = TimeSpan::Between(
+            Timestamp{ unix_utc_ms: 1000 }.into(),
+            Timestamp{ unix_utc_ms: 4000 }.into(),
+        );
+        let mut newest_first = vec![];
+        conn.item_revisions(&user, &original, span, &mut |row| {
+            newest_first.push(row.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(newest_first, vec![revisions[2].clone(), revisions[1].clone()]);
+    });
+}
+
+#[test]
+fn row_iters() {
+    each_factory(|factory| {
+        let mut conn = factory.open().unwrap();
+        let conn = conn.as_mut();
+        let user = server_user(conn);
+        let parent = save(conn, &user, 1, &mut post("parent"));
+
+        // More than a chunk of each, with timestamps that span chunk boundaries:
+        let count = 250;
+        for i in 0..count {
+            let unix_utc_ms = 1000 + (i / 7) as i64;
+            save(conn, &user, unix_utc_ms, &mut post("post"));
+
+            let mut comment = Item::new();
+            let reply_to = comment.mut_comment().mut_reply_to();
+            reply_to.mut_user_id().set_bytes(user.bytes().to_vec());
+            reply_to.mut_signature().set_bytes(parent.bytes().to_vec());
+            save(conn, &user, unix_utc_ms, &mut comment);
+        }
+
+        let before = Timestamp{ unix_utc_ms: 2000 };
+        let posts: Vec<_> = factory.user_items_iter(&user, TimeSpan::Before(before.into()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        // Includes comments, and the parent post:
+        assert_eq!(posts.len(), count * 2 + 1);
+        let unique: HashSet<_> = posts.iter().map(|row| row.signature.clone()).collect();
+        assert_eq!(unique.len(), posts.len());
+
+        let replies: Vec<_> = factory.reply_items_iter(&user, &parent, TimeSpan::Before(before.into()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(replies.len(), count);
+        let unique: HashSet<_> = replies.iter().map(|row| row.signature.clone()).collect();
+        assert_eq!(unique.len(), count);
+        assert!(replies.windows(2).all(|w| w[0].timestamp.unix_utc_ms >= w[1].timestamp.unix_utc_ms));
+
+        // Streams fetch the same rows, from the blocking thread pool:
+        let stream = factory.user_items_iter(&user, TimeSpan::Before(before.into())).into_stream();
+        let streamed: Vec<_> = futures::executor::block_on_stream(stream)
+            .map(|row| row.map(|row| row.signature))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let posts: Vec<_> = posts.into_iter().map(|row| row.signature).collect();
+        assert_eq!(streamed, posts);
+    });
+}
+
+#[test]
+fn thread_items() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let root = save(conn, &user, 1000, &mut post("Root"));
+
+        let reply = |conn: &mut dyn Backend, unix_utc_ms: i64, parent: &Signature| {
+            let mut comment = Item::new();
+            let reply_to = comment.mut_comment().mut_reply_to();
+            reply_to.mut_user_id().set_bytes(user.bytes().to_vec());
+            reply_to.mut_signature().set_bytes(parent.bytes().to_vec());
+            save(conn, &user, unix_utc_ms, &mut comment)
+        };
+        let late = reply(&mut *conn, 3000, &root);
+        let late_second = reply(&mut *conn, 4000, &late);
+        let late_first = reply(&mut *conn, 3500, &late);
+        // Sorts before replies with positive timestamps:
+        let early = reply(&mut *conn, -5000, &root);
+        let early_reply = reply(&mut *conn, 10, &early);
+
+        let thread = |conn: &dyn Backend, limit: usize| {
+            let mut rows = vec![];
+            conn.thread_items(&user, &root, limit, &mut |row| {
+                rows.push((row.item.item.signature, row.depth));
+                Ok(true)
+            }).unwrap();
+            rows
+        };
+        assert_eq!(thread(&*conn, 10), vec![
+            (early.clone(), 1),
+            (early_reply.clone(), 2),
+            (late.clone(), 1),
+            (late_first.clone(), 2),
+            (late_second, 2),
+        ]);
+        assert_eq!(thread(&*conn, 4), vec![
+            (early, 1),
+            (early_reply, 2),
+            (late, 1),
+            (late_first, 2),
+        ]);
+    });
+}
+
+#[test]
+fn reaction_counts() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let fan = server_user(conn);
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        let target = save(conn, &user, 1000, &mut post("React to me"));
+
+        let react = |conn: &mut dyn Backend, from: &UserID, unix_utc_ms: i64, reaction: &str| {
+            let mut item = Item::new();
+            let reply_to = item.mut_reaction().mut_reply_to();
+            reply_to.mut_user_id().set_bytes(user.bytes().to_vec());
+            reply_to.mut_signature().set_bytes(target.bytes().to_vec());
+            item.mut_reaction().set_reaction(reaction.into());
+            save(conn, from, unix_utc_ms, &mut item)
+        };
+        react(&mut *conn, &user, 2000, "👍");
+        // Each user is only counted once per reaction:
+        react(&mut *conn, &fan, 2000, "👍");
+        react(&mut *conn, &fan, 3000, "👍");
+        react(&mut *conn, &fan, 3000, "❤");
+        // Unknown users' reactions aren't counted:
+        react(&mut *conn, &stranger, 2000, "👍");
+        react(&mut *conn, &stranger, 2000, "👎");
+
+        let counts = |conn: &dyn Backend| -> Vec<(String, u64)> {
+            conn.reaction_counts(&user, &target).unwrap()
+                .into_iter()
+                .map(|count| (count.reaction, count.count))
+                .collect()
+        };
+        assert_eq!(counts(&*conn), vec![("👍".to_string(), 2), ("❤".to_string(), 1)]);
+
+        // Nor are blocked users':
+        conn.add_block(&Block::User(fan.clone()), "").unwrap();
+        assert_eq!(counts(&*conn), vec![("👍".to_string(), 1)]);
+    });
+}
+
+#[test]
+fn unknown_users() {
+    each_backend(|conn| {
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        assert!(!conn.user_known(&stranger).unwrap());
+
+        let item = post("Hello?");
+        let denied = conn.quota_check_item(&stranger, &[], &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::UnknownUser)));
+
+        // Once followed by a server user, they're known:
+        let user = server_user(conn);
+        let mut follow = crate::protos::Follow::new();
+        follow.mut_user().set_bytes(stranger.bytes().to_vec());
+        let mut profile = Item::new();
+        profile.mut_profile().mut_follows().push(follow);
+        save(conn, &user, 1000, &mut profile);
+
+        assert!(conn.user_known(&stranger).unwrap());
+        assert!(conn.quota_check_item(&stranger, &[], &item).unwrap().is_none());
+    });
+}
+
+#[test]
+fn revoked_users() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        let profile = |display_name: &str, revoked: bool| {
+            let mut follow = crate::protos::Follow::new();
+            follow.mut_user().set_bytes(stranger.bytes().to_vec());
+            let mut item = Item::new();
+            item.mut_profile().set_display_name(display_name.into());
+            item.mut_profile().mut_follows().push(follow);
+            if revoked {
+                item.mut_profile().mut_revocation().set_reason("Lost my key".into());
+            }
+            item
+        };
+        save(conn, &user, 1000, &mut profile("Me", false));
+
+        let item = post("Hello");
+        let bytes = item.write_to_bytes().unwrap();
+        assert!(!conn.user_revoked(&user).unwrap());
+        assert!(conn.quota_check_item(&user, &bytes, &item).unwrap().is_none());
+        assert!(conn.quota_check_item(&stranger, &bytes, &item).unwrap().is_none());
+
+        // Revoking an ID stops it from posting, and from vouching for its follows:
+        save(conn, &user, 2000, &mut profile("Revoked", true));
+        assert!(conn.user_revoked(&user).unwrap());
+        let denied = conn.quota_check_item(&user, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::ProfileRevoked)));
+        let denied = conn.quota_check_item(&stranger, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::UnknownUser)));
+
+        // Revocations are permanent:
+        save(conn, &user, 3000, &mut profile("Me again", false));
+        assert!(conn.user_revoked(&user).unwrap());
+        let denied = conn.quota_check_item(&user, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::ProfileRevoked)));
+    });
+}
+
+#[test]
+fn shared_items() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        let shared = save(conn, &stranger, 1000, &mut post("Share me"));
+        let other = save(conn, &stranger, 2000, &mut post("But not me"));
+
+        let mut share = Item::new();
+        let target = share.mut_share().mut_item();
+        target.mut_user_id().set_bytes(stranger.bytes().to_vec());
+        target.mut_signature().set_bytes(shared.bytes().to_vec());
+        save(conn, &user, 3000, &mut share);
+
+        // Sharing an item doesn't make its author known:
+        assert!(!conn.user_known(&stranger).unwrap());
+        assert!(conn.item_shared(&stranger, &shared).unwrap());
+        assert!(!conn.item_shared(&stranger, &other).unwrap());
+        assert!(conn.user_item(&stranger, &shared).unwrap().is_some());
+        assert!(conn.user_item(&stranger, &other).unwrap().is_none());
+
+        let result = conn.prune(PruneOpts{
+            dry_run: false,
+            attachments: false,
+            items: true,
+            blocked: false,
+        }).unwrap();
+        assert_eq!(result.items_count, 1);
+        assert!(conn.user_item_exists(&stranger, &shared).unwrap());
+        assert!(!conn.user_item_exists(&stranger, &other).unwrap());
+    });
+}
+
+#[test]
+fn deleted_items() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let signature = save(conn, &user, 1000, &mut post("Oops"));
+
+        let mut delete = Item::new();
+        delete.mut_delete().mut_signature().set_bytes(signature.bytes().to_vec());
+        save(conn, &user, 2000, &mut delete);
+
+        assert!(conn.user_item(&user, &signature).unwrap().is_none());
+        assert!(conn.user_item_deleted(&user, &signature).unwrap());
+    });
+}
+
+#[test]
+fn deleting_post_deletes_revisions() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let original = save(conn, &user, 1000, &mut post("Oops"));
+
+        let mut revision = Item::new();
+        revision.mut_revision().mut_original().set_bytes(original.bytes().to_vec());
+        revision.mut_revision().mut_post().set_title("Still oops".into());
+        let revision = save(conn, &user, 2000, &mut revision);
+
+        let mut delete = Item::new();
+        delete.mut_delete().mut_signature().set_bytes(original.bytes().to_vec());
+        save(conn, &user, 3000, &mut delete);
+
+        assert!(conn.user_item(&user, &revision).unwrap().is_none());
+        assert!(conn.user_item_deleted(&user, &revision).unwrap());
+    });
+}
+
+#[test]
+fn deleting_revision_restores_tags() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let original = save(conn, &user, 1000, &mut post("About #apples"));
+
+        let mut revision = Item::new();
+        revision.mut_revision().mut_original().set_bytes(original.bytes().to_vec());
+        revision.mut_revision().mut_post().set_title("About #oranges".into());
+        let revision = save(conn, &user, 2000, &mut revision);
+
+        let tagged = |conn: &dyn Backend, tag: &str| {
+            let mut found = vec![];
+            conn.tag_items(tag, TimeSpan::Before(Timestamp{ unix_utc_ms: 4000 }.into()), &mut |row| {
+                found.push(row.item.signature);
+                Ok(true)
+            }).unwrap();
+            found
+        };
+        assert!(tagged(&*conn, "apples").is_empty());
+        assert_eq!(tagged(&*conn, "oranges"), vec![original.clone()]);
+
+        let mut delete = Item::new();
+        delete.mut_delete().mut_signature().set_bytes(revision.bytes().to_vec());
+        save(conn, &user, 3000, &mut delete);
+
+        assert_eq!(tagged(&*conn, "apples"), vec![original]);
+        assert!(tagged(&*conn, "oranges").is_empty());
+    });
+}
+
+#[test]
+fn early_delete_of_profile() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+
+        let mut profile = Item::new();
+        profile.set_timestamp_ms_utc(1000);
+        profile.mut_profile().set_display_name("Me".into());
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(randombytes(64)).unwrap(),
+            timestamp: Timestamp{ unix_utc_ms: 1000 },
+            received: Timestamp{ unix_utc_ms: 1000 },
+            item_bytes: profile.write_to_bytes().unwrap(),
+        };
+
+        // The Delete arrives first:
+        let mut delete = Item::new();
+        delete.mut_delete().mut_signature().set_bytes(row.signature.bytes().to_vec());
+        save(conn, &user, 2000, &mut delete);
+        assert!(conn.user_item_deleted(&user, &row.signature).unwrap());
+
+        // Profiles can't be deleted, so the Delete is ignored:
+        conn.save_user_item(&row, &profile).unwrap();
+        assert!(!conn.user_item_deleted(&user, &row.signature).unwrap());
+        assert!(conn.user_profile(&user).unwrap().is_some());
+    });
+}
+
+#[test]
+fn item_quotas() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let item = post("Hello, world");
+        let bytes = item.write_to_bytes().unwrap();
+        assert!(conn.quota_check_item(&user, &bytes, &item).unwrap().is_none());
+
+        conn.set_server_user_quotas(&user, &ServerUserQuotas{
+            own: Quota{ item_bytes: Some(bytes.len() as u64), attachment_bytes: None },
+            follows: Quota::default(),
+        }).unwrap();
+        assert!(conn.quota_check_item(&user, &bytes, &item).unwrap().is_none());
+
+        save(conn, &user, 1000, &mut post("Hello, world"));
+        let denied = conn.quota_check_item(&user, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::ItemQuotaExceeded{ .. })));
+
+        // Users can still update their profile:
+        let mut profile = Item::new();
+        profile.mut_profile().set_display_name("Full".into());
+        let profile_bytes = profile.write_to_bytes().unwrap();
+        assert!(conn.quota_check_item(&user, &profile_bytes, &profile).unwrap().is_none());
+
+        // Only server users have quotas to set:
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        assert!(conn.set_server_user_quotas(&stranger, &ServerUserQuotas::default()).is_err());
+    });
+}
+
+#[test]
+fn shared_item_quotas() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let stranger = UserID::from_vec(randombytes(32)).unwrap();
+        let item = post("Share me");
+        let bytes = item.write_to_bytes().unwrap();
+        let denied = conn.quota_check_item(&stranger, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::UnknownUser)));
+
+        let mut share = Item::new();
+        let target = share.mut_share().mut_item();
+        target.mut_user_id().set_bytes(stranger.bytes().to_vec());
+        target.mut_signature().set_bytes(randombytes(64));
+        save(conn, &user, 1000, &mut share);
+        assert!(conn.quota_check_item(&stranger, &bytes, &item).unwrap().is_none());
+
+        // The share vouches for the author like a follow would, so they get the same quota:
+        conn.set_server_user_quotas(&user, &ServerUserQuotas{
+            own: Quota::default(),
+            follows: Quota{ item_bytes: Some(bytes.len() as u64 - 1), attachment_bytes: None },
+        }).unwrap();
+        let denied = conn.quota_check_item(&stranger, &bytes, &item).unwrap();
+        assert!(matches!(denied, Some(QuotaDenyReason::ItemQuotaExceeded{ .. })));
+    });
+}
+
+#[test]
+fn search_items() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let apples = save(conn, &user, 1000, &mut post("Apples and oranges"));
+        save(conn, &user, 2000, &mut post("Just oranges"));
+
+        let search = |query: &str| {
+            let mut found = vec![];
+            conn.search_items(query, SearchScope::All, TimeSpan::Before(Timestamp{ unix_utc_ms: 3000 }.into()), &mut |row| {
+                found.push(row.item.signature);
+                Ok(true)
+            }).unwrap();
+            found
+        };
+
+        assert_eq!(search("ORANGES apples!"), vec![apples]);
+        assert_eq!(search("oranges").len(), 2);
+        assert!(search("bananas").is_empty());
+        assert!(search("  ").is_empty());
+    });
+}
+
+#[test]
+fn blocks() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let first = save(conn, &user, 1000, &mut post("First"));
+        let second = save(conn, &user, 2000, &mut post("Second"));
+
+        let blocked_item = Block::Item(user.clone(), first.clone());
+        conn.add_block(&blocked_item, "Takedown request").unwrap();
+        assert!(conn.is_blocked(&blocked_item).unwrap());
+        assert!(!conn.is_blocked(&Block::Item(user.clone(), second.clone())).unwrap());
+
+        // Blocking a user blocks all of their items:
+        let blocked_user = Block::User(user.clone());
+        conn.add_block(&blocked_user, "").unwrap();
+        assert!(conn.is_blocked(&Block::Item(user.clone(), second.clone())).unwrap());
+        assert!(conn.remove_block(&blocked_user).unwrap());
+        assert!(!conn.remove_block(&blocked_user).unwrap());
+
+        let result = conn.prune(PruneOpts{
+            dry_run: false,
+            attachments: true,
+            items: true,
+            blocked: true,
+        }).unwrap();
+        assert_eq!(result.blocked_items_count, 1);
+        assert!(!conn.user_item_exists(&user, &first).unwrap());
+        assert!(conn.user_item_exists(&user, &second).unwrap());
+    });
+}
+
+#[test]
+fn blocked_items_not_listed() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let first = save(conn, &user, 1000, &mut post("First"));
+        let second = save(conn, &user, 2000, &mut post("Second"));
+        conn.add_block(&Block::Item(user.clone(), first), "Takedown request").unwrap();
+
+        // Blocked items are still stored until they're pruned, but aren't listed:
+        let span = || TimeSpan::Before(Timestamp{ unix_utc_ms: 3000 }.into());
+        let mut homepage = vec![];
+        conn.homepage_items(span(), &mut |row| {
+            homepage.push(row.item.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(homepage, vec![second.clone()]);
+
+        let mut feed = vec![];
+        conn.user_feed_items(&user, span(), &mut |row| {
+            feed.push(row.item.signature);
+            Ok(true)
+        }).unwrap();
+        assert_eq!(feed, vec![second]);
+
+        conn.add_block(&Block::User(user.clone()), "").unwrap();
+        let mut count = 0;
+        conn.homepage_items(span(), &mut |_| {
+            count += 1;
+            Ok(true)
+        }).unwrap();
+        assert_eq!(count, 0);
+    });
+}
+
+#[test]
+fn blocked_items_not_shared() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let author = server_user(conn);
+        let shared = save(conn, &author, 1000, &mut post("Share me"));
+
+        let mut share = Item::new();
+        let target = share.mut_share().mut_item();
+        target.mut_user_id().set_bytes(author.bytes().to_vec());
+        target.mut_signature().set_bytes(shared.bytes().to_vec());
+        let share = save(conn, &user, 2000, &mut share);
+
+        let share_row = |conn: &dyn Backend| {
+            let mut found = None;
+            conn.homepage_items(TimeSpan::Before(Timestamp{ unix_utc_ms: 3000 }.into()), &mut |row| {
+                if row.item.signature == share {
+                    found = Some(row);
+                }
+                Ok(true)
+            }).unwrap();
+            found.expect("the Share should be listed")
+        };
+        let row = share_row(&*conn);
+        assert_eq!(row.shared.map(|it| it.item.signature), Some(shared.clone()));
+
+        // The Share is still listed, but no longer embeds the blocked item:
+        conn.add_block(&Block::Item(author.clone(), shared.clone()), "Takedown request").unwrap();
+        assert!(share_row(&*conn).shared.is_none());
+        assert!(conn.shared_item(&user, &share).unwrap().is_none());
+    });
+}
+
+#[test]
+fn verify_items() {
+    use sodiumoxide::crypto::sign;
+
+    each_backend(|conn| {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let user = UserID::from_vec(public_key.as_ref().to_vec()).unwrap();
+
+        let mut item = post("Signed");
+        item.set_timestamp_ms_utc(1000);
+        let item_bytes = item.write_to_bytes().unwrap();
+        let signature = sign::sign_detached(&item_bytes, &secret_key);
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(signature.as_ref().to_vec()).unwrap(),
+            timestamp: Timestamp{ unix_utc_ms: 1000 },
+            received: Timestamp{ unix_utc_ms: 1000 },
+            item_bytes,
+        };
+        conn.save_user_item(&row, &item).unwrap();
+
+        // save() uses random bytes for the signature:
+        let forged = save(conn, &user, 2000, &mut post("Forged"));
+
+        let mut invalid = vec![];
+        let count = conn.verify_items(&mut |bad| {
+            invalid.push(bad.quarantine());
+            Ok(true)
+        }).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(invalid, vec![Some(Quarantine::Item(user, forged))]);
+    });
+}
+
+#[test]
+fn quarantined_items_replaced() {
+    each_backend(|conn| {
+        let user = server_user(conn);
+        let mut item = post("Corrupt");
+        let signature = save(conn, &user, 1000, &mut item);
+        conn.add_quarantine(&Quarantine::Item(user.clone(), signature.clone()), "Invalid signature").unwrap();
+
+        // Quarantined items aren't served or listed:
+        assert!(conn.user_item(&user, &signature).unwrap().is_none());
+        assert!(!conn.user_item_exists(&user, &signature).unwrap());
+        let mut count = 0;
+        conn.homepage_items(TimeSpan::Before(Timestamp{ unix_utc_ms: 3000 }.into()), &mut |_| {
+            count += 1;
+            Ok(true)
+        }).unwrap();
+        assert_eq!(count, 0);
+
+        // ... but unlike blocked items, prune keeps them:
+        conn.prune(PruneOpts{ dry_run: false, attachments: true, blocked: true, items: true }).unwrap();
+        let mut stored = 0;
+        conn.all_items(&mut |_| {
+            stored += 1;
+            Ok(true)
+        }).unwrap();
+        assert_eq!(stored, 1);
+
+        // Saving the item again replaces it, and lifts its quarantine:
+        let row = ItemRow{
+            user: user.clone(),
+            signature: signature.clone(),
+            timestamp: Timestamp{ unix_utc_ms: 1000 },
+            received: Timestamp{ unix_utc_ms: 2000 },
+            item_bytes: item.write_to_bytes().unwrap(),
+        };
+        conn.save_user_item(&row, &item).unwrap();
+        let found = conn.user_item(&user, &signature).unwrap().unwrap();
+        assert_eq!(found.received.unix_utc_ms, 2000);
+    });
+}
@@ -0,0 +1,261 @@
+//! Exports all of a user's data to a portable archive, so that they can take it
+//! to another server.
+//!
+//! The archive is a tar file containing:
+//!  * `items/<signature>.proto3`: The signed protobuf bytes of each Item.
+//!  * `attachments/<hash>`: The contents of each attached file, named by their (hex) SHA-512 hash.
+//!  * `manifest.txt`: Describes the above. It's written last, since we only know
+//!    which attachments we have once we've tried to read them.
+//!
+//! The manifest is UTF-8 text, with one tab-separated record per line:
+//!
+//! ```text
+//! feoblog-export  1
+//! user            <userID>
+//! exported        <unix_utc_ms>
+//! item            <signature>  <unix_utc_ms>
+//! file            <signature>  <hash>  <size>  <file name>
+//! missing         <signature>  <hash>  <size>  <file name>
+//! ```
+//!
+//! `missing` files were attached to an item, but we didn't have their contents.
+//! File names are percent-encoded so that they can't contain tabs or newlines.
+//!
+//! Since every Item is signed, whoever imports the archive can (and should)
+//! verify each one against the user ID in the manifest.
+
+use std::{collections::HashSet, fmt::Display, io::{Read, Seek, SeekFrom, Write}};
+
+use anyhow::{Context, Error, bail, format_err};
+use percent_encoding::{CONTROLS, utf8_percent_encode};
+use protobuf::Message;
+use sizedisplay::SizeDisplay;
+
+use crate::{backend::{Backend, Block, FileStream, SHA512, Timestamp, UserID}, protos::Item, util::AsHex};
+
+/// Increment if the archive layout changes incompatibly.
+const FORMAT_VERSION: u32 = 1;
+
+/// Write all of `user`'s items, and their attachments, to `out`.
+/// Blocked items and attachments are left out.
+pub(crate) fn export<W: Write>(conn: &dyn Backend, user: &UserID, out: W) -> Result<ExportResult, Error> {
+    let mut archive = tar::Builder::new(out);
+    let mut manifest = format!("feoblog-export\t{}\nuser\t{}\nexported\t{}\n", FORMAT_VERSION, user, Timestamp::now().unix_utc_ms);
+    let mut result = ExportResult::default();
+
+    // Attachments may be shared by several items, but we only need one copy:
+    let mut saved_hashes: HashSet<Vec<u8>> = HashSet::new();
+
+    conn.all_user_items(user, &mut |row| {
+        if conn.is_blocked(&Block::Item(row.user.clone(), row.signature.clone()))? {
+            result.blocked_count += 1;
+            return Ok(true);
+        }
+
+        let signature = row.signature.to_base58();
+        let size = row.item_bytes.len() as u64;
+        append(&mut archive, &format!("items/{}.proto3", signature), row.timestamp, size, row.item_bytes.as_slice())?;
+        manifest.push_str(&format!("item\t{}\t{}\n", signature, row.timestamp.unix_utc_ms));
+        result.items_count += 1;
+        result.items_bytes += size;
+
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes).with_context(|| format!("Error parsing item {}", signature))?;
+        let post = if item.has_revision() {
+            item.get_revision().get_post()
+        } else {
+            item.get_post()
+        };
+
+        for file in post.get_attachments().get_file() {
+            let hash = SHA512::from_hash_bytes(file.get_hash())?;
+            let details = format!(
+                "{}\t{}\t{}\t{}",
+                signature,
+                hash.bytes().as_hex(),
+                file.get_size(),
+                utf8_percent_encode(file.get_name(), CONTROLS),
+            );
+
+            if saved_hashes.contains(hash.bytes()) {
+                manifest.push_str(&format!("file\t{}\n", details));
+                continue;
+            }
+
+            let contents = if conn.is_blocked(&Block::Attachment(hash.clone()))? {
+                result.blocked_count += 1;
+                None
+            } else {
+                conn.get_contents(row.user.clone(), row.signature.clone(), file.get_name())?
+            };
+
+            let contents = match contents {
+                Some(contents) => contents,
+                None => {
+                    manifest.push_str(&format!("missing\t{}\n", details));
+                    result.missing_count += 1;
+                    continue;
+                },
+            };
+
+            let size = contents.size;
+            let temp = buffer(contents).with_context(|| format!("Error reading {}", hash))?;
+            append(&mut archive, &format!("attachments/{}", hash.bytes().as_hex()), row.timestamp, size, temp)?;
+            manifest.push_str(&format!("file\t{}\n", details));
+            saved_hashes.insert(hash.bytes().to_vec());
+            result.attachments_count += 1;
+            result.attachments_bytes += size;
+        }
+
+        Ok(true)
+    })?;
+
+    let manifest = manifest.into_bytes();
+    append(&mut archive, "manifest.txt", Timestamp::now(), manifest.len() as u64, manifest.as_slice())?;
+    archive.into_inner()?.flush()?;
+
+    Ok(result)
+}
+
+fn append<W: Write, R: Read>(archive: &mut tar::Builder<W>, path: &str, modified: Timestamp, size: u64, data: R) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime((modified.unix_utc_ms / 1000).max(0) as u64);
+    archive.append_data(&mut header, path, data).with_context(|| format!("Error writing {}", path))?;
+    Ok(())
+}
+
+/// Copy attachment contents to a temp file, since tar needs to know their exact size before writing them.
+fn buffer(file: FileStream) -> Result<std::fs::File, Error> {
+    let mut temp = tempfile::tempfile().context("Error opening temp file")?;
+    let mut size: u64 = 0;
+    for chunk in futures::executor::block_on_stream(file.stream) {
+        let chunk = chunk.map_err(|e| format_err!("{}", e))?;
+        temp.write_all(&chunk)?;
+        size += chunk.len() as u64;
+    }
+
+    if size != file.size {
+        bail!("Expected {} bytes but read {}", file.size, size);
+    }
+
+    temp.seek(SeekFrom::Start(0))?;
+    Ok(temp)
+}
+
+/// Report what was written to an export archive.
+#[derive(Default)]
+pub(crate) struct ExportResult {
+    pub items_count: u64,
+    pub items_bytes: u64,
+
+    pub attachments_count: u64,
+    pub attachments_bytes: u64,
+
+    /// Attachments we didn't have the contents for.
+    pub missing_count: u64,
+
+    /// Items and attachments that were left out because they're blocked.
+    pub blocked_count: u64,
+}
+
+impl Display for ExportResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Exported {} items ({}) and {} attachments ({}).",
+            self.items_count,
+            SizeDisplay::bytes(self.items_bytes),
+            self.attachments_count,
+            SizeDisplay::bytes(self.attachments_bytes),
+        )?;
+        if self.missing_count > 0 {
+            write!(f, "\n{} attachments were missing, and are listed in the manifest.", self.missing_count)?;
+        }
+        if self.blocked_count > 0 {
+            write!(f, "\n{} blocked items and attachments were left out.", self.blocked_count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use sodiumoxide::randombytes::randombytes;
+
+    use crate::{backend::{Factory as _, FactoryBuilder as _, ItemRow, ServerUser, Signature, memory}, protos::{Attachments, File, Post}};
+    use super::*;
+
+    #[test]
+    fn export_post_with_attachment() -> Result<(), Error> {
+        let mut conn = memory::FactoryBuilder::new().factory()?.open()?;
+        let user = UserID::from_vec(randombytes(32))?;
+        // Attachments are only served for known users:
+        conn.add_server_user(&ServerUser{
+            user: user.clone(),
+            notes: String::new(),
+            on_homepage: false,
+        })?;
+
+        let contents = b"Hello, world!".to_vec();
+        let hash = SHA512::from_file(&mut Cursor::new(&contents))?;
+
+        let mut file = File::new();
+        file.set_name("hello.txt".into());
+        file.set_size(contents.len() as u64);
+        file.set_hash(hash.bytes().to_vec());
+        let mut attachments = Attachments::new();
+        attachments.mut_file().push(file);
+        let mut post = Post::new();
+        post.set_title("Attached".into());
+        post.set_attachments(attachments);
+        let mut item = Item::new();
+        item.set_timestamp_ms_utc(1000);
+        item.set_post(post);
+
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(randombytes(64))?,
+            timestamp: Timestamp{ unix_utc_ms: 1000 },
+            received: Timestamp{ unix_utc_ms: 1000 },
+            item_bytes: item.write_to_bytes()?,
+        };
+        conn.save_user_item(&row, &item)?;
+        conn.save_attachment(contents.len() as u64, &hash, &mut Cursor::new(&contents))?;
+
+        let mut out = vec![];
+        let result = export(conn.as_ref(), &user, &mut out)?;
+        assert_eq!(result.items_count, 1);
+        assert_eq!(result.attachments_count, 1);
+
+        let mut paths = vec![];
+        let mut archive = tar::Archive::new(Cursor::new(out));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().to_string();
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            if path == "manifest.txt" {
+                let manifest = String::from_utf8(data)?;
+                assert!(manifest.contains(&format!("user\t{}\n", user)));
+                assert!(manifest.contains("\thello.txt\n"));
+            } else if path.starts_with("attachments/") {
+                assert_eq!(data, contents);
+            } else {
+                assert_eq!(data, row.item_bytes);
+            }
+            paths.push(path);
+        }
+
+        assert_eq!(paths, vec![
+            format!("items/{}.proto3", row.signature.to_base58()),
+            format!("attachments/{}", hash.bytes().as_hex()),
+            "manifest.txt".to_string(),
+        ]);
+
+        Ok(())
+    }
+}
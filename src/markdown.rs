@@ -1,289 +1,619 @@
-use std::{cell::RefCell, mem, num::NonZeroUsize, str::from_utf8};
-
-use crate::backend::{Signature, UserID};
-
-use comrak::{Arena, ComrakOptions, arena_tree::Node, format_html, nodes::{Ast, AstNode, NodeLink, NodeValue}, parse_document};
-
-// TODO: Getting all these individually requires parsing multiple times. Optimize? (Seems premature.)
-pub(crate) trait ToHTML {
-    /// Convert this markdown to a safe subset of HTML.
-    fn md_to_html(&self) -> String;
-
-    fn md_to_html_with(&self, options: Options) -> String;
-
-    /// Find all images embedded in the Markdown.
-    fn md_get_images(&self) -> Vec<Image>;
-
-    /// Get a text summary:
-    fn md_get_summary(&self, max_len: usize) -> String;
-}
-
-impl ToHTML for str {
-    fn md_to_html(&self) -> String {
-        self.md_to_html_with(Options::default())
-    }
-
-    fn md_to_html_with(&self, options: Options) -> String {
-
-        let md_options = ComrakOptions::default();
-
-        let arena = Arena::new();
-        let root = parse_document(&arena, self, &md_options);
-        
-        fix_relative_links(&arena, root, &options);
-
-        let mut html = vec![];
-
-        format_html(root, &md_options, &mut html).expect("Should be no I/O errors writing to a vec![]");
-        to_string_lossy(html)
-    }
-
-    fn md_get_images(&self) -> Vec<Image> {
-        let md_options = ComrakOptions::default();
-
-        let arena = Arena::new();
-        let root = parse_document(&arena, self, &md_options);
-
-        let mut images = vec![];
-
-        iter_nodes_mut(root, &mut |node| {
-            if let NodeValue::Image(ref img) = node.data.borrow().value {
-                images.push(image_from_node(node, img));
-            }
-        });
-
-
-        return images
-    }
-
-    fn md_get_summary(&self, max_len: usize) -> String {
-        let mut out = String::new();
-
-        let md_options = ComrakOptions::default();
-
-        let arena = Arena::new();
-        let root = parse_document(&arena, self, &md_options);
-
-        iter_nodes_mut(root, &mut |node| {
-            if out.len() >= max_len { return }
-
-            match node.data.borrow().value {
-                NodeValue::Text(ref text) => {
-                    if in_image(node) { return }
-
-                    if !out.is_empty() && !out.ends_with(' ') { out.push(' '); }
-                    let text = to_string_lossy(text.clone());
-                    out.push_str(text.trim());
-                    if is_heading(node) && !text.ends_with(":") { out.push(':') }
-                },
-                _ => {},
-            }
-        });
-
-        if out.len() > max_len {
-            safe_truncate(&mut out, max_len - 1);
-            out.push('…');
-        }
-
-        out
-    }
-}
-
-
-fn safe_truncate(value: &mut String, mut len: usize) {
-    if value.len() <= len { return }
-
-    // truncate panics if you try to truncate at a non-char-boundary. >.< 
-    while !value.is_char_boundary(len) {
-        len -= 1;
-    }
-
-    value.truncate(len);
-}
-
-fn is_heading(node: &Node<RefCell<Ast>>) -> bool {
-    if let Some(parent) = node.parent() {
-        if let NodeValue::Heading(_) = parent.data.borrow().value {
-            return true;
-        }
-    }
-    false
-}
-
-fn in_image(node: &Node<RefCell<Ast>>) -> bool {
-    let parent = match node.parent() {
-        Some(p) => p,
-        _ => return false,
-    };
-
-    match parent.data.borrow().value {
-        NodeValue::Image(_) => true,
-        _ => false,
-    }
-}
-
-fn image_from_node<'a>(node: &'a Node<'a, RefCell<Ast>>, img: &NodeLink) -> Image {
-    
-    let url = to_string_lossy(img.url.clone());
-
-    // A node's alt text is stored as a child text node:
-    let alt = node.children().next().map(|it| {
-        if let NodeValue::Text(ref text) = it.data.borrow().value {
-            return Some(to_string_lossy(text.clone()));
-        }
-        return None;
-    }).flatten();
-
-    Image{url, alt}
-}
-
-pub(crate) struct Image {
-    pub url: String,
-    pub alt: Option<String>,
-}
-
-fn fix_relative_links<'a>(arena: &Arena<Node<RefCell<Ast>>>, root: &'a AstNode<'a>, options: &Options) {
-    let (user_id, signature) = match (options.user_id, options.signature) {
-        (Some(u), Some(s)) => (u, s),
-        _ => return,
-    };
-
-    let abs_root = format!("/u/{}/i/{}/", user_id.to_base58(), signature.to_base58());
-
-    iter_nodes(root, &|node| {
-        match &mut node.data.borrow_mut().value {
-            &mut NodeValue::Link(ref mut node_link) => { fix_link(node_link, &abs_root); }
-            &mut NodeValue::Image(ref mut node_link) => { fix_link(node_link, &abs_root); }
-            _ => (),
-        }
-    });
-}
-
-fn fix_link(node_link: &mut NodeLink, abs_root: &String) -> () {
-    let url = std::str::from_utf8(node_link.url.as_slice());
-    let url = match url {
-        Ok(u) => u,
-        // We won't even bother otherwise:
-        Err(e) => return,
-    };
-
-    if url.starts_with("/") || url.contains("//") {
-        // Host-absolute url like /foo/bar
-        // protocol-relative urL like //example.com/foo/bar
-        // or absolute:  http://example.com/foo/bar
-        return
-    }
-
-    let url = format!("{}{}", abs_root, url);
-    node_link.url = url.into();
-}
-
-
-
-fn iter_nodes<'a, F>(node: &'a AstNode<'a>, f: &F)
-where F : Fn(&'a AstNode<'a>)
-{
-    f(node);
-    for c in node.children() {
-        iter_nodes(c, f);
-    }
-}
-
-fn iter_nodes_mut<'a, F>(node: &'a AstNode<'a>, f: &mut F)
-where F : FnMut(&'a AstNode<'a>)
-{
-    f(node);
-    for c in node.children() {
-        iter_nodes_mut(c, f);
-    }
-}
-
-
-
-fn to_string_lossy(bytes: Vec<u8>) -> String {
-    let err = match String::from_utf8(bytes) {
-        // This is the efficient happy path: (in-place conversion)
-        Ok(s) => return s,
-        Err(e) => e,
-    };
-
-    // Use a lossy copy instead: (allocates)
-    let s = String::from_utf8_lossy(err.as_bytes());
-    String::from(s)
-}
-
-#[derive(Default)]
-pub(crate) struct Options<'a> {
-
-    /// If both user_id and signature are specified, we can convert relative URLS to absolute.
-    /// This lets them work in feeds as well as the Item page.
-    pub user_id: Option<&'a UserID>,
-    pub signature: Option<&'a Signature>,
-}
-
-#[test]
-fn test_get_summary() {
-    let plaintext = "Hello, world! This is a test.";
-    assert_eq!(plaintext, plaintext.md_get_summary(1000));
-
-    let heading = "
-Heading
-=======
-
-Hello, world!
-";
-    assert_eq!("Heading: Hello, world!", heading.md_get_summary(1000));
-
-    let heading2 = "
-Heading:
-=======
-
-Hello, world!
-";
-    assert_eq!("Heading: Hello, world!", heading2.md_get_summary(1000));
-
-    let comment = "
-<!--- This is an HTML comment and should not be rendered. -->
-Hello, world!
-";
-    assert_eq!("Hello, world!", comment.md_get_summary(1000));
-
-    let inline_html = r#"
-<div id="foo">
-This is some inline HTML.  We strip this out.
-</div>
-
-Plain text.
-"#;
-    assert_eq!("Plain text.", inline_html.md_get_summary(1000));
-
-    let links = r#"
-
-If there are [links] I expect [just][1] the text.
-
-[links]: https://www.google.com/
-[1]: https://www.twitter.com/
-
-"#;
-    assert_eq!("If there are links I expect just the text.", links.md_get_summary(1000));
-
-    let paragraphs = r#"
-If there are multiple paragraphs
-
-I expect them to get joined with a space.
-"#;
-
-    assert_eq!("If there are multiple paragraphs I expect…", paragraphs.md_get_summary(42));
-
-    let nihongo = "日本語はかわいいです。";
-    // 日 is 4 bytes in utf-8. The first part of 本 will get dropped to be safe:
-    assert_eq!("日…", nihongo.md_get_summary(5));
-
-    let image = r#"
-Here's an image: ![image]
-
-[image]: files/image.png
-"#;
-
-    assert_eq!("Here's an image:", image.md_get_summary(1000));
-}
\ No newline at end of file
+use std::{cell::RefCell, collections::HashMap, mem, num::NonZeroUsize, str::from_utf8};
+
+use crate::backend::{Signature, UserID};
+
+use comrak::{Arena, ComrakOptions, arena_tree::Node, format_html, nodes::{Ast, AstNode, NodeLink, NodeValue}, parse_document};
+
+// TODO: Getting all these individually requires parsing multiple times. Optimize? (Seems premature.)
+pub(crate) trait ToHTML {
+    /// Convert this markdown to a safe subset of HTML.
+    fn md_to_html(&self) -> String;
+
+    fn md_to_html_with(&self, options: Options) -> String;
+
+    /// Find all images embedded in the Markdown.
+    fn md_get_images(&self) -> Vec<Image>;
+
+    /// Get a text summary:
+    fn md_get_summary(&self, max_len: usize) -> String;
+
+    /// Find all #hashtags in the Markdown. (Lowercased, without the #, and without duplicates.)
+    fn md_get_tags(&self) -> Vec<String>;
+
+    /// Find all @mentions of user IDs in the Markdown. (Without duplicates.)
+    fn md_get_mentions(&self) -> Vec<UserID>;
+}
+
+impl ToHTML for str {
+    fn md_to_html(&self) -> String {
+        self.md_to_html_with(Options::default())
+    }
+
+    fn md_to_html_with(&self, options: Options) -> String {
+
+        let md_options = ComrakOptions::default();
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, self, &md_options);
+        
+        let image_sizes = apply_attachment_meta(&arena, root, &options);
+        fix_relative_links(&arena, root, &options);
+        link_mentions(&arena, root, &options);
+
+        let mut html = vec![];
+
+        format_html(root, &md_options, &mut html).expect("Should be no I/O errors writing to a vec![]");
+        add_image_sizes(to_string_lossy(html), &image_sizes)
+    }
+
+    fn md_get_images(&self) -> Vec<Image> {
+        let md_options = ComrakOptions::default();
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, self, &md_options);
+
+        let mut images = vec![];
+
+        iter_nodes_mut(root, &mut |node| {
+            if let NodeValue::Image(ref img) = node.data.borrow().value {
+                images.push(image_from_node(node, img));
+            }
+        });
+
+
+        return images
+    }
+
+    fn md_get_summary(&self, max_len: usize) -> String {
+        let mut out = String::new();
+
+        let md_options = ComrakOptions::default();
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, self, &md_options);
+
+        iter_nodes_mut(root, &mut |node| {
+            if out.len() >= max_len { return }
+
+            match node.data.borrow().value {
+                NodeValue::Text(ref text) => {
+                    if in_image(node) { return }
+
+                    if !out.is_empty() && !out.ends_with(' ') { out.push(' '); }
+                    let text = to_string_lossy(text.clone());
+                    out.push_str(text.trim());
+                    if is_heading(node) && !text.ends_with(":") { out.push(':') }
+                },
+                _ => {},
+            }
+        });
+
+        if out.len() > max_len {
+            safe_truncate(&mut out, max_len - 1);
+            out.push('…');
+        }
+
+        out
+    }
+
+    fn md_get_tags(&self) -> Vec<String> {
+        let md_options = ComrakOptions::default();
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, self, &md_options);
+
+        let mut tags = vec![];
+
+        // Note: Only looks in Text nodes, so we'll skip things like `#code`.
+        iter_nodes_mut(root, &mut |node| {
+            if let NodeValue::Text(ref text) = node.data.borrow().value {
+                for tag in find_tags(&to_string_lossy(text.clone())) {
+                    if !tags.contains(&tag) { tags.push(tag); }
+                }
+            }
+        });
+
+        tags
+    }
+
+    fn md_get_mentions(&self) -> Vec<UserID> {
+        let md_options = ComrakOptions::default();
+
+        let arena = Arena::new();
+        let root = parse_document(&arena, self, &md_options);
+
+        let mut mentions = vec![];
+
+        iter_nodes_mut(root, &mut |node| {
+            if let NodeValue::Text(ref text) = node.data.borrow().value {
+                for mention in find_mentions(&to_string_lossy(text.clone())) {
+                    if !mentions.contains(&mention.user_id) { mentions.push(mention.user_id); }
+                }
+            }
+        });
+
+        mentions
+    }
+}
+
+/// Tags longer than this are ignored.
+const MAX_TAG_LEN: usize = 64;
+
+/// Find #hashtags in plain text. (Lowercased, without the #, and without duplicates.)
+pub(crate) fn find_tags(text: &str) -> Vec<String> {
+    fn is_tag_char(c: char) -> bool { c.is_alphanumeric() || c == '_' || c == '-' }
+
+    let mut tags = vec![];
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        // Tags must start at the beginning of a word. (ex: not "foo#bar", or "/#anchor")
+        let at_word_start = match prev {
+            None => true,
+            Some(p) => p.is_whitespace() || p == '(',
+        };
+        prev = Some(c);
+
+        if c != '#' || !at_word_start { continue }
+
+        let start = i + c.len_utf8();
+        let mut end = start;
+        while let Some(&(j, next)) = chars.peek() {
+            if !is_tag_char(next) { break }
+            end = j + next.len_utf8();
+            prev = Some(next);
+            chars.next();
+        }
+
+        let tag = &text[start..end];
+        // Skip things like "#1", which are usually not meant to be tags:
+        if !tag.chars().any(char::is_alphabetic) { continue }
+        if tag.chars().count() > MAX_TAG_LEN { continue }
+
+        let tag = tag.to_lowercase();
+        if !tags.contains(&tag) { tags.push(tag); }
+    }
+
+    tags
+}
+
+
+struct Mention {
+    /// The byte range of the "@userID" text.
+    start: usize,
+    end: usize,
+    user_id: UserID,
+}
+
+/// Find @mentions of user IDs in plain text.
+fn find_mentions(text: &str) -> Vec<Mention> {
+    // bs58's default (bitcoin) alphabet:
+    fn is_base58(c: char) -> bool {
+        c.is_ascii_alphanumeric() && !"0OIl".contains(c)
+    }
+
+    let mut mentions = vec![];
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        // Mentions must start at the beginning of a word. (ex: not email@addresses)
+        let at_word_start = match prev {
+            None => true,
+            Some(p) => p.is_whitespace() || p == '(',
+        };
+        prev = Some(c);
+
+        if c != '@' || !at_word_start { continue }
+
+        let mut end = start + 1;
+        while let Some(&(j, next)) = chars.peek() {
+            if !is_base58(next) { break }
+            end = j + 1;
+            prev = Some(next);
+            chars.next();
+        }
+
+        if let Ok(user_id) = UserID::from_base58(&text[start+1..end]) {
+            mentions.push(Mention{start, end, user_id});
+        }
+    }
+
+    mentions
+}
+
+/// Replace @userID mentions with links to the user's page.
+fn link_mentions<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, options: &Options) {
+    // Collect nodes first, since we'll be modifying the tree:
+    let mut text_nodes = vec![];
+    iter_nodes_mut(root, &mut |node| {
+        if let NodeValue::Text(_) = node.data.borrow().value {
+            // Don't nest links, or put them in image alt text:
+            if !in_link(node) && !in_image(node) { text_nodes.push(node); }
+        }
+    });
+
+    let new_node = |value: NodeValue| -> &'a AstNode<'a> {
+        arena.alloc(Node::new(RefCell::new(Ast::new(value))))
+    };
+
+    for node in text_nodes {
+        let text = match node.data.borrow().value {
+            NodeValue::Text(ref text) => to_string_lossy(text.clone()),
+            _ => continue,
+        };
+
+        let mentions = find_mentions(&text);
+        if mentions.is_empty() { continue }
+
+        let mut last = 0;
+        for Mention{start, end, user_id} in mentions {
+            if start > last {
+                node.insert_before(new_node(NodeValue::Text(text[last..start].into())));
+            }
+
+            let name = options.mention_names
+                .and_then(|names| names.get(&user_id))
+                .cloned()
+                .unwrap_or_else(|| user_id.to_base58());
+
+            let link = new_node(NodeValue::Link(NodeLink{
+                url: format!("/u/{}/", user_id.to_base58()).into(),
+                title: vec![],
+            }));
+            link.append(new_node(NodeValue::Text(format!("@{}", name).into())));
+            node.insert_before(link);
+
+            last = end;
+        }
+
+        // Whatever's left stays in the original node:
+        node.data.borrow_mut().value = NodeValue::Text(text[last..].into());
+    }
+}
+
+fn in_link(node: &Node<RefCell<Ast>>) -> bool {
+    let mut parent = node.parent();
+    while let Some(p) = parent {
+        if let NodeValue::Link(_) = p.data.borrow().value {
+            return true;
+        }
+        parent = p.parent();
+    }
+    false
+}
+
+fn safe_truncate(value: &mut String, mut len: usize) {
+    if value.len() <= len { return }
+
+    // truncate panics if you try to truncate at a non-char-boundary. >.< 
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    value.truncate(len);
+}
+
+fn is_heading(node: &Node<RefCell<Ast>>) -> bool {
+    if let Some(parent) = node.parent() {
+        if let NodeValue::Heading(_) = parent.data.borrow().value {
+            return true;
+        }
+    }
+    false
+}
+
+fn in_image(node: &Node<RefCell<Ast>>) -> bool {
+    let parent = match node.parent() {
+        Some(p) => p,
+        _ => return false,
+    };
+
+    match parent.data.borrow().value {
+        NodeValue::Image(_) => true,
+        _ => false,
+    }
+}
+
+fn image_from_node<'a>(node: &'a Node<'a, RefCell<Ast>>, img: &NodeLink) -> Image {
+    
+    let url = to_string_lossy(img.url.clone());
+
+    // A node's alt text is stored as a child text node:
+    let alt = node.children().next().map(|it| {
+        if let NodeValue::Text(ref text) = it.data.borrow().value {
+            return Some(to_string_lossy(text.clone()));
+        }
+        return None;
+    }).flatten();
+
+    Image{url, alt}
+}
+
+pub(crate) struct Image {
+    pub url: String,
+    pub alt: Option<String>,
+}
+
+/// Metadata about an attached file, used when rendering images.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AttachmentMeta {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub alt_text: Option<String>,
+}
+
+/// Marks the URLs of images whose sizes we know. Comrak can't render image sizes,
+/// so we add them to its HTML afterward. (See: add_image_sizes())
+/// Comrak leaves `#` in URLs as-is, but escapes `"`, so `{SIZE_MARKER}{index}"`
+/// can only appear at the end of a URL's attribute.
+const SIZE_MARKER: &str = "#feoblog-image-size-";
+
+/// Fill in alt text for attached images that don't have any.
+///
+/// Also marks images that have known dimensions, and returns those dimensions,
+/// to pass to add_image_sizes() once the HTML is rendered.
+fn apply_attachment_meta<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, options: &Options) -> Vec<(u32, u32)> {
+    let mut sizes = vec![];
+    let attachments = match options.attachments {
+        Some(a) => a,
+        None => return sizes,
+    };
+
+    iter_nodes_mut(root, &mut |node| {
+        let url = match node.data.borrow().value {
+            NodeValue::Image(ref img) => to_string_lossy(img.url.clone()),
+            _ => return,
+        };
+
+        let meta = match url.strip_prefix("files/").and_then(|name| attachments.get(name)) {
+            Some(m) => m,
+            None => return,
+        };
+
+        if let (Some(width), Some(height)) = (meta.width, meta.height) {
+            if let NodeValue::Image(ref mut img) = node.data.borrow_mut().value {
+                img.url.extend_from_slice(format!("{}{}", SIZE_MARKER, sizes.len()).as_bytes());
+            }
+            sizes.push((width, height));
+        }
+
+        // A node's alt text is stored as child nodes:
+        if node.children().next().is_some() { return }
+        if let Some(alt_text) = &meta.alt_text {
+            let text = Ast::new(NodeValue::Text(alt_text.clone().into()));
+            node.append(arena.alloc(Node::new(RefCell::new(text))));
+        }
+    });
+
+    sizes
+}
+
+/// Add width & height attributes to images marked by apply_attachment_meta().
+fn add_image_sizes(mut html: String, sizes: &[(u32, u32)]) -> String {
+    for (i, (width, height)) in sizes.iter().enumerate() {
+        let marker = format!("{}{}\"", SIZE_MARKER, i);
+        html = html.replace(&marker, &format!("\" width=\"{}\" height=\"{}\"", width, height));
+    }
+    html
+}
+
+fn fix_relative_links<'a>(arena: &Arena<Node<RefCell<Ast>>>, root: &'a AstNode<'a>, options: &Options) {
+    let (user_id, signature) = match (options.user_id, options.signature) {
+        (Some(u), Some(s)) => (u, s),
+        _ => return,
+    };
+
+    let abs_root = format!("/u/{}/i/{}/", user_id.to_base58(), signature.to_base58());
+
+    iter_nodes(root, &|node| {
+        match &mut node.data.borrow_mut().value {
+            &mut NodeValue::Link(ref mut node_link) => { fix_link(node_link, &abs_root); }
+            &mut NodeValue::Image(ref mut node_link) => { fix_link(node_link, &abs_root); }
+            _ => (),
+        }
+    });
+}
+
+fn fix_link(node_link: &mut NodeLink, abs_root: &String) -> () {
+    let url = std::str::from_utf8(node_link.url.as_slice());
+    let url = match url {
+        Ok(u) => u,
+        // We won't even bother otherwise:
+        Err(e) => return,
+    };
+
+    if url.starts_with("/") || url.contains("//") {
+        // Host-absolute url like /foo/bar
+        // protocol-relative urL like //example.com/foo/bar
+        // or absolute:  http://example.com/foo/bar
+        return
+    }
+
+    let url = format!("{}{}", abs_root, url);
+    node_link.url = url.into();
+}
+
+
+
+fn iter_nodes<'a, F>(node: &'a AstNode<'a>, f: &F)
+where F : Fn(&'a AstNode<'a>)
+{
+    f(node);
+    for c in node.children() {
+        iter_nodes(c, f);
+    }
+}
+
+fn iter_nodes_mut<'a, F>(node: &'a AstNode<'a>, f: &mut F)
+where F : FnMut(&'a AstNode<'a>)
+{
+    f(node);
+    for c in node.children() {
+        iter_nodes_mut(c, f);
+    }
+}
+
+
+
+fn to_string_lossy(bytes: Vec<u8>) -> String {
+    let err = match String::from_utf8(bytes) {
+        // This is the efficient happy path: (in-place conversion)
+        Ok(s) => return s,
+        Err(e) => e,
+    };
+
+    // Use a lossy copy instead: (allocates)
+    let s = String::from_utf8_lossy(err.as_bytes());
+    String::from(s)
+}
+
+#[derive(Default)]
+pub(crate) struct Options<'a> {
+
+    /// If both user_id and signature are specified, we can convert relative URLS to absolute.
+    /// This lets them work in feeds as well as the Item page.
+    pub user_id: Option<&'a UserID>,
+    pub signature: Option<&'a Signature>,
+
+    /// Names to display for @mentioned users. Defaults to their user IDs.
+    pub mention_names: Option<&'a HashMap<UserID, String>>,
+
+    /// Metadata about attached files, by file name.
+    pub attachments: Option<&'a HashMap<String, AttachmentMeta>>,
+}
+
+#[test]
+fn test_get_summary() {
+    let plaintext = "Hello, world! This is a test.";
+    assert_eq!(plaintext, plaintext.md_get_summary(1000));
+
+    let heading = "
+Heading
+=======
+
+Hello, world!
+";
+    assert_eq!("Heading: Hello, world!", heading.md_get_summary(1000));
+
+    let heading2 = "
+Heading:
+=======
+
+Hello, world!
+";
+    assert_eq!("Heading: Hello, world!", heading2.md_get_summary(1000));
+
+    let comment = "
+<!--- This is an HTML comment and should not be rendered. -->
+Hello, world!
+";
+    assert_eq!("Hello, world!", comment.md_get_summary(1000));
+
+    let inline_html = r#"
+<div id="foo">
+This is some inline HTML.  We strip this out.
+</div>
+
+Plain text.
+"#;
+    assert_eq!("Plain text.", inline_html.md_get_summary(1000));
+
+    let links = r#"
+
+If there are [links] I expect [just][1] the text.
+
+[links]: https://www.google.com/
+[1]: https://www.twitter.com/
+
+"#;
+    assert_eq!("If there are links I expect just the text.", links.md_get_summary(1000));
+
+    let paragraphs = r#"
+If there are multiple paragraphs
+
+I expect them to get joined with a space.
+"#;
+
+    assert_eq!("If there are multiple paragraphs I expect…", paragraphs.md_get_summary(42));
+
+    let nihongo = "日本語はかわいいです。";
+    // 日 is 4 bytes in utf-8. The first part of 本 will get dropped to be safe:
+    assert_eq!("日…", nihongo.md_get_summary(5));
+
+    let image = r#"
+Here's an image: ![image]
+
+[image]: files/image.png
+"#;
+
+    assert_eq!("Here's an image:", image.md_get_summary(1000));
+}
+
+#[test]
+fn test_get_tags() {
+    let text = "Hello #World! This is #rust_lang, and #RUST-lang and #world again.";
+    assert_eq!(vec!["world", "rust_lang", "rust-lang"], text.md_get_tags());
+
+    let not_tags = "
+# A Heading
+
+Issue #1 is `#code` and foo#bar and [a link](/#anchor).
+";
+    assert_eq!(Vec::<String>::new(), not_tags.md_get_tags());
+
+    let nihongo = "(#日本語)";
+    assert_eq!(vec!["日本語"], nihongo.md_get_tags());
+}
+
+#[test]
+fn test_mentions() {
+    let user_id = "3iUjoWm7tXM9PeYDL3CaP76s4bJvMXhLhfdnRfjUcGZ9";
+    let text = format!("Hello, @{}! And again, (@{}). But not foo@{}.", user_id, user_id, user_id);
+    let mentions = text.md_get_mentions();
+    assert_eq!(1, mentions.len());
+    assert_eq!(user_id, mentions[0].to_base58());
+
+    let mut names = HashMap::new();
+    names.insert(mentions[0].clone(), "Alice".to_string());
+    let html = text.md_to_html_with(Options{
+        mention_names: Some(&names),
+        ..Default::default()
+    });
+    assert_eq!(
+        format!("<p>Hello, <a href=\"/u/{}/\">@Alice</a>! And again, (<a href=\"/u/{}/\">@Alice</a>). But not foo@{}.</p>\n", user_id, user_id, user_id),
+        html
+    );
+}
+
+#[test]
+fn test_attachment_meta() {
+    let mut attachments = HashMap::new();
+    attachments.insert("cat.jpg".to_string(), AttachmentMeta{
+        width: Some(640),
+        height: Some(480),
+        alt_text: Some("A cat".into()),
+    });
+
+    let html = "![](files/cat.jpg) ![A dog](files/dog.jpg)".md_to_html_with(Options{
+        attachments: Some(&attachments),
+        ..Default::default()
+    });
+    assert_eq!(
+        "<p><img src=\"files/cat.jpg\" width=\"640\" height=\"480\" alt=\"A cat\" /> <img src=\"files/dog.jpg\" alt=\"A dog\" /></p>\n",
+        html
+    );
+}
+
+#[test]
+fn test_unsafe_html() {
+    let markdown = "<script>alert(1)</script>\n\nHi <b>there</b>. [link](javascript:alert(1)) ![](data:text/html,hi) ![](data:image/png,ok)";
+    let html = markdown.md_to_html();
+    assert!(!html.contains("<script>"));
+    assert!(!html.contains("<b>"));
+    assert!(!html.contains("javascript:"));
+    assert!(!html.contains("data:text/html"));
+    assert!(html.contains("data:image/png"));
+    assert!(html.contains("<!-- raw HTML omitted -->"));
+}
@@ -1,72 +1,241 @@
-use std::borrow::Cow; 
-use std::error::Error;
-use std::fmt::{self, Display, Formatter};
-
-mod feoblog;
-pub use feoblog::*;
-
-/// Since proto3 does not allow specifying required fields, we must do that
-/// in our own validation here.
-pub(crate) trait ProtoValid {
-    fn validate(&self) -> Result<(), ValidationError> {
-        match self.get_error() {
-            None => Ok(()),
-            Some(message) => Err(
-                ValidationError{ message }
-            ),
-        }
-    }
-
-    /// Return the first known error with the proto, or None if it's valid.
-    fn get_error(&self) -> Option<Cow<'static,str>>;
-}
-
-impl ProtoValid for Item {
-    fn get_error(&self) -> Option<Cow<'static,str>> {
-
-        // In proto3 we can't distinguish between 0 and not-present.
-        // So, you can't express exactly the UTC start date, but you can
-        // be 1ms on either side which seems good enough. :p
-        if self.timestamp_ms_utc == 0 {
-            return Some(
-                "Timestamp is required".into()
-            );
-        }
-
-        // TODO: Validations for specific item types.
-        if self.has_profile() {
-            let err = self.get_profile().get_error();
-            if err.is_some() {
-                return err;
-            }
-        }
-
-        None
-    }
-}
-
-impl ProtoValid for Profile {
-    fn get_error(&self) -> Option<Cow<'static, str>> {
-
-        for follow in self.get_follows() {
-            if follow.get_user().get_bytes().len() != 32 {
-                return Some("UserID.bytes must be 32 bytes".into())
-            }
-        }
-
-        None
-    }
-}
-
-#[derive(Debug)]
-pub(crate) struct ValidationError {
-    message: Cow<'static, str>,
-}
-
-impl Error for ValidationError {}
-
-impl Display for ValidationError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> { 
-        write!(f, "Protobuf validation error: {}", self.message)
-    }
+use std::borrow::Cow; 
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+mod feoblog;
+pub use feoblog::*;
+
+/// Since proto3 does not allow specifying required fields, we must do that
+/// in our own validation here.
+pub(crate) trait ProtoValid {
+    fn validate(&self) -> Result<(), ValidationError> {
+        match self.get_error() {
+            None => Ok(()),
+            Some(message) => Err(
+                ValidationError{ message }
+            ),
+        }
+    }
+
+    /// Return the first known error with the proto, or None if it's valid.
+    fn get_error(&self) -> Option<Cow<'static,str>>;
+}
+
+impl ProtoValid for Item {
+    fn get_error(&self) -> Option<Cow<'static,str>> {
+
+        // In proto3 we can't distinguish between 0 and not-present.
+        // So, you can't express exactly the UTC start date, but you can
+        // be 1ms on either side which seems good enough. :p
+        if self.timestamp_ms_utc == 0 {
+            return Some(
+                "Timestamp is required".into()
+            );
+        }
+
+        // TODO: Validations for specific item types.
+        if self.has_post() {
+            let err = self.get_post().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_profile() {
+            let err = self.get_profile().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_delete() {
+            let err = self.get_delete().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_revision() {
+            let err = self.get_revision().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_reaction() {
+            let err = self.get_reaction().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_share() {
+            let err = self.get_share().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_direct_message() {
+            let err = self.get_direct_message().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Post {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if self.get_content_warning().len() > 256 {
+            return Some("Post.content_warning must be at most 256 bytes".into())
+        }
+
+        for file in self.get_attachments().get_file() {
+            let err = file.get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for File {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        let mime_type = self.get_mime_type();
+        if !mime_type.is_empty() && mime_type.parse::<mime_guess::Mime>().is_err() {
+            return Some("File.mime_type must be a valid mime type".into())
+        }
+
+        if self.get_alt_text().len() > 1024 {
+            return Some("File.alt_text must be at most 1024 bytes".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Delete {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if self.get_signature().get_bytes().len() != 64 {
+            return Some("Delete.signature must be 64 bytes".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Profile {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+
+        for follow in self.get_follows() {
+            if follow.get_user().get_bytes().len() != 32 {
+                return Some("UserID.bytes must be 32 bytes".into())
+            }
+        }
+
+        if self.get_revocation().get_reason().len() > 1024 {
+            return Some("Revocation.reason must be at most 1024 bytes".into())
+        }
+
+        if self.has_successor() {
+            let successor = self.get_successor();
+            if successor.get_user_id().get_bytes().len() != 32 {
+                return Some("Successor.user_id must be 32 bytes".into())
+            }
+            if successor.get_signature().get_bytes().len() != 64 {
+                return Some("Successor.signature must be 64 bytes".into())
+            }
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Reaction {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        let reply_to = self.get_reply_to();
+        if reply_to.get_user_id().get_bytes().len() != 32 {
+            return Some("Reaction.reply_to.user_id must be 32 bytes".into())
+        }
+        if reply_to.get_signature().get_bytes().len() != 64 {
+            return Some("Reaction.reply_to.signature must be 64 bytes".into())
+        }
+
+        let reaction = self.get_reaction();
+        if reaction.is_empty() {
+            return Some("Reaction.reaction is required".into())
+        }
+        if reaction.len() > 32 {
+            return Some("Reaction.reaction must be at most 32 bytes".into())
+        }
+        if reaction.chars().any(char::is_whitespace) {
+            return Some("Reaction.reaction may not contain whitespace".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Share {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        let shared = self.get_item();
+        if shared.get_user_id().get_bytes().len() != 32 {
+            return Some("Share.item.user_id must be 32 bytes".into())
+        }
+        if shared.get_signature().get_bytes().len() != 64 {
+            return Some("Share.item.signature must be 64 bytes".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for DirectMessage {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        use sodiumoxide::crypto::box_;
+
+        if self.get_recipient().get_bytes().len() != 32 {
+            return Some("DirectMessage.recipient must be 32 bytes".into())
+        }
+        if self.get_nonce().len() != box_::NONCEBYTES {
+            return Some("DirectMessage.nonce must be 24 bytes".into())
+        }
+        if self.get_ciphertext().len() <= box_::MACBYTES {
+            return Some("DirectMessage.ciphertext is too short".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Revision {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if self.get_original().get_bytes().len() != 64 {
+            return Some("Revision.original must be 64 bytes".into())
+        }
+
+        if !self.has_post() {
+            return Some("Revision.post is required".into())
+        }
+
+        self.get_post().get_error()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ValidationError {
+    message: Cow<'static, str>,
+}
+
+impl Error for ValidationError {}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> { 
+        write!(f, "Protobuf validation error: {}", self.message)
+    }
 }
\ No newline at end of file
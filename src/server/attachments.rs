@@ -6,7 +6,7 @@
 
 use std::io::{BufReader, BufWriter, Seek, SeekFrom};
 
-use actix_web::{HttpRequest, HttpResponse, Responder, http::{header::{self, CONTENT_LENGTH}}, web::{Data, Path, Payload}};
+use actix_web::{HttpRequest, HttpResponse, http::{StatusCode, header::{self, CONTENT_LENGTH}}, web::{Data, Path, Payload}};
 use anyhow::Context;
 use futures::{AsyncSeekExt, AsyncWriteExt, StreamExt};
 use mime_guess::mime;
@@ -14,9 +14,9 @@ use sodiumoxide::crypto::hash::sha512;
 use tempfile::tempfile;
 use log::{debug};
 
-use crate::{backend::{SHA512, Signature, UserID}, server::html::file_not_found};
+use crate::{backend::{Backend, Block, SHA512, Signature, UserID}, server::html::Missing};
 
-use super::{AppData, Error, PLAINTEXT};
+use super::{AppData, Error, PLAINTEXT, Rejection, with_backend};
 
 pub(crate) async fn get_file(
     req: HttpRequest,
@@ -24,17 +24,28 @@ pub(crate) async fn get_file(
     path: Path<(UserID, Signature, String)>,
 ) -> Result<HttpResponse, Error> {
     let (user_id, signature, file_name) = path.into_inner();
-    let backend = data.backend_factory.open()?;
 
-    let contents = backend.get_contents(user_id, signature, file_name.as_str())?;
+    let contents = {
+        let file_name = file_name.clone();
+        with_backend(&data, move |backend| {
+            if attachment_blocked(backend, &user_id, &signature, &file_name)? {
+                return Ok(Err(Missing::Blocked));
+            }
+            match backend.get_contents(user_id, signature, file_name.as_str())? {
+                None => Ok(Err(Missing::NotFound("File not found"))),
+                Some(c) => Ok(Ok(c)),
+            }
+        }).await?
+    };
     let contents = match contents {
-        None => return Ok(
-            file_not_found("File not found").await.respond_to(&req).map_into_boxed_body()
-        ),
-        Some(c) => c,
+        Err(missing) => return Ok(missing.respond_to(&req).await),
+        Ok(c) => c,
     };
 
-    let mut mime_type = mime_guess::from_path(&file_name).first_or_octet_stream();
+    // Prefer the mime type from the file's metadata:
+    let mut mime_type = contents.mime_type.as_ref()
+        .and_then(|it| it.parse::<mime::Mime>().ok())
+        .unwrap_or_else(|| mime_guess::from_path(&file_name).first_or_octet_stream());
 
     // FeoBlog is not meant to be a general web server.
     // Plus, since the client also runs in the browser, any mime type that can run JavaScript
@@ -59,6 +70,19 @@ pub(crate) async fn get_file(
     Ok(response)
 }
 
+/// Has the server admin blocked this attachment, or the item that contains it?
+fn attachment_blocked(backend: &dyn Backend, user_id: &UserID, signature: &Signature, file_name: &str) -> Result<bool, anyhow::Error> {
+    if backend.is_blocked(&Block::Item(user_id.clone(), signature.clone()))? {
+        return Ok(true);
+    }
+
+    let metadata = match backend.get_attachment_meta(user_id, signature, file_name)? {
+        None => return Ok(false),
+        Some(metadata) => metadata,
+    };
+    backend.is_blocked(&Block::Attachment(metadata.hash))
+}
+
 // An allow-list for types we know can't embed JavaScript:
 fn safe_type(mime_type: &mime_guess::Mime) -> bool {
     return match (mime_type.type_().as_str(), mime_type.subtype().as_str()) {
@@ -82,25 +106,34 @@ pub(crate) async fn put_file(
     mut body: Payload,
 ) -> Result<HttpResponse, Error> {
     let (user_id, signature, file_name) = path.into_inner();
-    let backend = data.backend_factory.open()?;
 
-    let metadata = backend.get_attachment_meta(&user_id, &signature, &file_name)?;
-    // Drop our pooled connection while we wait for bytes, which could be slow:
-    drop(backend);
+    // Note: The pooled connection is released before we wait for bytes, which could be slow.
+    let metadata = {
+        let file_name = file_name.clone();
+        with_backend(&data, move |backend| {
+            if backend.user_revoked(&user_id)? {
+                return Ok(Err(Rejection::new(StatusCode::FORBIDDEN, "This user ID has been revoked.")));
+            }
+
+            if attachment_blocked(backend, &user_id, &signature, &file_name)? {
+                return Ok(Err(Rejection::new(StatusCode::FORBIDDEN, "This attachment has been blocked on this server.")));
+            }
+
+            match backend.get_attachment_meta(&user_id, &signature, &file_name)? {
+                Some(metadata) => Ok(Ok(metadata)),
+                // If we don't yet have the metadata for a file (provided in its Item), then you can't upload yet.
+                None => Ok(Err(Rejection::new(StatusCode::FORBIDDEN, "No such attachment for this Item, or no such Item."))),
+            }
+        }).await?
+    };
 
     let metadata = match metadata {
-        Some(d) => d,
-        None => {
+        Ok(d) => d,
+        Err(rejection) => {
             // note: NO drain() here.
             // see: https://stackoverflow.com/questions/14250991/is-it-acceptable-for-a-server-to-send-a-http-response-before-the-entire-request
             // regarding HTTP errros. This is an error condition anyway.
-
-            // If we don't yet have the metadata for a file (provided in its Item), then you can't upload yet.
-            return Ok(
-                HttpResponse::Forbidden()
-                .content_type(PLAINTEXT)
-                .body("No such attachment for this Item, or no such Item.")
-            );
+            return Ok(rejection.into());
         }
     };
     
@@ -198,9 +231,8 @@ pub(crate) async fn put_file(
     // Just grab the inner file to simplify types for the Backend:
     let mut file = file.into_inner().await;
 
-    blocking::unblock(move || -> Result<(), anyhow::Error> {
+    with_backend(&data, move |backend| {
         file.seek(SeekFrom::Start(0))?;
-        let backend = data.backend_factory.open()?;
         backend.save_attachment(metadata.size, &metadata.hash, &mut file)?;
         Ok(())
     }).await?;
@@ -233,14 +265,22 @@ pub(crate) async fn head_file(
     req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let (user_id, signature, file_name) = path.into_inner();
-    let backend = data.backend_factory.open()?;
 
-    let metadata = backend.get_attachment_meta(&user_id, &signature, &file_name)?;
-    let metadata = backend.get_attachment_meta(&user_id, &signature, &file_name)?;
+    let metadata = with_backend(&data, move |backend| {
+        if attachment_blocked(backend, &user_id, &signature, &file_name)? {
+            return Ok(Err(Missing::Blocked));
+        }
+        Ok(Ok(backend.get_attachment_meta(&user_id, &signature, &file_name)?))
+    }).await?;
 
     let metadata = match metadata {
-        Some(d) => d,
-        None => {
+        Ok(Some(d)) => d,
+        Err(_) => {
+            return Ok(
+                HttpResponse::UnavailableForLegalReasons().finish()
+            );
+        },
+        Ok(None) => {
             // Note: a 404 doesn't necessarily mean that you can upload.
             // The item doesn't yet exist, you can't upload a file here.
             return Ok(
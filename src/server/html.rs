@@ -3,13 +3,17 @@
 //! These redirect any javascript-enabled browser to the client UI.
 //! But any old browsers and search engines can use this to index content.
 
-use actix_web::{HttpRequest, HttpResponse, Responder, http::StatusCode, web::{Data, Path, Query}, error::ErrorInternalServerError};
+use std::collections::HashMap;
+
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, http::StatusCode, web::{Data, Path, Query}};
+use anyhow::bail;
 use askama_actix::Template;
 use askama_actix as askama;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 use protobuf::Message;
 
-use crate::{backend::{ItemDisplayRow, ItemRow, Signature, UserID}, markdown::ToHTML, protos::Item, server::{IndexPageItem, Nav, non_standard::identicon_url, pagination::Paginator}};
-use super::{AppData, Error, ProfileFollow, pagination::Pagination};
+use crate::{backend::{Backend, Block, ItemDisplayRow, ItemRow, ReactionCount, Signature, UserID}, markdown::{AttachmentMeta, ToHTML}, protos::{Item, Post}, server::{IndexPageItem, Nav, non_standard::identicon_url, pagination::Paginator}};
+use super::{AppData, Error, ProfileFollow, SearchParams, with_backend, pagination::Pagination};
 
 mod filters;
 
@@ -21,6 +25,45 @@ pub(crate) async fn file_not_found(msg: impl Into<String>) -> impl Responder {
     .with_status(StatusCode::NOT_FOUND)
 }
 
+/// Like file_not_found(), but for content that has been deleted.
+pub(crate) async fn item_gone(msg: impl Into<String>) -> impl Responder {
+    NotFoundPage {
+        message: msg.into()
+    }
+    .customize()
+    .with_status(StatusCode::GONE)
+}
+
+/// Like file_not_found(), but for content that the server admin has blocked.
+pub(crate) async fn item_blocked() -> impl Responder {
+    NotFoundPage {
+        message: "This content has been blocked on this server.".into()
+    }
+    .customize()
+    .with_status(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)
+}
+
+/// Why with_backend() couldn't find something to display.
+///
+/// HttpResponses aren't Send, so error pages are built once we're back in the handler.
+pub(crate) enum Missing {
+    /// Blocked by the server admin.
+    Blocked,
+    /// Deleted by its author.
+    Deleted(&'static str),
+    NotFound(&'static str),
+}
+
+impl Missing {
+    pub(crate) async fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        match self {
+            Missing::Blocked => item_blocked().await.respond_to(req).map_into_boxed_body(),
+            Missing::Deleted(msg) => item_gone(msg).await.respond_to(req).map_into_boxed_body(),
+            Missing::NotFound(msg) => file_not_found(msg).await.respond_to(req).map_into_boxed_body(),
+        }
+    }
+}
+
 /// The root (`/`) page.
 pub(crate) async fn view_homepage(
     data: Data<AppData>,
@@ -30,9 +73,7 @@ pub(crate) async fn view_homepage(
     let mut paginator = Paginator::new(
         pagination,
         |row: ItemDisplayRow| -> Result<IndexPageItem, anyhow::Error> {        
-            let mut item = Item::new();
-            item.merge_from_bytes(&row.item.item_bytes)?;
-            Ok(IndexPageItem{row, item})
+            IndexPageItem::new(row)
         },
         |ipi: &IndexPageItem| -> bool {
             display_by_default(&ipi.item)
@@ -40,37 +81,48 @@ pub(crate) async fn view_homepage(
     );
     paginator.max_items = 20;
 
-    let backend = data.backend_factory.open()?;
-    backend.homepage_items(paginator.time_span(), &mut paginator.callback())?;
-    
-
-    let mut nav = vec![
-        Nav::Text("FeoBlog".into()),
-        Nav::Link{
-            text: "Client".into(),
-            href: "/client/".into(),
+    let factory = data.backend_factory.dyn_clone();
+    let page = with_backend(&data, move |backend| {
+        paginator.extend(factory.homepage_items_iter(paginator.time_span()))?;
+
+        let mut nav = vec![
+            Nav::Text("FeoBlog".into()),
+            Nav::Link{
+                text: "Client".into(),
+                href: "/client/".into(),
+            },
+            Nav::Link{
+                text: "Search".into(),
+                href: "/search/".into(),
+            },
+        ];
+
+        if let Some(href) = paginator.newer_items_link("/") {
+            nav.push(Nav::Link{
+                text: "Newer Posts".into(),
+                href,
+            });
+        }
+        if let Some(href) = paginator.more_items_link("/") {
+            nav.push(Nav::Link{
+                text: "Older Posts".into(),
+                href,
+            });
         }
-    ];
 
-    if let Some(href) = paginator.newer_items_link("/") {
-        nav.push(Nav::Link{
-            text: "Newer Posts".into(),
-            href,
-        });
-    }
-    if let Some(href) = paginator.more_items_link("/") {
-        nav.push(Nav::Link{
-            text: "Older Posts".into(),
-            href,
-        });
-    }
+        let display_message = paginator.message();
+        let mut items = paginator.into_items();
+        finish_items(backend, &mut items)?;
 
-    Ok(IndexPage {
-        nav,
-        display_message:  paginator.message(),
-        items: paginator.into_items(),
-        show_authors: true,
-    })
+        Ok(IndexPage {
+            nav,
+            display_message,
+            items,
+            show_authors: true,
+        })
+    }).await?;
+
+    Ok(page)
 }
 
 pub(crate) async fn get_user_feed(
@@ -82,61 +134,175 @@ pub(crate) async fn get_user_feed(
     let mut paginator = Paginator::new(
         pagination,
         |row: ItemDisplayRow| -> Result<IndexPageItem,anyhow::Error> {
-            let mut item = Item::new();
-            item.merge_from_bytes(&row.item.item_bytes)?;
-            Ok(IndexPageItem{row, item})
+            IndexPageItem::new(row)
         }, 
         |page_item: &IndexPageItem| { 
             display_by_default(&page_item.item)
         }
     );
 
-    let backend = data.backend_factory.open()?;
-    backend.user_feed_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+    let factory = data.backend_factory.dyn_clone();
+    let page = with_backend(&data, move |backend| {
+        paginator.extend(factory.user_feed_items_iter(&user_id, paginator.time_span()))?;
 
-    let display_name = backend.user_profile(&user_id)?.map(
-        |row| -> Result<Item, anyhow::Error> {
-            let mut item = Item::new();
-            item.merge_from_bytes(&row.item_bytes)?;
-            Ok(item)
-        })
-        .transpose()?
-        .map(|item| -> Option<String> {
-            let name = item.get_profile().display_name.trim();
-            if name.len() > 0 {
-                Some(name.to_string())
-            } else {
-                None
-            }
+        let display_name = backend.user_profile(&user_id)?.map(
+            |row| -> Result<Item, anyhow::Error> {
+                let mut item = Item::new();
+                item.merge_from_bytes(&row.item_bytes)?;
+                Ok(item)
+            })
+            .transpose()?
+            .map(|item| -> Option<String> {
+                let name = item.get_profile().display_name.trim();
+                if name.len() > 0 {
+                    Some(name.to_string())
+                } else {
+                    None
+                }
+            })
+            .flatten()
+            .unwrap_or_else(|| user_id.to_base58().to_string());
+
+        let mut nav = vec![
+            Nav::Text(format!("Feed for: {}", display_name)),
+        ];
+
+        nav.push(Nav::Link{text: "Profile".into(), href: "../profile/".into()});
+
+
+        let this_page = format!("/u/{}/feed/", user_id.to_base58());
+        if let Some(href) = paginator.newer_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Newer Posts".into()})
+        };
+        if let Some(href) = paginator.more_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Older Posts".into()})
+        };
+
+
+        let display_message = paginator.message();
+        let mut items = paginator.into_items();
+        finish_items(backend, &mut items)?;
+
+        Ok(IndexPage {
+            nav,
+            display_message,
+            items,
+            show_authors: true,
         })
-        .flatten()
-        .unwrap_or_else(|| user_id.to_base58().to_string());
+    }).await?;
 
-    let mut nav = vec![
-        Nav::Text(format!("Feed for: {}", display_name)),
-    ];
+    Ok(page)
+}
 
-    nav.push(Nav::Link{text: "Profile".into(), href: "../profile/".into()});
 
+/// Posts tagged with a #tag.
+/// `/tags/{tag}/`
+pub(crate) async fn get_tag_items(
+    data: Data<AppData>,
+    path: Path<(String,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<impl Responder, Error> {
+    let (tag,) = path.into_inner();
+    let tag = tag.to_lowercase();
 
-    let this_page = format!("/u/{}/feed/", user_id.to_base58());
-    if let Some(href) = paginator.newer_items_link(&this_page) {
-        nav.push(Nav::Link{href, text: "Newer Posts".into()})
-    };
-    if let Some(href) = paginator.more_items_link(&this_page) {
-        nav.push(Nav::Link{href, text: "Older Posts".into()})
-    };
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,anyhow::Error> {
+            IndexPageItem::new(row)
+        }, 
+        |page_item: &IndexPageItem| { 
+            display_by_default(&page_item.item)
+        }
+    );
 
+    let page = with_backend(&data, move |backend| {
+        backend.tag_items(&tag, paginator.time_span(), &mut paginator.callback())?;
+
+        let mut nav = vec![
+            Nav::Text(format!("#{}", tag)),
+            Nav::Link{text: "Home".into(), href: "/".into()},
+        ];
+
+        let this_page = format!("/tags/{}/", utf8_percent_encode(&tag, NON_ALPHANUMERIC));
+        if let Some(href) = paginator.newer_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Newer Posts".into()})
+        };
+        if let Some(href) = paginator.more_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Older Posts".into()})
+        };
+
+        let display_message = paginator.message();
+        let mut items = paginator.into_items();
+        finish_items(backend, &mut items)?;
+
+        Ok(IndexPage {
+            nav,
+            display_message,
+            items,
+            show_authors: true,
+        })
+    }).await?;
 
-    Ok(IndexPage {
-        nav,
-        display_message: paginator.message(),
-        items: paginator.into_items(),
-        show_authors: true,
-    })
+    Ok(page)
 }
 
+/// Posts and Comments that contain some words.
+/// `/search/?q={query}`
+pub(crate) async fn get_search_items(
+    data: Data<AppData>,
+    Query(pagination): Query<Pagination>,
+    Query(search): Query<SearchParams>,
+) -> Result<impl Responder, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,anyhow::Error> {
+            IndexPageItem::new(row)
+        }, 
+        |page_item: &IndexPageItem| { 
+            // Like mentions, Comments are worth finding too:
+            page_item.item.has_post() || page_item.item.has_comment()
+        }
+    );
+
+    let page = with_backend(&data, move |backend| {
+        let query = search.q.trim();
+        if !query.is_empty() {
+            backend.search_items(query, search.scope(), paginator.time_span(), &mut paginator.callback())?;
+        }
+
+        let mut nav = vec![
+            Nav::Text("Search".into()),
+            Nav::Link{text: "Home".into(), href: "/".into()},
+        ];
+
+        let this_page = search.url("/search/");
+        if let Some(href) = paginator.newer_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Newer Results".into()})
+        };
+        if let Some(href) = paginator.more_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Older Results".into()})
+        };
+
+        let display_message = if query.is_empty() {
+            None
+        } else {
+            paginator.message()
+        };
+        let mut items = paginator.into_items();
+        finish_items(backend, &mut items)?;
+
+        Ok(SearchPage {
+            nav,
+            display_message,
+            items,
+            show_authors: true,
+            query: search.q.clone(),
+            scope_fields: search.scope_fields(),
+        })
+    }).await?;
 
+    Ok(page)
+}
 
 /// Display a single user's posts/etc.
 /// `/u/{userID}/`
@@ -144,20 +310,18 @@ pub(crate) async fn get_user_items(
     data: Data<AppData>,
     path: Path<(UserID,)>,
     Query(pagination): Query<Pagination>,
-) -> Result<impl Responder, Error> {
+) -> Result<Either<impl Responder, impl Responder>, Error> {
 
     let mut paginator = Paginator::new(
         pagination,
         |row: ItemRow| -> Result<IndexPageItem, anyhow::Error> {
-            let mut item = Item::new();
-            item.merge_from_bytes(&row.item_bytes)?;
-            Ok(IndexPageItem{ 
-                row: ItemDisplayRow{
-                    item: row,
-                    // We don't display the user's name on their own page.
-                    display_name: None,
-                },
-                item 
+            IndexPageItem::new(ItemDisplayRow{
+                item: row,
+                // We don't display the user's name on their own page.
+                display_name: None,
+                // Filled in by finish_items(), only for items we display:
+                reactions: vec![],
+                shared: None,
             })
         },
         |ipi: &IndexPageItem| -> bool {
@@ -167,52 +331,187 @@ pub(crate) async fn get_user_items(
     paginator.max_items = 10;
 
     let (user,) = path.into_inner();
-    let backend = data.backend_factory.open()?;
-    backend.user_items(&user, paginator.time_span(), &mut paginator.callback())?;
+    let factory = data.backend_factory.dyn_clone();
+    let page = with_backend(&data, move |backend| {
+        if backend.is_blocked(&Block::User(user.clone()))? {
+            return Ok(Err(Missing::Blocked));
+        }
+        paginator.extend(factory.user_items_iter(&user, paginator.time_span()))?;
 
-    
-    let mut nav = vec![];
-    let profile = backend.user_profile(&user)?;
-    if let Some(row) = profile {
-        let mut item = Item::new();
-        item.merge_from_bytes(&row.item_bytes)?;
+        
+        let mut nav = vec![];
+        let profile = backend.user_profile(&user)?;
+        if let Some(row) = profile {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
 
-        nav.push(
-            Nav::Text(item.get_profile().display_name.clone())
-        )
+            nav.push(
+                Nav::Text(item.get_profile().display_name.clone())
+            )
+        }
+
+        let this_url = format!("/u/{}/", user.to_base58());
+        if let Some(href) = paginator.newer_items_link(&this_url) {
+            nav.push(Nav::Link{ text: "Newer Posts".into(), href });
+        }
+
+        if let Some(href) = paginator.more_items_link(&this_url) {
+            nav.push(Nav::Link{ text: "Older Posts".into(), href });
+        }
+
+        nav.extend(vec![
+            Nav::Link{
+                text: "Profile".into(),
+                href: format!("/u/{}/profile/", user.to_base58()),
+            },
+            Nav::Link{
+                text: "Feed".into(),
+                href: format!("/u/{}/feed/", user.to_base58()),
+            },
+            Nav::Link{
+                text: "Home".into(),
+                href: "/".into()
+            },
+        ]);
+
+
+        let display_message = paginator.message();
+        let mut items = paginator.into_items();
+        for page_item in items.iter_mut() {
+            let row = &page_item.row;
+            if page_item.item.has_share() {
+                if let Some(shared) = backend.shared_item(&row.item.user, &row.item.signature)? {
+                    page_item.shared = Some(Box::new(IndexPageItem::new(shared)?));
+                }
+            }
+        }
+        finish_items(backend, &mut items)?;
+
+        Ok(Ok(IndexPage{
+            nav,
+            display_message,
+            items,
+            show_authors: false,
+        }))
+    }).await?;
+
+    match page {
+        Ok(page) => Ok(Either::Right(page)),
+        // Only blocked users are Missing here:
+        Err(_) => Ok(Either::Left(item_blocked().await)),
     }
+}
 
-    let this_url = format!("/u/{}/", user.to_base58());
-    if let Some(href) = paginator.newer_items_link(&this_url) {
-        nav.push(Nav::Link{ text: "Newer Posts".into(), href });
+/// Display the latest Revision of any Posts in `items` that have been revised,
+/// look up names for any users they @mention, and count their Reactions.
+fn finish_items(backend: &dyn Backend, items: &mut Vec<IndexPageItem>) -> Result<(), anyhow::Error> {
+    for page_item in items.iter_mut() {
+        let row = &mut page_item.row;
+        row.reactions = backend.reaction_counts(&row.item.user, &row.item.signature)?;
+        apply_revision(backend, page_item)?;
+        apply_mention_names(backend, page_item)?;
+        if let Some(shared) = page_item.shared.as_mut() {
+            apply_revision(backend, shared)?;
+            apply_mention_names(backend, shared)?;
+        }
     }
 
-    if let Some(href) = paginator.more_items_link(&this_url) {
-        nav.push(Nav::Link{ text: "Older Posts".into(), href });
+    Ok(())
+}
+
+fn apply_revision(backend: &dyn Backend, page_item: &mut IndexPageItem) -> Result<(), anyhow::Error> {
+    if !page_item.item.has_post() {
+        return Ok(());
     }
 
-    nav.extend(vec![
-        Nav::Link{
-            text: "Profile".into(),
-            href: format!("/u/{}/profile/", user.to_base58()),
-        },
-        Nav::Link{
-            text: "Feed".into(),
-            href: format!("/u/{}/feed/", user.to_base58()),
-        },
-        Nav::Link{
-            text: "Home".into(),
-            href: "/".into()
-        },
-    ]);
+    let row = &page_item.row.item;
+    let revision = match backend.latest_revision(&row.user, &row.signature)? {
+        None => return Ok(()),
+        Some(r) => r,
+    };
 
+    let mut revision_item = Item::new();
+    revision_item.merge_from_bytes(&revision.item_bytes)?;
+    let post = std::mem::take(revision_item.mut_revision().mut_post());
 
-    Ok(IndexPage{
-        nav,
-        display_message: paginator.message(),
-        items: paginator.into_items(),
-        show_authors: false,
-    })
+    page_item.item.set_post(post);
+    page_item.revision = Some(revision.signature);
+
+    Ok(())
+}
+
+fn apply_mention_names(backend: &dyn Backend, page_item: &mut IndexPageItem) -> Result<(), anyhow::Error> {
+    let item = &page_item.item;
+    let text = if item.has_post() {
+        item.get_post().get_body()
+    } else if item.has_comment() {
+        item.get_comment().get_text()
+    } else if item.has_share() {
+        item.get_share().get_commentary()
+    } else {
+        return Ok(());
+    };
+
+    let mentions = text.md_get_mentions();
+    page_item.mention_names = backend.display_names(&page_item.row.item.user, &mentions)?;
+
+    Ok(())
+}
+
+/// Posts and Comments that @mention a user.
+/// `/u/{userID}/mentions/`
+pub(crate) async fn get_mention_items(
+    data: Data<AppData>,
+    path: Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<impl Responder, Error> {
+    let (user_id,) = path.into_inner();
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,anyhow::Error> {
+            IndexPageItem::new(row)
+        }, 
+        |page_item: &IndexPageItem| { 
+            // Unlike other pages, we display Comments here, since that's
+            // often where users are mentioned:
+            page_item.item.has_post() || page_item.item.has_comment()
+        }
+    );
+
+    let page = with_backend(&data, move |backend| {
+        backend.mention_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+
+        let display_name = backend.display_names(&user_id, &[user_id.clone()])?
+            .remove(&user_id)
+            .unwrap_or_else(|| user_id.to_base58());
+
+        let mut nav = vec![
+            Nav::Text(format!("Mentions of: {}", display_name)),
+            Nav::Link{text: "Profile".into(), href: "../profile/".into()},
+        ];
+
+        let this_page = format!("/u/{}/mentions/", user_id.to_base58());
+        if let Some(href) = paginator.newer_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Newer Posts".into()})
+        };
+        if let Some(href) = paginator.more_items_link(&this_page) {
+            nav.push(Nav::Link{href, text: "Older Posts".into()})
+        };
+
+        let display_message = paginator.message();
+        let mut items = paginator.into_items();
+        finish_items(backend, &mut items)?;
+
+        Ok(IndexPage {
+            nav,
+            display_message,
+            items,
+            show_authors: true,
+        })
+    }).await?;
+
+    Ok(page)
 }
 
 
@@ -223,44 +522,96 @@ pub(crate) async fn show_item(
 ) -> Result<HttpResponse, Box<dyn std::error::Error>> {
 
     let (user_id, signature) = path.into_inner();
-    let backend = data.backend_factory.open()?;
+    let url = PageUrl::new(&req);
+    let page = with_backend(&data, move |backend| {
+        item_page(backend, &url, user_id, signature)
+    }).await?;
+
+    let page = match page {
+        Ok(page) => page,
+        Err(missing) => return Ok(missing.respond_to(&req).await),
+    };
+
+    let response = match page {
+        ItemPage::Profile(page) => page.respond_to(&req).map_into_boxed_body(),
+        ItemPage::Post(page) => page.respond_to(&req).map_into_boxed_body(),
+        ItemPage::Share(page) => page.respond_to(&req).map_into_boxed_body(),
+        ItemPage::Message(page) => page.respond_to(&req).map_into_boxed_body(),
+        ItemPage::Redirect(location) => {
+            HttpResponse::SeeOther()
+            .append_header(("location", location))
+            .finish()
+        },
+    };
+    Ok(response)
+}
+
+/// The page show_item() displays, depending on the type of the item.
+enum ItemPage {
+    Profile(ProfileUpdatePage),
+    Post(PostPage),
+    Share(IndexPage),
+    /// A message about why we can't display this item.
+    Message(NotFoundPage),
+    Redirect(String),
+}
+
+fn item_page(backend: &dyn Backend, url: &PageUrl, user_id: UserID, signature: Signature) -> Result<Result<ItemPage, Missing>, anyhow::Error> {
+    if backend.is_blocked(&Block::Item(user_id.clone(), signature.clone()))? {
+        return Ok(Err(Missing::Blocked));
+    }
+
     let row = backend.user_item(&user_id, &signature)?;
     let row = match row {
         Some(row) => row,
         None => { 
+            if backend.user_item_deleted(&user_id, &signature)? {
+                return Ok(Err(Missing::Deleted("This item has been deleted by its author.")));
+            }
+
             // TODO: We could display a nicer error page here, showing where
             // the user might find this item on other servers. Maybe I'll leave that
             // for the in-browser client.
 
-            let not_found = file_not_found("No such item").await;
-            let res = not_found.respond_to(&req);
-            return Ok(res.map_into_boxed_body());
+            return Ok(Err(Missing::NotFound("No such item")));
         }
     };
 
     let mut item = Item::new();
     item.merge_from_bytes(row.item_bytes.as_slice())?;
 
-    let row = backend.user_profile(&user_id)?;
+    let profile_row = backend.user_profile(&user_id)?;
     let display_name = {
         let mut item = Item::new();
-        if let Some(row) = row {
+        if let Some(row) = profile_row {
             item.merge_from_bytes(row.item_bytes.as_slice())?;
         }
         item
     }.get_profile().display_name.clone();
     
     use crate::protos::Item_oneof_item_type as ItemType;
-    match item.item_type {
-        None => Err(ErrorInternalServerError("No known item type provided."))?,
+    let page = match item.item_type {
+        None => bail!("No known item type provided."),
         Some(ItemType::profile(_)) => {
             let profile_url = format!("/u/{}/profile/", user_id.to_base58());
-            let page = ProfileUpdatePage{ nav: vec![], profile_url};
-
-            Ok(page.respond_to(&req).map_into_boxed_body())
+            ItemPage::Profile(ProfileUpdatePage{ nav: vec![], profile_url})
         },
-        Some(ItemType::post(p)) => {
-            let page = PostPage {
+        Some(ItemType::post(mut p)) => {
+            // Display the latest revision, if any. But keep the original's URL & timestamp:
+            let mut content_signature = signature.clone();
+            let mut revised_utc_ms = None;
+            if let Some(revision) = backend.latest_revision(&user_id, &signature)? {
+                let mut revision_item = Item::new();
+                revision_item.merge_from_bytes(&revision.item_bytes)?;
+                p = std::mem::take(revision_item.mut_revision().mut_post());
+                content_signature = revision.signature;
+                revised_utc_ms = Some(revision_item.timestamp_ms_utc);
+            }
+
+            let mention_names = backend.display_names(&user_id, &p.body.md_get_mentions())?;
+            let (comments, more_comments) = get_thread_comments(backend, &user_id, &signature)?;
+
+            ItemPage::Post(PostPage {
                 nav: vec![
                     Nav::Text(display_name.clone()),
                     Nav::Link {
@@ -272,44 +623,157 @@ pub(crate) async fn show_item(
                         href: "/".into()
                     }
                 ],
-                meta: get_post_meta(&req, &user_id, &p),
+                meta: get_post_meta(url, &user_id, &content_signature, &p),
+                reactions: backend.reaction_counts(&user_id, &signature)?,
                 user_id,
                 display_name,
                 signature,
+                mention_names,
+                comments,
+                more_comments,
+                content_signature,
+                revised_utc_ms,
                 text: p.body,
                 title: p.title,
+                content_warning: p.content_warning,
+                attachments: post_attachments(&p),
                 timestamp_utc_ms: item.timestamp_ms_utc,
                 utc_offset_minutes: item.utc_offset_minutes,
-            };
-
-            Ok(page.respond_to(&req).map_into_boxed_body())
+            })
         },
         Some(ItemType::comment(_)) => {
-            let page = NotFoundPage{message: "To view comments, please use the web client at /client/.".to_string()};
-            Ok(page.respond_to(&req).map_into_boxed_body())
+            ItemPage::Message(NotFoundPage{message: "To view comments, please use the web client at /client/.".to_string()})
+        },
+        Some(ItemType::revision(revision)) => {
+            // Revisions are displayed in place of the original Post:
+            let original = Signature::from_vec(revision.get_original().get_bytes().into())?;
+            ItemPage::Redirect(format!("/u/{}/i/{}/", user_id.to_base58(), original.to_base58()))
+        },
+        Some(ItemType::delete(_)) => {
+            ItemPage::Message(NotFoundPage{message: "This item records the deletion of another item.".to_string()})
+        },
+        Some(ItemType::direct_message(_)) => {
+            ItemPage::Message(NotFoundPage{message: "This item is an encrypted direct message. To read it, please use the web client at /client/.".to_string()})
+        },
+        Some(ItemType::share(_)) => {
+            // Render the Share (and the item it shares) like we would in a feed:
+            let page_item = IndexPageItem::new(ItemDisplayRow{
+                reactions: vec![],
+                shared: backend.shared_item(&user_id, &signature)?.map(Box::new),
+                display_name: Some(display_name.clone()),
+                item: row,
+            })?;
+            let mut items = vec![page_item];
+            finish_items(backend, &mut items)?;
+
+            ItemPage::Share(IndexPage {
+                nav: vec![
+                    Nav::Text(display_name),
+                    Nav::Link {
+                        text: "Profile".into(),
+                        href: format!("/u/{}/profile/", user_id.to_base58()),
+                    },
+                    Nav::Link {
+                        text: "Home".into(),
+                        href: "/".into()
+                    }
+                ],
+                items,
+                display_message: None,
+                show_authors: true,
+            })
+        },
+        Some(ItemType::reaction(reaction)) => {
+            // Reactions are displayed as counts on the item they react to:
+            let reply_to = reaction.get_reply_to();
+            let to_user = UserID::from_vec(reply_to.get_user_id().get_bytes().into())?;
+            let to_signature = Signature::from_vec(reply_to.get_signature().get_bytes().into())?;
+            ItemPage::Redirect(format!("/u/{}/i/{}/", to_user.to_base58(), to_signature.to_base58()))
+        },
+    };
+
+    Ok(Ok(page))
+}
+
+/// Get the Comments to display beneath a Post, and whether there were more than we could show.
+fn get_thread_comments(backend: &dyn Backend, user_id: &UserID, signature: &Signature) -> Result<(Vec<ThreadComment>, bool), anyhow::Error> {
+    let max_comments = 200;
+    let mut comments = vec![];
+    let mut more_comments = false;
+
+    // Fetch one extra so we know if there were more:
+    backend.thread_items(user_id, signature, max_comments + 1, &mut |row| {
+        if comments.len() >= max_comments {
+            more_comments = true;
+            return Ok(false);
+        }
+        comments.push(ThreadComment{
+            depth: row.depth,
+            comment: IndexPageItem::new(row.item)?,
+        });
+        Ok(true)
+    })?;
+
+    for thread_comment in comments.iter_mut() {
+        let row = &mut thread_comment.comment.row;
+        row.reactions = backend.reaction_counts(&row.item.user, &row.item.signature)?;
+        apply_mention_names(backend, &mut thread_comment.comment)?;
+    }
+
+    Ok((comments, more_comments))
+}
+
+/// The parts of a request's URL that we need to build absolute URLs.
+/// (HttpRequest isn't Send, so can't be used in with_backend().)
+struct PageUrl {
+    scheme: String,
+    host: String,
+    path: String,
+}
+
+impl PageUrl {
+    fn new(req: &HttpRequest) -> Self {
+        let info = req.connection_info();
+        Self {
+            scheme: info.scheme().to_string(),
+            host: info.host().to_string(), // seems to include :port.
+            path: req.uri().path().to_string(),
         }
     }
 }
 
-fn get_post_meta(req: &HttpRequest, user_id: &UserID, post: &crate::protos::Post) -> OGPMeta {
+/// `signature` is the signature of the item that `post` came from. (Which may be a Revision.)
+///
+/// If the Post has a content warning, previews show that instead of the Post's
+/// contents and images.
+fn get_post_meta(url: &PageUrl, user_id: &UserID, signature: &Signature, post: &Post) -> OGPMeta {
 
-    let info = req.connection_info();
-    let scheme = info.scheme();
-    let host = info.host(); // seems to include :port.
+    let scheme = &url.scheme;
+    let host = &url.host;
 
-    let post_url = format!("{}://{}{}", scheme, host, req.uri().path());
+    let post_url = format!("{}://{}{}", scheme, host, url.path);
+    let item_url = format!("{}://{}/u/{}/i/{}/", scheme, host, user_id.to_base58(), signature.to_base58());
 
     // TODO: Const somewhere?
     // We only include images that are directly attached.
     // Why? Apple Messages, for example, doesn't generate a card if the image isn't local.
     let files_prefix = "files/";
 
+    let content_warning = post.content_warning.trim();
+    let attachments = post_attachments(post);
+
     let mut images: Vec<_> = post.body.md_get_images()
         .into_iter()
+        .filter(|_| content_warning.is_empty())
         .filter(|i| i.url.starts_with(files_prefix))
-        .map(|i| OGPImage{
-            url: format!("{}{}", post_url, i.url),
-            alt: i.alt
+        .map(|i| {
+            let meta = attachments.get(&i.url[files_prefix.len()..]).cloned().unwrap_or_default();
+            OGPImage{
+                url: format!("{}{}", item_url, i.url),
+                alt: i.alt.or(meta.alt_text),
+                width: meta.width,
+                height: meta.height,
+            }
         })
         .collect();
 
@@ -319,13 +783,21 @@ fn get_post_meta(req: &HttpRequest, user_id: &UserID, post: &crate::protos::Post
         images.push(OGPImage{
             url: format!("{}://{}{}", scheme, host, identicon_url(user_id)),
             alt: None,
+            width: None,
+            height: None,
         })
     }
 
     OGPMeta {
         url: post_url,
         images,
-        description: Some(post.body.md_get_summary(200)),
+        description: Some(
+            if content_warning.is_empty() {
+                post.body.md_get_summary(200)
+            } else {
+                format!("Content warning: {}", content_warning)
+            }
+        ),
     }
 }
 
@@ -343,6 +815,22 @@ struct IndexPage {
     show_authors: bool,
 }
 
+/// An IndexPage with a search form.
+#[derive(Template)]
+#[template(path = "search.html")] 
+struct SearchPage {
+    nav: Vec<Nav>,
+    items: Vec<IndexPageItem>,
+    display_message: Option<String>,
+    show_authors: bool,
+
+    /// The query the user searched for.
+    query: String,
+
+    /// Hidden (name, value) fields to keep the scope of the search.
+    scope_fields: Vec<(&'static str, String)>,
+}
+
 /// Should this Item be displayed on the plain-HTML version of the site?
 /// i.e.: should it be indexed by search engines?
 // TODO: Rename.
@@ -358,6 +846,14 @@ fn display_by_default(item: &Item) -> bool {
         ItemType::post(_) => true,
         ItemType::profile(_) => false,
         ItemType::comment(_) => false,
+        ItemType::delete(_) => false,
+        // Displayed in place of the Post they revise:
+        ItemType::revision(_) => false,
+        // Displayed as counts on the item they react to:
+        ItemType::reaction(_) => false,
+        ItemType::share(_) => true,
+        // Encrypted, and only listed in the recipient's inbox:
+        ItemType::direct_message(_) => false,
     }
 }
 
@@ -370,15 +866,23 @@ pub(crate) async fn show_profile(
 ) -> Result<HttpResponse, Error> 
 {
     let (user_id,) = path.into_inner();
-    let backend = data.backend_factory.open()?;
+    let page = with_backend(&data, move |backend| profile_page(backend, user_id)).await?;
+    let page = match page {
+        Ok(page) => page,
+        Err(missing) => return Ok(missing.respond_to(&req).await),
+    };
+
+    Ok(page.respond_to(&req).map_into_boxed_body())
+}
 
-    let row = backend.user_profile(&user_id)?;
+fn profile_page(backend: &dyn Backend, user_id: UserID) -> Result<Result<ProfilePage, Missing>, anyhow::Error> {
+    if backend.is_blocked(&Block::User(user_id.clone()))? {
+        return Ok(Err(Missing::Blocked));
+    }
 
-    let row = match row {
+    let row = match backend.user_profile(&user_id)? {
         Some(r) => r,
-        None => {
-            return Ok(HttpResponse::NotFound().body("No such user, or profile."))
-        }
+        None => return Ok(Err(Missing::NotFound("No such user, or profile."))),
     };
 
     let mut item = Item::new();
@@ -403,9 +907,20 @@ pub(crate) async fn show_profile(
     let timestamp_utc_ms = item.timestamp_ms_utc;
     let utc_offset_minutes = item.utc_offset_minutes;
     let text = std::mem::take(&mut item.mut_profile().about);
+    let mention_names = backend.display_names(&user_id, &text.md_get_mentions())?;
+    let revoked = if item.get_profile().has_revocation() {
+        Some(item.get_profile().get_revocation().reason.clone())
+    } else {
+        None
+    };
+    let successor = if item.get_profile().has_successor() {
+        Some(UserID::from_vec(item.get_profile().get_successor().get_user_id().bytes.clone())?)
+    } else {
+        None
+    };
 
     let follows = std::mem::take(&mut item.get_profile()).follows.to_vec();
-    let follows = follows.into_iter().map(|mut follow: crate::protos::Follow | -> Result<ProfileFollow, Error>{
+    let follows = follows.into_iter().map(|mut follow: crate::protos::Follow | -> Result<ProfileFollow, anyhow::Error>{
         let mut user = std::mem::take(follow.mut_user());
         let user_id = UserID::from_vec(std::mem::take(&mut user.bytes))?;
         let display_name = follow.display_name;
@@ -419,13 +934,16 @@ pub(crate) async fn show_profile(
         text,
         display_name,
         follows,
+        revoked,
+        successor,
+        mention_names,
         timestamp_utc_ms,
         utc_offset_minutes,
         user_id: row.user,
         signature: row.signature,
     };
 
-    Ok(page.respond_to(&req).map_into_boxed_body())
+    Ok(Ok(page))
 }
 
 
@@ -445,6 +963,12 @@ struct ProfilePage {
     display_name: String,
     text: String,
     follows: Vec<ProfileFollow>,
+    /// If this user ID has been revoked, the (possibly empty) reason given.
+    revoked: Option<String>,
+    /// The user ID that this user has moved to, if any.
+    successor: Option<UserID>,
+    /// Names to display for users @mentioned in `text`.
+    mention_names: HashMap<UserID, String>,
     timestamp_utc_ms: i64,
     utc_offset_minutes: i32,
 }
@@ -462,15 +986,51 @@ struct PostPage {
     nav: Vec<Nav>,
     user_id: UserID,
     signature: Signature,
+
+    /// The signature of the item we're displaying contents from. (The Post, or its latest Revision.)
+    content_signature: Signature,
+    /// If the Post has been revised, the timestamp of the displayed Revision.
+    revised_utc_ms: Option<i64>,
+
+    reactions: Vec<ReactionCount>,
+
+    /// Names to display for users @mentioned in `text`.
+    mention_names: HashMap<UserID, String>,
+
+    /// The Comment thread beneath this Post, depth-first.
+    comments: Vec<ThreadComment>,
+    /// True if there were more comments than we could display.
+    more_comments: bool,
+
     display_name: String,
     text: String,
     title: String,
+    /// If not empty, `text` should be hidden behind this warning.
+    content_warning: String,
+    /// Metadata about attached files, by file name.
+    attachments: HashMap<String, AttachmentMeta>,
     timestamp_utc_ms: i64,
     utc_offset_minutes: i32,
 
     meta: OGPMeta,
 }
 
+/// A Comment in a thread beneath a Post.
+struct ThreadComment {
+    /// Direct replies to the Post have a depth of 1.
+    depth: u32,
+    comment: IndexPageItem,
+}
+
+impl ThreadComment {
+    /// How far to indent this comment, in `em`s.
+    fn indent(&self) -> u32 {
+        // Don't let deep threads run off the side of the page:
+        let max_indent_depth = 10;
+        (self.depth.min(max_indent_depth) - 1) * 2
+    }
+}
+
 /// Open Graph Protocol Metadata
 ///
 /// See: https://ogp.me/
@@ -492,5 +1052,20 @@ struct OGPImage {
     url: String,
 
     /// alt text. (NOT a caption, says ogp.me)
-    alt: Option<String>
+    alt: Option<String>,
+
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Metadata about a Post's attached files, by file name.
+pub(crate) fn post_attachments(post: &Post) -> HashMap<String, AttachmentMeta> {
+    post.get_attachments().get_file().iter().map(|file| {
+        let meta = AttachmentMeta{
+            width: Some(file.width).filter(|it| *it > 0),
+            height: Some(file.height).filter(|it| *it > 0),
+            alt_text: Some(file.alt_text.clone()).filter(|it| !it.trim().is_empty()),
+        };
+        (file.name.clone(), meta)
+    }).collect()
 }
\ No newline at end of file
@@ -3,14 +3,16 @@
 //! for my own sanity. :p 
 
 use std::{
-    fmt::Write,
+    fmt::{Display, Write},
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    str::FromStr,
 };
 
-use serde::Deserialize;
+use anyhow::bail;
+use serde::{Deserialize, Deserializer};
 
-use crate::backend::{TimeSpan, Timestamp};
+use crate::{backend::{Cursor, Signature, TimeSpan, Timestamp, UserID}, protos::ItemListEntry};
 use super::{IndexPageItem};
 
 /// Query params to control pagination:
@@ -19,16 +21,151 @@ pub(crate) struct Pagination {
     /// Time before which to show posts. Default is now.
     before: Option<i64>,
 
-    /// Time after which to show some posts. can not set before & after, and before takes precedence.
-    /// Note: posts will still be listed in reverse-chronological-order. (newest first).
+    /// Time after which to show some posts.
+    /// If `before` is also set, shows posts between the two, newest first.
     after: Option<i64>,
 
-    // TODO: add sig (signature) here for correct pagination.
+    /// An opaque position from a previous page, to continue paginating from.
+    /// Takes precedence over `before` and `after`.
+    cursor: Option<PageCursor>,
 
     /// Limit how many posts/items appear on a page.
     count: Option<usize>,
 }
 
+/// The `cursor` query param. Encodes a TimeSpan, including the exact item
+/// to continue from, so that items which share a timestamp aren't skipped.
+#[derive(Debug, Clone)]
+pub(crate) struct PageCursor(TimeSpan);
+
+// Encoded, a cursor is a direction byte, followed by one or two Cursors.
+// Each Cursor is a timestamp, then a flag byte and optional item:
+// i64 timestamp, 0 | 1 + user ID + signature
+const CURSOR_BYTES: usize = 8 + 1;
+const CURSOR_ITEM_BYTES: usize = 32 + 64;
+
+fn write_cursor(bytes: &mut Vec<u8>, cursor: &Cursor) {
+    bytes.extend_from_slice(&cursor.timestamp.unix_utc_ms.to_be_bytes());
+    match &cursor.item {
+        None => bytes.push(0),
+        Some((user, signature)) => {
+            bytes.push(1);
+            bytes.extend_from_slice(user.bytes());
+            bytes.extend_from_slice(signature.bytes());
+        }
+    }
+}
+
+/// Read a Cursor from the start of `bytes`, and advance past it.
+fn read_cursor(bytes: &mut &[u8]) -> anyhow::Result<Cursor> {
+    if bytes.len() < CURSOR_BYTES {
+        bail!("Cursor is too short");
+    }
+
+    let mut timestamp = [0u8; 8];
+    timestamp.copy_from_slice(&bytes[..8]);
+    let has_item = bytes[8];
+    *bytes = &bytes[CURSOR_BYTES..];
+
+    let item = match has_item {
+        0 => None,
+        1 => {
+            if bytes.len() < CURSOR_ITEM_BYTES {
+                bail!("Cursor is too short");
+            }
+            let user = UserID::from_vec(bytes[..32].to_vec())?;
+            let signature = Signature::from_vec(bytes[32..CURSOR_ITEM_BYTES].to_vec())?;
+            *bytes = &bytes[CURSOR_ITEM_BYTES..];
+            Some((user, signature))
+        },
+        _ => bail!("Invalid cursor item flag: {}", has_item),
+    };
+
+    Ok(Cursor{
+        timestamp: Timestamp{ unix_utc_ms: i64::from_be_bytes(timestamp) },
+        item,
+    })
+}
+
+impl Display for PageCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = vec![];
+        match &self.0 {
+            TimeSpan::Before(end) => {
+                bytes.push(b'b');
+                write_cursor(&mut bytes, end);
+            },
+            TimeSpan::After(start) => {
+                bytes.push(b'a');
+                write_cursor(&mut bytes, start);
+            },
+            TimeSpan::Between(start, end) => {
+                bytes.push(b'w');
+                write_cursor(&mut bytes, start);
+                write_cursor(&mut bytes, end);
+            },
+        }
+
+        write!(f, "{}", bs58::encode(bytes).into_string())
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let decoded = bs58::decode(value).into_vec()?;
+        let (direction, mut bytes) = match decoded.split_first() {
+            Some((direction, rest)) => (*direction, rest),
+            None => bail!("Empty cursor"),
+        };
+
+        let span = match direction {
+            b'b' => TimeSpan::Before(read_cursor(&mut bytes)?),
+            b'a' => TimeSpan::After(read_cursor(&mut bytes)?),
+            b'w' => {
+                let start = read_cursor(&mut bytes)?;
+                let end = read_cursor(&mut bytes)?;
+                TimeSpan::Between(start, end)
+            },
+            _ => bail!("Invalid cursor: {}", value),
+        };
+        if !bytes.is_empty() {
+            bail!("Invalid cursor: {}", value);
+        }
+
+        Ok(PageCursor(span))
+    }
+}
+
+impl<'de> Deserialize<'de> for PageCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Items which know their own position in a paginated list.
+pub(crate) trait Paginated {
+    fn cursor(&self) -> Option<Cursor>;
+}
+
+impl Paginated for IndexPageItem {
+    fn cursor(&self) -> Option<Cursor> {
+        let row = &self.row.item;
+        Some(Cursor::at(row.timestamp, &row.user, &row.signature))
+    }
+}
+
+impl Paginated for ItemListEntry {
+    fn cursor(&self) -> Option<Cursor> {
+        let user = UserID::from_vec(self.get_user_id().get_bytes().into()).ok()?;
+        let signature = Signature::from_vec(self.get_signature().get_bytes().into()).ok()?;
+        let timestamp = Timestamp{ unix_utc_ms: self.timestamp_ms_utc };
+        Some(Cursor::at(timestamp, &user, &signature))
+    }
+}
+
 
 /// Works with the callbacks in Backend to provide pagination.
 /// Handles max # items, tracking whether the source has_more items, 
@@ -50,6 +187,12 @@ where
     filter: Filter,
     have_flipped: bool,
 
+    /// How many items have been removed by take_items().
+    taken: usize,
+
+    /// The position of the last item removed by take_items().
+    taken_cursor: Option<Cursor>,
+
     _in: PhantomData<In>,
     _err: PhantomData<E>,
 }
@@ -67,7 +210,7 @@ where
             return Ok(true); // continue
         }
 
-        if self.items.len() >= max_len {
+        if self.items.len() + self.taken >= max_len {
             self.has_more = true;
             return Ok(false); // stop
         }
@@ -80,6 +223,31 @@ where
         move |input| self.accept(input)
     }
 
+    /// Like callback(), but takes items from an Iterator until we have enough.
+    pub fn extend(&mut self, inputs: impl Iterator<Item=Result<In, E>>) -> Result<(), E> {
+        for input in inputs {
+            if !self.accept(input?)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like extend(), but takes at most `count` inputs.
+    /// Returns false once we have enough items, or `inputs` runs out.
+    pub fn extend_by(&mut self, inputs: &mut impl Iterator<Item=Result<In, E>>, count: usize) -> Result<bool, E> {
+        for _ in 0..count {
+            let input = match inputs.next() {
+                None => return Ok(false),
+                Some(input) => input?,
+            };
+            if !self.accept(input)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     /// Creates a new paginator for collecting results from a Backend.
     /// mapper: Maps the row type passed to the callback to some other type.
     /// filter: Filters that type for inclusion in the paginated results.
@@ -93,6 +261,8 @@ where
             mapper,
             filter,
             have_flipped: false,
+            taken: 0,
+            taken_cursor: None,
             _in: PhantomData,
             _err: PhantomData,
         }
@@ -101,7 +271,7 @@ where
     /// An optional message about there being nothing/no more to display.
     pub fn message(&self) -> Option<String> {
         if self.items.is_empty() {
-            if self.params.before.is_none() {
+            if self.params.before.is_none() && self.params.cursor.is_none() {
                 Some("Nothing to display".into())
             } else {
                 Some("No more items to display.".into())
@@ -111,25 +281,24 @@ where
         }
     }
 
-    /// The time before which we should query for items.
-    /// Prefer time_span() if bidirectional pagination is supported.
-    /// TODO: Deprecate pagination by "before" only:
-    pub fn before(&self) -> Timestamp {
-        self.params.before.map(|t| Timestamp{ unix_utc_ms: t}).unwrap_or_else(|| Timestamp::now())
-    }
-
     /// The time span we should display for the current request:
     pub fn time_span(&self) -> TimeSpan {
-        // If both are specified, prefer "before":
-        if let Some(before) = self.params.before {
-            return TimeSpan::Before(Timestamp { unix_utc_ms: before });
+        if let Some(PageCursor(span)) = &self.params.cursor {
+            return span.clone();
         }
-        if let Some(after) = self.params.after {
-            return TimeSpan::After(Timestamp { unix_utc_ms: after });
+
+        match (self.params.after, self.params.before) {
+            (Some(after), Some(before)) => return TimeSpan::Between(
+                Timestamp { unix_utc_ms: after }.into(),
+                Timestamp { unix_utc_ms: before }.into(),
+            ),
+            (None, Some(before)) => return TimeSpan::Before(Timestamp { unix_utc_ms: before }.into()),
+            (Some(after), None) => return TimeSpan::After(Timestamp { unix_utc_ms: after }.into()),
+            (None, None) => {},
         }
 
         // else:
-        TimeSpan::Before(Timestamp::now())
+        TimeSpan::Before(Timestamp::now().into())
     }
 
     fn flip_items(&mut self) {
@@ -152,12 +321,50 @@ where
     }
 }
 
-impl<In, E, Mapper, Filter> Paginator<IndexPageItem, In, E, Mapper, Filter>
+impl<T, In, E, Mapper, Filter> Paginator<T, In, E, Mapper, Filter>
 where 
-    Mapper: Fn(In) -> Result<IndexPageItem,E>,
-    Filter: Fn(&IndexPageItem) -> bool,
+    T: Paginated,
+    Mapper: Fn(In) -> Result<T,E>,
+    Filter: Fn(&T) -> bool,
 {
-   pub fn more_items_link(&mut self, base_url: &str) -> Option<String> {
+    /// A cursor to fetch the next page of items, in the same direction as
+    /// this one. None if there are no more items.
+    pub fn next_cursor(&self) -> Option<PageCursor> {
+        if !self.has_more { return None; }
+
+        // The last item we received, before any flip_items():
+        let last = if self.have_flipped { self.items.first() } else { self.items.last() };
+        let cursor = match last {
+            Some(last) => last.cursor()?,
+            None => self.taken_cursor.clone()?,
+        };
+
+        Some(PageCursor(self.time_span().continued(cursor)))
+    }
+
+    /// Removes the items accepted so far, so that they can be sent before the
+    /// rest of the page. Set `complete` once no more items will be accepted.
+    ///
+    /// Items for a span that's fetched oldest first are kept until the page is
+    /// `complete`, because into_items() would flip them.
+    pub fn take_items(&mut self, complete: bool) -> Vec<T> {
+        if complete {
+            self.flip_items();
+        } else if !self.time_span().is_before() {
+            return vec![];
+        }
+
+        // The last item we received, before any flip_items():
+        let last = if self.have_flipped { self.items.first() } else { self.items.last() };
+        if let Some(cursor) = last.and_then(|last| last.cursor()) {
+            self.taken_cursor = Some(cursor);
+        }
+
+        self.taken += self.items.len();
+        std::mem::take(&mut self.items)
+    }
+
+    pub fn more_items_link(&mut self, base_url: &str) -> Option<String> {
         let span = self.time_span();
 
         let show_link = if span.is_before() {
@@ -171,12 +378,16 @@ where
         };
         if !show_link { return None; }
 
-        let last = match self.items().last() {
+        let cursor = match self.items().last().and_then(|last| last.cursor()) {
             None => return None, // Shouldn't happen, if has_more.
-            Some(last) => last,
+            Some(cursor) => match span {
+                // Stay within the requested window:
+                TimeSpan::Between(start, _) => PageCursor(TimeSpan::Between(start, cursor)),
+                _ => PageCursor(TimeSpan::Before(cursor)),
+            },
         };
 
-        let mut url = format!("{}?before={}", base_url, last.item.timestamp_ms_utc);
+        let mut url = format!("{}{}cursor={}", base_url, query_separator(base_url), cursor);
         if let Some(count) = self.params.count {
             write!(url, "&count={}", count).expect("write! to a string shouldn't panic.");
         }
@@ -187,20 +398,24 @@ where
     pub fn newer_items_link(&mut self, base_url: &str) -> Option<String> {
         let span = self.time_span();
 
+        // Windows are only listed newest first, so there's no "newer" page to
+        // go back to. (Users can start over from the window's original URL.)
+        if let TimeSpan::Between(_, _) = span { return None; }
+
         let show_link = if span.is_before() {
             // We can assume there are more newer items if the user has specified a ?before=...:
-            self.params.before.is_some()
+            self.params.before.is_some() || self.params.cursor.is_some()
         } else {
             self.has_more
         };
         if !show_link { return None; }
 
-        let first = match self.items().first() {
+        let cursor = match self.items().first().and_then(|first| first.cursor()) {
             None => return None, // Shouldn't happen, if has_more.
-            Some(first) => first,
+            Some(cursor) => PageCursor(TimeSpan::After(cursor)),
         };
 
-        let mut url = format!("{}?after={}", base_url, first.item.timestamp_ms_utc);
+        let mut url = format!("{}{}cursor={}", base_url, query_separator(base_url), cursor);
         if let Some(count) = self.params.count {
             write!(url, "&count={}", count).expect("write! to a string shouldn't panic.");
         }
@@ -208,6 +423,11 @@ where
         Some(url)    }
 }
 
+/// The character to append query params to `base_url` with.
+fn query_separator(base_url: &str) -> char {
+    if base_url.contains('?') { '&' } else { '?' }
+}
+
 /// Set lower and upper bounds for input T.
 fn bound<T: Ord>(input: T, lower: T, upper: T) -> T {
     use std::cmp::{min, max};
@@ -2,15 +2,17 @@
 //!
 //! Note: some endpoints are in attachments.rs, since they're used by both REST & HTML views.
 
-use actix_web::{HttpRequest, HttpResponse, web::{Data, Path, Payload, Query}, HttpResponseBuilder};
+use std::io::ErrorKind;
+
+use actix_web::{HttpRequest, HttpResponse, http::StatusCode, web::{Bytes, Data, Path, Payload, Query}, HttpResponseBuilder};
 use anyhow::{Context, format_err};
 use futures::StreamExt;
 use logging_timer::timer;
 use protobuf::Message;
 
-use crate::{backend::{ItemDisplayRow, ItemRow, Signature, Timestamp, UserID}, protos::{Item, ItemList, ItemListEntry, ItemType, Item_oneof_item_type, ProtoValid}, server::{MAX_ITEM_SIZE, PLAINTEXT}};
+use crate::{backend::{Backend, Block, Factory, ItemDisplayRow, ItemRow, ListRow, QuotaDenyReason, RowIter, Signature, Timestamp, UserID}, protos::{Item, ItemList, ItemListEntry, ItemType, Item_oneof_item_type, ProtoValid, ReactionCounts}, server::{MAX_ITEM_SIZE, PLAINTEXT, SearchParams}};
 
-use super::{AppData, Error, pagination::{Pagination, Paginator}, attachments::drain};
+use super::{AppData, Error, Rejection, SendError, with_backend, pagination::{Pagination, Paginator}, attachments::drain};
 
 
 // Get the protobuf ItemList for items on the homepage.
@@ -27,22 +29,16 @@ pub(crate) async fn homepage_item_list(
             Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
         }, 
         |entry: &ItemListEntry| { 
-            entry.get_item_type() == ItemType::POST
+            let item_type = entry.get_item_type();
+            item_type == ItemType::POST || item_type == ItemType::SHARE
         }
     );
-    // We're only holding ItemListEntries in memory, so we can up this limit and save some round trips.
+    // We only hold a chunk of ItemListEntries in memory at a time, so we can up this limit and save some round trips.
     paginator.max_items = 1000;
 
-    let backend = data.backend_factory.open()?;
-    backend.homepage_items(paginator.time_span(), &mut paginator.callback())?;
-    
-
-    let mut list = ItemList::new();
-    list.no_more_items = !paginator.has_more;
-    list.items = protobuf::RepeatedField::from(paginator.into_items());
-    Ok(
-        proto_ok().body(list.write_to_bytes()?)
-    )
+    let factory = data.backend_factory.dyn_clone();
+    let rows = factory.homepage_items_iter(paginator.time_span());
+    Ok(stream_item_list(factory, rows, paginator, true))
 }
 
 
@@ -61,20 +57,122 @@ pub(crate) async fn feed_item_list(
         }, 
         |_: &ItemListEntry| { true } // include all items
     );
-    // We're only holding ItemListEntries in memory, so we can up this limit and
-    // save some round trips.
+    // We only hold a chunk of ItemListEntries in memory at a time, so we can up
+    // this limit and save some round trips.
     paginator.max_items = 1000;
 
-    let backend = data.backend_factory.open()?;
-
     // Note: user_feed_items is doing a little bit of extra work to fetch
     // display_name, which we then throw away. We *could* make a more efficient
     // version that we use for just this case, but eh, reuse is nice.
-    backend.user_feed_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+    let factory = data.backend_factory.dyn_clone();
+    let rows = factory.user_feed_items_iter(&user_id, paginator.time_span());
+    Ok(stream_item_list(factory, rows, paginator, true))
+}
+
+/// Posts tagged with a #tag.
+///
+/// `/tags/{tag}/proto3`
+pub(crate) async fn tag_item_list(
+    data: Data<AppData>,
+    path: Path<(String,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<HttpResponse, Error> {
+    let (tag,) = path.into_inner();
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,anyhow::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        }, 
+        |_: &ItemListEntry| { true } // include all items
+    );
+    paginator.max_items = 1000;
+
+    let list = with_backend(&data, move |backend| {
+        backend.tag_items(&tag.to_lowercase(), paginator.time_span(), &mut paginator.callback())?;
+
+        let mut list = ItemList::new();
+        list.no_more_items = !paginator.has_more;
+        list.next_cursor = paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+        let mut items = paginator.into_items();
+        add_revisions(backend, &mut items)?;
+        list.items = protobuf::RepeatedField::from(items);
+        Ok(list)
+    }).await?;
+    Ok(
+        proto_ok()
+        .body(list.write_to_bytes()?)
+    )
+}
 
-    let mut list = ItemList::new();
-    list.no_more_items = !paginator.has_more;
-    list.items = protobuf::RepeatedField::from(paginator.into_items());
+/// Posts and Comments that contain all of the words in `q`.
+///
+/// `/search/proto3?q={query}`
+pub(crate) async fn search_item_list(
+    data: Data<AppData>,
+    Query(pagination): Query<Pagination>,
+    Query(search): Query<SearchParams>,
+) -> Result<HttpResponse, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,anyhow::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        }, 
+        |_: &ItemListEntry| { true } // include all items
+    );
+    paginator.max_items = 1000;
+
+    let list = with_backend(&data, move |backend| {
+        backend.search_items(&search.q, search.scope(), paginator.time_span(), &mut paginator.callback())?;
+
+        let mut list = ItemList::new();
+        list.no_more_items = !paginator.has_more;
+        list.next_cursor = paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+        let mut items = paginator.into_items();
+        add_revisions(backend, &mut items)?;
+        list.items = protobuf::RepeatedField::from(items);
+        Ok(list)
+    }).await?;
+    Ok(
+        proto_ok()
+        .body(list.write_to_bytes()?)
+    )
+}
+
+/// Posts and Comments that @mention a user.
+///
+/// `/u/{user_id}/mentions/proto3`
+pub(crate) async fn mention_item_list(
+    data: Data<AppData>,
+    path: Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<HttpResponse, Error> {
+    let (user_id,) = path.into_inner();
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,anyhow::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        }, 
+        |_: &ItemListEntry| { true } // include all items
+    );
+    paginator.max_items = 1000;
+
+    let list = with_backend(&data, move |backend| {
+        backend.mention_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+
+        let mut list = ItemList::new();
+        list.no_more_items = !paginator.has_more;
+        list.next_cursor = paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+        let mut items = paginator.into_items();
+        add_revisions(backend, &mut items)?;
+        list.items = protobuf::RepeatedField::from(items);
+        Ok(list)
+    }).await?;
     Ok(
         proto_ok()
         .body(list.write_to_bytes()?)
@@ -96,27 +194,92 @@ pub(crate) async fn user_item_list(
         }, 
         |_| { true } // include all items
     );
-    // We're only holding ItemListEntries in memory, so we can up this limit and
-    // save some round trips.
+    // We only hold a chunk of ItemListEntries in memory at a time, so we can up
+    // this limit and save some round trips.
     paginator.max_items = 1000;
 
-    let backend = data.backend_factory.open()?;
+    let blocked = Block::User(user_id.clone());
+    if with_backend(&data, move |backend| backend.is_blocked(&blocked)).await? {
+        return Ok(
+            Rejection::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "User has been blocked").into()
+        );
+    }
 
-    // Note: user_feed_items is doing a little bit of extra work to fetch
-    // display_name, which we then throw away. We *could* make a more efficient
-    // version that we use for just this case, but eh, reuse is nice.
-    backend.user_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+    let factory = data.backend_factory.dyn_clone();
+    let rows = factory.user_items_iter(&user_id, paginator.time_span());
+    Ok(stream_item_list(factory, rows, paginator, true))
+}
 
-    let mut list = ItemList::new();
-    list.no_more_items = !paginator.has_more;
-    list.items = protobuf::RepeatedField::from(paginator.into_items());
+pub(crate) async fn item_reply_list(
+    data: Data<AppData>,
+    path: Path<(UserID, Signature)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, signature) = path.into_inner();
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemRow| -> Result<ItemListEntry,anyhow::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            Ok(item_to_entry(&item, &row.user, &row.signature))
+        }, 
+        |_| { true } // include all items
+    );
+    // We only hold a chunk of ItemListEntries in memory at a time, so we can up
+    // this limit and save some round trips.
+    paginator.max_items = 1000;
+
+    let factory = data.backend_factory.dyn_clone();
+    let rows = factory.reply_items_iter(&user_id, &signature, paginator.time_span());
+    Ok(stream_item_list(factory, rows, paginator, false))
+}
+
+/// Lists all Comments in the thread beneath an item, depth-first.
+///
+/// `/u/{userID}/i/{sig}/thread/proto3`
+pub(crate) async fn item_thread_list(
+    data: Data<AppData>,
+    path: Path<(UserID, Signature)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, signature) = path.into_inner();
+
+    // Threads are ordered by structure, not time, so we can't paginate them
+    // with `before`/`after`. Just cap the size:
+    let max_items = 1000;
+
+    let list = with_backend(&data, move |backend| {
+        let mut items = vec![];
+        let mut has_more = false;
+        // Fetch one extra so we know if there were more:
+        backend.thread_items(&user_id, &signature, max_items + 1, &mut |row| {
+            if items.len() >= max_items {
+                has_more = true;
+                return Ok(false);
+            }
+
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item.item_bytes)?;
+            let mut entry = item_to_entry(&item, &row.item.item.user, &row.item.item.signature);
+            entry.set_thread_depth(row.depth);
+            items.push(entry);
+            Ok(true)
+        })?;
+
+        let mut list = ItemList::new();
+        list.no_more_items = !has_more;
+        list.items = protobuf::RepeatedField::from(items);
+        Ok(list)
+    }).await?;
     Ok(
         proto_ok()
         .body(list.write_to_bytes()?)
     )
 }
 
-pub(crate) async fn item_reply_list(
+/// Lists the Revisions of a Post, newest first.
+///
+/// `/u/{userID}/i/{sig}/revisions/proto3`
+pub(crate) async fn item_revision_list(
     data: Data<AppData>,
     path: Path<(UserID, Signature)>,
     Query(pagination): Query<Pagination>,
@@ -131,29 +294,89 @@ pub(crate) async fn item_reply_list(
         }, 
         |_| { true } // include all items
     );
-    // We're only holding ItemListEntries in memory, so we can up this limit and
-    // save some round trips.
     paginator.max_items = 1000;
 
-    let backend = data.backend_factory.open()?;
+    let list = with_backend(&data, move |backend| {
+        backend.item_revisions(&user_id, &signature, paginator.time_span(), &mut paginator.callback())?;
 
-    // Note: user_feed_items is doing a little bit of extra work to fetch
-    // display_name, which we then throw away. We *could* make a more efficient
-    // version that we use for just this case, but eh, reuse is nice.
-    backend.reply_items(&user_id, &signature, paginator.before(), &mut paginator.callback())?;
+        let mut list = ItemList::new();
+        list.no_more_items = !paginator.has_more;
+        list.next_cursor = paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+        list.items = protobuf::RepeatedField::from(paginator.into_items());
+        Ok(list)
+    }).await?;
+    Ok(
+        proto_ok()
+        .body(list.write_to_bytes()?)
+    )
+}
 
-    let mut list = ItemList::new();
-    list.no_more_items = !paginator.has_more;
-    list.items = protobuf::RepeatedField::from(paginator.into_items());
+/// Lists DirectMessages sent to a user, newest first.
+///
+/// `/u/{userID}/inbox/proto3`
+pub(crate) async fn inbox_item_list(
+    data: Data<AppData>,
+    path: Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<HttpResponse, Error> {
+    let (user_id,) = path.into_inner();
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemRow| -> Result<ItemListEntry,anyhow::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            Ok(item_to_entry(&item, &row.user, &row.signature))
+        }, 
+        |_| { true } // include all items
+    );
+    paginator.max_items = 1000;
+
+    let list = with_backend(&data, move |backend| {
+        backend.inbox_items(&user_id, paginator.time_span(), &mut paginator.callback())?;
+
+        let mut list = ItemList::new();
+        list.no_more_items = !paginator.has_more;
+        list.next_cursor = paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+        list.items = protobuf::RepeatedField::from(paginator.into_items());
+        Ok(list)
+    }).await?;
     Ok(
         proto_ok()
         .body(list.write_to_bytes()?)
     )
 }
 
+/// Aggregated counts of Reactions to an item.
+///
+/// `/u/{userID}/i/{sig}/reactions/proto3`
+pub(crate) async fn item_reaction_counts(
+    data: Data<AppData>,
+    path: Path<(UserID, Signature)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, signature) = path.into_inner();
+    let rows = with_backend(&data, move |backend| {
+        backend.reaction_counts(&user_id, &signature)
+    }).await?;
+
+    let mut counts = ReactionCounts::new();
+    for row in rows {
+        let mut count = crate::protos::ReactionCount::new();
+        count.set_reaction(row.reaction);
+        count.set_count(row.count);
+        counts.mut_counts().push(count);
+    }
+
+    Ok(
+        proto_ok()
+        .body(counts.write_to_bytes()?)
+    )
+}
+
 /// Accepts a proto3 Item
 /// Returns 201 if the PUT was successful.
 /// Returns 202 if the item already exists.
+/// Returns 410 if the item (or the Post it revises) has been deleted.
+/// Returns 403 if the user ID has been revoked.
 /// Returns ??? if the user lacks permission to post.
 /// Returns ??? if the signature is not valid.
 /// Returns a text body message w/ OK/Error message.
@@ -201,27 +424,33 @@ pub(crate) async fn put_item(
         );
     }
 
-    let mut backend = data.backend_factory.open()?;
-
-    // If the content already exists, do nothing.
-    if backend.user_item_exists(&user, &signature)? {
-        // *sigh* this bug again. Should I handle this in middleware?
-        drain(body).await;
-        
-        return Ok(
-            HttpResponse::Accepted()
-            .content_type(PLAINTEXT)
-            .body("Item already exists")
-        );
-    }
-
-    if !backend.user_known(&user)? {
-        return Ok(
-            HttpResponse::Forbidden()
-            .content_type(PLAINTEXT)
-            .body("Unknown user ID")
-        )
-    }
+    let precheck = {
+        let user = user.clone();
+        let signature = signature.clone();
+        with_backend(&data, move |backend| {
+            // If the content already exists, do nothing.
+            if backend.user_item_exists(&user, &signature)? {
+                return Ok(Err(Rejection::new(StatusCode::ACCEPTED, "Item already exists")));
+            }
+            if backend.is_blocked(&Block::Item(user.clone(), signature.clone()))? {
+                return Ok(Err(Rejection::new(StatusCode::FORBIDDEN, "Item has been blocked on this server")));
+            }
+            // We accept items shared by known users, but only those items:
+            let known = backend.user_known(&user)?;
+            if !known && !backend.item_shared(&user, &signature)? {
+                return Ok(Err(Rejection::new(StatusCode::FORBIDDEN, "Unknown user ID")));
+            }
+            Ok(Ok((known, backend.user_item_deleted(&user, &signature)?)))
+        }).await?
+    };
+    let (known, deleted) = match precheck {
+        Ok(result) => result,
+        Err(rejection) => {
+            // *sigh* this bug again. Should I handle this in middleware?
+            drain(body).await;
+            return Ok(rejection.into());
+        }
+    };
     
     let mut bytes: Vec<u8> = Vec::with_capacity(length);
     while let Some(chunk) = body.next().await {
@@ -237,6 +466,16 @@ pub(crate) async fn put_item(
     item.merge_from_bytes(&bytes)?;
     item.validate()?;
 
+    // A Delete may have arrived before the item it targets. Profiles and Deletes
+    // can't be deleted, so in that case we ignore the Delete. (See: Backend::save_user_item)
+    if deleted && !(item.has_profile() || item.has_delete()) {
+        return Ok(Rejection::new(StatusCode::GONE, "Item has been deleted").into());
+    }
+
+    if !known && !item.has_post() {
+        return Ok(Rejection::new(StatusCode::BAD_REQUEST, "Only Posts can be shared").into());
+    }
+
     if item.timestamp_ms_utc > Timestamp::now().unix_utc_ms {
         return Ok(
             HttpResponse::BadRequest()
@@ -245,26 +484,86 @@ pub(crate) async fn put_item(
         )
     }
 
-    if let Some(deny_reason) = backend.quota_check_item(&user, &bytes, &item)? {
-        return Ok(
-            HttpResponse::InsufficientStorage()
-            .body(format!("{}", deny_reason))
-        )
+    if item.get_profile().has_successor() {
+        // The successor must agree to succeed this user:
+        let successor = item.get_profile().get_successor();
+        let successor_id = UserID::from_vec(successor.get_user_id().get_bytes().into())?;
+        let counter_signature = Signature::from_vec(successor.get_signature().get_bytes().into())?;
+        if !counter_signature.is_valid(&successor_id, &user.successor_bytes(&successor_id)) {
+            return Ok(
+                HttpResponse::BadRequest()
+                .content_type(PLAINTEXT)
+                .body("Invalid Successor signature")
+            )
+        }
     }
 
     let message = format!("OK. Received {} bytes.", bytes.len());
-    
-    let row = ItemRow{
-        user: user,
-        signature: signature,
-        timestamp: Timestamp{ unix_utc_ms: item.get_timestamp_ms_utc()},
-        received: Timestamp::now(),
-        item_bytes: bytes,
-    };
 
-    let timer = timer!("save_user_item");
-    backend.save_user_item(&row, &item).context("Error saving user item")?;
-    drop(timer);
+    let rejection = with_backend(&data, move |backend| {
+        if item.has_delete() {
+            let target = Signature::from_vec(item.get_delete().get_signature().get_bytes().into())?;
+            if let Some(target_row) = backend.user_item(&user, &target)? {
+                let mut target_item = Item::new();
+                target_item.merge_from_bytes(&target_row.item_bytes)?;
+                if target_item.has_profile() || target_item.has_delete() {
+                    return Ok(Some(Rejection::new(StatusCode::BAD_REQUEST, "Profiles and Deletes can not be deleted")));
+                }
+            }
+        }
+
+        if item.has_share() {
+            let shared = item.get_share().get_item();
+            let shared_user = UserID::from_vec(shared.get_user_id().get_bytes().into())?;
+            let shared_signature = Signature::from_vec(shared.get_signature().get_bytes().into())?;
+            if let Some(shared_row) = backend.user_item(&shared_user, &shared_signature)? {
+                let mut shared_item = Item::new();
+                shared_item.merge_from_bytes(&shared_row.item_bytes)?;
+                if !shared_item.has_post() {
+                    return Ok(Some(Rejection::new(StatusCode::BAD_REQUEST, "Only Posts can be shared")));
+                }
+            }
+        }
+
+        if item.has_revision() {
+            let original = Signature::from_vec(item.get_revision().get_original().get_bytes().into())?;
+            if backend.user_item_deleted(&user, &original)? {
+                return Ok(Some(Rejection::new(StatusCode::GONE, "The revised Post has been deleted")));
+            }
+            if let Some(original_row) = backend.user_item(&user, &original)? {
+                let mut original_item = Item::new();
+                original_item.merge_from_bytes(&original_row.item_bytes)?;
+                if !original_item.has_post() {
+                    return Ok(Some(Rejection::new(StatusCode::BAD_REQUEST, "Only Posts can be revised")));
+                }
+            }
+        }
+
+        if let Some(deny_reason) = backend.quota_check_item(&user, &bytes, &item)? {
+            let status = match deny_reason {
+                QuotaDenyReason::ProfileRevoked => StatusCode::FORBIDDEN,
+                _ => StatusCode::INSUFFICIENT_STORAGE,
+            };
+            return Ok(Some(Rejection::new(status, deny_reason.to_string())));
+        }
+
+        let row = ItemRow{
+            user: user,
+            signature: signature,
+            timestamp: Timestamp{ unix_utc_ms: item.get_timestamp_ms_utc()},
+            received: Timestamp::now(),
+            item_bytes: bytes,
+        };
+
+        let timer = timer!("save_user_item");
+        backend.save_user_item(&row, &item).context("Error saving user item")?;
+        drop(timer);
+
+        Ok(None)
+    }).await?;
+    if let Some(rejection) = rejection {
+        return Ok(rejection.into());
+    }
 
     let response = HttpResponse::Created()
         .content_type(PLAINTEXT)
@@ -283,15 +582,26 @@ pub(crate) async fn get_item(
     path: Path<(UserID, Signature,)>,
 ) -> Result<HttpResponse, Error> {
     let (user_id, signature) = path.into_inner();
-    let backend = data.backend_factory.open()?;
-    let item = backend.user_item(&user_id, &signature)?;
-    let item = match item {
-        Some(item) => item,
-        None => { 
-            return Ok(
-                HttpResponse::NotFound().body("No such item")
-            );
+    let item = with_backend(&data, move |backend| {
+        if backend.is_blocked(&Block::Item(user_id.clone(), signature.clone()))? {
+            return Ok(Err(
+                Rejection::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "Item has been blocked")
+            ));
         }
+        let item = match backend.user_item(&user_id, &signature)? {
+            Some(item) => item,
+            None => {
+                if backend.user_item_deleted(&user_id, &signature)? {
+                    return Ok(Err(Rejection::new(StatusCode::GONE, "Item has been deleted")));
+                }
+                return Ok(Err(Rejection::new(StatusCode::NOT_FOUND, "No such item")));
+            }
+        };
+        Ok(Ok(item))
+    }).await?;
+    let item = match item {
+        Ok(item) => item,
+        Err(rejection) => return Ok(rejection.into()),
     };
 
     // We could in theory validate the bytes ourselves, but if a client is directly fetching the 
@@ -311,15 +621,20 @@ pub(crate) async fn get_profile_item(
     path: Path<(UserID,)>,
 ) -> Result<HttpResponse, Error> {
     let (user_id,) = path.into_inner();
-    let backend = data.backend_factory.open()?;
-    let item = backend.user_profile(&user_id,)?;
-    let item = match item {
-        Some(item) => item,
-        None => { 
-            return Ok(
-                HttpResponse::NotFound().body("No such item")
-            );
+    let item = with_backend(&data, move |backend| {
+        if backend.is_blocked(&Block::User(user_id.clone()))? {
+            return Ok(Err(
+                Rejection::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "User has been blocked")
+            ));
         }
+        match backend.user_profile(&user_id)? {
+            Some(item) => Ok(Ok(item)),
+            None => Ok(Err(Rejection::new(StatusCode::NOT_FOUND, "No such item"))),
+        }
+    }).await?;
+    let item = match item {
+        Ok(item) => item,
+        Err(rejection) => return Ok(rejection.into()),
     };
 
     // We could in theory validate the bytes ourselves, but if a client is directly fetching the 
@@ -360,9 +675,129 @@ fn item_to_entry(item: &Item, user_id: &UserID, signature: &Signature) -> ItemLi
             Some(Item_oneof_item_type::post(_)) => ItemType::POST,
             Some(Item_oneof_item_type::profile(_)) => ItemType::PROFILE,
             Some(Item_oneof_item_type::comment(_)) => ItemType::COMMENT,
+            Some(Item_oneof_item_type::delete(_)) => ItemType::DELETE,
+            Some(Item_oneof_item_type::revision(_)) => ItemType::REVISION,
+            Some(Item_oneof_item_type::reaction(_)) => ItemType::REACTION,
+            Some(Item_oneof_item_type::share(_)) => ItemType::SHARE,
+            Some(Item_oneof_item_type::direct_message(_)) => ItemType::DIRECT_MESSAGE,
             None => ItemType::UNKNOWN,
         }
     );
 
     entry
 }
+
+/// Sets `latest_revision` on any Post entries that have been revised, so that
+/// clients know to fetch the latest contents.
+/// How many rows to fetch for each chunk of a streamed ItemList.
+const ITEM_LIST_CHUNK_ROWS: usize = 100;
+
+/// Streams an ItemList response from the blocking thread pool, a chunk at a time.
+///
+/// Protobuf messages merge when they're concatenated, so each chunk is written
+/// as its own ItemList. The last one sets `no_more_items` and `next_cursor`.
+/// Once the response has started, an error can only cut it short.
+fn stream_item_list<In, Mapper, Filter>(
+    factory: Box<dyn Factory>,
+    rows: RowIter<In>,
+    paginator: Paginator<ItemListEntry, In, anyhow::Error, Mapper, Filter>,
+    revisions: bool,
+) -> HttpResponse
+where
+    In: ListRow + Send + 'static,
+    Mapper: Fn(In) -> Result<ItemListEntry, anyhow::Error> + Send + 'static,
+    Filter: Fn(&ItemListEntry) -> bool + Send + 'static,
+{
+    let chunks = ItemListChunks{ factory, rows, paginator, revisions, complete: false };
+    proto_ok().streaming(blocking::Unblock::with_capacity(2, chunks))
+}
+
+/// The chunks of a streamed ItemList. See: [`stream_item_list()`]
+struct ItemListChunks<In, Mapper, Filter>
+where
+    Mapper: Fn(In) -> Result<ItemListEntry, anyhow::Error>,
+    Filter: Fn(&ItemListEntry) -> bool,
+{
+    factory: Box<dyn Factory>,
+    rows: RowIter<In>,
+    paginator: Paginator<ItemListEntry, In, anyhow::Error, Mapper, Filter>,
+
+    /// Add each Post's latest revision to its entry? See: add_revisions()
+    revisions: bool,
+
+    /// Set once we've written the last chunk.
+    complete: bool,
+}
+
+impl<In, Mapper, Filter> ItemListChunks<In, Mapper, Filter>
+where
+    In: ListRow,
+    Mapper: Fn(In) -> Result<ItemListEntry, anyhow::Error>,
+    Filter: Fn(&ItemListEntry) -> bool,
+{
+    fn next_chunk(&mut self) -> Result<ItemList, anyhow::Error> {
+        loop {
+            self.complete = !self.paginator.extend_by(&mut self.rows, ITEM_LIST_CHUNK_ROWS)?;
+            let mut items = self.paginator.take_items(self.complete);
+            // Some pages can only be sent once they're complete. (See: take_items())
+            if items.is_empty() && !self.complete {
+                continue;
+            }
+
+            if self.revisions && !items.is_empty() {
+                add_revisions(self.factory.open()?.as_ref(), &mut items)?;
+            }
+
+            let mut list = ItemList::new();
+            list.items = protobuf::RepeatedField::from(items);
+            if self.complete {
+                list.no_more_items = !self.paginator.has_more;
+                list.next_cursor = self.paginator.next_cursor().map(|c| c.to_string()).unwrap_or_default();
+            }
+            return Ok(list);
+        }
+    }
+}
+
+impl<In, Mapper, Filter> Iterator for ItemListChunks<In, Mapper, Filter>
+where
+    In: ListRow,
+    Mapper: Fn(In) -> Result<ItemListEntry, anyhow::Error>,
+    Filter: Fn(&ItemListEntry) -> bool,
+{
+    type Item = Result<Bytes, SendError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.complete {
+            return None;
+        }
+
+        let bytes = self.next_chunk().and_then(|list| Ok(list.write_to_bytes()?));
+        match bytes {
+            Ok(bytes) => Some(Ok(bytes.into())),
+            Err(err) => {
+                self.complete = true;
+                // Errors that happen while streaming must be std Errors:
+                Some(Err(std::io::Error::new(ErrorKind::Other, err).into()))
+            },
+        }
+    }
+}
+
+fn add_revisions(backend: &dyn Backend, entries: &mut Vec<ItemListEntry>) -> Result<(), anyhow::Error> {
+    for entry in entries {
+        if entry.get_item_type() != ItemType::POST {
+            continue;
+        }
+
+        let user_id = UserID::from_vec(entry.get_user_id().get_bytes().into())?;
+        let signature = Signature::from_vec(entry.get_signature().get_bytes().into())?;
+        if let Some(revision) = backend.latest_revision(&user_id, &signature)? {
+            let mut sig = crate::protos::Signature::new();
+            sig.set_bytes(revision.signature.bytes().into());
+            entry.set_latest_revision(sig);
+        }
+    }
+
+    Ok(())
+}
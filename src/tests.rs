@@ -95,6 +95,55 @@ fn time_duration() {
     assert_eq!(292471208, max_feo.whole_days() / 365);
 }
 
+// A successor's counter-signature can't be replayed to name it from another user's Profile.
+#[test]
+fn successor_signature() {
+    use crate::backend::{Signature, UserID};
+    use sodiumoxide::crypto::sign;
+
+    let user_id = |pk: &sign::PublicKey| UserID::from_vec(pk.as_ref().to_vec()).unwrap();
+    let (old_pk, _) = sign::gen_keypair();
+    let (other_pk, _) = sign::gen_keypair();
+    let (new_pk, new_sk) = sign::gen_keypair();
+    let (old, other, new) = (user_id(&old_pk), user_id(&other_pk), user_id(&new_pk));
+
+    let signature = sign::sign_detached(&old.successor_bytes(&new), &new_sk);
+    let signature = Signature::from_vec(signature.as_ref().to_vec()).unwrap();
+
+    assert!(signature.is_valid(&new, &old.successor_bytes(&new)));
+    assert!(!signature.is_valid(&new, &other.successor_bytes(&new)));
+    assert!(!signature.is_valid(&new, old.bytes()));
+}
+
+// DirectMessages are encrypted to a user's ed25519 key, converted to curve25519.
+#[test]
+fn box_to_user_id() {
+    use crate::backend::UserID;
+    use sodiumoxide::crypto::{box_, sign};
+
+    let (sender_pk, sender_sk) = sign::gen_keypair();
+    let (recipient_pk, recipient_sk) = sign::gen_keypair();
+    let sender = UserID::from_vec(sender_pk.as_ref().to_vec()).unwrap();
+    let recipient = UserID::from_vec(recipient_pk.as_ref().to_vec()).unwrap();
+
+    let nonce = box_::gen_nonce();
+    let ciphertext = box_::seal(
+        b"Hello, world!",
+        &nonce,
+        &recipient.to_box_public_key().unwrap(),
+        &sign::ed25519::to_curve25519_sk(&sender_sk).unwrap(),
+    );
+
+    let plaintext = box_::open(
+        &ciphertext,
+        &nonce,
+        &sender.to_box_public_key().unwrap(),
+        &sign::ed25519::to_curve25519_sk(&recipient_sk).unwrap(),
+    ).unwrap();
+
+    assert_eq!(b"Hello, world!".to_vec(), plaintext);
+}
+
 /// Test that Snowpack/Rollup didn't generate files with NTFS alternate data streams.
 /// See: https://github.com/NfNitLoop/feoblog/issues/16
 /// These are unintended side-effects of using file paths that include a : in the name.
@@ -172,3 +221,17 @@ fn walk_files<P: AsRef<Path>>(dir_path: P) -> Result<impl Iterator<Item=io::Resu
 
     Ok(iter)
 }
+
+#[test]
+fn quota_sizes() {
+    use crate::QuotaSize;
+
+    let parse = |value: &str| value.parse::<QuotaSize>().ok();
+    assert_eq!(parse("unlimited"), Some(QuotaSize(None)));
+    assert_eq!(parse("100"), Some(QuotaSize(Some(100))));
+    assert_eq!(parse("2KiB"), Some(QuotaSize(Some(2048))));
+    assert_eq!(parse("10 MB"), Some(QuotaSize(Some(10 * 1024 * 1024))));
+    assert_eq!(parse("1g"), Some(QuotaSize(Some(1024 * 1024 * 1024))));
+    assert_eq!(parse("lots"), None);
+    assert_eq!(parse("10 parsecs"), None);
+}